@@ -0,0 +1,262 @@
+//! Managing an ssh-agent with `ssh-agent` and `ssh-add`.
+//!
+//! This allows a program to set up the keys it authenticates with before
+//! connecting, without relying on an agent set up by the user:
+//!
+//! ```rust,no_run
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use openssh::{agent::Agent, KnownHosts, SessionBuilder};
+//! use std::time::Duration;
+//!
+//! let agent = Agent::start().await?;
+//! agent
+//!     .add_with_lifetime("deploy-key", Duration::from_secs(600))
+//!     .await?;
+//!
+//! let session = SessionBuilder::default()
+//!     .ssh_auth_sock(agent.socket())
+//!     .known_hosts_check(KnownHosts::Strict)
+//!     .connect("ssh://me@example.com")
+//!     .await?;
+//! # Ok(()) }
+//! ```
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::time::Duration;
+
+use tempfile::TempDir;
+use tokio::process;
+
+/// A handle to an ssh-agent.
+///
+/// Agents started by [`Agent::start`] are stopped on drop, while agents
+/// opened with [`Agent::from_socket`] or [`Agent::from_env`] are left
+/// running.
+#[derive(Debug)]
+pub struct Agent {
+    socket: PathBuf,
+    /// The pid and socket directory of an agent started by us.
+    owned: Option<(libc::pid_t, TempDir)>,
+}
+
+impl Agent {
+    /// Start a new agent (`ssh-agent`) listening on a socket in a private
+    /// temporary directory.
+    pub async fn start() -> io::Result<Self> {
+        let dir = tempfile::Builder::new().prefix(".ssh-agent").tempdir()?;
+        let socket = dir.path().join("agent.sock");
+
+        let output = process::Command::new("ssh-agent")
+            .stdin(Stdio::null())
+            .arg("-s")
+            .arg("-a")
+            .arg(&socket)
+            .output()
+            .await?;
+        let stdout = check(output)?;
+
+        // e.g. "SSH_AGENT_PID=1234; export SSH_AGENT_PID;"
+        let pid = String::from_utf8_lossy(&stdout)
+            .split(';')
+            .find_map(|var| var.trim().strip_prefix("SSH_AGENT_PID=")?.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "ssh-agent did not print its pid",
+                )
+            })?;
+
+        Ok(Self {
+            socket,
+            owned: Some((pid, dir)),
+        })
+    }
+
+    /// Use the running agent listening on `socket`.
+    pub fn from_socket(socket: impl AsRef<Path>) -> Self {
+        Self {
+            socket: socket.as_ref().to_owned(),
+            owned: None,
+        }
+    }
+
+    /// Use the running agent given by the `SSH_AUTH_SOCK` environment
+    /// variable, failing with [`io::ErrorKind::NotFound`] if it is not set.
+    pub fn from_env() -> io::Result<Self> {
+        env::var_os("SSH_AUTH_SOCK")
+            .map(Self::from_socket)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "SSH_AUTH_SOCK is not set"))
+    }
+
+    /// Return the socket of the agent, e.g. to pass to
+    /// [`SessionBuilder::ssh_auth_sock`](crate::SessionBuilder::ssh_auth_sock).
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Add the private key in `keyfile` to the agent (`ssh-add`).
+    ///
+    /// The key must not be encrypted.
+    pub async fn add(&self, keyfile: impl AsRef<Path>) -> io::Result<()> {
+        self.ssh_add()
+            .arg("--")
+            .arg(keyfile.as_ref())
+            .output()
+            .await
+            .and_then(check)?;
+        Ok(())
+    }
+
+    /// Like [`add`](Self::add), but have the agent remove the key again
+    /// after `lifetime` (`ssh-add -t`), rounded down to whole seconds.
+    pub async fn add_with_lifetime(
+        &self,
+        keyfile: impl AsRef<Path>,
+        lifetime: Duration,
+    ) -> io::Result<()> {
+        self.ssh_add()
+            .arg("-t")
+            .arg(lifetime.as_secs().max(1).to_string())
+            .arg("--")
+            .arg(keyfile.as_ref())
+            .output()
+            .await
+            .and_then(check)?;
+        Ok(())
+    }
+
+    /// Remove the key in `keyfile`, or whose public key is in `keyfile`, from
+    /// the agent (`ssh-add -d`).
+    pub async fn remove(&self, keyfile: impl AsRef<Path>) -> io::Result<()> {
+        self.ssh_add()
+            .arg("-d")
+            .arg("--")
+            .arg(keyfile.as_ref())
+            .output()
+            .await
+            .and_then(check)?;
+        Ok(())
+    }
+
+    /// Remove all keys from the agent (`ssh-add -D`).
+    pub async fn remove_all(&self) -> io::Result<()> {
+        self.ssh_add().arg("-D").output().await.and_then(check)?;
+        Ok(())
+    }
+
+    /// List the keys held by the agent (`ssh-add -l`).
+    pub async fn identities(&self) -> io::Result<Vec<Identity>> {
+        let output = self.ssh_add().arg("-l").output().await?;
+
+        // `ssh-add` exits with 1 if the agent holds no keys.
+        if output.status.code() == Some(1) && output.stdout.starts_with(b"The agent has no") {
+            return Ok(Vec::new());
+        }
+
+        let stdout = check(output)?;
+        String::from_utf8_lossy(&stdout)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                Identity::parse(line).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected output of ssh-add: {}", line),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn ssh_add(&self) -> process::Command {
+        let mut cmd = process::Command::new("ssh-add");
+        cmd.stdin(Stdio::null()).env("SSH_AUTH_SOCK", &self.socket);
+        cmd
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        if let Some((pid, _)) = self.owned {
+            // SAFETY: `kill` has no memory safety requirements.
+            unsafe {
+                libc::kill(pid, libc::SIGTERM);
+            }
+        }
+    }
+}
+
+/// A key held by an [`Agent`], see [`Agent::identities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Identity {
+    /// The size of the key in bits, e.g. `256`.
+    pub bits: u32,
+
+    /// The fingerprint of the key, e.g. `SHA256:...`.
+    pub fingerprint: String,
+
+    /// The comment of the key, usually the file it was loaded from or
+    /// `user@host`.
+    pub comment: String,
+
+    /// The type of the key, e.g. `ED25519` or `RSA`.
+    pub key_type: String,
+}
+
+impl Identity {
+    /// Parse a line of `ssh-add -l`, e.g.
+    /// `256 SHA256:... me@example.com (ED25519)`.
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let (bits, rest) = line.trim().split_once(' ')?;
+        let (fingerprint, rest) = rest.split_once(' ')?;
+        let (comment, key_type) = rest.rsplit_once('(')?;
+
+        Some(Self {
+            bits: bits.parse().ok()?,
+            fingerprint: fingerprint.to_owned(),
+            comment: comment.trim().to_owned(),
+            key_type: key_type.strip_suffix(')')?.to_owned(),
+        })
+    }
+}
+
+/// Return the stdout of a successful command, or its stderr as an error.
+pub(crate) fn check(output: Output) -> io::Result<Vec<u8>> {
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(io::Error::new(io::ErrorKind::Other, stderr.trim()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_identity() {
+        assert_eq!(
+            Identity::parse(
+                "256 SHA256:4nBq5EKh0e8Hg9vB+9rW9GnWQhRfEzN0Ui6Yk2cu4bU me@example.com (ED25519)"
+            ),
+            Some(Identity {
+                bits: 256,
+                fingerprint: "SHA256:4nBq5EKh0e8Hg9vB+9rW9GnWQhRfEzN0Ui6Yk2cu4bU".into(),
+                comment: "me@example.com".into(),
+                key_type: "ED25519".into(),
+            })
+        );
+        assert_eq!(
+            Identity::parse("3072 MD5:0a:1b my key (RSA)\n").map(|id| id.comment),
+            Some("my key".into())
+        );
+        assert_eq!(Identity::parse("The agent has no identities."), None);
+    }
+}
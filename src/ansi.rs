@@ -0,0 +1,109 @@
+//! Removal of ANSI escape sequences, such as colors, from captured output.
+
+/// Where in an escape sequence the previous chunk ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    #[default]
+    Ground,
+    /// After `ESC`.
+    Escape,
+    /// After `ESC` and intermediate bytes, e.g. `ESC (`.
+    EscapeIntermediate,
+    /// In a control sequence, `ESC [`, which ends with a byte in `@..=~`.
+    Csi,
+    /// In an operating system command, `ESC ]`, or a device control, privacy
+    /// message or application program command string, which end with `ST`
+    /// (`ESC \`) or, by convention, `BEL`.
+    String,
+    /// After `ESC` in a string.
+    StringEscape,
+}
+
+/// Strips ANSI escape sequences from output read in chunks, which may split
+/// a sequence.
+///
+/// Other control characters, such as `\r` and `\t`, are kept.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AnsiStripper {
+    state: State,
+}
+
+impl AnsiStripper {
+    /// Return `chunk` without the escape sequences in it, or the parts of
+    /// sequences continued from or continuing in other chunks.
+    pub(crate) fn strip(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len());
+
+        for &byte in chunk {
+            self.state = match (self.state, byte) {
+                (State::StringEscape, b'\\') => State::Ground,
+                (State::String, 0x07) => State::Ground,
+                (State::String | State::StringEscape, 0x1b) => State::StringEscape,
+                (State::String | State::StringEscape, _) => State::String,
+
+                (_, 0x1b) => State::Escape,
+
+                (State::Ground, _) => {
+                    out.push(byte);
+                    State::Ground
+                }
+
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']' | b'P' | b'X' | b'^' | b'_') => State::String,
+                (State::Escape | State::EscapeIntermediate, 0x20..=0x2f) => {
+                    State::EscapeIntermediate
+                }
+                // The final byte, or an invalid one that ends the sequence.
+                (State::Escape | State::EscapeIntermediate, _) => State::Ground,
+
+                (State::Csi, 0x40..=0x7e) => State::Ground,
+                (State::Csi, _) => State::Csi,
+            };
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_ansi(output: &[u8]) -> Vec<u8> {
+        AnsiStripper::default().strip(output)
+    }
+
+    #[test]
+    fn strip() {
+        assert_eq!(strip_ansi(b"plain\r\n\ttext"), b"plain\r\n\ttext");
+        assert_eq!(
+            strip_ansi(b"\x1b[1;31merror\x1b[0m: \x1b[38;5;208mfailed\x1b[m\n"),
+            b"error: failed\n"
+        );
+        // Cursor movement, private modes and character sets.
+        assert_eq!(
+            strip_ansi(b"\x1b[2K\x1b[?25l50%\x1b(B\x1b=\x1b[?25h"),
+            b"50%"
+        );
+        // Window titles and hyperlinks, ended with `BEL` or `ST`.
+        assert_eq!(
+            strip_ansi(b"\x1b]0;title\x07\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"),
+            b"link"
+        );
+        assert_eq!(
+            strip_ansi("\x1b[32mgrün\x1b[0m".as_bytes()),
+            "grün".as_bytes()
+        );
+    }
+
+    #[test]
+    fn split_sequences() {
+        let input = b"a\x1b[31mb\x1b]0;t\x1b\\c";
+        for i in 0..=input.len() {
+            let mut stripper = AnsiStripper::default();
+            let mut out = stripper.strip(&input[..i]);
+            out.extend(stripper.strip(&input[i..]));
+            assert_eq!(out, b"abc", "split at {}", i);
+        }
+    }
+}
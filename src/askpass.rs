@@ -0,0 +1,304 @@
+//! Supplying secrets to ssh through a generated `SSH_ASKPASS` helper, or
+//! through `sshpass`.
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::process;
+
+/// A secret, such as a password, that is not revealed by its `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Secret(Box<str>);
+
+impl Secret {
+    pub(crate) fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into().into_boxed_str())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// The passphrase of an encrypted key, or a callback returning it when
+/// connecting.
+#[derive(Clone)]
+pub(crate) enum Passphrase {
+    Secret(Secret),
+    Callback(Arc<dyn Fn() -> io::Result<String> + Send + Sync>),
+}
+
+impl Passphrase {
+    pub(crate) fn get(&self) -> io::Result<Secret> {
+        match self {
+            Passphrase::Secret(secret) => Ok(secret.clone()),
+            Passphrase::Callback(callback) => callback().map(Secret::new),
+        }
+    }
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+/// A file only readable by the current user that holds a secret, which is
+/// removed on drop.
+///
+/// Secrets are passed this way rather than in the environment or on the
+/// command line, where other users may be able to read them.
+#[derive(Debug)]
+struct SecretFile {
+    path: PathBuf,
+}
+
+impl SecretFile {
+    fn new(path: PathBuf, secret: &Secret) -> io::Result<Self> {
+        write_private(&path, 0o600, format!("{}\n", secret.0).as_bytes())?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for SecretFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// An askpass helper in a private directory, which answers the prompts of
+/// ssh with a password and the passphrase of encrypted keys.
+///
+/// Both the helper and the secrets are removed on drop.
+#[derive(Debug)]
+pub(crate) struct Askpass {
+    script: PathBuf,
+    /// Only kept to be removed on drop.
+    _secrets: Vec<SecretFile>,
+}
+
+impl Askpass {
+    /// Create the helper in `dir`, which must only be accessible by the
+    /// current user.
+    ///
+    /// Prompts for key passphrases are answered with `passphrase`, and all
+    /// other prompts with `password`. Prompts without a secret fail.
+    pub(crate) fn new(
+        dir: &Path,
+        password: Option<&Secret>,
+        passphrase: Option<&Secret>,
+    ) -> io::Result<Self> {
+        let mut secrets = Vec::new();
+        let mut cat = |name: &str, secret: &Secret| -> io::Result<OsString> {
+            let file = SecretFile::new(dir.join(name), secret)?;
+            let mut cmd = OsString::from("exec cat -- ");
+            cmd.push(crate::escape::escape(file.path.as_os_str()));
+            secrets.push(file);
+            Ok(cmd)
+        };
+
+        let mut script = OsString::from("#!/bin/sh\n");
+        if let Some(passphrase) = passphrase {
+            // e.g. "Enter passphrase for key '/home/me/.ssh/id_ed25519': "
+            script.push("case \"$1\" in 'Enter passphrase '*) ");
+            script.push(cat("askpass-passphrase", passphrase)?);
+            script.push(";; esac\n");
+        }
+        match password {
+            Some(password) => script.push(cat("askpass-password", password)?),
+            None => script.push("exit 1"),
+        }
+        script.push("\n");
+
+        let askpass = Self {
+            script: dir.join("askpass"),
+            _secrets: secrets,
+        };
+        write_private(&askpass.script, 0o700, script.as_bytes())?;
+
+        Ok(askpass)
+    }
+
+    /// Make `cmd` ask the helper for secrets instead of the terminal.
+    pub(crate) fn configure(&self, cmd: &mut process::Command) {
+        cmd.env("SSH_ASKPASS", &self.script)
+            .env("SSH_ASKPASS_REQUIRE", "force");
+
+        // ssh before 8.4 ignores `SSH_ASKPASS_REQUIRE`, and only uses the
+        // helper if `DISPLAY` is set and it has no controlling terminal.
+        if env::var_os("DISPLAY").is_none() {
+            cmd.env("DISPLAY", ":0");
+        }
+
+        // SAFETY: `setsid` is async-signal-safe.
+        unsafe {
+            cmd.pre_exec(|| {
+                libc::setsid();
+                Ok(())
+            });
+        }
+    }
+}
+
+impl Drop for Askpass {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.script);
+    }
+}
+
+/// A password passed to `sshpass -f` in a private directory, which is
+/// removed on drop.
+#[derive(Debug)]
+pub(crate) struct Sshpass {
+    program: PathBuf,
+    secret: SecretFile,
+}
+
+impl Sshpass {
+    /// Store `secret` in `dir`, which must only be accessible by the current
+    /// user, for the `sshpass` binary at `program`.
+    pub(crate) fn new(dir: &Path, program: PathBuf, secret: &Secret) -> io::Result<Self> {
+        Ok(Self {
+            program,
+            secret: SecretFile::new(dir.join("sshpass-secret"), secret)?,
+        })
+    }
+
+    /// Find the `sshpass` binary in `PATH`.
+    pub(crate) fn find() -> io::Result<PathBuf> {
+        find_in(env::var_os("PATH").as_deref().unwrap_or_default())
+    }
+
+    /// Return a command running `ssh` through `sshpass`.
+    pub(crate) fn command(&self) -> process::Command {
+        let mut cmd = process::Command::new(&self.program);
+        cmd.arg("-f").arg(&self.secret.path).arg("ssh");
+        cmd
+    }
+}
+
+/// How the password of a [`SessionBuilder`](crate::SessionBuilder) is
+/// supplied to ssh.
+#[derive(Debug)]
+pub(crate) enum PasswordAuth {
+    Askpass(Askpass),
+    Sshpass(Sshpass),
+}
+
+impl PasswordAuth {
+    /// Return the command to launch ssh with.
+    pub(crate) fn command(&self) -> process::Command {
+        match self {
+            PasswordAuth::Askpass(askpass) => {
+                let mut cmd = process::Command::new("ssh");
+                askpass.configure(&mut cmd);
+                cmd
+            }
+            PasswordAuth::Sshpass(sshpass) => sshpass.command(),
+        }
+    }
+}
+
+fn find_in(path: &OsStr) -> io::Result<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join("sshpass"))
+        .find(|candidate| {
+            fs::metadata(candidate)
+                .map(|metadata| metadata.is_file())
+                .unwrap_or(false)
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "sshpass not found in PATH"))
+}
+
+fn write_private(path: &Path, mode: u32, contents: &[u8]) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ask(askpass: &Askpass, prompt: &str) -> Option<Vec<u8>> {
+        let output = std::process::Command::new(&askpass.script)
+            .arg(prompt)
+            .output()
+            .unwrap();
+        Some(output.stdout).filter(|_| output.status.success())
+    }
+
+    #[test]
+    fn askpass() {
+        let dir = tempfile::tempdir().unwrap();
+        let password = Secret::new("it's a secret");
+        let passphrase = Secret::new("open sesame");
+        assert_eq!(format!("{:?}", password), "Secret(..)");
+
+        let password_prompt = "test-user@localhost's password: ";
+        let passphrase_prompt = "Enter passphrase for key '/home/test-user/.ssh/id_ed25519': ";
+
+        let askpass = Askpass::new(dir.path(), Some(&password), None).unwrap();
+        assert_eq!(ask(&askpass, password_prompt).unwrap(), b"it's a secret\n");
+        assert_eq!(
+            ask(&askpass, passphrase_prompt).unwrap(),
+            b"it's a secret\n"
+        );
+        drop(askpass);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let askpass = Askpass::new(dir.path(), Some(&password), Some(&passphrase)).unwrap();
+        assert_eq!(ask(&askpass, password_prompt).unwrap(), b"it's a secret\n");
+        assert_eq!(ask(&askpass, passphrase_prompt).unwrap(), b"open sesame\n");
+        drop(askpass);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let askpass = Askpass::new(dir.path(), None, Some(&passphrase)).unwrap();
+        assert_eq!(ask(&askpass, password_prompt), None);
+        assert_eq!(ask(&askpass, passphrase_prompt).unwrap(), b"open sesame\n");
+        drop(askpass);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn sshpass() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("bin");
+        fs::create_dir(&bin).unwrap();
+
+        let path = env::join_paths([dir.path().join("missing"), bin.clone()]).unwrap();
+        let err = find_in(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        fs::write(bin.join("sshpass"), "").unwrap();
+        assert_eq!(find_in(&path).unwrap(), bin.join("sshpass"));
+
+        let program = bin.join("sshpass");
+        let sshpass = Sshpass::new(dir.path(), program.clone(), &Secret::new("secret")).unwrap();
+        let secret = dir.path().join("sshpass-secret");
+        assert_eq!(fs::read(&secret).unwrap(), b"secret\n");
+
+        let cmd = sshpass.command();
+        assert_eq!(cmd.as_std().get_program(), program);
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            [OsStr::new("-f"), secret.as_os_str(), OsStr::new("ssh")]
+        );
+
+        drop(sshpass);
+        assert!(!secret.exists());
+    }
+}
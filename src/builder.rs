@@ -1,12 +1,17 @@
-use super::{Error, Session};
+#[cfg(feature = "password-auth")]
+use super::askpass::{Askpass, Passphrase, PasswordAuth, Secret, Sshpass};
+use super::known_hosts::{HostKeyCallback, LogCallback};
+use super::{Error, ForwardType, HostKeyUpdate, Session, Socket};
 
 use std::borrow::Cow;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::iter::IntoIterator;
+use std::os::unix::fs::FileTypeExt;
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::str;
-use std::{fs, io};
+use std::time::Duration;
+use std::{fmt, fs, io};
 
 use once_cell::sync::OnceCell;
 use tempfile::{Builder, TempDir};
@@ -75,22 +80,118 @@ fn clean_history_control_dir(socketdir: &Path, prefix: &str) -> io::Result<()> {
     Ok(())
 }
 
+/// The file in the control directory of a session the version of the server
+/// is written to, see [`SessionBuilder::capture_server_version`].
+pub(crate) const SERVER_VERSION_FILE: &str = "server-version";
+
 /// Build a [`Session`] with options.
-#[derive(Debug, Clone)]
+///
+/// Its `Debug` implementation redacts the paths of keys, configuration
+/// files and the agent socket, see [`to_ssh_args`](Self::to_ssh_args) for
+/// logging them deliberately.
+#[derive(Clone)]
 pub struct SessionBuilder {
     user: Option<String>,
     port: Option<String>,
     keyfile: Option<PathBuf>,
-    connect_timeout: Option<String>,
+    connect_timeout: Option<Duration>,
+    connection_attempts: Option<u32>,
     server_alive_interval: Option<u64>,
+    tcp_keepalive: Option<bool>,
+    idle_timeout: Option<Duration>,
     known_hosts_check: KnownHosts,
+    host_key_fingerprint: Option<Box<str>>,
     control_dir: Option<PathBuf>,
     clean_history_control_dir: bool,
+    control_path: Option<Box<str>>,
     config_file: Option<PathBuf>,
     compression: Option<bool>,
+    hostbased_authentication: Option<bool>,
+    gssapi_authentication: Option<bool>,
+    gssapi_delegate_credentials: Option<bool>,
     jump_hosts: Vec<Box<str>>,
+    jump_chain: bool,
     user_known_hosts_file: Option<Box<Path>>,
+    ephemeral_known_hosts: bool,
+    revoked_host_keys: Option<Box<Path>>,
+    update_host_keys: Option<bool>,
+    host_key_callback: Option<HostKeyCallback>,
+    capture_server_version: bool,
+    verbosity: u8,
+    log_callback: Option<LogCallback>,
     ssh_auth_sock: Option<Box<Path>>,
+    happy_eyeballs: bool,
+    tun_forward: Option<TunForward>,
+    security_profile: Option<SecurityProfile>,
+    stream_local_bind_unlink: Option<bool>,
+    proxy_session: Option<Box<Path>>,
+    forwards: Vec<(ForwardType, Socket<'static>, Socket<'static>)>,
+    #[cfg(feature = "password-auth")]
+    password: Option<Secret>,
+    #[cfg(feature = "password-auth")]
+    sshpass: bool,
+    #[cfg(feature = "password-auth")]
+    passphrase: Option<Passphrase>,
+}
+
+/// Printed in place of a redacted value.
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl fmt::Debug for SessionBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redact = |value: Option<&Path>| value.map(|_| Redacted);
+
+        let mut f = f.debug_struct("SessionBuilder");
+        f.field("user", &self.user)
+            .field("port", &self.port)
+            .field("keyfile", &redact(self.keyfile.as_deref()))
+            .field("connect_timeout", &self.connect_timeout)
+            .field("connection_attempts", &self.connection_attempts)
+            .field("server_alive_interval", &self.server_alive_interval)
+            .field("tcp_keepalive", &self.tcp_keepalive)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("known_hosts_check", &self.known_hosts_check)
+            .field("host_key_fingerprint", &self.host_key_fingerprint)
+            .field("control_dir", &self.control_dir)
+            .field("clean_history_control_dir", &self.clean_history_control_dir)
+            .field("control_path", &self.control_path)
+            .field("config_file", &redact(self.config_file.as_deref()))
+            .field("compression", &self.compression)
+            .field("hostbased_authentication", &self.hostbased_authentication)
+            .field("gssapi_authentication", &self.gssapi_authentication)
+            .field(
+                "gssapi_delegate_credentials",
+                &self.gssapi_delegate_credentials,
+            )
+            .field("jump_hosts", &self.jump_hosts)
+            .field("jump_chain", &self.jump_chain)
+            .field("user_known_hosts_file", &self.user_known_hosts_file)
+            .field("ephemeral_known_hosts", &self.ephemeral_known_hosts)
+            .field("revoked_host_keys", &self.revoked_host_keys)
+            .field("update_host_keys", &self.update_host_keys)
+            .field("host_key_callback", &self.host_key_callback)
+            .field("capture_server_version", &self.capture_server_version)
+            .field("verbosity", &self.verbosity)
+            .field("log_callback", &self.log_callback)
+            .field("ssh_auth_sock", &redact(self.ssh_auth_sock.as_deref()))
+            .field("happy_eyeballs", &self.happy_eyeballs)
+            .field("tun_forward", &self.tun_forward)
+            .field("security_profile", &self.security_profile)
+            .field("stream_local_bind_unlink", &self.stream_local_bind_unlink)
+            .field("proxy_session", &self.proxy_session)
+            .field("forwards", &self.forwards);
+        #[cfg(feature = "password-auth")]
+        f.field("password", &self.password.as_ref().map(|_| Redacted))
+            .field("sshpass", &self.sshpass)
+            .field("passphrase", &self.passphrase.as_ref().map(|_| Redacted));
+        f.finish()
+    }
 }
 
 impl Default for SessionBuilder {
@@ -100,15 +201,43 @@ impl Default for SessionBuilder {
             port: None,
             keyfile: None,
             connect_timeout: None,
+            connection_attempts: None,
             server_alive_interval: None,
+            tcp_keepalive: None,
+            idle_timeout: None,
             known_hosts_check: KnownHosts::Add,
+            host_key_fingerprint: None,
             control_dir: None,
             clean_history_control_dir: false,
+            control_path: None,
             config_file: None,
             compression: None,
+            hostbased_authentication: None,
+            gssapi_authentication: None,
+            gssapi_delegate_credentials: None,
             jump_hosts: Vec::new(),
+            jump_chain: false,
             user_known_hosts_file: None,
+            ephemeral_known_hosts: false,
+            revoked_host_keys: None,
+            update_host_keys: None,
+            host_key_callback: None,
+            capture_server_version: false,
+            verbosity: 0,
+            log_callback: None,
             ssh_auth_sock: None,
+            happy_eyeballs: false,
+            tun_forward: None,
+            security_profile: None,
+            stream_local_bind_unlink: None,
+            proxy_session: None,
+            forwards: Vec::new(),
+            #[cfg(feature = "password-auth")]
+            password: None,
+            #[cfg(feature = "password-auth")]
+            sshpass: false,
+            #[cfg(feature = "password-auth")]
+            passphrase: None,
         }
     }
 }
@@ -156,12 +285,53 @@ impl SessionBuilder {
         self
     }
 
+    /// Only accept the host key with fingerprint `fingerprint`, e.g.
+    /// `SHA256:...` as printed by `ssh-keygen -l`, failing with
+    /// [`Error::HostKeyMismatch`] for any other key.
+    ///
+    /// This is stricter than any [`KnownHosts`] mode: the known_hosts files,
+    /// including [`user_known_hosts_file`](Self::user_known_hosts_file), are
+    /// ignored and [`known_hosts_check`](Self::known_hosts_check) has no
+    /// effect. The key is checked by a generated `KnownHostsCommand` in the
+    /// control directory, which requires OpenSSH 8.5 or later.
+    ///
+    /// Both `SHA256:` and `MD5:` fingerprints are supported.
+    ///
+    /// Defaults to `None`.
+    pub fn host_key_fingerprint(&mut self, fingerprint: impl Into<String>) -> &mut Self {
+        self.host_key_fingerprint = Some(fingerprint.into().into_boxed_str());
+        self
+    }
+
     /// Set the connection timeout (`ssh -o ConnectTimeout`).
     ///
-    /// This value is specified in seconds. Any sub-second duration remainder will be ignored.
+    /// This value is specified in seconds. Any sub-second duration remainder will be ignored,
+    /// and a timeout of less than a second is rounded up to one second.
     /// Defaults to `None`.
+    ///
+    /// `ConnectTimeout` only covers establishing the TCP connection, so the
+    /// ssh process establishing the master connection is additionally killed,
+    /// returning [`Error::Timeout`], if the whole handshake including key
+    /// exchange and authentication has not finished within this timeout per
+    /// [connection attempt](Self::connection_attempts), plus the second ssh
+    /// waits between attempts and a grace period of one second. Timeouts too
+    /// large to add up are not enforced this way.
     pub fn connect_timeout(&mut self, d: std::time::Duration) -> &mut Self {
-        self.connect_timeout = Some(d.as_secs().to_string());
+        self.connect_timeout = Some(d);
+        self
+    }
+
+    /// Set the number of attempts to make to establish the TCP connection,
+    /// a second apart, before giving up (`ssh -o ConnectionAttempts`).
+    ///
+    /// This rides out e.g. a host that drops connections while it boots.
+    /// Each attempt is bounded by the [`connect_timeout`](Self::connect_timeout),
+    /// which is extended accordingly. Attempts are only repeated if the
+    /// connection cannot be established, not if authentication fails.
+    ///
+    /// Defaults to `None`, which makes a single attempt.
+    pub fn connection_attempts(&mut self, attempts: u32) -> &mut Self {
+        self.connection_attempts = Some(attempts.max(1));
         self
     }
 
@@ -175,6 +345,40 @@ impl SessionBuilder {
         self
     }
 
+    /// Enable or disable TCP keepalive messages on the connection to the
+    /// server (`ssh -o TCPKeepAlive`).
+    ///
+    /// Unlike [`server_alive_interval`](Self::server_alive_interval), these
+    /// are sent by the TCP stack outside of the encrypted channel. Enabling
+    /// them keeps stateful firewalls and NATs from expiring the connection
+    /// while it is silent, e.g. during long transfers that stall, while
+    /// disabling them keeps the connection from being dropped on temporary
+    /// routing outages.
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which defaults
+    /// to enabled.
+    pub fn tcp_keepalive(&mut self, enable: bool) -> &mut Self {
+        self.tcp_keepalive = Some(enable);
+        self
+    }
+
+    /// Close the master connection automatically once no commands, transfers
+    /// or forwarded connections have been running through it for `d`
+    /// (`ssh -o ControlPersist`), e.g. to free the session on the server in
+    /// long-running programs with sporadic activity.
+    ///
+    /// Once closed, the session fails like a disconnected one, see
+    /// [`Session::check`](crate::Session::check). Forwardings that are
+    /// established, but carry no connections, do not keep it open.
+    ///
+    /// This value is specified in seconds. Any sub-second duration remainder will be ignored.
+    /// Defaults to `None`, which keeps the master open until the session is
+    /// closed.
+    pub fn idle_timeout(&mut self, d: std::time::Duration) -> &mut Self {
+        self.idle_timeout = Some(d);
+        self
+    }
+
     /// Set the directory in which the temporary directory containing the control socket will
     /// be created.
     ///
@@ -202,6 +406,28 @@ impl SessionBuilder {
         self
     }
 
+    /// Set a template for the path of the control socket of the master,
+    /// e.g. `~/.ssh/cm-%C`, instead of placing it in the temporary directory
+    /// of the session.
+    ///
+    /// The template is expanded by ssh like `ControlPath`: `%C` is a hash of
+    /// the local host name, remote host name, port and user, `%h`, `%p` and
+    /// `%r` are the remote host name, port and user, and `%%` is a literal
+    /// `%`. A template based on `%C` gives every destination a socket of its
+    /// own at a path that is the same in every process, so that other
+    /// processes can attach to the master with
+    /// [`Session::resume_verified`](crate::Session::resume_verified) at the
+    /// path returned by [`control_path_for`](Self::control_path_for).
+    ///
+    /// Connecting fails with [`Error::Master`] if another master is already
+    /// listening on the socket, while a socket left behind by a master that
+    /// exited is removed. The log of the master is still kept in the
+    /// temporary directory.
+    pub fn control_path(&mut self, template: impl Into<String>) -> &mut Self {
+        self.control_path = Some(template.into().into_boxed_str());
+        self
+    }
+
     /// Set an alternative per-user configuration file.
     ///
     /// By default, ssh uses `~/.ssh/config`. This is equivalent to `ssh -F <p>`.
@@ -227,6 +453,56 @@ impl SessionBuilder {
         self
     }
 
+    /// Restrict the ciphers, MACs, key exchange and host key algorithms
+    /// (`ssh -o Ciphers`, `MACs`, `KexAlgorithms` and `HostKeyAlgorithms`)
+    /// to those of `profile`.
+    ///
+    /// Note that this does not apply to the [jump hosts](Self::jump_hosts).
+    ///
+    /// By default, ssh uses the values set in `~/.ssh/config`, which default
+    /// to the defaults of ssh.
+    pub fn security_profile(&mut self, profile: SecurityProfile) -> &mut Self {
+        self.security_profile = Some(profile);
+        self
+    }
+
+    /// Enable or disable host-based authentication
+    /// (`ssh -o HostbasedAuthentication`).
+    ///
+    /// Host-based authentication also requires `EnableSSHKeysign` in the
+    /// system-wide ssh config, as `ssh-keysign` needs access to the host
+    /// keys of the local machine.
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which defaults
+    /// to disabled.
+    pub fn hostbased_authentication(&mut self, enable: bool) -> &mut Self {
+        self.hostbased_authentication = Some(enable);
+        self
+    }
+
+    /// Enable or disable GSSAPI (e.g. Kerberos) authentication
+    /// (`ssh -o GSSAPIAuthentication`).
+    ///
+    /// The credentials, e.g. a Kerberos ticket obtained with `kinit`, must be
+    /// available to ssh before connecting.
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which defaults
+    /// to disabled.
+    pub fn gssapi_authentication(&mut self, enable: bool) -> &mut Self {
+        self.gssapi_authentication = Some(enable);
+        self
+    }
+
+    /// Enable or disable forwarding (delegation) of GSSAPI credentials to
+    /// the remote host (`ssh -o GSSAPIDelegateCredentials`).
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which defaults
+    /// to disabled.
+    pub fn gssapi_delegate_credentials(&mut self, enable: bool) -> &mut Self {
+        self.gssapi_delegate_credentials = Some(enable);
+        self
+    }
+
     /// Specify one or multiple jump hosts.
     ///
     /// Connect to the target host by first making a ssh connection to the
@@ -248,6 +524,18 @@ impl SessionBuilder {
         self
     }
 
+    /// Accept jump hosts preceding the destination, separated by `+`, e.g.
+    /// `bastion+me@internal`, see [`try_resolve`](Self::try_resolve).
+    ///
+    /// `+` is valid in user names, so without this, `deploy+ci@host` is
+    /// connected to as the user `deploy+ci`.
+    ///
+    /// Defaults to `false`.
+    pub fn jump_chain(&mut self, enable: bool) -> &mut Self {
+        self.jump_chain = enable;
+        self
+    }
+
     /// Specify the path to the `known_hosts` file.
     ///
     /// The path provided may use tilde notation (`~`) to refer to the user's
@@ -260,6 +548,108 @@ impl SessionBuilder {
         self
     }
 
+    /// Keep accepted host keys only for the lifetime of the session, in a
+    /// `known_hosts` file in the control directory that is removed with it,
+    /// rather than in the user's `known_hosts` file.
+    ///
+    /// No host keys are trusted up front: the
+    /// [`user_known_hosts_file`](Self::user_known_hosts_file) and the
+    /// system-wide known_hosts files are ignored. This is meant for scanners
+    /// and test rigs, so use it with [`KnownHosts::Add`] or
+    /// [`KnownHosts::Accept`], as [`KnownHosts::Strict`] rejects every host.
+    ///
+    /// Note that this does not apply to the [jump hosts](Self::jump_hosts).
+    ///
+    /// Defaults to `false`.
+    pub fn ephemeral_known_hosts(&mut self, ephemeral: bool) -> &mut Self {
+        self.ephemeral_known_hosts = ephemeral;
+        self
+    }
+
+    /// Reject host keys listed in `revoked_host_keys`, a file of public keys
+    /// or a key revocation list (KRL) generated by `ssh-keygen -k`
+    /// (`ssh -o RevokedHostKeys`), failing with [`Error::HostKeyRevoked`].
+    ///
+    /// The default is `None`.
+    pub fn revoked_host_keys(&mut self, revoked_host_keys: impl AsRef<Path>) -> &mut Self {
+        self.revoked_host_keys = Some(revoked_host_keys.as_ref().to_owned().into_boxed_path());
+        self
+    }
+
+    /// Enable or disable updating the known_hosts file with the host keys the
+    /// server offers after authentication (`ssh -o UpdateHostKeys`), so that
+    /// connections keep working while the server rotates its keys.
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which defaults
+    /// to enabled unless a custom known_hosts file is used.
+    pub fn update_host_keys(&mut self, update: bool) -> &mut Self {
+        self.update_host_keys = Some(update);
+        self
+    }
+
+    /// Call `callback` whenever ssh updates the known host keys of the
+    /// remote host, see [`update_host_keys`](Self::update_host_keys).
+    ///
+    /// The updates are parsed from the log of the master connection, which
+    /// is checked once a second for as long as the session is open. This
+    /// makes the master log at `LogLevel=VERBOSE`. `callback` is called from
+    /// a tokio task and must not block.
+    ///
+    /// Defaults to `None`.
+    pub fn on_host_key_update<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(HostKeyUpdate) + Send + Sync + 'static,
+    {
+        self.host_key_callback = Some(HostKeyCallback(std::sync::Arc::new(callback)));
+        self
+    }
+
+    /// Capture the version the server announces when connecting, e.g.
+    /// `OpenSSH_8.9p1 Ubuntu-3ubuntu0.1`, for
+    /// [`Session::server_version`].
+    ///
+    /// The version is parsed from the log of the master connection, which
+    /// ssh only writes it to at `LogLevel=DEBUG1`. The master keeps logging
+    /// at that level to a file in its control directory for as long as the
+    /// session is open, including the remote commands it runs.
+    ///
+    /// Defaults to `false`.
+    pub fn capture_server_version(&mut self, capture: bool) -> &mut Self {
+        self.capture_server_version = capture;
+        self
+    }
+
+    /// Make the master log debugging messages, e.g. about key exchange and
+    /// authentication, at `level` from 0 to 3 (`ssh -v` to `ssh -vvv`).
+    /// Levels above 3 are treated as 3.
+    ///
+    /// The messages are written to the log of the master in its control
+    /// directory, and can be received with [`on_log`](Self::on_log). They are
+    /// left out of the errors returned when connecting fails.
+    ///
+    /// Defaults to 0, which logs no debugging messages.
+    pub fn verbosity(&mut self, level: u8) -> &mut Self {
+        self.verbosity = level.min(3);
+        self
+    }
+
+    /// Call `callback` with every line the master writes to its log, e.g.
+    /// the debugging messages enabled with [`verbosity`](Self::verbosity).
+    ///
+    /// The lines logged while connecting are passed once connecting
+    /// succeeded or failed, later lines as the log is checked once a second
+    /// for as long as the session is open. `callback` is called from a tokio
+    /// task and must not block.
+    ///
+    /// Defaults to `None`.
+    pub fn on_log<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.log_callback = Some(LogCallback(std::sync::Arc::new(callback)));
+        self
+    }
+
     /// Specify the path to the ssh-agent.
     ///
     /// The path provided may use tilde notation (`~`) to refer to the user's
@@ -271,13 +661,188 @@ impl SessionBuilder {
         self
     }
 
+    /// Race the connection attempts over IPv4 and IPv6 (`ssh -4` and `ssh -6`)
+    /// if the destination resolves to addresses of both families, and keep
+    /// whichever master connection is established first.
+    ///
+    /// This avoids the long stall before falling back to IPv4 on networks
+    /// where IPv6 is advertised but broken. Like RFC 8305 recommends, the
+    /// IPv6 attempt gets a head start of 250ms, so the IPv4 attempt is only
+    /// made if IPv6 is slow or fails. Otherwise, both attempts authenticate,
+    /// which counts twice towards limits such as `MaxAuthTries`.
+    ///
+    /// If the destination cannot be resolved locally (e.g. it is a `Host`
+    /// alias from `~/.ssh/config`), a single connection attempt is made, as
+    /// it is with a [`control_path`](Self::control_path), on which both
+    /// attempts would listen, and with password or passphrase authentication,
+    /// which would prompt for each attempt.
+    ///
+    /// Defaults to `false`.
+    pub fn happy_eyeballs(&mut self, happy_eyeballs: bool) -> &mut Self {
+        self.happy_eyeballs = happy_eyeballs;
+        self
+    }
+
+    /// Forward a tun device between the local and the remote host (`ssh -w`),
+    /// e.g. for building an ad-hoc VPN over the session.
+    ///
+    /// The tun device is set up by the master connection, which requires
+    /// permission to create tun devices on both hosts and `PermitTunnel` in
+    /// the sshd config of the remote host. Configuring addresses and routes on
+    /// the created interfaces, see [`TunForward::local_interface`] and
+    /// [`TunForward::remote_interface`], is up to the caller.
+    ///
+    /// Note that ssh does not report it if the remote host refuses to open
+    /// the tun device, in which case the session is established without it.
+    ///
+    /// Defaults to `None`.
+    pub fn tun_forward(&mut self, tun_forward: TunForward) -> &mut Self {
+        self.tun_forward = Some(tun_forward);
+        self
+    }
+
+    /// Remove an existing unix socket before listening on it for a local
+    /// forwarding (`ssh -o StreamLocalBindUnlink`).
+    ///
+    /// Without this, a local forwarding cannot listen on a socket left behind
+    /// by a previous session. This has no effect on remote forwardings, which
+    /// are governed by the sshd config of the remote host, see
+    /// [`Session::expose_unix_socket`].
+    ///
+    /// By default, ssh uses the value set in `~/.ssh/config`, which defaults to `no`.
+    pub fn stream_local_bind_unlink(&mut self, unlink: bool) -> &mut Self {
+        self.stream_local_bind_unlink = Some(unlink);
+        self
+    }
+
+    /// Connect through an already established [`Session`], so that hosts
+    /// only reachable from its remote host can be reached.
+    ///
+    /// The connection is tunnelled through the multiplex master of `session`
+    /// using `ssh -W` as the `ProxyCommand`, so `session` must stay open for
+    /// as long as the new session is used. Sessions connected this way can be
+    /// used as proxy in turn to hop through any number of hosts.
+    ///
+    /// This is mutually exclusive with [`SessionBuilder::jump_hosts`], and
+    /// connecting fails with [`Error::InvalidDestination`] if both are set,
+    /// including jump hosts given by the destination.
+    ///
+    /// Defaults to `None`.
+    #[cfg(not(windows))]
+    #[cfg_attr(docsrs, doc(cfg(not(windows))))]
+    pub fn proxy_session(&mut self, session: &Session) -> &mut Self {
+        self.proxy_session = Some(session.control_socket().into());
+        self
+    }
+
+    /// Log in with `password` rather than with a key.
+    ///
+    /// **Prefer keys wherever possible.** This is only meant for hosts that
+    /// cannot be set up for public key authentication. The password is handed
+    /// to ssh through a generated `SSH_ASKPASS` helper that reads it from a
+    /// file only accessible by the current user in the control directory,
+    /// which is removed once the connection is established. ssh is run in a
+    /// new session without a controlling terminal so that it uses the helper.
+    ///
+    /// The password is only tried once, and unlike other connections, the
+    /// master connection is not started with `BatchMode=yes`.
+    ///
+    /// Defaults to `None`.
+    #[cfg(feature = "password-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "password-auth")))]
+    pub fn password(&mut self, password: impl Into<String>) -> &mut Self {
+        self.password = Some(Secret::new(password));
+        self
+    }
+
+    /// Hand the [`password`](Self::password) to ssh through `sshpass`
+    /// instead of an askpass helper.
+    ///
+    /// This is a fallback for legacy appliances that only accept password
+    /// authentication in setups where the askpass helper is not used, such
+    /// as with ssh older than 8.4 and `DISPLAY` pointing at no X server.
+    /// `sshpass` instead answers the password prompt of ssh on a
+    /// pseudo-terminal. The password is read by `sshpass`
+    /// from a file only accessible by the current user in the control
+    /// directory, which is removed once the connection is established.
+    ///
+    /// The `sshpass` binary is looked up in `PATH` when connecting, failing
+    /// with [`Error::Connect`] if it cannot be found. Has no effect unless a
+    /// password is set.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "password-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "password-auth")))]
+    pub fn sshpass(&mut self, sshpass: bool) -> &mut Self {
+        self.sshpass = sshpass;
+        self
+    }
+
+    /// Unlock encrypted keys, such as the [`keyfile`](Self::keyfile), with
+    /// `passphrase` rather than requiring them to be loaded into an agent.
+    ///
+    /// Like the [`password`](Self::password), the passphrase is handed to ssh
+    /// through a generated `SSH_ASKPASS` helper that reads it from a file
+    /// only accessible by the current user in the control directory, which
+    /// is removed once the connection is established. It is not used if
+    /// [`sshpass`](Self::sshpass) is enabled.
+    ///
+    /// Defaults to `None`.
+    #[cfg(feature = "password-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "password-auth")))]
+    pub fn passphrase(&mut self, passphrase: impl Into<String>) -> &mut Self {
+        self.passphrase = Some(Passphrase::Secret(Secret::new(passphrase)));
+        self
+    }
+
+    /// Like [`passphrase`](Self::passphrase), but get the passphrase from
+    /// `callback` each time a session is connected, e.g. to prompt the user
+    /// or to fetch it from a secret store.
+    ///
+    /// Connecting fails with [`Error::Connect`] if `callback` fails.
+    #[cfg(feature = "password-auth")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "password-auth")))]
+    pub fn passphrase_with<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn() -> io::Result<String> + Send + Sync + 'static,
+    {
+        self.passphrase = Some(Passphrase::Callback(std::sync::Arc::new(callback)));
+        self
+    }
+
+    /// Request a local/remote port forwarding (`ssh -L`/`ssh -R`) while
+    /// establishing the session, see
+    /// [`Session::request_port_forward`] for the meaning of the arguments.
+    ///
+    /// Can be called multiple times to request multiple forwardings.
+    ///
+    /// Unlike forwardings requested from an established session, these stay
+    /// open until the session is closed. If any of them is refused,
+    /// establishing the session fails with [`Error::ConnectForwardRefused`]
+    /// (`ssh -o ExitOnForwardFailure=yes`).
+    pub fn port_forward<'a, 'b>(
+        &mut self,
+        forward_type: impl Into<ForwardType>,
+        listen_socket: impl Into<Socket<'a>>,
+        connect_socket: impl Into<Socket<'b>>,
+    ) -> &mut Self {
+        self.forwards.push((
+            forward_type.into(),
+            listen_socket.into().into_owned(),
+            connect_socket.into().into_owned(),
+        ));
+        self
+    }
+
     /// Connect to the host at the given `host` over SSH using process impl, which will
     /// spawn a new ssh process for each `Child` created.
     ///
     /// The format of `destination` is the same as the `destination` argument to `ssh`. It may be
     /// specified as either `[user@]hostname` or a URI of the form `ssh://[user@]hostname[:port]`.
-    /// A username or port that is specified in the connection string overrides the one set in the
-    /// builder (but does not change the builder).
+    /// A username, port or option that is specified in the connection string overrides the one set
+    /// in the builder (but does not change the builder), see [`try_resolve`](Self::try_resolve)
+    /// for the options a URI can carry. With [`jump_chain`](Self::jump_chain) enabled, jump hosts
+    /// may precede the destination, separated by `+`, e.g. `bastion+me@internal`.
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
     /// password), the connection will fail. Consider setting up keypair-based authentication
@@ -296,8 +861,10 @@ impl SessionBuilder {
     ///
     /// The format of `destination` is the same as the `destination` argument to `ssh`. It may be
     /// specified as either `[user@]hostname` or a URI of the form `ssh://[user@]hostname[:port]`.
-    /// A username or port that is specified in the connection string overrides the one set in the
-    /// builder (but does not change the builder).
+    /// A username, port or option that is specified in the connection string overrides the one set
+    /// in the builder (but does not change the builder), see [`try_resolve`](Self::try_resolve)
+    /// for the options a URI can carry. With [`jump_chain`](Self::jump_chain) enabled, jump hosts
+    /// may precede the destination, separated by `+`, e.g. `bastion+me@internal`.
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
     /// password), the connection will fail. Consider setting up keypair-based authentication
@@ -309,19 +876,45 @@ impl SessionBuilder {
             .await
     }
 
-    async fn connect_impl(
+    pub(crate) async fn connect_impl(
         &self,
         destination: &str,
         f: fn(TempDir) -> Session,
     ) -> Result<Session, Error> {
-        let (builder, destination) = self.resolve(destination);
+        let (builder, destination) = self.try_resolve(destination)?;
         let tempdir = builder.launch_master(destination).await?;
-        Ok(f(tempdir))
+        let mut session = f(tempdir);
+
+        // ssh prefers the user set with `-l` over one in `user@host`.
+        let (user, host) = match destination.rsplit_once('@') {
+            Some((user, host)) => (builder.get_user().or(Some(user)), host),
+            None => (builder.get_user(), destination),
+        };
+        session.set_origin(crate::session::Origin {
+            destination: host.into(),
+            user: user.map(Into::into),
+            port: builder.get_port().and_then(|port| port.parse().ok()),
+            known_hosts: builder.known_hosts_check.clone(),
+        });
+
+        for (forward_type, listen_socket, connect_socket) in &builder.forwards {
+            session.track_forward(crate::ActiveForward::new(
+                *forward_type,
+                listen_socket.clone(),
+                connect_socket.clone(),
+            ));
+        }
+
+        Ok(session)
     }
 
     /// [`SessionBuilder`] support for `destination` parsing.
     /// The format of `destination` is the same as the `destination` argument to `ssh`.
     ///
+    /// Options in the query of a URI destination are applied as described
+    /// for [`try_resolve`](Self::try_resolve), ignoring options that are
+    /// unknown or invalid.
+    ///
     /// # Examples
     ///
     /// ```rust
@@ -332,13 +925,109 @@ impl SessionBuilder {
     /// assert_eq!(b.get_user().as_deref(), Some("test-user"));
     /// assert_eq!(d, "127.0.0.1");
     /// ```
-    pub fn resolve<'a, 'b>(&'a self, mut destination: &'b str) -> (Cow<'a, Self>, &'b str) {
+    pub fn resolve<'a, 'b>(&'a self, destination: &'b str) -> (Cow<'a, Self>, &'b str) {
+        match self.resolve_impl(destination, false) {
+            Ok(resolved) => resolved,
+            Err(_) => unreachable!("options are not validated"),
+        }
+    }
+
+    /// Like [`resolve`](Self::resolve), but fail with
+    /// [`Error::InvalidDestination`] if an option in the query of a URI
+    /// destination is unknown or invalid, or if jump hosts are combined with
+    /// a [`proxy_session`](Self::proxy_session).
+    ///
+    /// A URI destination of the form
+    /// `ssh://[user@]hostname[:port][?option=value[&option=value...]]` can
+    /// carry the following options, whose values may be percent-encoded:
+    ///
+    /// | Option                  | Builder method                                            |
+    /// |-------------------------|-----------------------------------------------------------|
+    /// | `identityfile`          | [`keyfile`](Self::keyfile)                                |
+    /// | `proxyjump`             | [`jump_hosts`](Self::jump_hosts), separated by `,`        |
+    /// | `connect_timeout`       | [`connect_timeout`](Self::connect_timeout) in seconds     |
+    /// | `server_alive_interval` | [`server_alive_interval`](Self::server_alive_interval) in seconds |
+    /// | `compression`           | [`compression`](Self::compression), `yes` or `no`         |
+    /// | `user_known_hosts_file` | [`user_known_hosts_file`](Self::user_known_hosts_file)    |
+    ///
+    /// Options are case-insensitive and may also be spelled like the ssh
+    /// option, e.g. `ConnectTimeout`, or the builder method, e.g. `keyfile`.
+    ///
+    /// With [`jump_chain`](Self::jump_chain) enabled, any destination may be
+    /// preceded by jump hosts separated by `+`, e.g. `bastion+me@internal` or
+    /// `jump@bastion:2222+ssh://me@internal`, which replace the
+    /// [`jump_hosts`](Self::jump_hosts) of the builder. Each jump host has the
+    /// same format as an entry of `ProxyJump`. [`connect`](Self::connect) and
+    /// [`connect_mux`](Self::connect_mux) resolve destinations this way.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use openssh::SessionBuilder;
+    /// let b = SessionBuilder::default();
+    /// let (b, d) = b
+    ///     .try_resolve("ssh://me@example.com?identityfile=~/.ssh/id_deploy&connect_timeout=10")
+    ///     .unwrap();
+    /// assert_eq!(b.get_user(), Some("me"));
+    /// assert_eq!(d, "example.com");
+    ///
+    /// assert!(b.try_resolve("ssh://example.com?timeout=10").is_err());
+    ///
+    /// let mut b = SessionBuilder::default();
+    /// b.jump_chain(true);
+    /// let (_, d) = b.try_resolve("bastion.example.com+me@db.internal").unwrap();
+    /// assert_eq!(d, "me@db.internal");
+    /// ```
+    pub fn try_resolve<'a, 'b>(
+        &'a self,
+        destination: &'b str,
+    ) -> Result<(Cow<'a, Self>, &'b str), Error> {
+        let (builder, destination) = self.resolve_impl(destination, true)?;
+
+        // ssh refuses `-J` along with a `ProxyCommand`.
+        if builder.proxy_session.is_some() && !builder.jump_hosts.is_empty() {
+            return Err(Error::InvalidDestination(format!(
+                "jump hosts for `{}` cannot be combined with a proxy session",
+                destination
+            )));
+        }
+
+        Ok((builder, destination))
+    }
+
+    fn resolve_impl<'a, 'b>(
+        &'a self,
+        mut destination: &'b str,
+        strict: bool,
+    ) -> Result<(Cow<'a, Self>, &'b str), Error> {
         // the "new" ssh://user@host:port form is not supported by all versions of ssh,
         // so we always translate it into the option form.
         let mut user = None;
         let mut port = None;
+        let mut query = None;
+
+        // `jump1+jump2+target` connects to `target` through the jump hosts,
+        // which may have options of their own in the query of the target.
+        let mut jump_hosts = None;
+        let end = destination.find('?').unwrap_or(destination.len());
+        if let Some(plus) = destination[..end].rfind('+').filter(|_| self.jump_chain) {
+            let hops: Vec<&str> = destination[..plus].split('+').collect();
+            if strict && hops.iter().any(|hop| hop.is_empty()) {
+                return Err(Error::InvalidDestination(format!(
+                    "empty jump host in `{}`",
+                    destination
+                )));
+            }
+            jump_hosts = Some(hops);
+            destination = &destination[(plus + 1)..];
+        }
+
         if destination.starts_with("ssh://") {
             destination = &destination[6..];
+            if let Some((authority, options)) = destination.split_once('?') {
+                destination = authority;
+                query = Some(options);
+            }
             if let Some(at) = destination.rfind('@') {
                 // specified a username -- extract it:
                 user = Some(&destination[..at]);
@@ -354,11 +1043,14 @@ impl SessionBuilder {
             }
         }
 
-        if user.is_none() && port.is_none() {
-            return (Cow::Borrowed(self), destination);
+        if user.is_none() && port.is_none() && query.is_none() && jump_hosts.is_none() {
+            return Ok((Cow::Borrowed(self), destination));
         }
 
         let mut with_overrides = self.clone();
+        if let Some(jump_hosts) = jump_hosts {
+            with_overrides.jump_hosts(jump_hosts.into_iter().filter(|hop| !hop.is_empty()));
+        }
         if let Some(user) = user {
             with_overrides.user(user.to_owned());
         }
@@ -367,12 +1059,182 @@ impl SessionBuilder {
             with_overrides.port(port);
         }
 
-        (Cow::Owned(with_overrides), destination)
+        for option in query.into_iter().flat_map(|query| query.split('&')) {
+            if option.is_empty() {
+                continue;
+            }
+            let result = with_overrides.apply_uri_option(option);
+            if strict {
+                result?;
+            }
+        }
+
+        Ok((Cow::Owned(with_overrides), destination))
+    }
+
+    /// Apply `option` of the form `name=value` from the query of a URI
+    /// destination, see [`try_resolve`](Self::try_resolve).
+    fn apply_uri_option(&mut self, option: &str) -> Result<(), Error> {
+        let invalid = || Error::InvalidDestination(format!("invalid option `{}`", option));
+
+        let (name, value) = option.split_once('=').ok_or_else(invalid)?;
+        let value = percent_decode(value).ok_or_else(invalid)?;
+        let seconds = || {
+            value
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| invalid())
+        };
+
+        match &*name.to_ascii_lowercase().replace('_', "") {
+            "identityfile" | "keyfile" => {
+                self.keyfile(&value);
+            }
+            "proxyjump" | "jumphosts" => {
+                self.jump_hosts(value.split(','));
+            }
+            "connecttimeout" => {
+                self.connect_timeout(seconds()?);
+            }
+            "serveraliveinterval" => {
+                self.server_alive_interval(seconds()?);
+            }
+            "compression" => {
+                self.compression(match &*value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(invalid()),
+                });
+            }
+            "userknownhostsfile" => {
+                self.user_known_hosts_file(&value);
+            }
+            _ => {
+                return Err(Error::InvalidDestination(format!(
+                    "unknown option `{}`",
+                    name
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the arguments `ssh` is run with to establish the master
+    /// connection to `destination` on [`connect`](Self::connect), e.g. to
+    /// log them deliberately.
+    ///
+    /// The control socket and the log of the master are in a directory
+    /// only created when connecting, which is given as `<control-dir>`.
+    /// Passwords and passphrases are never passed as arguments.
+    pub fn to_ssh_args(&self, destination: &str) -> Vec<OsString> {
+        let (builder, destination) = self.resolve(destination);
+
+        #[cfg(feature = "password-auth")]
+        let batch_mode = builder.password.is_none() && builder.passphrase.is_none();
+        #[cfg(not(feature = "password-auth"))]
+        let batch_mode = true;
+
+        let mut cmd = process::Command::new("ssh");
+        builder.add_ssh_args(
+            &mut cmd,
+            Path::new("<control-dir>"),
+            destination,
+            None,
+            batch_mode,
+        );
+        cmd.as_std().get_args().map(ToOwned::to_owned).collect()
     }
 
     /// Create ssh master session and return [`TempDir`] which
     /// contains the ssh control socket.
     pub async fn launch_master(&self, destination: &str) -> Result<TempDir, Error> {
+        #[cfg(feature = "password-auth")]
+        let prompts = self.password.is_some() || self.passphrase.is_some();
+        #[cfg(not(feature = "password-auth"))]
+        let prompts = false;
+
+        if self.happy_eyeballs
+            && self.control_path.is_none()
+            && !prompts
+            && self.resolves_to_both_families(destination).await
+        {
+            self.launch_master_racing(destination).await
+        } else {
+            self.launch_master_impl(destination, None).await
+        }
+    }
+
+    /// Return true if `destination` resolves to both IPv4 and IPv6 addresses.
+    async fn resolves_to_both_families(&self, destination: &str) -> bool {
+        let host = destination
+            .rfind('@')
+            .map(|at| &destination[(at + 1)..])
+            .unwrap_or(destination);
+        let port = self
+            .port
+            .as_deref()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(22);
+
+        let addrs = match tokio::net::lookup_host((host, port)).await {
+            Ok(addrs) => addrs,
+            Err(_) => return false,
+        };
+
+        let (mut ipv4, mut ipv6) = (false, false);
+        for addr in addrs {
+            ipv4 |= addr.is_ipv4();
+            ipv6 |= addr.is_ipv6();
+        }
+
+        ipv4 && ipv6
+    }
+
+    #[allow(clippy::incompatible_msrv)]
+    async fn launch_master_racing(&self, destination: &str) -> Result<TempDir, Error> {
+        let ipv6 = self.launch_master_impl(destination, Some(AddressFamily::Inet6));
+        tokio::pin!(ipv6);
+
+        // IPv4 is only tried right away if IPv6 fails within its head start.
+        tokio::select! {
+            biased;
+
+            res = &mut ipv6 => {
+                return match res {
+                    Ok(dir) => Ok(dir),
+                    Err(_) => {
+                        self.launch_master_impl(destination, Some(AddressFamily::Inet))
+                            .await
+                    }
+                };
+            }
+            () = tokio::time::sleep(HAPPY_EYEBALLS_DELAY) => (),
+        }
+
+        let ipv4 = self.launch_master_impl(destination, Some(AddressFamily::Inet));
+        tokio::pin!(ipv4);
+
+        // The losing attempt is dropped, which kills its ssh process and
+        // shuts down its master if it has already been established.
+        tokio::select! {
+            biased;
+
+            res = &mut ipv6 => match res {
+                Ok(dir) => Ok(dir),
+                Err(_) => ipv4.await,
+            },
+            res = &mut ipv4 => match res {
+                Ok(dir) => Ok(dir),
+                Err(_) => ipv6.await,
+            },
+        }
+    }
+
+    async fn launch_master_impl(
+        &self,
+        destination: &str,
+        address_family: Option<AddressFamily>,
+    ) -> Result<TempDir, Error> {
         let socketdir = if let Some(socketdir) = self.control_dir.as_ref() {
             socketdir
         } else {
@@ -391,28 +1253,266 @@ impl SessionBuilder {
             .map_err(Error::Master)?;
 
         let log = dir.path().join("log");
+        let ctl = dir.path().join("master");
+
+        // The session finds the socket in the directory like any other.
+        if let Some(template) = self.control_path.as_deref() {
+            let path = self.expand_control_path(template, destination).await?;
+            remove_stale_socket(&path).map_err(Error::Master)?;
+            std::os::unix::fs::symlink(path, &ctl).map_err(Error::Master)?;
+        }
+
+        // Removed once the master is established, as it is only used to
+        // authenticate.
+        #[cfg(feature = "password-auth")]
+        let password_auth = self.password_auth(dir.path())?;
 
+        #[cfg(feature = "password-auth")]
+        let mut init = password_auth
+            .as_ref()
+            .map_or_else(|| process::Command::new("ssh"), PasswordAuth::command);
+        #[cfg(not(feature = "password-auth"))]
         let mut init = process::Command::new("ssh");
 
-        init.stdin(Stdio::null())
+        if let Some(fingerprint) = self.host_key_fingerprint.as_deref() {
+            crate::known_hosts::pin(dir.path(), fingerprint).map_err(Error::Master)?;
+        }
+
+        #[cfg(feature = "password-auth")]
+        let batch_mode = password_auth.is_none();
+        #[cfg(not(feature = "password-auth"))]
+        let batch_mode = true;
+
+        init.kill_on_drop(true)
+            .stdin(Stdio::null())
             .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .arg("-E")
+            .stderr(Stdio::null());
+        self.add_ssh_args(
+            &mut init,
+            dir.path(),
+            destination,
+            address_family,
+            batch_mode,
+        );
+
+        // If this future is dropped before ssh forks, `kill_on_drop` takes care of
+        // the ssh process, but it might have already forked into a master.
+        let guard = MasterGuard {
+            ctl: ctl.clone(),
+            dir: Some(dir),
+        };
+
+        // we spawn and immediately wait, because the process is supposed to fork.
+        let status = if let Some(deadline) = self.master_deadline() {
+            tokio::time::timeout(deadline, init.status())
+                .await
+                .map_err(|_| Error::Timeout)?
+        } else {
+            init.status().await
+        }
+        .map_err(Error::Connect)?;
+        let dir = guard.disarm();
+
+        if !status.success() {
+            let mut output = fs::read_to_string(log).map_err(Error::Connect)?;
+            if let Some(callback) = &self.log_callback {
+                output.lines().for_each(|line| (callback.0)(line));
+            }
+            if self.host_key_callback.is_some() || self.capture_server_version || self.verbosity > 0
+            {
+                output = crate::known_hosts::strip_verbose(&output);
+            }
+
+            if let Some((forward_type, listen_socket)) = self.find_refused_forward(&output) {
+                return Err(Error::ConnectForwardRefused {
+                    forward_type,
+                    listen_socket: listen_socket.clone(),
+                    source: io::Error::new(io::ErrorKind::Other, output.trim()),
+                });
+            }
+
+            // e.g. "Host key ED25519 SHA256:... revoked by file /etc/ssh/krl"
+            if self.revoked_host_keys.is_some() && output.contains(" revoked by file ") {
+                return Err(Error::HostKeyRevoked(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    output.trim(),
+                )));
+            }
+
+            if let Some(fingerprint) = self.host_key_fingerprint.as_deref() {
+                if output.contains("Host key verification failed.") {
+                    return Err(Error::HostKeyMismatch {
+                        expected: fingerprint.to_owned(),
+                        source: io::Error::new(io::ErrorKind::PermissionDenied, output.trim()),
+                    });
+                }
+            }
+
+            Err(Error::interpret_ssh_error(&output))
+        } else {
+            if self.capture_server_version {
+                let output = fs::read_to_string(&log).map_err(Error::Connect)?;
+                if let Some(version) = crate::known_hosts::server_version(&output) {
+                    fs::write(dir.path().join(SERVER_VERSION_FILE), version)
+                        .map_err(Error::Connect)?;
+                }
+            }
+            if let Some(callback) = &self.log_callback {
+                let output = fs::read_to_string(&log).map_err(Error::Connect)?;
+                // The rest of a partial line is passed on by the watch.
+                let end = output.rfind('\n').map_or(0, |end| end + 1);
+                output[..end].lines().for_each(|line| (callback.0)(line));
+
+                let callback = callback.clone();
+                crate::known_hosts::watch_log(log.clone(), end, move |line| (callback.0)(line));
+            }
+            if let Some(callback) = &self.host_key_callback {
+                crate::known_hosts::watch_updates(log, callback.clone());
+            }
+            Ok(dir)
+        }
+    }
+
+    /// Return the path of the control socket of the master connected to
+    /// `destination`, with the tokens of the template set with
+    /// [`control_path`](Self::control_path) expanded, or `None` if no
+    /// template is set.
+    ///
+    /// `destination` is resolved like with [`connect`](Self::connect), and
+    /// `ssh -G` is run to expand the template exactly as ssh does.
+    pub async fn control_path_for(&self, destination: &str) -> Result<Option<PathBuf>, Error> {
+        let (builder, destination) = self.try_resolve(destination)?;
+        match builder.control_path.as_deref() {
+            Some(template) => builder
+                .expand_control_path(template, destination)
+                .await
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Expand the tokens of the control path `template` for `destination`
+    /// with `ssh -G`, which prints the configuration ssh would connect with.
+    async fn expand_control_path(
+        &self,
+        template: &str,
+        destination: &str,
+    ) -> Result<PathBuf, Error> {
+        let mut cmd = process::Command::new("ssh");
+        cmd.arg("-G")
+            .arg("-o")
+            .arg(format!("ControlPath={}", template));
+        if let Some(port) = &self.port {
+            cmd.arg("-p").arg(&**port);
+        }
+        if let Some(user) = &self.user {
+            cmd.arg("-l").arg(&**user);
+        }
+        if let Some(config_file) = &self.config_file {
+            cmd.arg("-F").arg(config_file);
+        }
+        // Newer versions of ssh include the jump hosts in `%C`.
+        if !self.jump_hosts.is_empty() {
+            cmd.arg("-J").arg(self.jump_hosts.join(","));
+        }
+        let output = cmd
+            .arg(destination)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(Error::Ssh)?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let path = stdout
+            .lines()
+            .find_map(|line| line.strip_prefix("controlpath "))
+            .filter(|_| output.status.success());
+        match path {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Err(Error::Master(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot expand control path `{}`: {}",
+                    template,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ))),
+        }
+    }
+
+    /// The `ConnectTimeout` passed to ssh, in whole seconds but at least one,
+    /// as ssh treats zero as no timeout.
+    fn connect_timeout_secs(&self) -> Option<u64> {
+        self.connect_timeout.map(|timeout| timeout.as_secs().max(1))
+    }
+
+    /// How long the ssh process establishing the master connection may run
+    /// before it is killed, or `None` if it is not bounded.
+    fn master_deadline(&self) -> Option<Duration> {
+        // Give ssh a chance to report a timed out TCP connection itself
+        // before killing it.
+        // ssh waits a second between connection attempts.
+        let secs = self.connect_timeout_secs()?;
+        let attempts = u64::from(self.connection_attempts.unwrap_or(1));
+        let secs = secs.checked_mul(attempts)?.checked_add(attempts - 1)?;
+        Duration::from_secs(secs).checked_add(MASTER_TIMEOUT_GRACE)
+    }
+
+    /// Add the arguments establishing the master connection to
+    /// `destination` with its control socket and log in `dir` to `init`.
+    fn add_ssh_args(
+        &self,
+        init: &mut process::Command,
+        dir: &Path,
+        destination: &str,
+        address_family: Option<AddressFamily>,
+        batch_mode: bool,
+    ) {
+        let log = dir.join("log");
+        let ctl = dir.join("master");
+        // ssh expands the tokens of the template itself.
+        let ctl = match self.control_path.as_deref() {
+            Some(template) => OsStr::new(template),
+            None => ctl.as_os_str(),
+        };
+
+        init.arg("-E")
             .arg(&log)
             .arg("-S")
-            .arg(dir.path().join("master"))
+            .arg(ctl)
             .arg("-M")
             .arg("-f")
             .arg("-N")
             .arg("-o")
-            .arg("ControlPersist=yes")
-            .arg("-o")
-            .arg("BatchMode=yes")
-            .arg("-o")
-            .arg(self.known_hosts_check.as_option());
+            .arg(match self.idle_timeout {
+                Some(timeout) => format!("ControlPersist={}s", timeout.as_secs().max(1)),
+                None => "ControlPersist=yes".to_owned(),
+            });
+
+        if let Some(fingerprint) = self.host_key_fingerprint.as_deref() {
+            for option in crate::known_hosts::pin_options(dir, fingerprint) {
+                init.arg("-o").arg(option);
+            }
+        } else {
+            init.arg("-o").arg(self.known_hosts_check.as_option());
+        }
+
+        if batch_mode {
+            init.arg("-o").arg("BatchMode=yes");
+        } else {
+            init.arg("-o")
+                .arg("BatchMode=no")
+                .arg("-o")
+                .arg("NumberOfPasswordPrompts=1");
+        }
+
+        if let Some(secs) = self.connect_timeout_secs() {
+            init.arg("-o").arg(format!("ConnectTimeout={}", secs));
+        }
 
-        if let Some(ref timeout) = self.connect_timeout {
-            init.arg("-o").arg(format!("ConnectTimeout={}", timeout));
+        if let Some(attempts) = self.connection_attempts {
+            init.arg("-o")
+                .arg(format!("ConnectionAttempts={}", attempts));
         }
 
         if let Some(ref interval) = self.server_alive_interval {
@@ -444,6 +1544,34 @@ impl SessionBuilder {
             init.arg("-o").arg(format!("Compression={}", arg));
         }
 
+        if let Some(profile) = self.security_profile {
+            for option in profile.as_options() {
+                init.arg("-o").arg(option);
+            }
+        }
+
+        for (option, value) in [
+            ("TCPKeepAlive", self.tcp_keepalive),
+            ("HostbasedAuthentication", self.hostbased_authentication),
+            ("GSSAPIAuthentication", self.gssapi_authentication),
+            (
+                "GSSAPIDelegateCredentials",
+                self.gssapi_delegate_credentials,
+            ),
+        ] {
+            if let Some(value) = value {
+                let arg = if value { "yes" } else { "no" };
+
+                init.arg("-o").arg(format!("{}={}", option, arg));
+            }
+        }
+
+        if let Some(unlink) = self.stream_local_bind_unlink {
+            let arg = if unlink { "yes" } else { "no" };
+
+            init.arg("-o").arg(format!("StreamLocalBindUnlink={}", arg));
+        }
+
         if let Some(ssh_auth_sock) = self.ssh_auth_sock.as_deref() {
             init.env("SSH_AUTH_SOCK", ssh_auth_sock);
         }
@@ -462,23 +1590,251 @@ impl SessionBuilder {
             init.arg("-J").arg(&dest);
         }
 
-        if let Some(user_known_hosts_file) = &self.user_known_hosts_file {
+        if self.ephemeral_known_hosts {
+            let mut option: OsString = "UserKnownHostsFile=".into();
+            option.push(dir.join("known_hosts"));
+            init.arg("-o")
+                .arg(option)
+                .arg("-o")
+                .arg("GlobalKnownHostsFile=/dev/null");
+        } else if let Some(user_known_hosts_file) = &self.user_known_hosts_file {
             let mut option: OsString = "UserKnownHostsFile=".into();
             option.push(&**user_known_hosts_file);
             init.arg("-o").arg(option);
         }
 
+        if let Some(update) = self.update_host_keys {
+            let arg = if update { "yes" } else { "no" };
+
+            init.arg("-o").arg(format!("UpdateHostKeys={}", arg));
+        }
+
+        // `-v` already logs at `LogLevel=DEBUG1` or above.
+        if self.verbosity > 0 {
+            init.arg(format!("-{}", "v".repeat(self.verbosity.into())));
+        } else if self.capture_server_version {
+            init.arg("-o").arg("LogLevel=DEBUG1");
+        } else if self.host_key_callback.is_some() {
+            init.arg("-o").arg("LogLevel=VERBOSE");
+        }
+
+        if let Some(revoked_host_keys) = &self.revoked_host_keys {
+            let mut option: OsString = "RevokedHostKeys=".into();
+            option.push(&**revoked_host_keys);
+            init.arg("-o").arg(option);
+        }
+
+        if let Some(tun_forward) = &self.tun_forward {
+            init.arg("-o")
+                .arg(format!("Tunnel={}", tun_forward.mode.as_str()))
+                .arg("-o")
+                .arg(format!(
+                    "TunnelDevice={}:{}",
+                    tun_forward.local_unit, tun_forward.remote_unit
+                ));
+        }
+
+        if let Some(ctl) = self.proxy_session.as_deref() {
+            let mut option: OsString = "ProxyCommand=".into();
+            option.push(proxy_command(ctl));
+            init.arg("-o").arg(option);
+        }
+
+        if !self.forwards.is_empty() {
+            init.arg("-o").arg("ExitOnForwardFailure=yes");
+        }
+
+        for (forward_type, listen_socket, connect_socket) in &self.forwards {
+            init.args(crate::port_forwarding::forwarding_args(
+                *forward_type,
+                listen_socket,
+                connect_socket,
+            ));
+        }
+
+        match address_family {
+            Some(AddressFamily::Inet) => {
+                init.arg("-4");
+            }
+            Some(AddressFamily::Inet6) => {
+                init.arg("-6");
+            }
+            None => {}
+        }
+
         init.arg(destination);
+    }
+}
 
-        // we spawn and immediately wait, because the process is supposed to fork.
-        let status = init.status().await.map_err(Error::Connect)?;
+impl SessionBuilder {
+    /// Set up supplying the password and passphrase of the builder, if any,
+    /// to ssh in `dir`.
+    #[cfg(feature = "password-auth")]
+    fn password_auth(&self, dir: &Path) -> Result<Option<PasswordAuth>, Error> {
+        let auth = match (&self.password, &self.passphrase) {
+            (None, None) => return Ok(None),
+            (Some(password), _) if self.sshpass => {
+                let program = Sshpass::find().map_err(Error::Connect)?;
+                PasswordAuth::Sshpass(Sshpass::new(dir, program, password).map_err(Error::Master)?)
+            }
+            (password, passphrase) => {
+                let passphrase = passphrase
+                    .as_ref()
+                    .map(Passphrase::get)
+                    .transpose()
+                    .map_err(Error::Connect)?;
+                let askpass = Askpass::new(dir, password.as_ref(), passphrase.as_ref())
+                    .map_err(Error::Master)?;
+                PasswordAuth::Askpass(askpass)
+            }
+        };
+        Ok(Some(auth))
+    }
 
-        if !status.success() {
-            let output = fs::read_to_string(log).map_err(Error::Connect)?;
+    /// Find the forwarding ssh failed to set up from its log.
+    fn find_refused_forward(&self, log: &str) -> Option<(ForwardType, &Socket<'static>)> {
+        for line in log.lines() {
+            let line = line.trim_end();
+
+            // e.g. "Error: remote port forwarding failed for listen port 80"
+            // or "channel_setup_fwd_listener_tcpip: cannot listen to port: 80"
+            let forward_type = if line.contains("remote port forwarding failed") {
+                ForwardType::Remote
+            } else if line.contains("cannot listen to port") || line.contains("cannot bind to path")
+            {
+                ForwardType::Local
+            } else {
+                continue;
+            };
+
+            let found = self
+                .forwards
+                .iter()
+                .filter(|(ty, _, _)| *ty == forward_type)
+                .map(|(_, listen_socket, _)| listen_socket)
+                .find(|listen_socket| match listen_socket {
+                    Socket::UnixSocket { path } => path
+                        .to_str()
+                        .map(|path| line.contains(path))
+                        .unwrap_or(false),
+                    Socket::TcpSocket { port, .. } => line.ends_with(&format!(" {}", port)),
+                });
+
+            if let Some(listen_socket) = found {
+                return Some((forward_type, listen_socket));
+            }
+        }
 
-            Err(Error::interpret_ssh_error(&output))
+        None
+    }
+}
+
+/// Decode the `%XX` escapes in `s`, returning `None` if they are malformed
+/// or do not decode to UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = str::from_utf8(tail.get(..2)?).ok()?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
         } else {
-            Ok(dir)
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Build a `ProxyCommand` tunnelling through the multiplex master at `ctl`.
+fn proxy_command(ctl: &Path) -> OsString {
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    let ctl = crate::escape::escape(ctl.as_os_str());
+
+    let mut command = b"ssh -o BatchMode=yes -S ".to_vec();
+    for &b in ctl.as_bytes() {
+        // ssh expands `%` tokens in the ProxyCommand.
+        if b == b'%' {
+            command.push(b'%');
+        }
+        command.push(b);
+    }
+    command.extend_from_slice(b" -W %h:%p none");
+
+    OsString::from_vec(command)
+}
+
+/// Extra time granted to the ssh process establishing the master connection
+/// on top of [`SessionBuilder::connect_timeout`].
+const MASTER_TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+/// The head start of the IPv6 attempt of [`SessionBuilder::happy_eyeballs`],
+/// the "Connection Attempt Delay" of RFC 8305.
+const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Copy, Clone)]
+enum AddressFamily {
+    Inet,
+    Inet6,
+}
+
+/// Remove the socket at `path` if no master listens on it anymore, as ssh
+/// refuses to listen on an existing path.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("a master is already listening on {}", path.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(_) => match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            )),
+            Err(_) => Ok(()),
+        },
+    }
+}
+
+/// Shuts down the master listening on `ctl` on drop, unless disarmed, and
+/// only removes `dir` afterwards.
+struct MasterGuard {
+    ctl: PathBuf,
+    dir: Option<TempDir>,
+}
+
+impl MasterGuard {
+    /// Keep the master running, returning its directory.
+    fn disarm(mut self) -> TempDir {
+        self.dir.take().expect("the guard is armed")
+    }
+}
+
+impl Drop for MasterGuard {
+    fn drop(&mut self) {
+        let dir = match self.dir.take() {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        let child = crate::session::new_ctl_cmd(&self.ctl, &["-O", "exit"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        // Drop cannot wait for ssh, so reap it from a thread instead, which
+        // removes the directory of the control socket once ssh is done.
+        if let Ok(mut child) = child {
+            std::thread::spawn(move || {
+                let _ = child.wait();
+                drop(dir);
+            });
         }
     }
 }
@@ -512,9 +1868,133 @@ impl KnownHosts {
     }
 }
 
+/// Layer at which a tun device forwards traffic.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TunnelMode {
+    /// Forward IP packets (layer 3) over `tun` devices.
+    ///
+    /// This corresponds to `ssh -o Tunnel=point-to-point`.
+    PointToPoint,
+    /// Forward ethernet frames (layer 2) over `tap` devices.
+    ///
+    /// This corresponds to `ssh -o Tunnel=ethernet`.
+    Ethernet,
+}
+
+impl TunnelMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            TunnelMode::PointToPoint => "point-to-point",
+            TunnelMode::Ethernet => "ethernet",
+        }
+    }
+
+    fn interface_prefix(self) -> &'static str {
+        match self {
+            TunnelMode::PointToPoint => "tun",
+            TunnelMode::Ethernet => "tap",
+        }
+    }
+}
+
+/// A preset of the algorithms ssh may use, see
+/// [`SessionBuilder::security_profile`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum SecurityProfile {
+    /// Only modern algorithms: ChaCha20-Poly1305 and AES-GCM ciphers,
+    /// encrypt-then-MAC SHA-2 MACs, Curve25519 key exchange, and Ed25519 and
+    /// RSA-SHA2 host keys.
+    ///
+    /// Servers older than OpenSSH 6.5 are not supported.
+    Modern,
+
+    /// Only algorithms approved by FIPS 140-2: AES-GCM and AES-CTR ciphers,
+    /// SHA-2 MACs, NIST ECDH and SHA-2 Diffie-Hellman key exchange, and ECDSA
+    /// and RSA-SHA2 host keys.
+    ///
+    /// Note that this only restricts the negotiated algorithms, and does not
+    /// make the ssh binary itself a FIPS validated module.
+    Fips,
+
+    /// The defaults of ssh, plus legacy algorithms for old servers and
+    /// appliances: AES-CBC ciphers, HMAC-SHA1, SHA-1 Diffie-Hellman key
+    /// exchange and SHA-1 RSA (`ssh-rsa`) host keys.
+    Compatible,
+}
+
+impl SecurityProfile {
+    fn as_options(self) -> [&'static str; 4] {
+        match self {
+            SecurityProfile::Modern => [
+                "Ciphers=chacha20-poly1305@openssh.com,aes256-gcm@openssh.com,aes128-gcm@openssh.com",
+                "MACs=hmac-sha2-512-etm@openssh.com,hmac-sha2-256-etm@openssh.com",
+                "KexAlgorithms=curve25519-sha256,curve25519-sha256@libssh.org",
+                "HostKeyAlgorithms=ssh-ed25519-cert-v01@openssh.com,ssh-ed25519,\
+                 rsa-sha2-512-cert-v01@openssh.com,rsa-sha2-256-cert-v01@openssh.com,\
+                 rsa-sha2-512,rsa-sha2-256",
+            ],
+            SecurityProfile::Fips => [
+                "Ciphers=aes256-gcm@openssh.com,aes128-gcm@openssh.com,\
+                 aes256-ctr,aes192-ctr,aes128-ctr",
+                "MACs=hmac-sha2-512-etm@openssh.com,hmac-sha2-256-etm@openssh.com,\
+                 hmac-sha2-512,hmac-sha2-256",
+                "KexAlgorithms=ecdh-sha2-nistp521,ecdh-sha2-nistp384,ecdh-sha2-nistp256,\
+                 diffie-hellman-group-exchange-sha256,diffie-hellman-group16-sha512,\
+                 diffie-hellman-group14-sha256",
+                "HostKeyAlgorithms=ecdsa-sha2-nistp521,ecdsa-sha2-nistp384,ecdsa-sha2-nistp256,\
+                 rsa-sha2-512,rsa-sha2-256",
+            ],
+            SecurityProfile::Compatible => [
+                "Ciphers=+aes128-cbc,aes256-cbc",
+                "MACs=+hmac-sha1",
+                "KexAlgorithms=+diffie-hellman-group14-sha1,diffie-hellman-group1-sha1",
+                "HostKeyAlgorithms=+ssh-rsa",
+            ],
+        }
+    }
+}
+
+/// A tun device forwarding, see [`SessionBuilder::tun_forward`].
+///
+/// The unit numbers of the devices on both hosts must be given explicitly,
+/// so that the names of the created interfaces are known.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TunForward {
+    mode: TunnelMode,
+    local_unit: u32,
+    remote_unit: u32,
+}
+
+impl TunForward {
+    /// Forward between tun device `local_unit` on the local host and tun
+    /// device `remote_unit` on the remote host (`ssh -w local_unit:remote_unit`).
+    pub fn new(mode: TunnelMode, local_unit: u32, remote_unit: u32) -> Self {
+        Self {
+            mode,
+            local_unit,
+            remote_unit,
+        }
+    }
+
+    /// Return the name of the interface on the local host, e.g. `tun0`.
+    pub fn local_interface(&self) -> String {
+        format!("{}{}", self.mode.interface_prefix(), self.local_unit)
+    }
+
+    /// Return the name of the interface on the remote host, e.g. `tun0`.
+    pub fn remote_interface(&self) -> String {
+        format!("{}{}", self.mode.interface_prefix(), self.remote_unit)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::SessionBuilder;
+    use super::{proxy_command, SecurityProfile, SessionBuilder, TunForward, TunnelMode};
+    use crate::{ForwardType, Socket};
+    use std::ffi::OsString;
+    use std::path::Path;
+    use std::time::Duration;
 
     #[test]
     fn resolve() {
@@ -548,10 +2028,308 @@ mod tests {
         assert_eq!(b.user.as_deref(), None);
         assert_eq!(d, "opensshtest");
 
+        let b = SessionBuilder::default();
+        let (b, d) = b.resolve("ssh://opensshtest:2222?compression=maybe&ConnectTimeout=10");
+        assert_eq!(b.port.as_deref(), Some("2222"));
+        assert_eq!(b.compression, None);
+        assert_eq!(b.connect_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(d, "opensshtest");
+
         let b = SessionBuilder::default();
         let (b, d) = b.resolve("opensshtest");
         assert_eq!(b.port.as_deref(), None);
         assert_eq!(b.user.as_deref(), None);
         assert_eq!(d, "opensshtest");
     }
+
+    #[test]
+    fn tun_forward() {
+        let tun = TunForward::new(TunnelMode::PointToPoint, 0, 5);
+        assert_eq!(tun.local_interface(), "tun0");
+        assert_eq!(tun.remote_interface(), "tun5");
+
+        let tap = TunForward::new(TunnelMode::Ethernet, 3, 1);
+        assert_eq!(tap.local_interface(), "tap3");
+        assert_eq!(tap.remote_interface(), "tap1");
+    }
+
+    #[test]
+    fn security_profile() {
+        for profile in [
+            SecurityProfile::Modern,
+            SecurityProfile::Fips,
+            SecurityProfile::Compatible,
+        ] {
+            let options = profile.as_options();
+            assert!(options
+                .iter()
+                .all(|option| !option.contains(char::is_whitespace)));
+
+            // ssh rejects unknown algorithms when parsing its options.
+            let mut ssh = std::process::Command::new("ssh");
+            for option in options {
+                ssh.arg("-o").arg(option);
+            }
+            let output = ssh.arg("-G").arg("localhost").output().unwrap();
+            assert!(
+                output.status.success(),
+                "{:?}: {}",
+                profile,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    #[test]
+    fn test_proxy_command() {
+        assert_eq!(
+            proxy_command(Path::new("/tmp/.ssh-connection123/master")),
+            "ssh -o BatchMode=yes -S /tmp/.ssh-connection123/master -W %h:%p none"
+        );
+        assert_eq!(
+            proxy_command(Path::new("/tmp/100% sure/master")),
+            "ssh -o BatchMode=yes -S '/tmp/100%% sure/master' -W %h:%p none"
+        );
+    }
+
+    #[test]
+    fn find_refused_forward() {
+        let mut b = SessionBuilder::default();
+        b.port_forward(
+            ForwardType::Local,
+            Socket::new("127.0.0.1", 8080),
+            Socket::new("localhost", 80),
+        )
+        .port_forward(
+            ForwardType::Remote,
+            Socket::new("", 8080),
+            Socket::new("localhost", 80),
+        )
+        .port_forward(
+            ForwardType::Remote,
+            Path::new("/tmp/agent"),
+            Path::new("/a"),
+        );
+
+        let log = "Warning: Permanently added '[127.0.0.1]:2222' (ED25519) to the list of known hosts.\r\n\
+                   Error: remote port forwarding failed for listen port 8080\r\n";
+        assert_eq!(
+            b.find_refused_forward(log),
+            Some((ForwardType::Remote, &Socket::new("", 8080)))
+        );
+
+        let log = "bind [127.0.0.1]:8080: Address already in use\r\n\
+                   channel_setup_fwd_listener_tcpip: cannot listen to port: 8080\r\n\
+                   Could not request local forwarding.\r\n";
+        assert_eq!(
+            b.find_refused_forward(log),
+            Some((ForwardType::Local, &Socket::new("127.0.0.1", 8080)))
+        );
+
+        let log = "Error: remote port forwarding failed for listen path /tmp/agent\r\n";
+        assert_eq!(
+            b.find_refused_forward(log),
+            Some((ForwardType::Remote, &Path::new("/tmp/agent").into()))
+        );
+
+        let log = "Permission denied (publickey).\r\n";
+        assert_eq!(b.find_refused_forward(log), None);
+    }
+
+    #[tokio::test]
+    async fn control_path_for() {
+        let mut b = SessionBuilder::default();
+        assert_eq!(b.control_path_for("example.com").await.unwrap(), None);
+
+        b.control_path("/tmp/%h-%p-%r-%%-%C");
+        let path = b
+            .control_path_for("ssh://me@example.com:2222")
+            .await
+            .unwrap()
+            .unwrap();
+        let path = path.to_str().unwrap();
+        let hash = path.strip_prefix("/tmp/example.com-2222-me-%-").unwrap();
+        assert_eq!(hash.len(), 40, "{}", path);
+
+        b.control_path("/tmp/%Z");
+        let err = b.control_path_for("example.com").await.unwrap_err();
+        assert!(matches!(err, crate::Error::Master(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn master_deadline() {
+        let mut builder = SessionBuilder::default();
+        assert_eq!(builder.master_deadline(), None);
+
+        builder.connect_timeout(Duration::from_millis(500));
+        assert_eq!(builder.connect_timeout_secs(), Some(1));
+        assert_eq!(builder.master_deadline(), Some(Duration::from_secs(2)));
+
+        builder
+            .connect_timeout(Duration::from_secs(5))
+            .connection_attempts(3);
+        assert_eq!(builder.master_deadline(), Some(Duration::from_secs(18)));
+
+        builder.connect_timeout(Duration::MAX);
+        assert_eq!(builder.master_deadline(), None);
+    }
+
+    #[test]
+    fn remove_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cm");
+        super::remove_stale_socket(&path).unwrap();
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let err = super::remove_stale_socket(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+
+        drop(listener);
+        super::remove_stale_socket(&path).unwrap();
+        assert!(!path.exists());
+
+        std::fs::write(&path, "").unwrap();
+        super::remove_stale_socket(&path).unwrap_err();
+    }
+
+    #[test]
+    fn redacted_debug() {
+        let mut builder = SessionBuilder::default();
+        builder
+            .user("me".into())
+            .keyfile("/home/me/.ssh/id_secret")
+            .config_file("/home/me/.ssh/config-secret")
+            .connection_attempts(3);
+
+        let debug = format!("{:?}", builder);
+        assert!(debug.contains(r#"user: Some("me")"#), "{}", debug);
+        assert!(debug.contains("keyfile: Some(<redacted>)"), "{}", debug);
+        assert!(!debug.contains("secret"), "{}", debug);
+
+        let args = builder.to_ssh_args("ssh://example.com:2222");
+        let arg = |arg: &str| args.contains(&OsString::from(arg));
+        assert!(arg("-E") && arg("<control-dir>/log"), "{:?}", args);
+        assert!(arg("-i") && arg("/home/me/.ssh/id_secret"), "{:?}", args);
+        assert!(arg("-l") && arg("me"), "{:?}", args);
+        assert!(arg("-p") && arg("2222"), "{:?}", args);
+        assert!(arg("ConnectionAttempts=3"), "{:?}", args);
+        assert_eq!(args.last().unwrap(), "example.com");
+    }
+
+    #[test]
+    fn try_resolve() {
+        let b = SessionBuilder::default();
+        let (b, d) = b
+            .try_resolve(
+                "ssh://me@opensshtest:2222?identityfile=%2Fkeys%2Fid%20a&proxyjump=a,b@c:22\
+                 &connect_timeout=10&ServerAliveInterval=5&compression=no\
+                 &user_known_hosts_file=/tmp/known_hosts",
+            )
+            .unwrap();
+        assert_eq!(d, "opensshtest");
+        assert_eq!(b.user.as_deref(), Some("me"));
+        assert_eq!(b.port.as_deref(), Some("2222"));
+        assert_eq!(b.keyfile.as_deref(), Some(Path::new("/keys/id a")));
+        assert_eq!(b.jump_hosts, ["a".into(), "b@c:22".into()]);
+        assert_eq!(b.connect_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(b.server_alive_interval, Some(5));
+        assert_eq!(b.compression, Some(false));
+        assert_eq!(
+            b.user_known_hosts_file.as_deref(),
+            Some(Path::new("/tmp/known_hosts"))
+        );
+
+        for destination in [
+            "ssh://opensshtest?timeout=10",
+            "ssh://opensshtest?connect_timeout=ten",
+            "ssh://opensshtest?compression",
+            "ssh://opensshtest?identityfile=%2",
+        ] {
+            let err = SessionBuilder::default()
+                .try_resolve(destination)
+                .unwrap_err();
+            assert!(
+                matches!(err, crate::Error::InvalidDestination(_)),
+                "{}: {:?}",
+                destination,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_jump_hosts() {
+        let mut b = SessionBuilder::default();
+        b.jump_chain(true);
+        let (b, d) = b
+            .try_resolve("jump@bastion:2222+ssh://hop@other:22+ssh://me@opensshtest?user_known_hosts_file=/a+b")
+            .unwrap();
+        assert_eq!(d, "opensshtest");
+        assert_eq!(b.user.as_deref(), Some("me"));
+        assert_eq!(
+            b.jump_hosts,
+            ["jump@bastion:2222".into(), "ssh://hop@other:22".into()]
+        );
+        assert_eq!(b.user_known_hosts_file.as_deref(), Some(Path::new("/a+b")));
+
+        let mut b = SessionBuilder::default();
+        b.jump_hosts(["old"]).jump_chain(true);
+        let (b, d) = b.resolve("++bastion+opensshtest");
+        assert_eq!(d, "opensshtest");
+        assert_eq!(b.jump_hosts, ["bastion".into()]);
+
+        for destination in ["+opensshtest", "a++opensshtest"] {
+            let err = SessionBuilder::default()
+                .jump_chain(true)
+                .try_resolve(destination)
+                .unwrap_err();
+            assert!(
+                matches!(err, crate::Error::InvalidDestination(_)),
+                "{}: {:?}",
+                destination,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn proxy_session_and_jump_hosts() {
+        let b = SessionBuilder {
+            proxy_session: Some(Path::new("/tmp/master").into()),
+            ..SessionBuilder::default()
+        };
+        b.try_resolve("opensshtest").unwrap();
+
+        let mut with_jump_hosts = b.clone();
+        with_jump_hosts.jump_hosts(["bastion"]);
+        let mut with_jump_chain = b.clone();
+        with_jump_chain.jump_chain(true);
+
+        for (b, destination) in [
+            (&with_jump_hosts, "opensshtest"),
+            (&with_jump_chain, "bastion+opensshtest"),
+            (&b, "ssh://opensshtest?proxyjump=bastion"),
+        ] {
+            let err = b.try_resolve(destination).unwrap_err();
+            assert!(
+                matches!(err, crate::Error::InvalidDestination(_)),
+                "{}: {:?}",
+                destination,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_plus_in_user() {
+        let b = SessionBuilder::default();
+        let (r, d) = b.resolve("deploy+ci@opensshtest");
+        assert_eq!(d, "deploy+ci@opensshtest");
+        assert!(r.jump_hosts.is_empty());
+
+        let (r, d) = b.try_resolve("ssh://deploy+ci@opensshtest:2222").unwrap();
+        assert_eq!(d, "opensshtest");
+        assert_eq!(r.user.as_deref(), Some("deploy+ci"));
+        assert!(r.jump_hosts.is_empty());
+    }
 }
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// A token for cancelling the multi-host operations of a
+/// [`SessionPool`](crate::SessionPool), see
+/// [`SessionPool::with_cancellation`](crate::SessionPool::with_cancellation).
+///
+/// Clones of a token share its state, so one clone can be cancelled, e.g.
+/// from a Ctrl-C handler, while another is used by the operations.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    /// Create a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations using this token.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Return whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // Created before checking the flag so that a concurrent `cancel`
+            // is not missed.
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel() {
+        let token = CancellationToken::new();
+        assert!(!token.is_cancelled());
+
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+
+        token.cancel();
+        assert!(token.is_cancelled());
+        waiter.await.unwrap();
+
+        // Already cancelled tokens do not wait.
+        token.cancelled().await;
+    }
+}
@@ -2,6 +2,211 @@
 use crate::*;
 
 /// TODO: RENAME THIS INTO THE NEXT VERSION BEFORE RELEASE
+///
+/// ## Added
+///  - [`SessionBuilder::happy_eyeballs`] to race IPv4 and IPv6 connection
+///    attempts when establishing the master connection.
+///  - [`Error::Timeout`]
+///  - [`Session::connect_remote`] and [`RemoteStream`] for opening a stream
+///    to a socket reachable from the remote host using `ssh -W`.
+///  - [`Session::tunnel_tcp`] and [`ForwardGuard`] for forwarding an
+///    ephemeral local tcp port to the remote host.
+///  - [`Socket::into_owned`]
+///  - [`Error::Tunnel`]
+///  - [`Error::PortForwardRefused`], returned by [`Session::request_port_forward`]
+///    when the multiplex master or the remote host refuses a forwarding.
+///  - [`Session::socks_proxy`] and [`SocksProxy`] for dynamic forwarding
+///    through the remote host.
+///  - [`SessionBuilder::tun_forward`], [`TunForward`] and [`TunnelMode`] for
+///    forwarding tun devices (`ssh -w`).
+///  - [`Session::expose_unix_socket`] for forwarding a local unix socket to a
+///    path on the remote host, replacing any stale socket there.
+///  - [`SessionBuilder::stream_local_bind_unlink`]
+///  - [`SessionBuilder::proxy_session`] for connecting through another
+///    [`Session`].
+///  - [`copy_between`] for copying a file between two remote hosts.
+///  - Feature `hyper`, which enables `SessionConnector` for HTTP clients
+///    dialing through the session.
+///  - [`Session::supervise_port_forward`], [`SupervisedForward`] and
+///    [`ForwardEvent`] for keeping long-lived forwardings up.
+///  - [`SessionBuilder::port_forward`] for requesting forwardings while
+///    establishing the session, which fails with [`Error::ConnectForwardRefused`]
+///    if any of them is refused.
+///  - [`Session::forwards`] and [`ActiveForward`] for listing the forwardings
+///    established through a session.
+///  - [`copy_between_with`] and [`CopyOptions`], with
+///    [`CopyOptions::channels`] for copying ranges of a file concurrently.
+///  - [`Session::glob`] for expanding a glob on the remote host.
+///  - [`CopyOptions::preserve_mode`], [`CopyOptions::preserve_mtime`] and
+///    [`CopyOptions::preserve_owner`] for preserving the attributes of the
+///    source, and [`CopyOptions::mode`] and [`CopyOptions::mtime`] for
+///    setting them explicitly.
+///  - [`Session::tail_file`] and [`TailFile`] for streaming the lines of a
+///    remote file, optionally following it like `tail -F`.
+///  - [`Session::watch`], [`FileWatch`], [`WatchEvent`] and [`WatchEventKind`]
+///    for watching the remote file system with `inotifywait` or `fswatch`.
+///  - [`CopyOptions::inplace`] and [`CopyOptions::partial`] for choosing how
+///    [`copy_between_with`] writes the destination, like the options of `rsync`.
+///  - [`copy_dir_between`] for copying a directory recursively, with
+///    [`CopyOptions::delete`] for removing files missing from the source.
+///  - [`CopyOptions::sparse`] for keeping copies of sparse files sparse.
+///  - [`ChildStdout::copy_to`] for piping a remote child into another one,
+///    using `splice` on Linux.
+///  - [`CopyOptions::compress`] for compressing a single copy.
+///  - [`CopyOptions::exclude`] and [`CopyOptions::include`] for filtering the
+///    files [`copy_dir_between`] copies with gitignore-style patterns.
+///  - [`SessionPool`], [`SessionPoolBuilder`] and [`PooledSession`] for
+///    reusing sessions per destination with a limit on open connections.
+///  - [`SessionPool::run_on_all`] for running a command on many hosts at once.
+///  - [`SessionPool::run_rolling`], [`Rollout`] and [`RolloutReport`] for
+///    running a command on many hosts in batches, with a failure threshold.
+///  - [`Inventory`] and [`Host`] for loading hosts with their connection
+///    settings and labels, and [`SessionPool::get_host`] for connecting to them.
+///  - [`CommandTemplate`] for commands with per-host placeholders such as
+///    `{host}` and `{label:region}`, accepted by [`SessionPool::run_on_all`]
+///    and [`SessionPool::run_rolling`].
+///  - [`HostOutput`] with the output and duration of a command run through
+///    a [`SessionPool`], and a `serde` feature that implements `Serialize`
+///    for it and [`RolloutReport`].
+///  - [`SessionPool::push_file`] for copying a local file to many hosts at
+///    once, verifying its checksum on each host.
+///  - [`SessionPool::facts`] and [`Facts`] for gathering the OS, architecture
+///    and shell of a host, cached for [`SessionPoolBuilder::facts_ttl`].
+///  - [`Inventory::jump_hosts`] for declaring jump hosts shared by all hosts
+///    of an inventory.
+///  - [`SessionPool::run_canary`] for running a command on canary hosts
+///    before rolling it out to the rest.
+///  - [`Selector`] and [`Inventory::select`] for selecting hosts by their
+///    labels, e.g. `role=web && region=eu`.
+///  - [`SessionPool::progress`], [`FleetProgress`] and [`FleetEvent`] for
+///    following the progress of multi-host operations.
+///  - [`SessionPool::with_cancellation`] and [`CancellationToken`] for
+///    aborting multi-host operations midway, returning partial results.
+///  - [`SessionPoolBuilder::max_connecting`] and
+///    [`SessionPoolBuilder::connect_interval`] for limiting the rate at which
+///    a pool connects sessions.
+///  - [`Rollout::retries`] and [`Rollout::retry_backoff`] for retrying a
+///    command on the hosts on which it failed, and
+///    [`RolloutReport::exhausted`].
+///  - `SessionBuilder::password` behind the `password-auth` feature, for
+///    hosts that only allow password authentication.
+///  - `SessionBuilder::sshpass` behind the `password-auth` feature, for
+///    handing the password to ssh through `sshpass` instead.
+///  - `SessionBuilder::passphrase` and `SessionBuilder::passphrase_with`
+///    behind the `password-auth` feature, for unlocking encrypted keys
+///    without an agent.
+///  - The [`agent`] module for starting an ssh-agent and managing its keys.
+///  - The [`keygen`] module for generating keypairs, computing fingerprints
+///    and converting public keys with `ssh-keygen`.
+///  - [`SessionBuilder::hostbased_authentication`],
+///    [`SessionBuilder::gssapi_authentication`] and
+///    [`SessionBuilder::gssapi_delegate_credentials`].
+///  - [`SessionBuilder::host_key_fingerprint`] for pinning the host key of
+///    the remote host, failing with [`Error::HostKeyMismatch`] otherwise.
+///  - [`SessionBuilder::ephemeral_known_hosts`] for keeping accepted host
+///    keys only for the lifetime of the session.
+///  - [`SessionBuilder::revoked_host_keys`] for rejecting revoked host keys
+///    with [`Error::HostKeyRevoked`].
+///  - [`SessionBuilder::update_host_keys`] and
+///    [`SessionBuilder::on_host_key_update`] for following host key
+///    rotations, reported as [`HostKeyUpdate`]s.
+///  - [`SessionBuilder::security_profile`] for restricting the algorithms
+///    ssh may use to a [`SecurityProfile`].
+///  - [`Session::resume_verified`] and [`Session::resume_mux_verified`] for
+///    refusing control sockets that could have been planted by other users
+///    with [`Error::UntrustedControlSocket`].
+///  - [`OwningCommand::strict`] and [`Session::set_strict_commands`] for
+///    rejecting arguments containing shell metacharacters with
+///    [`Error::RejectedArgument`].
+///  - [`Session::set_spawn_pool_size`] for running commands by ssh processes
+///    spawned ahead of time.
+///  - [`Session::spawn_all`] for spawning many commands at once.
+///  - [`OwningCommand::pipe_buffer_size`] for enlarging the stdio pipes of
+///    children on Linux.
+///  - [`ChildStdin::write_all_vectored`] and [`ChildStdin::into_buffered`].
+///  - [`Session::set_control_timeout`] for bounding the time
+///    [`Session::check`], [`Session::close`] and
+///    [`Session::request_port_forward`] wait for the master.
+///  - [`SessionBuilder::idle_timeout`] for closing idle sessions
+///    automatically.
+///  - [`SessionBuilder::tcp_keepalive`].
+///  - [`OwningCommand::on_stdout`] and [`OwningCommand::on_stderr`] for
+///    streaming output to callbacks with backpressure.
+///  - [`OwningCommand::kill_on_drop`] for killing the remote process when
+///    its [`Child`] or the future spawning it is dropped.
+///  - [`OwningCommand::drop_policy`] with [`DropPolicy`] for choosing whether
+///    dropping a [`Child`] detaches from, disconnects from or kills the
+///    remote process.
+///  - [`OwningCommand::nice`], [`OwningCommand::ionice`] and
+///    [`OwningCommand::cpu_affinity`] for the scheduling of remote processes.
+///  - [`OwningCommand::limit`] for the resource limits of remote processes.
+///  - [`Session::ping`] for measuring the latency of a session.
+///  - [`SessionBuilder::capture_server_version`] and
+///    [`Session::server_version`].
+///  - [`Session::destination`], [`Session::user`], [`Session::port`] and
+///    [`Session::known_hosts`] for what a session was connected with.
+///  - [`SessionBuilder::to_ssh_args`] for logging the arguments ssh is run
+///    with.
+///  - Options in the query of URI destinations, e.g.
+///    `ssh://me@example.com?identityfile=/keys/id&connect_timeout=10`, see
+///    [`SessionBuilder::try_resolve`].
+///  - [`SshConfig`] to make a [`SessionBuilder`] from a `Host` entry of an ssh
+///    config file.
+///  - [`SessionBuilder::jump_chain`] for jump hosts preceding the destination,
+///    separated by `+`, e.g. `bastion+me@internal`.
+///  - [`SessionBuilder::control_path`] to place the control socket at a path
+///    with `%C`, `%h`, `%p` and `%r` tokens, and
+///    [`SessionBuilder::control_path_for`] to find it from other processes.
+///  - [`SessionBuilder::connection_attempts`] (`ssh -o ConnectionAttempts`).
+///  - [`SessionBuilder::verbosity`] for `ssh -v` to `ssh -vvv`, and
+///    [`SessionBuilder::on_log`] to receive the log of the master.
+///  - [`OwningCommand::request_tty`] to allocate a pseudo-terminal, see
+///    [`RequestTty`].
+///  - [`OwningCommand::escape_char`], which is disabled by default so that
+///    binary data cannot end the connection of a remote process with a
+///    pseudo-terminal.
+///  - [`Child::resize`] to resize the pseudo-terminal of a remote process.
+///  - [`Session::interactive_shell`] to bridge the local terminal to a remote
+///    login shell.
+///  - [`OwningCommand::strip_ansi`] to strip ANSI escape sequences from
+///    captured and streamed output.
+///  - [`OutputExt`] with lossy UTF-8 and trimmed accessors for the output of
+///    a remote command.
+///  - [`OwningCommand::output_json`] and [`OwningCommand::output_json_lines`]
+///    to deserialize the output of a remote command, with the `serde` feature.
+///  - [`OwningCommand::output_streamed`] to collect the output of a remote
+///    command while also passing it to callbacks as it arrives.
+///  - [`Session::set_envs`], [`Session::source_env`] and [`Session::clear_envs`]
+///    to set environment variables for all later commands of a session.
+///  - [`Session::remote_env`] to get the environment of remote commands.
+///  - [`OwningCommand::locale`] and [`Session::set_locale`] to run remote
+///    commands with a fixed locale such as `C`.
+///  - [`OwningCommand::umask`] to set the file mode creation mask of a remote
+///    process.
+///  - [`Session::su_output`] to run a command as another user with `su`.
+///  - [`OwningCommand::as_transient_unit`] and [`TransientUnit`] to run a
+///    remote process in a transient systemd unit.
+///  - [`Session::supervise_command`] to restart a remote process when it exits.
+///  - [`Session::run_periodically`] to run a remote command at a fixed interval.
+///  - [`Session::forward_docker_socket`] and [`DockerSocket`] for docker
+///    clients managing the containers of the remote host through the session.
+///  - `SessionConnector::to_socket` for dialing a fixed remote socket, such
+///    as that of the docker daemon, for every request.
+/// ## Changed
+///  - [`copy_between`] and [`copy_between_with`] splice the data between the
+///    ssh processes on Linux instead of copying it through userspace.
+///  - [`SessionBuilder::connect_timeout`] now also kills the ssh process
+///    establishing the master connection if key exchange or authentication
+///    hangs, returning [`Error::Timeout`].
+///  - [`Error::Ssh`] is no longer specific to feature `process-mux`.
+///  - [`Session::request_port_forward`] now returns a [`ForwardGuard`] that
+///    cancels the forwarding when dropped. The port allocated by the remote host
+///    for remote forwardings listening on port `0` is available through
+///    [`ForwardGuard::allocated_port`]. Use [`ForwardGuard::cancel`] to close the
+///    forwarding and find out whether that succeeded.
+///  - The `Debug` implementations of [`Session`] and [`SessionBuilder`] no
+///    longer print the paths of keys, configuration files and the agent
+///    socket.
 #[doc(hidden)]
 pub mod unreleased {}
 
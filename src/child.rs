@@ -1,9 +1,17 @@
 use super::{ChildStderr, ChildStdin, ChildStdout, Error};
 
+use crate::ansi::AnsiStripper;
+use crate::terminal::Terminal;
+use crate::wrapper::RemoteKill;
+
+use std::fmt;
+use std::future::Future;
 use std::io;
+use std::pin::Pin;
 use std::process::{ExitStatus, Output};
+use std::sync::{Mutex, PoisonError};
 
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::try_join;
 
 #[derive(Debug)]
@@ -40,6 +48,120 @@ macro_rules! delegate {
     }};
 }
 
+/// The most bytes passed to an [`OutputCallback`] at once, the default
+/// capacity of a pipe on Linux.
+const CHUNK_LEN: usize = 64 * 1024;
+
+type OutputFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A callback the output of a child is streamed to, see
+/// [`OwningCommand::on_stdout`](crate::OwningCommand::on_stdout).
+///
+/// Only used through `Mutex::into_inner`, so that `Child` stays `Sync`.
+pub(crate) struct OutputCallback(Mutex<Box<dyn FnMut(Vec<u8>) -> OutputFuture + Send>>);
+
+impl OutputCallback {
+    pub(crate) fn new<F, Fut>(mut callback: F) -> Self
+    where
+        F: FnMut(Vec<u8>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Mutex::new(Box::new(move |chunk| Box::pin(callback(chunk)))))
+    }
+
+    /// Pass everything read from `stream` until EOF to the callback, only
+    /// reading on once the future it returned for the previous chunk has
+    /// completed, without escape sequences if there is a `stripper`.
+    async fn drain(
+        self,
+        mut stream: impl AsyncRead + Unpin,
+        mut stripper: Option<AnsiStripper>,
+    ) -> Result<(), Error> {
+        let mut callback = self.0.into_inner().unwrap_or_else(PoisonError::into_inner);
+
+        loop {
+            let mut chunk = vec![0; CHUNK_LEN];
+            let n = stream.read(&mut chunk).await.map_err(Error::ChildIo)?;
+            if n == 0 {
+                break Ok(());
+            }
+
+            chunk.truncate(n);
+            if let Some(stripper) = &mut stripper {
+                chunk = stripper.strip(&chunk);
+                if chunk.is_empty() {
+                    continue;
+                }
+            }
+            callback(chunk).await;
+        }
+    }
+}
+
+impl fmt::Debug for OutputCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OutputCallback(..)")
+    }
+}
+
+/// Read `stream` until EOF, if there is one, passing each chunk to `callback`
+/// as it arrives, and return everything read. Escape sequences are removed
+/// first if there is a `stripper`.
+async fn read_streamed(
+    stream: Option<impl AsyncRead + Unpin>,
+    callback: &mut impl FnMut(&[u8]),
+    mut stripper: Option<AnsiStripper>,
+) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let mut stream = match stream {
+        Some(stream) => stream,
+        None => return Ok(output),
+    };
+
+    let mut chunk = vec![0; CHUNK_LEN];
+    loop {
+        let n = stream.read(&mut chunk).await.map_err(Error::ChildIo)?;
+        if n == 0 {
+            break Ok(output);
+        }
+
+        let start = output.len();
+        match &mut stripper {
+            Some(stripper) => output.extend(stripper.strip(&chunk[..n])),
+            None => output.extend_from_slice(&chunk[..n]),
+        }
+        if output.len() > start {
+            callback(&output[start..]);
+        }
+    }
+}
+
+/// What happens to a remote process when its [`Child`] is dropped before it
+/// exited, see [`OwningCommand::drop_policy`](crate::OwningCommand::drop_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum DropPolicy {
+    /// Leave the local `ssh` process running until the remote process exits.
+    ///
+    /// Streams of the remote process configured as
+    /// [`Stdio::piped`](crate::Stdio::piped) are closed with the `Child`,
+    /// so the remote process should not use them once it is detached.
+    ///
+    /// Only supported by the `process-mux` implementation, the `native-mux`
+    /// implementation closes its connection to the remote process like
+    /// [`KillSsh`](Self::KillSsh).
+    Detach,
+
+    /// Kill the local `ssh` process, disconnecting from the remote process
+    /// but leaving it running. This is the default.
+    #[default]
+    KillSsh,
+
+    /// Kill the local `ssh` process and send `SIGTERM` to the remote
+    /// process, see [`OwningCommand::kill_on_drop`](crate::OwningCommand::kill_on_drop).
+    KillRemote,
+}
+
 /// Representation of a running or exited remote child process.
 ///
 /// This structure is used to represent and manage remote child
@@ -54,8 +176,8 @@ macro_rules! delegate {
 ///
 /// Unlike [`std::process::Child`], `Child` *does* implement [`Drop`], and will terminate the
 /// local `ssh` process corresponding to the remote process when it goes out of scope. Note that
-/// this does _not_ terminate the remote process. If you want to do that, you will need to kill it
-/// yourself by executing a remote command like `pkill` to kill it on the remote side.
+/// by default this does _not_ terminate the remote process, see [`DropPolicy`] for what
+/// happens instead.
 ///
 /// As a result, `Child` cannot expose `stdin`, `stdout`, and `stderr` as fields for
 /// split-borrows like [`std::process::Child`] does. Instead, it exposes
@@ -84,6 +206,17 @@ pub struct Child<S> {
     stdin: Option<ChildStdin>,
     stdout: Option<ChildStdout>,
     stderr: Option<ChildStderr>,
+
+    on_stdout: Option<OutputCallback>,
+    on_stderr: Option<OutputCallback>,
+    /// Whether ANSI escape sequences are stripped from the collected and
+    /// streamed output.
+    strip_ansi: bool,
+
+    /// Kills the remote process if the child is dropped before it exited.
+    remote_kill: Option<RemoteKill>,
+
+    terminal: Option<Terminal>,
 }
 
 impl<S> Child<S> {
@@ -102,14 +235,106 @@ impl<S> Child<S> {
             stdout,
             stderr,
             imp,
+
+            on_stdout: None,
+            on_stderr: None,
+            strip_ansi: false,
+
+            remote_kill: None,
+
+            terminal: None,
+        }
+    }
+
+    pub(crate) fn with_terminal(mut self, terminal: Option<Terminal>) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// Resize the pseudo-terminal of the remote process to `cols` columns
+    /// and `rows` rows, so that full-screen programs redraw for the new size.
+    ///
+    /// This sets the size of the local terminal that is the stdin of the
+    /// remote process, e.g. the slave of a pseudo-terminal created by the
+    /// caller for a terminal emulator, and has the master propagate it.
+    /// A local terminal inherited as stdin is tracked by ssh itself, as long
+    /// as ssh receives its `SIGWINCH`.
+    ///
+    /// Fails with [`Error::ChildIo`] if the command was spawned without a
+    /// pseudo-terminal, see
+    /// [`OwningCommand::request_tty`](crate::OwningCommand::request_tty), or
+    /// its stdin is not a local terminal.
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<(), Error> {
+        let terminal = self.terminal.as_ref().ok_or_else(|| {
+            Error::ChildIo(io::Error::new(
+                io::ErrorKind::Other,
+                "the remote process has no pseudo-terminal with a local terminal as stdin",
+            ))
+        })?;
+        terminal.resize(cols, rows).map_err(Error::ChildIo)?;
+        terminal.notify_master().await
+    }
+
+    pub(crate) fn with_remote_kill(mut self, remote_kill: Option<RemoteKill>) -> Self {
+        self.remote_kill = remote_kill;
+        self
+    }
+
+    pub(crate) fn with_output_callbacks(
+        mut self,
+        on_stdout: Option<OutputCallback>,
+        on_stderr: Option<OutputCallback>,
+    ) -> Self {
+        self.on_stdout = on_stdout;
+        self.on_stderr = on_stderr;
+        self
+    }
+
+    pub(crate) fn with_strip_ansi(mut self, strip_ansi: bool) -> Self {
+        self.strip_ansi = strip_ansi;
+        self
+    }
+
+    /// Return a future streaming stdout and stderr to their callbacks, if
+    /// any, until EOF.
+    #[allow(clippy::incompatible_msrv)]
+    fn drain_to_callbacks(&mut self) -> impl Future<Output = Result<(), Error>> {
+        let stdout = self
+            .on_stdout
+            .take()
+            .and_then(|callback| Some((callback, self.stdout.take()?)));
+        let stderr = self
+            .on_stderr
+            .take()
+            .and_then(|callback| Some((callback, self.stderr.take()?)));
+        let stripper = self.strip_ansi.then(AnsiStripper::default);
+
+        async move {
+            let stdout = async {
+                match stdout {
+                    Some((callback, stdout)) => callback.drain(stdout, stripper.clone()).await,
+                    None => Ok(()),
+                }
+            };
+            let stderr = async {
+                match stderr {
+                    Some((callback, stderr)) => callback.drain(stderr, stripper.clone()).await,
+                    None => Ok(()),
+                }
+            };
+            try_join!(stdout, stderr).map(|_| ())
         }
     }
 
     /// Disconnect from this given remote child process.
     ///
     /// Note that disconnecting does _not_ kill the remote process, it merely kills the local
-    /// handle to that remote process.
-    pub async fn disconnect(self) -> io::Result<()> {
+    /// handle to that remote process, even if
+    /// [`OwningCommand::kill_on_drop`](crate::OwningCommand::kill_on_drop) is set.
+    pub async fn disconnect(mut self) -> io::Result<()> {
+        if let Some(remote_kill) = &mut self.remote_kill {
+            remote_kill.disarm();
+        }
         delegate!(self.imp, imp, { imp.disconnect().await })
     }
 
@@ -121,12 +346,23 @@ impl<S> Child<S> {
     /// The stdin handle to the child process, if any, will be closed before waiting. This helps
     /// avoid deadlock: it ensures that the child does not block waiting for input from the parent,
     /// while the parent waits for the child to exit.
+    ///
+    /// The output is streamed to the callbacks set with
+    /// [`OwningCommand::on_stdout`](crate::OwningCommand::on_stdout) and
+    /// [`OwningCommand::on_stderr`](crate::OwningCommand::on_stderr), if
+    /// any, before waiting.
     pub async fn wait(mut self) -> Result<ExitStatus, Error> {
         // Close stdin so that if the remote process is reading stdin,
         // it would return EOF and the remote process can exit.
         self.stdin().take();
 
-        delegate!(self.imp, imp, { imp.wait().await })
+        self.drain_to_callbacks().await?;
+
+        let status: Result<ExitStatus, Error> = delegate!(self.imp, imp, { imp.wait().await });
+        if let (Ok(_), Some(remote_kill)) = (&status, &mut self.remote_kill) {
+            remote_kill.disarm();
+        }
+        status
     }
 
     /// Simultaneously waits for the remote child to exit and collect all remaining output on the
@@ -139,38 +375,34 @@ impl<S> Child<S> {
     /// By default, stdin, stdout and stderr are inherited from the parent. In order to capture the
     /// output into this `Result<Output>` it is necessary to create new pipes between parent and
     /// child. Use `stdout(Stdio::piped())` or `stderr(Stdio::piped())`, respectively.
-    pub async fn wait_with_output(mut self) -> Result<Output, Error> {
-        let child_stdout = self.stdout.take();
-        let stdout_read = async move {
-            let mut stdout = Vec::new();
-
-            if let Some(mut child_stdout) = child_stdout {
-                child_stdout
-                    .read_to_end(&mut stdout)
-                    .await
-                    .map_err(Error::ChildIo)?;
-            }
-
-            Ok::<_, Error>(stdout)
-        };
-
-        let child_stderr = self.stderr.take();
-        let stderr_read = async move {
-            let mut stderr = Vec::new();
+    ///
+    /// Output streamed to callbacks set with
+    /// [`OwningCommand::on_stdout`](crate::OwningCommand::on_stdout) or
+    /// [`OwningCommand::on_stderr`](crate::OwningCommand::on_stderr) is not
+    /// collected.
+    pub async fn wait_with_output(self) -> Result<Output, Error> {
+        self.wait_with_output_streamed(|_| (), |_| ()).await
+    }
 
-            if let Some(mut child_stderr) = child_stderr {
-                child_stderr
-                    .read_to_end(&mut stderr)
-                    .await
-                    .map_err(Error::ChildIo)?;
-            }
+    /// Like [`wait_with_output`](Self::wait_with_output), and also pass the
+    /// collected stdout and stderr to `on_stdout` and `on_stderr` as it is
+    /// read, see
+    /// [`OwningCommand::output_streamed`](crate::OwningCommand::output_streamed).
+    pub(crate) async fn wait_with_output_streamed(
+        mut self,
+        mut on_stdout: impl FnMut(&[u8]),
+        mut on_stderr: impl FnMut(&[u8]),
+    ) -> Result<Output, Error> {
+        let callbacks = self.drain_to_callbacks();
+        let stripper = self.strip_ansi.then(AnsiStripper::default);
 
-            Ok::<_, Error>(stderr)
-        };
+        let stdout_read = read_streamed(self.stdout.take(), &mut on_stdout, stripper.clone());
+        let stderr_read = read_streamed(self.stderr.take(), &mut on_stderr, stripper);
 
         // Execute them concurrently to avoid the pipe buffer being filled up
         // and cause the remote process to block forever.
-        let (stdout, stderr) = try_join!(stdout_read, stderr_read)?;
+        #[allow(clippy::incompatible_msrv)]
+        let (stdout, stderr, ()) = try_join!(stdout_read, stderr_read, callbacks)?;
         Ok(Output {
             // The self.wait() future terminates the stdout and stderr futures
             // when it resolves, even if there may still be more data arriving
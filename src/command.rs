@@ -1,13 +1,19 @@
 use crate::escape::escape;
 
-use super::child::Child;
+use super::child::{Child, DropPolicy, OutputCallback};
 use super::stdio::TryFromChildIo;
+use super::terminal::Terminal;
+use super::wrapper::{IoPriority, RemoteKill, Rlimit, TransientUnit, Wrapper};
 use super::Stdio;
 use super::{Error, Session};
 
 use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::future::Future;
+use std::mem;
 use std::ops::Deref;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
 use std::process;
 
 #[derive(Debug)]
@@ -32,6 +38,13 @@ impl From<super::native_mux_impl::Command> for CommandImp {
     }
 }
 
+/// Characters that have a special meaning to a POSIX shell, rejected in
+/// arguments by [`OwningCommand::strict`].
+const SHELL_METACHARACTERS: &[char] = &[
+    '|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '"', '\'', ' ', '\t', '\n', '\r', '*', '?',
+    '[', ']', '#', '~', '{', '}', '!',
+];
+
 #[cfg(any(feature = "process-mux", feature = "native-mux"))]
 macro_rules! delegate {
     ($impl:expr, $var:ident, $then:block) => {{
@@ -69,7 +82,7 @@ pub trait OverSsh {
     /// ###  Examples
     ///
     /// 1. Consider the implementation of `OverSsh` for `std::process::Command`. Let's build a
-    /// `ls -l -a -h` command and execute it over an SSH session.
+    ///    `ls -l -a -h` command and execute it over an SSH session.
     ///
     /// ```no_run
     /// # #[tokio::main(flavor = "current_thread")]
@@ -93,7 +106,7 @@ pub trait OverSsh {
     ///
     /// ```
     /// 2. Building a command with environment variables or a current working directory set will
-    /// results in an error.
+    ///    results in an error.
     ///
     /// ```no_run
     /// # #[tokio::main(flavor = "current_thread")]
@@ -176,6 +189,33 @@ where
     }
 }
 
+/// Whether a pseudo-terminal is allocated for a remote process, see
+/// [`OwningCommand::request_tty`].
+///
+/// A pseudo-terminal merges the stderr of the remote process into its
+/// stdout, and makes programs behave as they do interactively, e.g. `sudo`
+/// prompting for a password or programs colorizing their output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequestTty {
+    /// Never allocate a pseudo-terminal (`ssh -T`).
+    #[default]
+    No,
+
+    /// Let ssh decide, which only allocates a pseudo-terminal for login
+    /// sessions, not for commands (`ssh -o RequestTTY=auto`).
+    Auto,
+
+    /// Allocate a pseudo-terminal if the stdin of the remote process is a
+    /// local terminal (`ssh -t`).
+    Yes,
+
+    /// Always allocate a pseudo-terminal, even if there is no local
+    /// terminal (`ssh -tt`), e.g. for automating programs that insist on
+    /// one.
+    Force,
+}
+
 /// A remote process builder, providing fine-grained control over how a new remote process should
 /// be spawned.
 ///
@@ -216,6 +256,19 @@ pub struct OwningCommand<S> {
     stdin_set: bool,
     stdout_set: bool,
     stderr_set: bool,
+
+    strict: bool,
+    /// The first argument rejected in strict mode.
+    rejected: Option<String>,
+
+    pipe_buffer_size: Option<usize>,
+
+    on_stdout: Option<OutputCallback>,
+    on_stderr: Option<OutputCallback>,
+    strip_ansi: bool,
+
+    wrapper: Wrapper,
+    drop_policy: DropPolicy,
 }
 
 impl<S> OwningCommand<S> {
@@ -227,7 +280,281 @@ impl<S> OwningCommand<S> {
             stdin_set: false,
             stdout_set: false,
             stderr_set: false,
+
+            strict: false,
+            rejected: None,
+
+            pipe_buffer_size: None,
+
+            on_stdout: None,
+            on_stderr: None,
+            strip_ansi: false,
+
+            wrapper: Wrapper::default(),
+            drop_policy: DropPolicy::default(),
+        }
+    }
+
+    /// Reject the arguments subsequently added with [`arg`](Self::arg) or
+    /// [`args`](Self::args) that contain shell metacharacters, such as `$`,
+    /// `;`, `*` or whitespace, in which case spawning the command fails with
+    /// [`Error::RejectedArgument`].
+    ///
+    /// Arguments are escaped before they are passed to the remote shell
+    /// either way, so this is a defense in depth against command injection
+    /// when arguments come from untrusted input, e.g. in case the remote
+    /// program evaluates its arguments in a shell itself. Arguments added
+    /// with [`raw_arg`](Self::raw_arg) or [`raw_args`](Self::raw_args) are
+    /// explicitly marked raw and are not checked.
+    ///
+    /// Defaults to the mode of the session, see
+    /// [`Session::set_strict_commands`].
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Set the capacity of the pipes created for the stdin, stdout and stderr
+    /// of the remote process configured as [`Stdio::piped`] to `size` bytes
+    /// (`F_SETPIPE_SZ`), which is rounded up to a power of two pages.
+    ///
+    /// Larger pipes than the default of 64 KiB help the throughput of large
+    /// outputs. Unprivileged users may not exceed
+    /// `/proc/sys/fs/pipe-max-size` (1 MiB by default), otherwise spawning
+    /// fails with [`Error::ChildIo`].
+    ///
+    /// Only has an effect on Linux.
+    pub fn pipe_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.pipe_buffer_size = Some(size);
+        self
+    }
+
+    /// Stream the stdout of the remote process to `callback` in chunks, which
+    /// makes stdout [`Stdio::piped`].
+    ///
+    /// The chunks are read while waiting for the process, e.g. with
+    /// [`status`](Self::status) or [`Child::wait`]. No more is read until
+    /// the future returned by `callback` for the previous chunk has
+    /// completed, so a slow consumer makes the remote process block on
+    /// writing rather than buffering its output in memory.
+    ///
+    /// The callback is moved into the next spawned child.
+    pub fn on_stdout<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(Vec<u8>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.stdout(Stdio::piped());
+        self.on_stdout = Some(OutputCallback::new(callback));
+        self
+    }
+
+    /// Stream the stderr of the remote process to `callback`, like
+    /// [`on_stdout`](Self::on_stdout) does for stdout.
+    pub fn on_stderr<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(Vec<u8>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.stderr(Stdio::piped());
+        self.on_stderr = Some(OutputCallback::new(callback));
+        self
+    }
+
+    /// Strip ANSI escape sequences, such as colors, cursor movement and
+    /// window titles, from the stdout and stderr collected by
+    /// [`output`](Self::output) and [`Child::wait_with_output`], and from
+    /// the chunks passed to [`on_stdout`](Self::on_stdout) and
+    /// [`on_stderr`](Self::on_stderr).
+    ///
+    /// Many programs emit colors when they run with a pseudo-terminal, see
+    /// [`request_tty`](Self::request_tty), which this keeps out of logs and
+    /// parsers. Sequences split across chunks are stripped as well. Streams
+    /// taken from the [`Child`] directly are not affected.
+    ///
+    /// Defaults to `false`.
+    pub fn strip_ansi(&mut self, strip: bool) -> &mut Self {
+        self.strip_ansi = strip;
+        self
+    }
+
+    /// Controls what happens to the remote process when the [`Child`]
+    /// spawned from this command is dropped before it exited, including when
+    /// the future of [`output`](Self::output) or [`status`](Self::status)
+    /// is dropped, e.g. by losing a `tokio::select!`.
+    ///
+    /// Defaults to [`DropPolicy::KillSsh`].
+    pub fn drop_policy(&mut self, policy: DropPolicy) -> &mut Self {
+        #[cfg(feature = "process-mux")]
+        #[allow(irrefutable_let_patterns)]
+        if let CommandImp::ProcessImpl(imp) = &mut self.imp {
+            imp.detach(policy == DropPolicy::Detach);
         }
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Controls whether the remote process is killed when the [`Child`]
+    /// spawned from this command is dropped before it exited, setting the
+    /// [`drop_policy`](Self::drop_policy) to [`DropPolicy::KillRemote`]
+    /// or [`DropPolicy::KillSsh`].
+    ///
+    /// The local connection to the remote process is closed on drop either
+    /// way, which the remote process only notices once it writes to its
+    /// output or reads from its input. With `kill_on_drop` set, the remote
+    /// process is also sent `SIGTERM` by a separate command run in the
+    /// background, for which the remote command line is run by `sh` to
+    /// record the pid of the remote process. [`Child::disconnect`] leaves
+    /// the remote process running.
+    ///
+    /// Has no effect on subsystems. Defaults to `false`.
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Self {
+        self.drop_policy(if kill_on_drop {
+            DropPolicy::KillRemote
+        } else {
+            DropPolicy::KillSsh
+        })
+    }
+
+    /// Controls whether a pseudo-terminal is allocated for the remote
+    /// process.
+    ///
+    /// Defaults to [`RequestTty::No`].
+    pub fn request_tty(&mut self, tty: RequestTty) -> &mut Self {
+        delegate!(&mut self.imp, imp, {
+            imp.request_tty(tty);
+        });
+        self
+    }
+
+    /// Set the escape character recognized at the beginning of a line of
+    /// the stdin of a remote process with a pseudo-terminal, e.g. `~` to
+    /// disconnect with `~.`, or `None` to disable it (`ssh -e`).
+    ///
+    /// Escape sequences in binary data would otherwise end the connection
+    /// mid-transfer, so this only suits a local terminal bridged to the
+    /// remote process. Only ASCII characters can be used, others disable the
+    /// escape character as well.
+    ///
+    /// Has no effect without a pseudo-terminal, see
+    /// [`request_tty`](Self::request_tty). Defaults to `None`.
+    pub fn escape_char(&mut self, escape_char: Option<char>) -> &mut Self {
+        let escape_char = escape_char.filter(char::is_ascii);
+        delegate!(&mut self.imp, imp, {
+            imp.escape_char(escape_char);
+        });
+        self
+    }
+
+    /// Run the remote process with its niceness adjusted by `adjustment`
+    /// with `nice(1)`, from -20 (most favorable scheduling) to 19 (least
+    /// favorable). Negative adjustments require privileges on the remote
+    /// host.
+    ///
+    /// Has no effect on subsystems.
+    pub fn nice(&mut self, adjustment: i8) -> &mut Self {
+        self.wrapper.nice = Some(adjustment);
+        self
+    }
+
+    /// Run the remote process with the I/O scheduling class and priority
+    /// `priority` with `ionice(1)`.
+    ///
+    /// Has no effect on subsystems.
+    pub fn ionice(&mut self, priority: IoPriority) -> &mut Self {
+        self.wrapper.ionice = Some(priority);
+        self
+    }
+
+    /// Only run the remote process on the CPUs numbered `cpus` with
+    /// `taskset(1)`, or on any CPU if `cpus` is empty.
+    ///
+    /// Has no effect on subsystems.
+    pub fn cpu_affinity<I: IntoIterator<Item = usize>>(&mut self, cpus: I) -> &mut Self {
+        let cpus: Vec<_> = cpus.into_iter().collect();
+        self.wrapper.cpu_affinity = (!cpus.is_empty()).then_some(cpus);
+        self
+    }
+
+    /// Run the remote process with the resource limit `limit`, set with
+    /// the `ulimit` builtin of the remote shell, replacing the limit of the
+    /// same kind set before, if any.
+    ///
+    /// Limits can only be raised up to the hard limits of the remote user,
+    /// otherwise the remote command fails without being run.
+    ///
+    /// Has no effect on subsystems.
+    pub fn limit(&mut self, limit: Rlimit) -> &mut Self {
+        let limits = &mut self.wrapper.limits;
+        limits.retain(|set| mem::discriminant(set) != mem::discriminant(&limit));
+        limits.push(limit);
+        self
+    }
+
+    /// Run the remote process with the locale `locale`, e.g. `C` or
+    /// `C.UTF-8`, by setting `LC_ALL`, so that the messages and formats of
+    /// the tools it runs are not localized and can be parsed.
+    ///
+    /// With the `process-mux` implementation, the local ssh process is run
+    /// with the locale as well. Only the local ssh process is affected for
+    /// subsystems.
+    ///
+    /// Defaults to the locale of the session, see [`Session::set_locale`],
+    /// and otherwise leaves the locale of the remote user in effect.
+    pub fn locale(&mut self, locale: &str) -> &mut Self {
+        #[cfg(feature = "process-mux")]
+        #[allow(irrefutable_let_patterns)]
+        if let CommandImp::ProcessImpl(imp) = &mut self.imp {
+            imp.locale(locale);
+        }
+        let env = &mut self.wrapper.env;
+        env.retain(|(name, _)| name != "LC_ALL");
+        env.push(("LC_ALL".into(), locale.into()));
+        self
+    }
+
+    /// Set the environment variables set on the session, see
+    /// [`Session::set_envs`].
+    pub(crate) fn session_env(&mut self, env: Vec<(String, String)>) -> &mut Self {
+        self.wrapper.env = env;
+        self
+    }
+
+    /// Run the remote process with the file mode creation mask `umask`,
+    /// e.g. `0o022`, set with the `umask` builtin of the remote shell, so
+    /// that the permissions of the files it creates do not depend on the
+    /// defaults of the remote user. Only the permission bits are used.
+    ///
+    /// Has no effect on subsystems.
+    pub fn umask(&mut self, umask: u32) -> &mut Self {
+        self.wrapper.umask = Some(umask);
+        self
+    }
+
+    /// Run the remote process in the transient systemd unit `unit` with
+    /// `systemd-run`, e.g. to have a long job survive a disconnect, limit
+    /// its resources and make it visible to `systemctl`.
+    ///
+    /// The other settings of the command, such as [`nice`](Self::nice) or
+    /// [`limit`](Self::limit), apply to the process in the unit. A service
+    /// is not a child of the remote shell, so
+    /// [`DropPolicy::KillRemote`] does not stop it, use `systemctl stop`
+    /// instead.
+    ///
+    /// Has no effect on subsystems.
+    pub fn as_transient_unit(&mut self, unit: &TransientUnit) -> &mut Self {
+        self.wrapper.transient_unit = Some(unit.clone());
+        self
+    }
+
+    /// Record `arg` as rejected if it contains shell metacharacters in
+    /// strict mode, returning whether it was rejected.
+    pub(crate) fn reject_unsafe(&mut self, arg: &str) -> bool {
+        let reject = self.strict && arg.contains(SHELL_METACHARACTERS);
+        if reject && self.rejected.is_none() {
+            self.rejected = Some(arg.to_owned());
+        }
+        reject
     }
 
     /// Adds an argument to pass to the remote program.
@@ -255,6 +582,9 @@ impl<S> OwningCommand<S> {
     ///
     /// To pass multiple arguments see [`args`](Self::args).
     pub fn arg<A: AsRef<str>>(&mut self, arg: A) -> &mut Self {
+        if self.reject_unsafe(arg.as_ref()) {
+            return self;
+        }
         self.raw_arg(&*shell_escape::unix::escape(Cow::Borrowed(arg.as_ref())))
     }
 
@@ -358,10 +688,37 @@ impl<S> OwningCommand<S> {
 
 impl<S: Clone> OwningCommand<S> {
     async fn spawn_impl(&mut self) -> Result<Child<S>, Error> {
-        Ok(Child::new(
+        if let Some(arg) = &self.rejected {
+            return Err(Error::RejectedArgument(arg.clone()));
+        }
+
+        // Created before spawning, so that the remote process is killed
+        // even if spawning is cancelled after it started.
+        let remote_kill = if self.drop_policy == DropPolicy::KillRemote {
+            delegate!(&self.imp, imp, {
+                (!imp.is_subsystem()).then(|| RemoteKill::new(imp.ctl()))
+            })
+        } else {
+            None
+        };
+        let mut wrapper = self.wrapper.clone();
+        let remote_kill = remote_kill.map(|(remote_kill, id)| {
+            wrapper.kill_id = Some(id);
+            remote_kill
+        });
+
+        // Taken before spawning, which may consume the stdin of the command.
+        let terminal = delegate!(&self.imp, imp, {
+            imp.terminal().map(|fd| Terminal {
+                fd,
+                ctl: imp.ctl().into(),
+            })
+        });
+
+        let mut child = Child::new(
             self.session.clone(),
             delegate!(&mut self.imp, imp, {
-                let (imp, stdin, stdout, stderr) = imp.spawn().await?;
+                let (imp, stdin, stdout, stderr) = imp.spawn(&wrapper).await?;
                 (
                     imp.into(),
                     stdin.map(TryFromChildIo::try_from).transpose()?,
@@ -369,7 +726,25 @@ impl<S: Clone> OwningCommand<S> {
                     stderr.map(TryFromChildIo::try_from).transpose()?,
                 )
             }),
-        ))
+        )
+        .with_remote_kill(remote_kill)
+        .with_terminal(terminal)
+        .with_output_callbacks(self.on_stdout.take(), self.on_stderr.take())
+        .with_strip_ansi(self.strip_ansi);
+
+        #[cfg(target_os = "linux")]
+        if let Some(size) = self.pipe_buffer_size {
+            let fds = [
+                child.stdin().as_ref().map(AsRawFd::as_raw_fd),
+                child.stdout().as_ref().map(AsRawFd::as_raw_fd),
+                child.stderr().as_ref().map(AsRawFd::as_raw_fd),
+            ];
+            for fd in fds.iter().flatten() {
+                crate::stdio::set_pipe_size(fd, size).map_err(Error::ChildIo)?;
+            }
+        }
+
+        Ok(child)
     }
 
     /// Executes the remote command without waiting for it, returning a handle to it
@@ -408,6 +783,123 @@ impl<S: Clone> OwningCommand<S> {
         self.spawn_impl().await?.wait_with_output().await
     }
 
+    /// Executes the remote command like [`output`](Self::output), and also
+    /// passes its stdout and stderr to `on_stdout` and `on_stderr` as they
+    /// arrive, e.g. to show the progress of a command whose output is
+    /// needed afterwards.
+    ///
+    /// The callbacks are called with the chunks read from the remote
+    /// process, which need not end at line boundaries. Streams that are not
+    /// [`Stdio::piped`] are not passed to the callbacks, nor are the ones
+    /// streamed to callbacks set with [`on_stdout`](Self::on_stdout) or
+    /// [`on_stderr`](Self::on_stderr).
+    ///
+    /// ```rust,no_run
+    /// # async fn foo(session: &openssh::Session) -> Result<(), openssh::Error> {
+    /// let mut lines = 0;
+    /// let output = session
+    ///     .command("apt-get")
+    ///     .args(["install", "-y", "nginx"])
+    ///     .output_streamed(
+    ///         |chunk| {
+    ///             lines += chunk.iter().filter(|&&b| b == b'\n').count();
+    ///             eprint!("\r{} lines", lines);
+    ///         },
+    ///         |_| (),
+    ///     )
+    ///     .await?;
+    /// eprintln!();
+    /// assert!(output.status.success());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn output_streamed(
+        &mut self,
+        on_stdout: impl FnMut(&[u8]),
+        on_stderr: impl FnMut(&[u8]),
+    ) -> Result<process::Output, Error> {
+        if !self.stdin_set {
+            self.stdin(Stdio::null());
+        }
+        if !self.stdout_set {
+            self.stdout(Stdio::piped());
+        }
+        if !self.stderr_set {
+            self.stderr(Stdio::piped());
+        }
+
+        self.spawn_impl()
+            .await?
+            .wait_with_output_streamed(on_stdout, on_stderr)
+            .await
+    }
+
+    /// Executes the remote command like [`output`](Self::output), and
+    /// deserializes its stdout as JSON, e.g. of a tool invoked with
+    /// `--format json`.
+    ///
+    /// Fails with [`Error::Remote`] if the command exits with a non-zero
+    /// status, with its stderr as the message, or if stdout is not valid
+    /// JSON for `T`, with an error of kind
+    /// [`InvalidData`](std::io::ErrorKind::InvalidData).
+    ///
+    /// ```rust,no_run
+    /// # async fn foo(session: &openssh::Session) -> Result<(), openssh::Error> {
+    /// #[derive(serde::Deserialize)]
+    /// struct Container {
+    ///     #[serde(rename = "Id")]
+    ///     id: String,
+    /// }
+    ///
+    /// let containers: Vec<Container> = session
+    ///     .command("docker")
+    ///     .args(["container", "ls", "--format", "{{json .}}"])
+    ///     .output_json_lines()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub async fn output_json<T: serde::de::DeserializeOwned>(&mut self) -> Result<T, Error> {
+        let stdout = self.successful_stdout().await?;
+        serde_json::from_slice(&stdout).map_err(invalid_json)
+    }
+
+    /// Executes the remote command like [`output_json`](Self::output_json),
+    /// and deserializes its stdout as a sequence of JSON values, such as
+    /// JSON lines with one value per line.
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub async fn output_json_lines<T: serde::de::DeserializeOwned>(
+        &mut self,
+    ) -> Result<Vec<T>, Error> {
+        let stdout = self.successful_stdout().await?;
+        serde_json::Deserializer::from_slice(&stdout)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(invalid_json)
+    }
+
+    /// Return the stdout of the command, or its stderr as an error if it
+    /// failed.
+    #[cfg(feature = "serde")]
+    async fn successful_stdout(&mut self) -> Result<Vec<u8>, Error> {
+        let output = self.output().await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let message = match stderr.trim() {
+                "" => output.status.to_string(),
+                stderr => stderr.to_owned(),
+            };
+            return Err(Error::Remote(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                message,
+            )));
+        }
+        Ok(output.stdout)
+    }
+
     /// Executes the remote command, waiting for it to finish and collecting its exit status.
     ///
     /// By default, stdin, stdout and stderr are inherited.
@@ -415,3 +907,8 @@ impl<S: Clone> OwningCommand<S> {
         self.spawn().await?.wait().await
     }
 }
+
+#[cfg(feature = "serde")]
+fn invalid_json(err: serde_json::Error) -> Error {
+    Error::Remote(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,110 @@
+use super::{Error, RemoteStream, Session, Socket};
+
+use std::future::{ready, Ready};
+use std::path::Path;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::Uri;
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::rt::TokioIo;
+use tower_service::Service;
+
+/// A connector for HTTP clients that dials every connection from the remote
+/// host using [`Session::connect_remote`].
+///
+/// This implements `tower_service::Service<http::Uri>` and can be used with
+/// the legacy client of `hyper-util` to query HTTP endpoints that are only
+/// reachable from the remote host, such as admin panels or metrics bound to
+/// its loopback interface:
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "process-mux")]
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use http_body_util::Empty;
+/// use hyper::body::Bytes;
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+/// use openssh::{KnownHosts, Session, SessionConnector};
+///
+/// let session = Session::connect("me@ssh.example.com", KnownHosts::Strict).await?;
+///
+/// let client = Client::builder(TokioExecutor::new())
+///     .build::<_, Empty<Bytes>>(SessionConnector::new(&session));
+/// let res = client.get("http://localhost:9090/metrics".parse()?).await?;
+/// # Ok(()) }
+/// ```
+///
+/// TLS is not handled by the connector, so wrap it with e.g. `hyper-rustls`
+/// for `https` URIs.
+///
+/// The connector does not keep the [`Session`] alive, so connections fail
+/// once it is closed.
+#[derive(Debug, Clone)]
+#[cfg_attr(docsrs, doc(cfg(feature = "hyper")))]
+pub struct SessionConnector {
+    ctl: Arc<Path>,
+    socket: Option<Socket<'static>>,
+}
+
+impl SessionConnector {
+    /// Create a connector dialing through `session`.
+    pub fn new(session: &Session) -> Self {
+        Self {
+            ctl: session.control_socket().into(),
+            socket: None,
+        }
+    }
+
+    /// Create a connector dialing `socket` through `session` for every URI,
+    /// ignoring its host and port.
+    ///
+    /// This serves HTTP APIs listening on a unix socket of the remote host,
+    /// such as the docker daemon at `/var/run/docker.sock`, which is how
+    /// `DOCKER_HOST=ssh://` reaches it. Clients like `bollard` accept such a
+    /// connector as a custom transport. To forward the socket to the local
+    /// machine instead, see [`Session::forward_docker_socket`].
+    pub fn to_socket(session: &Session, socket: impl Into<Socket<'static>>) -> Self {
+        Self {
+            ctl: session.control_socket().into(),
+            socket: Some(socket.into()),
+        }
+    }
+}
+
+impl Service<Uri> for SessionConnector {
+    type Response = TokioIo<RemoteStream>;
+    type Error = Error;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        if let Some(socket) = &self.socket {
+            return ready(RemoteStream::connect(&self.ctl, socket).map(TokioIo::new));
+        }
+
+        let host = uri.host().unwrap_or("localhost");
+        // IPv6 literals are bracketed in URIs, but not in `ssh -W`.
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        let port = uri.port_u16().unwrap_or_else(|| match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+
+        ready(RemoteStream::connect(&self.ctl, &Socket::new(host, port)).map(TokioIo::new))
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "hyper")))]
+impl Connection for RemoteStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
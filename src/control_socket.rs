@@ -0,0 +1,105 @@
+use super::Error;
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use tokio::net::UnixStream;
+
+/// Verify that the control socket `ctl` of an existing ssh multiplex master
+/// can be trusted before attaching to it.
+///
+/// The socket must be owned by and only accessible to the current user, its
+/// directory must not be writable by other users unless it is sticky, and the
+/// master listening on it must be run by the current user.
+pub(crate) async fn verify(ctl: &Path) -> Result<(), Error> {
+    // SAFETY: `geteuid` is always successful.
+    let uid = unsafe { libc::geteuid() };
+    let untrusted = |msg: String| {
+        Error::UntrustedControlSocket(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{}: {}", ctl.display(), msg),
+        ))
+    };
+
+    let metadata = fs::symlink_metadata(ctl).map_err(Error::Connect)?;
+    if !metadata.file_type().is_socket() {
+        return Err(untrusted("not a socket".into()));
+    }
+    if metadata.uid() != uid {
+        return Err(untrusted(format!("owned by uid {}", metadata.uid())));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(untrusted(format!(
+            "accessible by other users (mode {:o})",
+            metadata.mode() & 0o777
+        )));
+    }
+
+    let dir = match ctl.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let metadata = fs::metadata(dir).map_err(Error::Connect)?;
+    if metadata.uid() != uid && metadata.uid() != 0 {
+        return Err(untrusted(format!(
+            "directory owned by uid {}",
+            metadata.uid()
+        )));
+    }
+    if metadata.mode() & 0o022 != 0 && metadata.mode() & 0o1000 == 0 {
+        return Err(untrusted(format!(
+            "directory writable by other users (mode {:o})",
+            metadata.mode() & 0o7777
+        )));
+    }
+
+    let stream = UnixStream::connect(ctl).await.map_err(Error::Connect)?;
+    let cred = stream.peer_cred().map_err(Error::Connect)?;
+    if cred.uid() != uid {
+        return Err(untrusted(format!("master run by uid {}", cred.uid())));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctl = dir.path().join("master");
+        let _listener = UnixListener::bind(&ctl).unwrap();
+
+        fs::set_permissions(&ctl, fs::Permissions::from_mode(0o600)).unwrap();
+        super::verify(&ctl).await.unwrap();
+
+        fs::set_permissions(&ctl, fs::Permissions::from_mode(0o666)).unwrap();
+        let err = super::verify(&ctl).await.unwrap_err();
+        assert!(matches!(err, Error::UntrustedControlSocket(_)), "{:?}", err);
+
+        fs::set_permissions(&ctl, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        let err = super::verify(&ctl).await.unwrap_err();
+        assert!(matches!(err, Error::UntrustedControlSocket(_)), "{:?}", err);
+
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o1777)).unwrap();
+        super::verify(&ctl).await.unwrap();
+
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        let err = super::verify(&file).await.unwrap_err();
+        assert!(matches!(err, Error::UntrustedControlSocket(_)), "{:?}", err);
+
+        let err = super::verify(&dir.path().join("missing"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Connect(_)), "{:?}", err);
+    }
+}
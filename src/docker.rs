@@ -0,0 +1,79 @@
+use super::{Error, ForwardGuard};
+
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+use tokio::net::UnixStream;
+
+/// The docker socket of the remote host, forwarded to a unix socket on the
+/// local machine, created by
+/// [`Session::forward_docker_socket`](crate::Session::forward_docker_socket).
+///
+/// This is what `DOCKER_HOST=ssh://host` does, except that the connection to
+/// the remote docker daemon rides the already authenticated session. Docker
+/// clients can use it through [`docker_host`](DockerSocket::docker_host),
+/// e.g. the `docker` cli:
+///
+/// ```rust,no_run
+/// # async fn foo(session: &openssh::Session) -> Result<(), Box<dyn std::error::Error>> {
+/// let docker = session.forward_docker_socket("/var/run/docker.sock").await?;
+///
+/// let status = tokio::process::Command::new("docker")
+///     .arg("ps")
+///     .env("DOCKER_HOST", docker.docker_host())
+///     .status()
+///     .await?;
+/// # Ok(()) }
+/// ```
+///
+/// or `bollard`:
+///
+/// ```rust,ignore
+/// let docker = session.forward_docker_socket("/var/run/docker.sock").await?;
+/// let client = bollard::Docker::connect_with_unix(
+///     &docker.docker_host(),
+///     120,
+///     bollard::API_DEFAULT_VERSION,
+/// )?;
+/// ```
+///
+/// This is a local forwarding (`ssh -L`) of the multiplex master, which is
+/// cancelled when the `DockerSocket` is dropped. To dial the remote docker
+/// socket with `ssh -W` instead, see
+/// [`Session::connect_remote`](crate::Session::connect_remote).
+#[derive(Debug)]
+#[must_use = "the forwarding is cancelled when dropped"]
+pub struct DockerSocket {
+    // Cancelled before the directory of the socket is removed.
+    guard: ForwardGuard,
+    path: PathBuf,
+    dir: TempDir,
+}
+
+impl DockerSocket {
+    pub(crate) fn new(guard: ForwardGuard, path: PathBuf, dir: TempDir) -> Self {
+        Self { guard, path, dir }
+    }
+
+    /// Return the path of the local socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Return the value of `DOCKER_HOST` for the local socket, e.g.
+    /// `unix:///tmp/.tmpa1b2c3/docker.sock`.
+    pub fn docker_host(&self) -> String {
+        format!("unix://{}", self.path.display())
+    }
+
+    /// Open a connection to the remote docker daemon.
+    pub async fn connect(&self) -> Result<UnixStream, Error> {
+        UnixStream::connect(&self.path).await.map_err(Error::Tunnel)
+    }
+
+    /// Cancel the forwarding, reporting any error.
+    pub async fn close(self) -> Result<(), Error> {
+        self.guard.cancel().await?;
+        self.dir.close().map_err(Error::Cleanup)
+    }
+}
@@ -0,0 +1,136 @@
+//! Parsing of `.env` files, see [`Session::source_env`](crate::Session::source_env).
+
+use std::io;
+
+/// Return whether `name` can be the name of an environment variable in a
+/// POSIX shell.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Parse the `NAME=value` lines of a `.env` file, which may be prefixed with
+/// `export`. Values may be quoted, with `\"`, `\\` and `\n` escapes between
+/// double quotes, and unquoted values end at a ` #` comment.
+pub(crate) fn parse(s: &str) -> io::Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+
+    for (n, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let var = parse_line(line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", n + 1, err),
+            )
+        })?;
+        vars.push(var);
+    }
+
+    Ok(vars)
+}
+
+fn parse_line(line: &str) -> Result<(String, String), String> {
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (name, value) = line
+        .split_once('=')
+        .ok_or_else(|| format!("expected `NAME=value`, found `{}`", line))?;
+
+    let name = name.trim();
+    if !is_valid_name(name) {
+        return Err(format!("invalid variable name `{}`", name));
+    }
+
+    let value = value.trim_start();
+    let value = if let Some(rest) = value.strip_prefix('\'') {
+        let (value, _) = rest
+            .split_once('\'')
+            .ok_or_else(|| format!("unterminated quote in `{}`", name))?;
+        value.to_owned()
+    } else if let Some(rest) = value.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => return Err(format!("unterminated quote in `{}`", name)),
+                },
+                Some(c) => value.push(c),
+                None => return Err(format!("unterminated quote in `{}`", name)),
+            }
+        }
+        value
+    } else {
+        let value = match value.find(" #") {
+            Some(comment) => &value[..comment],
+            None => value,
+        };
+        value.trim_end().to_owned()
+    };
+
+    Ok((name.to_owned(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_env_file() {
+        let vars = parse(
+            "# database\n\
+             DB_HOST=db.internal\n\
+             export DB_PORT = 5432 # default\n\
+             \n\
+             GREETING='single $quoted # value'\n\
+             MOTD=\"line 1\\nsay \\\"hi\\\" # not a comment\"\n\
+             EMPTY=\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("DB_HOST".to_owned(), "db.internal".to_owned()),
+                ("DB_PORT".to_owned(), "5432".to_owned()),
+                ("GREETING".to_owned(), "single $quoted # value".to_owned()),
+                (
+                    "MOTD".to_owned(),
+                    "line 1\nsay \"hi\" # not a comment".to_owned()
+                ),
+                ("EMPTY".to_owned(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let err = parse("A=1\nB\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 2: expected `NAME=value`, found `B`");
+
+        let err = parse("1A=1").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid variable name `1A`");
+
+        let err = parse("A=\"open").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unterminated quote in `A`");
+    }
+
+    #[test]
+    fn valid_name() {
+        assert!(is_valid_name("_PATH2"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("A-B"));
+        assert!(!is_valid_name("A=B"));
+    }
+}
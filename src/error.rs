@@ -13,8 +13,6 @@ pub enum Error {
     Connect(#[source] io::Error),
 
     /// Failed to run the `ssh` command locally.
-    #[cfg(feature = "process-mux")]
-    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     #[error("the local ssh command could not be executed")]
     Ssh(#[source] io::Error),
 
@@ -60,6 +58,76 @@ pub enum Error {
     #[error("the remote process has terminated")]
     RemoteProcessTerminated,
 
+    /// The operation did not complete in time.
+    ///
+    /// For [`SessionBuilder::connect_timeout`](crate::SessionBuilder::connect_timeout),
+    /// this means the ssh process did not finish key exchange and authentication
+    /// in time and has been killed.
+    #[error("the operation timed out")]
+    Timeout,
+
+    /// The ssh multiplex master or the remote host refused a port forwarding.
+    ///
+    /// For remote forwardings, this is typically because the remote port is
+    /// already in use, is privileged, or the requested bind address is not
+    /// permitted by `GatewayPorts` in the sshd config of the remote host.
+    #[error("the port forwarding request was refused")]
+    PortForwardRefused(#[source] io::Error),
+
+    /// A forwarding requested with
+    /// [`SessionBuilder::port_forward`](crate::SessionBuilder::port_forward)
+    /// was refused while establishing the session.
+    #[error("the {forward_type:?} forwarding listening on {listen_socket} was refused")]
+    ConnectForwardRefused {
+        /// Type of the refused forwarding.
+        forward_type: crate::ForwardType,
+        /// Listening side of the refused forwarding.
+        listen_socket: crate::Socket<'static>,
+        /// The error reported by ssh.
+        #[source]
+        source: io::Error,
+    },
+
+    /// The host key of the remote host does not have the fingerprint pinned
+    /// with
+    /// [`SessionBuilder::host_key_fingerprint`](crate::SessionBuilder::host_key_fingerprint).
+    #[error("the host key does not match the pinned fingerprint {expected}")]
+    HostKeyMismatch {
+        /// The pinned fingerprint.
+        expected: String,
+        /// The error reported by ssh.
+        #[source]
+        source: io::Error,
+    },
+
+    /// The host key of the remote host has been revoked in the file set with
+    /// [`SessionBuilder::revoked_host_keys`](crate::SessionBuilder::revoked_host_keys).
+    #[error("the host key has been revoked")]
+    HostKeyRevoked(#[source] io::Error),
+
+    /// An existing control socket failed verification before attaching to it,
+    /// e.g. because it is owned by another user, see
+    /// [`Session::resume_verified`](crate::Session::resume_verified).
+    #[error("refusing to use an untrusted control socket")]
+    UntrustedControlSocket(#[source] io::Error),
+
+    /// A destination passed to
+    /// [`SessionBuilder::connect`](crate::SessionBuilder::connect) carries an
+    /// unknown or invalid option, or has jump hosts as well as a
+    /// [proxy session](crate::SessionBuilder::proxy_session), see
+    /// [`SessionBuilder::try_resolve`](crate::SessionBuilder::try_resolve).
+    #[error("invalid destination: {0}")]
+    InvalidDestination(String),
+
+    /// An argument containing shell metacharacters was passed to a command in
+    /// strict mode, see [`OwningCommand::strict`](crate::OwningCommand::strict).
+    #[error("rejected argument `{0}` containing shell metacharacters")]
+    RejectedArgument(String),
+
+    /// Failed to set up the local end of a tunnel.
+    #[error("failed to set up the local end of the tunnel")]
+    Tunnel(#[source] io::Error),
+
     /// Failed to remove temporary dir where ssh socket and output is stored.
     #[error("failed to remove temporary ssh session directory")]
     Cleanup(#[source] io::Error),
@@ -77,6 +145,16 @@ pub enum Error {
     /// However, OverSsh does not support setting a working directory for commands to be executed over ssh.
     #[error("rejected runing a command over ssh that expects a specific working directory to be carried over to remote.")]
     CommandHasCwd,
+
+    /// A [`CommandTemplate`](crate::CommandTemplate) refers to a label that
+    /// the host it is expanded for does not have.
+    #[error("the host has no label `{0}` used in the command template")]
+    MissingLabel(String),
+
+    /// The operation was cancelled with a
+    /// [`CancellationToken`](crate::CancellationToken).
+    #[error("the operation was cancelled")]
+    Cancelled,
 }
 
 #[cfg(feature = "native-mux")]
@@ -207,18 +285,15 @@ mod tests {
         assert_eq!(e.kind(), expect.kind());
         assert_eq!(format!("{}", e), format!("{}", expect));
 
-        #[cfg(feature = "process-mux")]
-        {
-            let e = Error::Ssh(ioe());
-            assert!(!format!("{}", e).is_empty());
-            let e = e
-                .source()
-                .expect("source failed")
-                .downcast_ref::<io::Error>()
-                .expect("source not io");
-            assert_eq!(e.kind(), expect.kind());
-            assert_eq!(format!("{}", e), format!("{}", expect));
-        }
+        let e = Error::Ssh(ioe());
+        assert!(!format!("{}", e).is_empty());
+        let e = e
+            .source()
+            .expect("source failed")
+            .downcast_ref::<io::Error>()
+            .expect("source not io");
+        assert_eq!(e.kind(), expect.kind());
+        assert_eq!(format!("{}", e), format!("{}", expect));
 
         let e = Error::Remote(ioe());
         assert!(!format!("{}", e).is_empty());
@@ -50,6 +50,56 @@ pub(crate) fn escape(s: &OsStr) -> Cow<'_, OsStr> {
     OsString::from_vec(escaped).into()
 }
 
+/// Escape a glob pattern for a shell, keeping only `*`, `?` and simple
+/// bracket expressions such as `[0-9]` unquoted so that the shell expands them.
+pub(crate) fn escape_glob(pattern: &OsStr) -> OsString {
+    fn allowed_in_brackets(byte: u8) -> bool {
+        byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'!' | b'^' | b'_' | b'.')
+    }
+
+    let bytes = pattern.as_bytes();
+    let mut escaped = Vec::with_capacity(bytes.len() + 2);
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    fn push_literal(escaped: &mut Vec<u8>, literal: &[u8]) {
+        if !literal.is_empty() {
+            escaped.extend_from_slice(escape(OsStr::from_bytes(literal)).as_bytes());
+        }
+    }
+
+    while i < bytes.len() {
+        let unquoted_len = match bytes[i] {
+            b'*' | b'?' => 1,
+            b'[' => bytes[i + 1..]
+                .iter()
+                .position(|&b| b == b']')
+                .filter(|&len| {
+                    len > 0
+                        && bytes[i + 1..i + 1 + len]
+                            .iter()
+                            .copied()
+                            .all(allowed_in_brackets)
+                })
+                .map(|len| len + 2)
+                .unwrap_or(0),
+            _ => 0,
+        };
+
+        if unquoted_len == 0 {
+            i += 1;
+        } else {
+            push_literal(&mut escaped, &bytes[literal_start..i]);
+            escaped.extend_from_slice(&bytes[i..i + unquoted_len]);
+            i += unquoted_len;
+            literal_start = i;
+        }
+    }
+    push_literal(&mut escaped, &bytes[literal_start..]);
+
+    OsString::from_vec(escaped)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,4 +138,21 @@ mod tests {
             &[b'\'', 0x66, 0x6f, 0x80, 0x6f, b'\''],
         );
     }
+
+    fn test_escape_glob_case(input: &str, expected: &str) {
+        assert_eq!(escape_glob(OsStr::new(input)), OsStr::new(expected));
+    }
+
+    #[test]
+    fn test_escape_glob() {
+        test_escape_glob_case("logs/*.gz", "logs/*.gz");
+        test_escape_glob_case("my logs/*.gz", "'my logs/'*.gz");
+        test_escape_glob_case("log.[0-9]?", "log.[0-9]?");
+        test_escape_glob_case("[!a]*", "[!a]*");
+        test_escape_glob_case("$(reboot)*", "'$(reboot)'*");
+        test_escape_glob_case("a[;reboot]*", "'a[;reboot]'*");
+        test_escape_glob_case("a[]b", "'a[]b'");
+        test_escape_glob_case("a[", "'a['");
+        test_escape_glob_case("*", "*");
+    }
 }
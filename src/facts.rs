@@ -0,0 +1,82 @@
+use super::{Error, Session, Stdio};
+
+use std::io;
+
+/// Facts about a remote host, gathered and cached by
+/// [`SessionPool::facts`](crate::SessionPool::facts).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Facts {
+    /// The name of the operating system as printed by `uname -s`, e.g.
+    /// `Linux` or `Darwin`.
+    pub os: String,
+
+    /// The hardware architecture as printed by `uname -m`, e.g. `x86_64` or
+    /// `aarch64`.
+    pub arch: String,
+
+    /// The login shell of the user, e.g. `/bin/bash`, or an empty string if
+    /// it is not known.
+    pub shell: String,
+}
+
+impl Facts {
+    const PROBE: &'static str = "uname -s; uname -m; printf '%s\\n' \"$SHELL\"";
+
+    pub(crate) async fn probe(session: &Session) -> Result<Self, Error> {
+        let output = session
+            .raw_command(Self::PROBE)
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::Remote(io::Error::new(
+                io::ErrorKind::Other,
+                stderr.trim(),
+            )));
+        }
+
+        Self::parse(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+            Error::Remote(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected output when probing host facts",
+            ))
+        })
+    }
+
+    fn parse(output: &str) -> Option<Self> {
+        let mut lines = output.lines().map(str::trim);
+
+        let os = lines.next().filter(|os| !os.is_empty())?.to_owned();
+        let arch = lines.next().filter(|arch| !arch.is_empty())?.to_owned();
+        let shell = lines.next().unwrap_or("").to_owned();
+
+        Some(Self { os, arch, shell })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Facts::parse("Linux\nx86_64\n/bin/bash\n"),
+            Some(Facts {
+                os: "Linux".into(),
+                arch: "x86_64".into(),
+                shell: "/bin/bash".into(),
+            })
+        );
+        assert_eq!(
+            Facts::parse("Darwin\narm64\n\n").map(|facts| facts.shell),
+            Some(String::new())
+        );
+        assert_eq!(Facts::parse("Linux\n"), None);
+    }
+}
@@ -0,0 +1,219 @@
+//! gitignore-style include and exclude patterns for directory copies.
+
+/// A single pattern of a [`Filter`].
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Vec<u8>,
+    /// Whether a matching file is included rather than excluded.
+    include: bool,
+    /// Whether the pattern only matches directories, i.e. ended with `/`.
+    dir_only: bool,
+    /// Whether the pattern is matched against the whole path rather than
+    /// the file name, i.e. contained a `/` other than a trailing one.
+    anchored: bool,
+}
+
+impl Rule {
+    fn new(pattern: &str, include: bool) -> Self {
+        let mut pattern = pattern.as_bytes();
+
+        let dir_only = pattern.len() > 1 && pattern.ends_with(b"/");
+        if dir_only {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+
+        let anchored = pattern.contains(&b'/');
+        if let Some(rest) = pattern.strip_prefix(b"/") {
+            pattern = rest;
+        }
+
+        Self {
+            pattern: pattern.to_vec(),
+            include,
+            dir_only,
+            anchored,
+        }
+    }
+
+    fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            glob(&self.pattern, path)
+        } else {
+            let name = match path.iter().rposition(|&b| b == b'/') {
+                Some(n) => &path[n + 1..],
+                None => path,
+            };
+            glob(&self.pattern, name)
+        }
+    }
+}
+
+/// An ordered list of gitignore-style patterns; the last pattern matching a
+/// file decides whether it is excluded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    rules: Vec<Rule>,
+}
+
+impl Filter {
+    pub(crate) fn exclude(&mut self, pattern: &str) {
+        self.rules.push(Rule::new(pattern, false));
+    }
+
+    pub(crate) fn include(&mut self, pattern: &str) {
+        self.rules.push(Rule::new(pattern, true));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Return whether `path`, relative to the copied directory and without a
+    /// leading `./`, is excluded.
+    ///
+    /// Like with gitignore, a file in an excluded directory is excluded as
+    /// well, even if it matches an include pattern.
+    pub(crate) fn is_excluded(&self, path: &[u8], is_dir: bool) -> bool {
+        let parents = path
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'/')
+            .map(|(n, _)| (&path[..n], true));
+
+        parents
+            .chain(Some((path, is_dir)))
+            .any(|(path, is_dir)| self.excludes(path, is_dir))
+    }
+
+    fn excludes(&self, path: &[u8], is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .map_or(false, |rule| !rule.include)
+    }
+}
+
+/// Match `path` against the glob `pattern`, where `*` and `?` do not match
+/// `/`, `**/` matches any number of directories and a trailing `/**`
+/// matches everything below a directory.
+fn glob(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(n, &b)| b == b'/' && glob(rest, &path[n + 1..]))
+        }
+        [b'/', b'*', b'*'] => path.len() > 1 && path[0] == b'/',
+        [b'*', b'*'] => true,
+        [b'*', rest @ ..] => {
+            let segment = path.iter().position(|&b| b == b'/').unwrap_or(path.len());
+            (0..=segment).any(|n| glob(rest, &path[n..]))
+        }
+        [b'?', rest @ ..] => match path {
+            [b, path @ ..] if *b != b'/' => glob(rest, path),
+            _ => false,
+        },
+        [b'[', class @ ..] => match (class_len(class), path) {
+            (Some(len), [b, path @ ..]) => {
+                *b != b'/' && class_matches(&class[..len], *b) && glob(&class[len + 1..], path)
+            }
+            // An unterminated bracket is matched literally.
+            (None, [b'[', path @ ..]) => glob(class, path),
+            _ => false,
+        },
+        [b'\\', c, rest @ ..] | [c, rest @ ..] => match path {
+            [b, path @ ..] if b == c => glob(rest, path),
+            _ => false,
+        },
+    }
+}
+
+/// Return the length of the bracket expression `class` up to its closing `]`.
+fn class_len(class: &[u8]) -> Option<usize> {
+    // A `]` right after the opening bracket or its negation is literal.
+    let start = match class {
+        [b'!' | b'^', b']', ..] => 2,
+        [b'!' | b'^', ..] | [b']', ..] => 1,
+        _ => 0,
+    };
+    class[start..]
+        .iter()
+        .position(|&b| b == b']')
+        .map(|n| start + n)
+}
+
+fn class_matches(class: &[u8], b: u8) -> bool {
+    let (negated, mut class) = match class {
+        [b'!' | b'^', class @ ..] => (true, class),
+        class => (false, class),
+    };
+
+    let mut matched = false;
+    while let Some(&first) = class.first() {
+        match class {
+            [low, b'-', high, rest @ ..] => {
+                matched |= (*low..=*high).contains(&b);
+                class = rest;
+            }
+            [_, rest @ ..] => {
+                matched |= first == b;
+                class = rest;
+            }
+            [] => unreachable!(),
+        }
+    }
+    matched != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob(b"*.log", b"a.log"));
+        assert!(!glob(b"*.log", b"dir/a.log"));
+        assert!(glob(b"a?c", b"abc"));
+        assert!(!glob(b"a?c", b"a/c"));
+        assert!(glob(b"[a-c]x", b"bx"));
+        assert!(!glob(b"[!a-c]x", b"bx"));
+        assert!(glob(b"[]]", b"]"));
+        assert!(glob(b"[", b"["));
+        assert!(glob(b"\\*", b"*"));
+        assert!(!glob(b"\\*", b"a"));
+        assert!(glob(b"**/tmp", b"tmp"));
+        assert!(glob(b"**/tmp", b"a/b/tmp"));
+        assert!(glob(b"a/**/b", b"a/b"));
+        assert!(glob(b"a/**/b", b"a/x/y/b"));
+        assert!(glob(b"a/**", b"a/x/y"));
+        assert!(!glob(b"a/**", b"a"));
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut filter = Filter::default();
+        filter.exclude("node_modules");
+        filter.exclude("/target/");
+        filter.exclude("*.log");
+        filter.include("keep.log");
+
+        assert!(filter.is_excluded(b"node_modules", true));
+        assert!(filter.is_excluded(b"web/node_modules/react/index.js", false));
+        assert!(filter.is_excluded(b"target", true));
+        assert!(filter.is_excluded(b"target/debug/app", false));
+        assert!(!filter.is_excluded(b"target", false));
+        assert!(!filter.is_excluded(b"crates/x/target", true));
+        assert!(filter.is_excluded(b"logs/app.log", false));
+        assert!(!filter.is_excluded(b"logs/keep.log", false));
+        assert!(filter.is_excluded(b"node_modules/keep.log", false));
+        assert!(!filter.is_excluded(b"src/main.rs", false));
+    }
+}
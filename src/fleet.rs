@@ -0,0 +1,636 @@
+use super::{CommandTemplate, Error, FleetEvent, Host, SessionPool};
+
+use std::collections::HashMap;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::process::Output;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use futures_util::stream::{self, StreamExt};
+
+/// How [`SessionPool::run_rolling`] rolls a command out.
+#[derive(Debug, Clone)]
+pub struct Rollout {
+    batch_size: NonZeroUsize,
+    max_failure_rate: f64,
+    timeout: Duration,
+    retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Rollout {
+    /// Roll out to `batch_size` hosts at a time.
+    pub fn new(batch_size: NonZeroUsize) -> Self {
+        Self {
+            batch_size,
+            max_failure_rate: 0.0,
+            timeout: Duration::from_secs(60),
+            retries: 0,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Abort the rollout after a batch if the share of the hosts so far on
+    /// which the command failed exceeds `max_failure_rate`, between `0.0`
+    /// and `1.0`.
+    ///
+    /// The command fails on a host if it cannot be run there, or exits with
+    /// a non-zero status.
+    ///
+    /// Defaults to `0.0`, which aborts after the first batch with a failure.
+    pub fn max_failure_rate(&mut self, max_failure_rate: f64) -> &mut Self {
+        self.max_failure_rate = max_failure_rate;
+        self
+    }
+
+    /// Set the timeout per host, see [`SessionPool::run_on_all`].
+    ///
+    /// Defaults to 60 seconds.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry the command up to `retries` times on the hosts of a batch on
+    /// which it failed, before the batch counts towards the
+    /// [failure rate](Rollout::max_failure_rate).
+    ///
+    /// Each retry pass only runs on the hosts that failed in the previous
+    /// one, after a [backoff](Rollout::retry_backoff). Hosts on which the
+    /// command still fails are reported by [`RolloutReport::exhausted`].
+    ///
+    /// Defaults to `0`.
+    pub fn retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set the backoff before the first retry pass, which doubles with every
+    /// further pass up to `max`.
+    ///
+    /// Defaults to 1 second, up to 30 seconds.
+    pub fn retry_backoff(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Return the backoff before retry pass `retry`, starting at `1`.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry - 1))
+            .min(self.max_backoff)
+    }
+}
+
+/// The result of running a command on a host of a [`SessionPool`].
+///
+/// With the `serde` feature, this serializes to an object with the fields
+/// `success`, `status` (the exit code, or `null` if the command did not
+/// exit normally), `stdout` and `stderr` (decoded as lossy UTF-8), `error`
+/// (the message of the error, or `null`), `duration` (in seconds) and
+/// `attempts`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct HostOutput {
+    /// The captured output of the command.
+    pub output: Result<Output, Error>,
+
+    /// How long connecting to the host and running the command took.
+    pub duration: Duration,
+
+    /// How often the command was run on the host, including
+    /// [retries](Rollout::retries).
+    pub attempts: u32,
+}
+
+impl HostOutput {
+    /// Return whether the command ran and exited with a zero status.
+    pub fn succeeded(&self) -> bool {
+        matches!(&self.output, Ok(output) if output.status.success())
+    }
+}
+
+#[cfg(feature = "serde")]
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl serde::Serialize for HostOutput {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let (status, stdout, stderr, error) = match &self.output {
+            Ok(output) => (
+                output.status.code(),
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr),
+                None,
+            ),
+            Err(err) => (None, "".into(), "".into(), Some(err.to_string())),
+        };
+
+        let mut state = serializer.serialize_struct("HostOutput", 7)?;
+        state.serialize_field("success", &self.succeeded())?;
+        state.serialize_field("status", &status)?;
+        state.serialize_field("stdout", &stdout)?;
+        state.serialize_field("stderr", &stderr)?;
+        state.serialize_field("error", &error)?;
+        state.serialize_field("duration", &self.duration.as_secs_f64())?;
+        state.serialize_field("attempts", &self.attempts)?;
+        state.end()
+    }
+}
+
+/// The result of [`SessionPool::run_rolling`] and
+/// [`SessionPool::run_canary`].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct RolloutReport {
+    /// The output of each host the command ran on, by name.
+    pub outputs: HashMap<String, HostOutput>,
+
+    /// The hosts that were skipped because the rollout was aborted, in order.
+    pub skipped: Vec<String>,
+
+    /// The number of attempts allowed per host, see [`Rollout::retries`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    max_attempts: u32,
+}
+
+impl RolloutReport {
+    /// Return whether the rollout was aborted because too many hosts failed,
+    /// the canaries did not pass validation, or it was
+    /// [cancelled](SessionPool::with_cancellation).
+    pub fn aborted(&self) -> bool {
+        !self.skipped.is_empty()
+    }
+
+    /// Return the names of the hosts on which the command failed.
+    pub fn failed(&self) -> impl Iterator<Item = &str> {
+        self.outputs
+            .iter()
+            .filter(|(_, output)| !output.succeeded())
+            .map(|(destination, _)| destination.as_str())
+    }
+
+    /// Return the names of the hosts on which the command still failed after
+    /// all [retries](Rollout::retries).
+    pub fn exhausted(&self) -> impl Iterator<Item = &str> {
+        self.outputs
+            .iter()
+            .filter(move |(_, output)| {
+                !output.succeeded()
+                    && !matches!(output.output, Err(Error::Cancelled))
+                    && output.attempts >= self.max_attempts
+            })
+            .map(|(destination, _)| destination.as_str())
+    }
+}
+
+impl SessionPool {
+    /// Run `command` on every host in `hosts` concurrently, returning the
+    /// captured output of each host by its [name](Host::name).
+    ///
+    /// `hosts` may be destinations in the format of [`SessionPool::get`], or
+    /// [`Host`]s, e.g. of an [`Inventory`](crate::Inventory).
+    ///
+    /// `command` is passed to the remote shell as-is, like with
+    /// [`Session::raw_command`](crate::Session::raw_command), or may be a
+    /// [`CommandTemplate`] that is expanded for each host. Connecting to a
+    /// host and running the command there must finish within `timeout`,
+    /// otherwise the result for that host is [`Error::Timeout`] and its
+    /// remote process is killed, see
+    /// [`OwningCommand::kill_on_drop`](crate::OwningCommand::kill_on_drop).
+    /// The number of hosts the command runs on at the same time is bounded
+    /// by [`SessionPoolBuilder::max_open`](crate::SessionPoolBuilder::max_open).
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{SessionBuilder, SessionPoolBuilder};
+    /// use std::time::Duration;
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let hosts = ["web1.example.com", "web2.example.com"];
+    /// for (host, result) in pool.run_on_all(hosts, "uptime", Duration::from_secs(10)).await {
+    ///     match result.output {
+    ///         Ok(output) => println!("{}: {}", host, String::from_utf8_lossy(&output.stdout)),
+    ///         Err(err) => eprintln!("{}: {}", host, err),
+    ///     }
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn run_on_all<I, H, C>(
+        &self,
+        hosts: I,
+        command: C,
+        timeout: Duration,
+    ) -> HashMap<String, HostOutput>
+    where
+        I: IntoIterator<Item = H>,
+        H: Into<Host>,
+        C: Into<CommandTemplate>,
+    {
+        let command = &command.into();
+        let runs = hosts.into_iter().map(|host| async move {
+            let host = host.into();
+            let output = self.run_on(&host, command, timeout).await;
+            (host.name().to_owned(), output)
+        });
+
+        join_all(runs).await.into_iter().collect()
+    }
+
+    /// Run `command` on `host`, capturing its output.
+    pub(crate) async fn run_on(
+        &self,
+        host: &Host,
+        command: &CommandTemplate,
+        timeout: Duration,
+    ) -> HostOutput {
+        if self.is_cancelled() {
+            return HostOutput {
+                output: Err(Error::Cancelled),
+                duration: Duration::ZERO,
+                attempts: 1,
+            };
+        }
+
+        self.emit(FleetEvent::Started {
+            host: host.name().to_owned(),
+        });
+
+        let start = Instant::now();
+        let run = async {
+            let command = command.render(host)?;
+            let session = self.get_host(host).await?;
+            let output = session
+                .raw_command(command)
+                .kill_on_drop(true)
+                .output()
+                .await;
+            output
+        };
+
+        // Dropping the command on cancellation or timeout kills the remote
+        // process.
+        #[allow(clippy::incompatible_msrv)]
+        let output = tokio::select! {
+            output = tokio::time::timeout(timeout, run) => output.unwrap_or(Err(Error::Timeout)),
+            _ = self.cancelled() => Err(Error::Cancelled),
+        };
+        let output = HostOutput {
+            output,
+            duration: start.elapsed(),
+            attempts: 1,
+        };
+
+        self.emit(FleetEvent::Finished {
+            host: host.name().to_owned(),
+            success: output.succeeded(),
+            duration: output.duration,
+        });
+        output
+    }
+
+    /// Run `command` on `hosts` in batches, in order, aborting once too many
+    /// of them failed, like a rolling deployment.
+    ///
+    /// Each batch runs like [`run_on_all`](SessionPool::run_on_all), and the
+    /// next batch only starts once the previous one has finished.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{Rollout, SessionBuilder, SessionPoolBuilder};
+    /// use std::num::NonZeroUsize;
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let hosts = (1..=20).map(|n| format!("web{}.example.com", n));
+    /// let report = pool
+    ///     .run_rolling(
+    ///         hosts,
+    ///         "systemctl restart app",
+    ///         Rollout::new(NonZeroUsize::new(5).unwrap()).max_failure_rate(0.1),
+    ///     )
+    ///     .await;
+    ///
+    /// if report.aborted() {
+    ///     eprintln!("rollout aborted, failed on {:?}", report.failed().collect::<Vec<_>>());
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn run_rolling<I, H, C>(
+        &self,
+        hosts: I,
+        command: C,
+        rollout: &Rollout,
+    ) -> RolloutReport
+    where
+        I: IntoIterator<Item = H>,
+        H: Into<Host>,
+        C: Into<CommandTemplate>,
+    {
+        let hosts: Vec<Host> = hosts.into_iter().map(Into::into).collect();
+        let command = &command.into();
+
+        let mut outputs = HashMap::new();
+        let mut failures = 0;
+
+        let mut batches = hosts.chunks(rollout.batch_size.get());
+        for batch in batches.by_ref() {
+            let batch_outputs = self.run_with_retries(batch, command, rollout).await;
+
+            failures += batch_outputs
+                .values()
+                .filter(|output| !output.succeeded())
+                .count();
+            outputs.extend(batch_outputs);
+
+            if self.is_cancelled()
+                || failure_rate_exceeded(failures, outputs.len(), rollout.max_failure_rate)
+            {
+                break;
+            }
+        }
+
+        RolloutReport {
+            outputs,
+            skipped: batches
+                .flatten()
+                .map(|host| host.name().to_owned())
+                .collect(),
+            max_attempts: rollout.retries.saturating_add(1),
+        }
+    }
+
+    /// Run `command` on `hosts` like [`run_on_all`](SessionPool::run_on_all),
+    /// retrying it on the hosts on which it failed as configured by
+    /// `rollout`.
+    #[allow(clippy::incompatible_msrv)]
+    async fn run_with_retries(
+        &self,
+        hosts: &[Host],
+        command: &CommandTemplate,
+        rollout: &Rollout,
+    ) -> HashMap<String, HostOutput> {
+        let mut outputs = self.run_on_all(hosts, command, rollout.timeout).await;
+
+        for retry in 1..=rollout.retries {
+            let failed: Vec<&Host> = hosts
+                .iter()
+                .filter(|host| {
+                    let output = &outputs[host.name()];
+                    !output.succeeded() && !matches!(output.output, Err(Error::Cancelled))
+                })
+                .collect();
+            if failed.is_empty() {
+                break;
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(rollout.backoff(retry)) => (),
+                _ = self.cancelled() => break,
+            }
+
+            for (host, mut output) in self.run_on_all(failed, command, rollout.timeout).await {
+                output.attempts = retry + 1;
+                outputs.insert(host, output);
+            }
+        }
+
+        outputs
+    }
+
+    /// Run `command` on the `canaries` first, and only roll it out to
+    /// `hosts` like [`run_rolling`](SessionPool::run_rolling) if `validate`
+    /// accepts the outputs of the canaries.
+    ///
+    /// The canaries run all at once, with the timeout and retries of
+    /// `rollout`. If
+    /// `validate` returns `false`, all of `hosts` are
+    /// [skipped](RolloutReport::skipped). The outputs of the canaries are
+    /// part of the returned report either way.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{Rollout, SessionBuilder, SessionPoolBuilder};
+    /// use std::num::NonZeroUsize;
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let hosts = (2..=20).map(|n| format!("web{}.example.com", n));
+    /// let report = pool
+    ///     .run_canary(
+    ///         ["web1.example.com"],
+    ///         hosts,
+    ///         "deploy-app && curl -fs localhost/health",
+    ///         &Rollout::new(NonZeroUsize::new(5).unwrap()),
+    ///         |canaries| canaries.values().all(|output| output.succeeded()),
+    ///     )
+    ///     .await;
+    /// # Ok(()) }
+    /// ```
+    pub async fn run_canary<I, H, J, K, C, F>(
+        &self,
+        canaries: I,
+        hosts: J,
+        command: C,
+        rollout: &Rollout,
+        validate: F,
+    ) -> RolloutReport
+    where
+        I: IntoIterator<Item = H>,
+        H: Into<Host>,
+        J: IntoIterator<Item = K>,
+        K: Into<Host>,
+        C: Into<CommandTemplate>,
+        F: FnOnce(&HashMap<String, HostOutput>) -> bool,
+    {
+        let command = &command.into();
+        let canaries: Vec<Host> = canaries.into_iter().map(Into::into).collect();
+        let canary_outputs = self.run_with_retries(&canaries, command, rollout).await;
+
+        let mut report = if !self.is_cancelled() && validate(&canary_outputs) {
+            self.run_rolling(hosts, command, rollout).await
+        } else {
+            RolloutReport {
+                outputs: HashMap::new(),
+                skipped: hosts
+                    .into_iter()
+                    .map(|host| host.into().name().to_owned())
+                    .collect(),
+                max_attempts: rollout.retries.saturating_add(1),
+            }
+        };
+        report.outputs.extend(canary_outputs);
+
+        report
+    }
+
+    /// Copy the local file at `local` to `remote_path` on every host in
+    /// `hosts`, uploading to at most `concurrency` hosts at a time.
+    ///
+    /// The file is written to a temporary file next to `remote_path` first,
+    /// which only replaces `remote_path` once its `cksum` has been verified
+    /// on the host. The result of each host is returned by its
+    /// [name](Host::name), and reading `local` fails before anything is
+    /// uploaded.
+    ///
+    /// `local` is read into memory once, so this is meant for configuration
+    /// files and small binaries rather than large files.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use openssh::{SessionBuilder, SessionPoolBuilder};
+    /// use std::num::NonZeroUsize;
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let hosts = ["web1.example.com", "web2.example.com"];
+    /// let results = pool
+    ///     .push_file(hosts, "nginx.conf", "/etc/nginx/nginx.conf", NonZeroUsize::new(8).unwrap())
+    ///     .await?;
+    /// for (host, result) in results {
+    ///     if let Err(err) = result {
+    ///         eprintln!("{}: {}", host, err);
+    ///     }
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn push_file<I, H>(
+        &self,
+        hosts: I,
+        local: impl AsRef<Path>,
+        remote_path: impl AsRef<Path>,
+        concurrency: NonZeroUsize,
+    ) -> io::Result<HashMap<String, Result<(), Error>>>
+    where
+        I: IntoIterator<Item = H>,
+        H: Into<Host>,
+    {
+        let local = local.as_ref().to_owned();
+        let data = tokio::task::spawn_blocking(move || std::fs::read(local))
+            .await
+            .expect("reading the file does not panic")?;
+        let (data, remote_path) = (&data, remote_path.as_ref());
+
+        let pushes = hosts.into_iter().map(|host| async move {
+            let host: Host = host.into();
+            let name = host.name().to_owned();
+            if self.is_cancelled() {
+                return (name, Err(Error::Cancelled));
+            }
+            self.emit(FleetEvent::Started { host: name.clone() });
+
+            let start = Instant::now();
+            let pushed = match self.get_host(&host).await {
+                Ok(session) => crate::transfer::push(&session, data, remote_path).await,
+                Err(err) => Err(err),
+            };
+
+            if pushed.is_ok() {
+                self.emit(FleetEvent::Transferred {
+                    host: name.clone(),
+                    bytes: data.len() as u64,
+                });
+            }
+            self.emit(FleetEvent::Finished {
+                host: name.clone(),
+                success: pushed.is_ok(),
+                duration: start.elapsed(),
+            });
+            (name, pushed)
+        });
+
+        Ok(stream::iter(pushes)
+            .buffer_unordered(concurrency.get())
+            .collect()
+            .await)
+    }
+}
+
+fn failure_rate_exceeded(failures: usize, hosts: usize, max_failure_rate: f64) -> bool {
+    hosts > 0 && failures as f64 / hosts as f64 > max_failure_rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_rate_exceeded() {
+        assert!(!failure_rate_exceeded(0, 0, 0.0));
+        assert!(!failure_rate_exceeded(0, 5, 0.0));
+        assert!(failure_rate_exceeded(1, 5, 0.0));
+        assert!(!failure_rate_exceeded(1, 10, 0.1));
+        assert!(failure_rate_exceeded(2, 10, 0.1));
+        assert!(!failure_rate_exceeded(10, 10, 1.0));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut rollout = Rollout::new(NonZeroUsize::new(1).unwrap());
+        rollout.retry_backoff(Duration::from_secs(1), Duration::from_secs(5));
+
+        let backoffs: Vec<_> = (1..=5)
+            .map(|retry| rollout.backoff(retry).as_secs())
+            .collect();
+        assert_eq!(backoffs, [1, 2, 4, 5, 5]);
+        assert_eq!(rollout.backoff(100), Duration::from_secs(5));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_host_output() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+
+        let output = HostOutput {
+            output: Ok(Output {
+                status: ExitStatus::from_raw(1 << 8),
+                stdout: b"out\n".to_vec(),
+                stderr: b"err\xff".to_vec(),
+            }),
+            duration: Duration::from_millis(1500),
+            attempts: 1,
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "success": false,
+                "status": 1,
+                "stdout": "out\n",
+                "stderr": "err\u{fffd}",
+                "error": null,
+                "duration": 1.5,
+                "attempts": 1,
+            })
+        );
+
+        let output = HostOutput {
+            output: Err(Error::Timeout),
+            duration: Duration::from_secs(10),
+            attempts: 3,
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({
+                "success": false,
+                "status": null,
+                "stdout": "",
+                "stderr": "",
+                "error": "the operation timed out",
+                "duration": 10.0,
+                "attempts": 3,
+            })
+        );
+    }
+}
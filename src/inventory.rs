@@ -0,0 +1,331 @@
+use super::{Selector, SessionBuilder};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A host of an [`Inventory`], with its connection settings and labels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Host {
+    name: String,
+    user: Option<String>,
+    port: Option<u16>,
+    jump_hosts: Vec<String>,
+    labels: BTreeMap<String, String>,
+}
+
+impl Host {
+    /// Create a host that is connected to at `name`, which has the same
+    /// format as the `destination` argument of
+    /// [`SessionBuilder::connect`].
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            user: None,
+            port: None,
+            jump_hosts: Vec::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Return the name of the host.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the user to log in as, see [`SessionBuilder::user`].
+    pub fn user(&mut self, user: impl Into<String>) -> &mut Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Set the port to connect to, see [`SessionBuilder::port`].
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Set the jump hosts to connect through, see [`SessionBuilder::jump_hosts`].
+    pub fn jump_hosts<T: AsRef<str>>(&mut self, hosts: impl IntoIterator<Item = T>) -> &mut Self {
+        self.jump_hosts = hosts.into_iter().map(|s| s.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Set the label `key` to `value`, e.g. `role=web`.
+    pub fn label(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Return the value of the label `key`.
+    pub fn get_label(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(String::as_str)
+    }
+
+    /// Return all labels of the host.
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    /// Return a copy of `builder` with the connection settings of the host
+    /// applied, to connect to [`name`](Host::name).
+    pub fn session_builder(&self, builder: &SessionBuilder) -> SessionBuilder {
+        let mut builder = builder.clone();
+
+        if let Some(user) = &self.user {
+            builder.user(user.clone());
+        }
+        if let Some(port) = self.port {
+            builder.port(port);
+        }
+        if !self.jump_hosts.is_empty() {
+            builder.jump_hosts(&self.jump_hosts);
+        }
+
+        builder
+    }
+
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+
+        let mut host = Host::new(words.next().expect("line is not empty"));
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or_else(|| format!("expected `key=value`, found `{}`", word))?;
+
+            match key {
+                "user" => {
+                    host.user(value);
+                }
+                "port" => {
+                    let port = value
+                        .parse()
+                        .map_err(|_| format!("invalid port `{}`", value))?;
+                    host.port(port);
+                }
+                "jump" => {
+                    host.jump_hosts(value.split(','));
+                }
+                _ => {
+                    host.label(key, value);
+                }
+            }
+        }
+
+        Ok(host)
+    }
+}
+
+impl From<&str> for Host {
+    fn from(name: &str) -> Self {
+        Host::new(name)
+    }
+}
+
+impl From<String> for Host {
+    fn from(name: String) -> Self {
+        Host::new(name)
+    }
+}
+
+impl From<&Host> for Host {
+    fn from(host: &Host) -> Self {
+        host.clone()
+    }
+}
+
+/// A list of hosts to orchestrate, e.g. with a
+/// [`SessionPool`](crate::SessionPool).
+///
+/// An inventory can be built with [`add`](Inventory::add), or loaded from a
+/// file with one host per line: the name of the host, followed by
+/// `key=value` pairs separated by whitespace. The keys `user`, `port` and
+/// `jump` (a comma-separated list of jump hosts) set the connection settings
+/// of the host, all other keys are labels. Empty lines and lines starting
+/// with `#` are ignored.
+///
+/// ```text
+/// # web servers
+/// web1.example.com user=deploy role=web region=eu
+/// web2.example.com user=deploy port=2222 role=web region=us
+/// db1.internal jump=bastion.example.com role=db region=eu
+/// ```
+///
+/// Hosts that are all reached through the same bastion do not need to
+/// declare it each, see [`Inventory::jump_hosts`].
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    hosts: Vec<Host>,
+    jump_hosts: Vec<String>,
+}
+
+impl Inventory {
+    /// Create an empty inventory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse an inventory in the format described above.
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let mut inventory = Self::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let host = Host::parse(line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", n + 1, err),
+                )
+            })?;
+            inventory.add(host);
+        }
+
+        Ok(inventory)
+    }
+
+    /// Read and parse the inventory file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Add `host` to the inventory.
+    pub fn add(&mut self, mut host: Host) -> &mut Self {
+        if host.jump_hosts.is_empty() {
+            host.jump_hosts = self.jump_hosts.clone();
+        }
+        self.hosts.push(host);
+        self
+    }
+
+    /// Set the jump hosts shared by the hosts of the inventory, e.g. a
+    /// bastion in front of a private network.
+    ///
+    /// They apply to all hosts without jump hosts of their own, including
+    /// those already in the inventory, so that every [`SessionBuilder`] made
+    /// for them connects through the bastion.
+    ///
+    /// ```rust
+    /// use openssh::Inventory;
+    ///
+    /// let mut inventory = Inventory::parse("db1.internal\ndb2.internal port=2222\n")?;
+    /// inventory.jump_hosts(["deploy@bastion.example.com"]);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn jump_hosts<T: AsRef<str>>(&mut self, hosts: impl IntoIterator<Item = T>) -> &mut Self {
+        let jump_hosts: Vec<String> = hosts.into_iter().map(|s| s.as_ref().to_owned()).collect();
+
+        for host in &mut self.hosts {
+            if host.jump_hosts.is_empty() || host.jump_hosts == self.jump_hosts {
+                host.jump_hosts = jump_hosts.clone();
+            }
+        }
+        self.jump_hosts = jump_hosts;
+
+        self
+    }
+
+    /// Return the host named `name`.
+    pub fn get(&self, name: &str) -> Option<&Host> {
+        self.hosts.iter().find(|host| host.name == name)
+    }
+
+    /// Iterate over the hosts in the order they were added.
+    pub fn hosts(&self) -> impl Iterator<Item = &Host> {
+        self.hosts.iter()
+    }
+
+    /// Iterate over the hosts that `selector` matches, in the order they were
+    /// added.
+    ///
+    /// The hosts can be passed straight to e.g.
+    /// [`SessionPool::run_on_all`](crate::SessionPool::run_on_all) to run a
+    /// command on a subset of the inventory.
+    pub fn select<'a>(&'a self, selector: &'a Selector) -> impl Iterator<Item = &'a Host> + 'a {
+        self.hosts.iter().filter(move |host| selector.matches(host))
+    }
+
+    /// Return a [`SessionBuilder`] for every host, based on `builder`.
+    pub fn session_builders<'a>(
+        &'a self,
+        builder: &'a SessionBuilder,
+    ) -> impl Iterator<Item = (&'a Host, SessionBuilder)> + 'a {
+        self.hosts
+            .iter()
+            .map(move |host| (host, host.session_builder(builder)))
+    }
+}
+
+impl<'a> IntoIterator for &'a Inventory {
+    type Item = &'a Host;
+    type IntoIter = std::slice::Iter<'a, Host>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.hosts.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let inventory = Inventory::parse(
+            "# web servers\n\
+             web1.example.com user=deploy role=web region=eu\n\
+             \n\
+             db1.internal port=2222 jump=bastion1,bastion2 role=db\n",
+        )
+        .unwrap();
+
+        let hosts: Vec<_> = inventory.hosts().collect();
+        assert_eq!(hosts.len(), 2);
+
+        let mut web1 = Host::new("web1.example.com");
+        web1.user("deploy")
+            .label("role", "web")
+            .label("region", "eu");
+        assert_eq!(hosts[0], &web1);
+
+        let db1 = inventory.get("db1.internal").unwrap();
+        assert_eq!(db1.port, Some(2222));
+        assert_eq!(db1.jump_hosts, ["bastion1", "bastion2"]);
+        assert_eq!(db1.get_label("role"), Some("db"));
+        assert_eq!(db1.get_label("region"), None);
+
+        let builder = db1.session_builder(&SessionBuilder::default());
+        assert_eq!(builder.get_port(), Some("2222"));
+    }
+
+    #[test]
+    fn jump_hosts() {
+        let mut inventory = Inventory::parse("web1\ndb1 jump=db-bastion\n").unwrap();
+        inventory.jump_hosts(["old-bastion"]);
+        inventory.jump_hosts(["bastion"]);
+        inventory.add(Host::new("web2"));
+
+        let jump_hosts = |name| inventory.get(name).unwrap().jump_hosts.clone();
+        assert_eq!(jump_hosts("web1"), ["bastion"]);
+        assert_eq!(jump_hosts("web2"), ["bastion"]);
+        assert_eq!(jump_hosts("db1"), ["db-bastion"]);
+    }
+
+    #[test]
+    fn parse_error() {
+        let err = Inventory::parse("web1\nweb2 role\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "line 2: expected `key=value`, found `role`"
+        );
+
+        let err = Inventory::parse("web1 port=ssh").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: invalid port `ssh`");
+    }
+}
@@ -0,0 +1,233 @@
+//! Generating and inspecting keys with `ssh-keygen`.
+//!
+//! ```rust,no_run
+//! # #[tokio::main]
+//! # async fn main() -> std::io::Result<()> {
+//! use openssh::keygen::{self, KeyGenerator, KeyType};
+//!
+//! KeyGenerator::new(KeyType::Ed25519)
+//!     .comment("deploy@example.com")
+//!     .generate("deploy-key")
+//!     .await?;
+//!
+//! println!("{}", keygen::fingerprint("deploy-key.pub").await?);
+//! # Ok(()) }
+//! ```
+
+use super::agent::{check, Identity};
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+
+use tokio::process;
+
+/// The type of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum KeyType {
+    /// An Ed25519 key (`ssh-keygen -t ed25519`).
+    Ed25519,
+    /// An ECDSA key (`ssh-keygen -t ecdsa`).
+    Ecdsa,
+    /// An RSA key (`ssh-keygen -t rsa`).
+    Rsa,
+}
+
+impl KeyType {
+    fn as_str(self) -> &'static str {
+        match self {
+            KeyType::Ed25519 => "ed25519",
+            KeyType::Ecdsa => "ecdsa",
+            KeyType::Rsa => "rsa",
+        }
+    }
+}
+
+/// A format other than OpenSSH's own that public keys can be converted to
+/// and from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum KeyFormat {
+    /// An RFC 4716 (SSH2) public key (`ssh-keygen -m RFC4716`).
+    Rfc4716,
+    /// A PKCS#8 public key (`ssh-keygen -m PKCS8`), not supported for
+    /// Ed25519 keys.
+    Pkcs8,
+    /// A PEM (PKCS#1) public key (`ssh-keygen -m PEM`), only supported for
+    /// RSA keys.
+    Pem,
+}
+
+impl KeyFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            KeyFormat::Rfc4716 => "RFC4716",
+            KeyFormat::Pkcs8 => "PKCS8",
+            KeyFormat::Pem => "PEM",
+        }
+    }
+}
+
+/// Generate keypairs with options (`ssh-keygen -t`).
+#[derive(Debug, Clone)]
+pub struct KeyGenerator {
+    key_type: KeyType,
+    bits: Option<u32>,
+    comment: Option<String>,
+}
+
+impl KeyGenerator {
+    /// Create a generator for keys of type `key_type`.
+    pub fn new(key_type: KeyType) -> Self {
+        Self {
+            key_type,
+            bits: None,
+            comment: None,
+        }
+    }
+
+    /// Set the size of the keys in bits (`ssh-keygen -b`).
+    ///
+    /// Defaults to the default of `ssh-keygen` for the key type.
+    pub fn bits(&mut self, bits: u32) -> &mut Self {
+        self.bits = Some(bits);
+        self
+    }
+
+    /// Set the comment of the keys (`ssh-keygen -C`).
+    ///
+    /// Defaults to `user@host` of the current user.
+    pub fn comment(&mut self, comment: impl Into<String>) -> &mut Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Generate a keypair, writing the private key without a passphrase to
+    /// `path` and the public key to `path` with `.pub` appended.
+    ///
+    /// Fails if `path` already exists.
+    pub async fn generate(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut cmd = ssh_keygen();
+        cmd.arg("-q")
+            .arg("-t")
+            .arg(self.key_type.as_str())
+            .arg("-N")
+            .arg("");
+
+        if let Some(bits) = self.bits {
+            cmd.arg("-b").arg(bits.to_string());
+        }
+        if let Some(comment) = &self.comment {
+            cmd.arg("-C").arg(comment);
+        }
+
+        cmd.arg("-f")
+            .arg(path.as_ref())
+            .output()
+            .await
+            .and_then(check)?;
+        Ok(())
+    }
+}
+
+/// Return the fingerprint of the key in `path`, e.g. `SHA256:...`
+/// (`ssh-keygen -l`).
+///
+/// `path` may contain either a public key or an unencrypted private key.
+pub async fn fingerprint(path: impl AsRef<Path>) -> io::Result<String> {
+    let stdout = run(&["-l".as_ref(), "-f".as_ref(), path.as_ref().as_os_str()]).await?;
+
+    Identity::parse(&stdout)
+        .map(|identity| identity.fingerprint)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected output of ssh-keygen: {}", stdout.trim()),
+            )
+        })
+}
+
+/// Return the public key of the unencrypted private key in `path` in
+/// OpenSSH format (`ssh-keygen -y`).
+pub async fn public_key(path: impl AsRef<Path>) -> io::Result<String> {
+    run(&["-y".as_ref(), "-f".as_ref(), path.as_ref().as_os_str()]).await
+}
+
+/// Convert the public key in `path` from OpenSSH format to `format`
+/// (`ssh-keygen -e`).
+pub async fn export_public_key(path: impl AsRef<Path>, format: KeyFormat) -> io::Result<String> {
+    run(&[
+        "-e".as_ref(),
+        "-m".as_ref(),
+        format.as_str().as_ref(),
+        "-f".as_ref(),
+        path.as_ref().as_os_str(),
+    ])
+    .await
+}
+
+/// Convert the public key in `path` from `format` to OpenSSH format
+/// (`ssh-keygen -i`).
+pub async fn import_public_key(path: impl AsRef<Path>, format: KeyFormat) -> io::Result<String> {
+    run(&[
+        "-i".as_ref(),
+        "-m".as_ref(),
+        format.as_str().as_ref(),
+        "-f".as_ref(),
+        path.as_ref().as_os_str(),
+    ])
+    .await
+}
+
+fn ssh_keygen() -> process::Command {
+    let mut cmd = process::Command::new("ssh-keygen");
+    cmd.stdin(Stdio::null());
+    cmd
+}
+
+async fn run(args: &[&OsStr]) -> io::Result<String> {
+    let stdout = ssh_keygen().args(args).output().await.and_then(check)?;
+    String::from_utf8(stdout).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keygen() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key");
+        let public = dir.path().join("key.pub");
+
+        let mut generator = KeyGenerator::new(KeyType::Ed25519);
+        generator.comment("test@example.com");
+        generator.generate(&key).await.unwrap();
+        generator.generate(&key).await.unwrap_err();
+
+        let fingerprint = fingerprint(&key).await.unwrap();
+        assert!(fingerprint.starts_with("SHA256:"), "{}", fingerprint);
+        assert_eq!(super::fingerprint(&public).await.unwrap(), fingerprint);
+
+        let public_key = public_key(&key).await.unwrap();
+        assert!(public_key.starts_with("ssh-ed25519 "), "{}", public_key);
+        assert_eq!(std::fs::read_to_string(&public).unwrap(), public_key);
+
+        let rfc4716 = dir.path().join("key.rfc4716");
+        let exported = export_public_key(&public, KeyFormat::Rfc4716)
+            .await
+            .unwrap();
+        assert!(exported.starts_with("---- BEGIN SSH2 PUBLIC KEY ----"));
+        std::fs::write(&rfc4716, exported).unwrap();
+        // The comment is not preserved.
+        let imported = import_public_key(&rfc4716, KeyFormat::Rfc4716)
+            .await
+            .unwrap();
+        assert_eq!(
+            format!("{} test@example.com\n", imported.trim_end()),
+            public_key
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Checking host keys against a pinned fingerprint with a generated
+//! `KnownHostsCommand`, and watching the log of the master, e.g. for host
+//! keys it updated.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The name of the script created by [`pin`].
+const PIN_SCRIPT: &str = "known-hosts-command";
+
+/// Create a script in `dir` that prints a known_hosts entry for the host key
+/// it is invoked with if the key has the fingerprint `fingerprint`, see
+/// [`pin_options`].
+pub(crate) fn pin(dir: &Path, fingerprint: &str) -> io::Result<()> {
+    // Invoked as `known-hosts-command %H %f %t %K`.
+    let script = format!(
+        "#!/bin/sh\n[ \"$2\" = {} ] || exit 0\nprintf '%s %s %s\\n' \"$1\" \"$3\" \"$4\"\n",
+        shell_escape::unix::escape(fingerprint.into()),
+    );
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(dir.join(PIN_SCRIPT))?
+        .write_all(script.as_bytes())
+}
+
+/// Return the ssh options making ssh only trust keys printed by the script
+/// created by [`pin`] in `dir`.
+pub(crate) fn pin_options(dir: &Path, fingerprint: &str) -> Vec<OsString> {
+    let path = dir.join(PIN_SCRIPT);
+
+    let mut command = b"KnownHostsCommand=".to_vec();
+    for &b in crate::escape::escape(path.as_os_str()).as_bytes() {
+        // ssh expands `%` tokens in the KnownHostsCommand.
+        if b == b'%' {
+            command.push(b'%');
+        }
+        command.push(b);
+    }
+    command.extend_from_slice(b" %H %f %t %K");
+
+    let hash = if fingerprint.starts_with("MD5:") {
+        "md5"
+    } else {
+        "sha256"
+    };
+
+    vec![
+        "StrictHostKeyChecking=yes".into(),
+        "UserKnownHostsFile=/dev/null".into(),
+        "GlobalKnownHostsFile=/dev/null".into(),
+        "UpdateHostKeys=no".into(),
+        format!("FingerprintHash={}", hash).into(),
+        OsString::from_vec(command),
+    ]
+}
+
+/// A change to the known host keys of the remote host made by ssh when the
+/// server rotates its keys, see
+/// [`SessionBuilder::on_host_key_update`](crate::SessionBuilder::on_host_key_update).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HostKeyUpdate {
+    /// A new host key offered by the server was added to the known_hosts
+    /// file.
+    Learned {
+        /// The type of the key, e.g. `ED25519`.
+        key_type: String,
+        /// The fingerprint of the key, e.g. `SHA256:...`.
+        fingerprint: String,
+    },
+
+    /// A host key no longer offered by the server was removed from the
+    /// known_hosts file.
+    Deprecated {
+        /// The type of the key, e.g. `ED25519`.
+        key_type: String,
+        /// The fingerprint of the key, e.g. `SHA256:...`.
+        fingerprint: String,
+    },
+}
+
+#[derive(Clone)]
+pub(crate) struct HostKeyCallback(pub(crate) Arc<dyn Fn(HostKeyUpdate) + Send + Sync>);
+
+impl fmt::Debug for HostKeyCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HostKeyCallback(..)")
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LogCallback(pub(crate) Arc<dyn Fn(&str) + Send + Sync>);
+
+impl fmt::Debug for LogCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LogCallback(..)")
+    }
+}
+
+/// How often the log of the master is checked for new lines.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Call `callback` for the host keys updated by the master logging to `log`
+/// at `LogLevel=VERBOSE`, until the log is removed with the session.
+pub(crate) fn watch_updates(log: PathBuf, callback: HostKeyCallback) {
+    let mut parser = UpdateParser::default();
+    watch_log(log, 0, move |line| {
+        if let Some(update) = parser.parse(line) {
+            (callback.0)(update);
+        }
+    });
+}
+
+/// Call `on_line` for every complete line of the master `log` past `offset`,
+/// until the log is removed with the session.
+pub(crate) fn watch_log(
+    log: PathBuf,
+    mut offset: usize,
+    mut on_line: impl FnMut(&str) + Send + 'static,
+) {
+    tokio::spawn(async move {
+        loop {
+            let log = log.clone();
+            let contents = match tokio::task::spawn_blocking(move || fs::read(log)).await {
+                Ok(Ok(contents)) => contents,
+                _ => break,
+            };
+
+            // Only consume complete lines.
+            let end = match contents.iter().rposition(|&b| b == b'\n') {
+                Some(end) if end >= offset => end + 1,
+                _ => offset,
+            };
+            for line in String::from_utf8_lossy(&contents[offset..end]).lines() {
+                on_line(line);
+            }
+            offset = end;
+
+            tokio::time::sleep(WATCH_INTERVAL).await;
+        }
+    });
+}
+
+/// Parses the host keys updated by ssh from lines such as:
+///
+/// ```text
+/// Learned new hostkey: ED25519 SHA256:...
+/// RSA SHA256:...
+/// Deprecating obsolete hostkey: ECDSA SHA256:...
+/// ```
+#[derive(Debug, Default)]
+struct UpdateParser {
+    /// Whether the previous line listed learned (`Some(true)`) or deprecated
+    /// (`Some(false)`) keys.
+    learned: Option<bool>,
+}
+
+impl UpdateParser {
+    fn parse(&mut self, line: &str) -> Option<HostKeyUpdate> {
+        // Interleaved at `LogLevel=DEBUG1`.
+        if line.starts_with("debug") {
+            return None;
+        }
+        let line = line.trim();
+        let key = if let Some(key) = line.strip_prefix("Learned new hostkey: ") {
+            self.learned = Some(true);
+            key
+        } else if let Some(key) = line.strip_prefix("Deprecating obsolete hostkey: ") {
+            self.learned = Some(false);
+            key
+        } else {
+            line
+        };
+
+        let learned = self.learned?;
+        let parsed = key.split_once(' ').filter(|(key_type, fingerprint)| {
+            !key_type.is_empty()
+                && (fingerprint.starts_with("SHA256:") || fingerprint.starts_with("MD5:"))
+        });
+        let (key_type, fingerprint) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                self.learned = None;
+                return None;
+            }
+        };
+
+        let (key_type, fingerprint) = (key_type.to_owned(), fingerprint.to_owned());
+        Some(if learned {
+            HostKeyUpdate::Learned {
+                key_type,
+                fingerprint,
+            }
+        } else {
+            HostKeyUpdate::Deprecated {
+                key_type,
+                fingerprint,
+            }
+        })
+    }
+}
+
+/// Return the version the server announced from the `log` of a master at
+/// `LogLevel=DEBUG1`.
+pub(crate) fn server_version(log: &str) -> Option<&str> {
+    // e.g. "debug1: Remote protocol version 2.0, remote software version OpenSSH_9.0"
+    log.lines()
+        .find_map(|line| line.split_once(", remote software version "))
+        .map(|(_, version)| version.trim())
+        .filter(|version| !version.is_empty())
+}
+
+/// Remove the lines logged at `LogLevel=VERBOSE` or any of the debug levels
+/// that precede errors from the `log` of a master that failed to connect.
+pub(crate) fn strip_verbose(log: &str) -> String {
+    log.lines()
+        .filter(|line| {
+            ![
+                "Authenticated to ",
+                "Server accepts key: ",
+                "Authentication succeeded",
+                "debug1: ",
+                "debug2: ",
+                "debug3: ",
+                // The version ssh prints first with `-v`.
+                "OpenSSH_",
+            ]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pin() {
+        let dir = tempfile::tempdir().unwrap();
+        super::pin(dir.path(), "SHA256:abc'd").unwrap();
+        let options = pin_options(dir.path(), "SHA256:abc'd");
+        assert_eq!(options[4], "FingerprintHash=sha256");
+
+        let script = dir.path().join(PIN_SCRIPT);
+        let run = |fingerprint: &str| {
+            let output = std::process::Command::new(&script)
+                .args(["[localhost]:2222", fingerprint, "ssh-ed25519", "AAAA"])
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(run("SHA256:abc'd"), "[localhost]:2222 ssh-ed25519 AAAA\n");
+        assert_eq!(run("SHA256:abc"), "");
+
+        let options = pin_options(dir.path(), "MD5:00:11");
+        assert_eq!(options[4], "FingerprintHash=md5");
+    }
+
+    #[test]
+    fn parse_updates() {
+        let mut parser = UpdateParser::default();
+        let updates: Vec<_> = [
+            "Authenticated to 127.0.0.1 ([127.0.0.1]:2222) using \"publickey\".",
+            "ED25519 SHA256:ignored",
+            "Learned new hostkey: ED25519 SHA256:new1",
+            "debug1: client_input_hostkeys: searching known_hosts",
+            "RSA SHA256:new2",
+            "Deprecating obsolete hostkey: ECDSA SHA256:old",
+            "Session started",
+            "DSA SHA256:ignored",
+        ]
+        .into_iter()
+        .filter_map(|line| parser.parse(line))
+        .collect();
+
+        let learned = |key_type: &str, fingerprint: &str| HostKeyUpdate::Learned {
+            key_type: key_type.into(),
+            fingerprint: fingerprint.into(),
+        };
+        assert_eq!(
+            updates,
+            [
+                learned("ED25519", "SHA256:new1"),
+                learned("RSA", "SHA256:new2"),
+                HostKeyUpdate::Deprecated {
+                    key_type: "ECDSA".into(),
+                    fingerprint: "SHA256:old".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn server_version() {
+        let log = "debug1: Local version string SSH-2.0-OpenSSH_9.0\n\
+                   debug1: Remote protocol version 2.0, remote software version OpenSSH_8.9p1 Ubuntu-3\n";
+        assert_eq!(super::server_version(log), Some("OpenSSH_8.9p1 Ubuntu-3"));
+        assert_eq!(super::server_version("Authenticated to host\n"), None);
+    }
+
+    #[test]
+    fn strip_verbose() {
+        let log = "OpenSSH_9.2p1 Debian-2+deb12u6, OpenSSL 3.0.19 27 Jan 2026\n\
+                   Server accepts key: /home/me/.ssh/id_ed25519 ED25519 SHA256:x\n\
+                   debug1: Authentications that can continue: publickey\n\
+                   debug3: send packet: type 50\n\
+                   me@example.com: Permission denied (publickey).\n";
+        assert_eq!(
+            super::strip_verbose(log),
+            "me@example.com: Permission denied (publickey).\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        fs::write(&log, "skipped\nfirst\nsec").unwrap();
+
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let lines_ = lines.clone();
+        super::watch_log(log.clone(), "skipped\n".len(), move |line| {
+            lines_.lock().unwrap().push(line.to_owned());
+        });
+
+        tokio::time::sleep(WATCH_INTERVAL / 2).await;
+        assert_eq!(*lines.lock().unwrap(), ["first"]);
+
+        // Only complete lines are passed on.
+        OpenOptions::new()
+            .append(true)
+            .open(&log)
+            .unwrap()
+            .write_all(b"ond\nthird\n")
+            .unwrap();
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        assert_eq!(*lines.lock().unwrap(), ["first", "second", "third"]);
+    }
+}
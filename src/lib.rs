@@ -117,6 +117,67 @@
 //!
 //! For sftp and other ssh subsystem, check [`Session::subsystem`] for more information.
 //!
+//! This crate does not implement the sftp protocol itself. Use [`openssh-sftp-client`] on top of
+//! the `sftp` subsystem for file system operations, e.g. to list a remote directory along with
+//! the size, modification time, permissions and type of its entries:
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "native-mux")]
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use futures_util::StreamExt;
+//! use openssh::{KnownHosts, Session, Stdio};
+//! use openssh_sftp_client::Sftp;
+//!
+//! let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+//!
+//! let mut child = session
+//!     .subsystem("sftp")
+//!     .stdin(Stdio::piped())
+//!     .stdout(Stdio::piped())
+//!     .spawn()
+//!     .await?;
+//!
+//! let sftp = Sftp::new(
+//!     child.stdin().take().unwrap(),
+//!     child.stdout().take().unwrap(),
+//!     Default::default(),
+//! )
+//! .await?;
+//!
+//! let mut entries = Box::pin(sftp.fs().open_dir("/var/log").await?.read_dir());
+//! while let Some(entry) = entries.next().await {
+//!     let entry = entry?;
+//!     let metadata = entry.metadata();
+//!
+//!     println!(
+//!         "{:?} {:?} {:?} {:?} {}",
+//!         entry.file_type(),
+//!         metadata.permissions(),
+//!         metadata.len(),
+//!         metadata.modified().map(|mtime| mtime.as_system_time()),
+//!         entry.filename().display(),
+//!     );
+//! }
+//! drop(entries);
+//!
+//! sftp.close().await?;
+//! # Ok(()) }
+//! ```
+//!
+//! The [`Fs`] of `openssh-sftp-client` covers the other file system operations as well:
+//!
+//! | Operation | [`Fs`] method                                          |
+//! |-----------|--------------------------------------------------------|
+//! | rename    | `rename`                                               |
+//! | remove    | `remove_file`                                          |
+//! | mkdir     | `create_dir`, or `dir_builder` to set the permissions |
+//! | rmdir     | `remove_dir`                                           |
+//! | symlink   | `symlink`                                              |
+//! | readlink  | `read_link`                                            |
+//! | chmod     | `set_permissions`                                      |
+//! | utimes    | `set_metadata` with `MetaDataBuilder::time`            |
+//!
 //! # Examples
 //!
 //! ```rust,no_run
@@ -140,6 +201,8 @@
 //!   [`ControlMaster`]: https://en.wikibooks.org/wiki/OpenSSH/Cookbook/Multiplexing
 //!   [`sshd_config`]: https://linux.die.net/man/5/sshd_config
 //!   [`shell-escape`]: https://crates.io/crates/shell-escape
+//!   [`openssh-sftp-client`]: https://crates.io/crates/openssh-sftp-client
+//!   [`Fs`]: https://docs.rs/openssh-sftp-client/latest/openssh_sftp_client/fs/struct.Fs.html
 
 #![warn(
     missing_docs,
@@ -150,7 +213,14 @@
 )]
 #![cfg_attr(
     not(any(feature = "process-mux", feature = "native-mux")),
-    allow(unused_variables, unreachable_code, unused_imports, dead_code)
+    allow(
+        unused_variables,
+        unreachable_code,
+        unused_imports,
+        unused_mut,
+        dead_code,
+        clippy::diverging_sub_expression
+    )
 )]
 // only enables the nightly `doc_cfg` feature when
 // the `docsrs` configuration attribute is defined
@@ -166,17 +236,42 @@ mod session;
 pub use session::Session;
 
 mod builder;
-pub use builder::{KnownHosts, SessionBuilder};
+pub use builder::{KnownHosts, SecurityProfile, SessionBuilder, TunForward, TunnelMode};
 
 mod command;
-pub use command::{OverSsh, OwningCommand};
+pub use command::{OverSsh, OwningCommand, RequestTty};
 /// Convenience [`OwningCommand`] alias when working with a session reference.
 pub type Command<'s> = OwningCommand<&'s Session>;
 
+#[cfg(feature = "password-auth")]
+mod askpass;
+
+mod control_socket;
+
 mod escape;
 
+mod env_file;
+
+mod wrapper;
+pub use wrapper::{IoPriority, Rlimit, TransientUnit};
+
+mod terminal;
+
+mod su;
+
+mod ansi;
+
+mod filter;
+
+mod known_hosts;
+pub use known_hosts::HostKeyUpdate;
+
 mod child;
-pub use child::Child;
+pub use child::{Child, DropPolicy};
+
+mod output;
+pub use output::OutputExt;
+
 /// Convenience [`Child`] alias when working with a session reference.
 pub type RemoteChild<'a> = Child<&'a Session>;
 
@@ -196,6 +291,69 @@ pub mod changelog;
 mod port_forwarding;
 pub use port_forwarding::*;
 
+mod remote_stream;
+pub use remote_stream::RemoteStream;
+
+mod socks_proxy;
+pub use socks_proxy::SocksProxy;
+
+mod docker;
+pub use docker::DockerSocket;
+
+mod supervised_forward;
+pub use supervised_forward::{ForwardEvent, SupervisedForward};
+
+mod supervisor;
+pub use supervisor::{RemoteSupervisor, RestartPolicy, SupervisorEvent};
+
+mod periodic;
+pub use periodic::{PeriodicTask, Schedule};
+
+pub mod agent;
+
+pub mod keygen;
+
+mod cancel;
+pub use cancel::CancellationToken;
+
+mod facts;
+pub use facts::Facts;
+
+mod fleet;
+pub use fleet::{HostOutput, Rollout, RolloutReport};
+
+mod inventory;
+pub use inventory::{Host, Inventory};
+
+mod pool;
+pub use pool::{PooledSession, SessionPool, SessionPoolBuilder};
+
+mod progress;
+pub use progress::{FleetEvent, FleetProgress};
+
+mod selector;
+pub use selector::Selector;
+
+mod ssh_config;
+pub use ssh_config::SshConfig;
+
+mod tail;
+pub use tail::TailFile;
+
+mod template;
+pub use template::CommandTemplate;
+
+mod watch;
+pub use watch::{FileWatch, WatchEvent, WatchEventKind};
+
+mod transfer;
+pub use transfer::{copy_between, copy_between_with, copy_dir_between, CopyOptions};
+
+#[cfg(feature = "hyper")]
+mod connector;
+#[cfg(feature = "hyper")]
+pub use connector::SessionConnector;
+
 /// Types to create and interact with the Remote Process
 pub mod process {
     pub use super::{ChildStderr, ChildStdin, ChildStdout, Command, RemoteChild, Stdio};
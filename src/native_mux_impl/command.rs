@@ -2,9 +2,13 @@ use super::Error;
 use super::RemoteChild;
 use super::{ChildStderr, ChildStdin, ChildStdout, Stdio};
 
+use crate::wrapper::Wrapper;
+use crate::RequestTty;
+
 use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::OwnedFd;
 use std::path::Path;
 
 use openssh_mux_client::{Connection, NonZeroByteSlice, Session};
@@ -14,6 +18,8 @@ pub(crate) struct Command {
     cmd: Vec<u8>,
     ctl: Box<Path>,
     subsystem: bool,
+    tty: RequestTty,
+    escape_char: Option<char>,
 
     stdin_v: Stdio,
     stdout_v: Stdio,
@@ -26,6 +32,8 @@ impl Command {
             cmd,
             ctl,
             subsystem,
+            tty: RequestTty::default(),
+            escape_char: None,
 
             stdin_v: Stdio::inherit(),
             stdout_v: Stdio::inherit(),
@@ -33,6 +41,31 @@ impl Command {
         }
     }
 
+    pub(crate) fn ctl(&self) -> &Path {
+        &self.ctl
+    }
+
+    pub(crate) fn is_subsystem(&self) -> bool {
+        self.subsystem
+    }
+
+    pub(crate) fn request_tty(&mut self, tty: RequestTty) {
+        self.tty = tty;
+    }
+
+    /// Return the local terminal that is the stdin of the remote process,
+    /// if it has a pseudo-terminal.
+    pub(crate) fn terminal(&self) -> Option<OwnedFd> {
+        match self.tty {
+            RequestTty::No => None,
+            _ => self.stdin_v.terminal(),
+        }
+    }
+
+    pub(crate) fn escape_char(&mut self, escape_char: Option<char>) {
+        self.escape_char = escape_char;
+    }
+
     pub(crate) fn raw_arg<S: AsRef<OsStr>>(&mut self, arg: S) {
         self.cmd.push(b' ');
         self.cmd.extend_from_slice(arg.as_ref().as_bytes());
@@ -52,6 +85,7 @@ impl Command {
 
     pub(crate) async fn spawn(
         &mut self,
+        wrapper: &Wrapper,
     ) -> Result<
         (
             RemoteChild,
@@ -71,11 +105,29 @@ impl Command {
             stderr.as_raw_fd_or_null_fd()?,
         ];
 
-        let cmd = NonZeroByteSlice::new(&self.cmd).ok_or(Error::InvalidCommand)?;
+        let cmd = if self.subsystem {
+            Cow::Borrowed(&*self.cmd)
+        } else {
+            wrapper.wrap(&self.cmd)
+        };
+        let cmd = NonZeroByteSlice::new(&cmd).ok_or(Error::InvalidCommand)?;
+
+        // Like ssh, which only requests one for login sessions with `auto`.
+        let tty = match self.tty {
+            RequestTty::Yes => {
+                // SAFETY: `isatty` only inspects the fd.
+                unsafe { libc::isatty(stdios[0]) == 1 }
+            }
+            RequestTty::Force => true,
+            _ => false,
+        };
 
         let session = Session::builder()
             .cmd(Cow::Borrowed(cmd))
             .subsystem(self.subsystem)
+            .tty(tty)
+            // `char::MAX` disables the escape character.
+            .escape_ch(self.escape_char.unwrap_or(char::MAX))
             .build();
 
         let established_session = Connection::connect(&self.ctl)
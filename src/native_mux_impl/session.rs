@@ -1,6 +1,7 @@
 use super::{Command, Error};
 
 use std::ffi::OsStr;
+use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 
@@ -54,7 +55,15 @@ impl Session {
         forward_type: crate::ForwardType,
         listen_socket: crate::Socket<'_>,
         connect_socket: crate::Socket<'_>,
-    ) -> Result<(), Error> {
+    ) -> Result<Option<u16>, Error> {
+        if crate::port_forwarding::allocates_port(forward_type, &listen_socket) {
+            // openssh_mux_client does not support the reply carrying the
+            // port allocated by the remote host, so let ssh handle it.
+            return self
+                .request_port_forward_with_ssh(forward_type, &listen_socket, &connect_socket)
+                .await;
+        }
+
         Connection::connect(&self.ctl)
             .await?
             .request_port_forward(
@@ -62,9 +71,28 @@ impl Session {
                 &listen_socket.into(),
                 &connect_socket.into(),
             )
-            .await?;
+            .await
+            .map_err(|err| match err {
+                openssh_mux_client::Error::RequestFailure(reason) => Error::PortForwardRefused(
+                    io::Error::new(io::ErrorKind::Other, reason.into_string()),
+                ),
+                err => err.into(),
+            })?;
+
+        Ok(None)
+    }
 
-        Ok(())
+    async fn request_port_forward_with_ssh(
+        &self,
+        forward_type: crate::ForwardType,
+        listen_socket: &crate::Socket<'_>,
+        connect_socket: &crate::Socket<'_>,
+    ) -> Result<Option<u16>, Error> {
+        let args =
+            crate::port_forwarding::forwarding_args(forward_type, listen_socket, connect_socket);
+        let stdout = crate::port_forwarding::forward_with_ssh(&self.ctl, &args).await?;
+
+        Ok(crate::port_forwarding::parse_allocated_port(&stdout))
     }
 
     async fn close_impl(&self) -> Result<(), Error> {
@@ -86,6 +114,8 @@ impl Session {
     }
 
     pub(crate) fn detach(mut self) -> (Box<Path>, Option<Box<Path>>) {
+        // `TempDir::keep` is not available in the minimal supported tempfile.
+        #[allow(deprecated)]
         (
             self.ctl.clone(),
             self.tempdir.take().map(TempDir::into_path).map(|mut path| {
@@ -3,10 +3,11 @@ use crate::{stdio::StdioImpl, Error, Stdio};
 use std::{
     fs::{File, OpenOptions},
     io,
+    os::raw::c_int,
     os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
 };
 
-use libc::{c_int, fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
+use libc::{fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
 use once_cell::sync::OnceCell;
 use tokio_pipe::{pipe, PipeRead, PipeWrite};
 
@@ -0,0 +1,72 @@
+use std::borrow::Cow;
+use std::process::Output;
+
+/// Accessors for the text of the [`Output`] of a remote command, as returned
+/// by [`OwningCommand::output`](crate::OwningCommand::output) and
+/// [`Child::wait_with_output`](crate::Child::wait_with_output).
+///
+/// ```rust,no_run
+/// # async fn foo(session: &openssh::Session) -> Result<(), openssh::Error> {
+/// use openssh::OutputExt;
+///
+/// let output = session.command("hostname").output().await?;
+/// println!("connected to {}", output.stdout_trimmed());
+/// # Ok(())
+/// # }
+/// ```
+pub trait OutputExt {
+    /// Return stdout as a string, with bytes that are not valid UTF-8
+    /// replaced by `U+FFFD`.
+    fn stdout_lossy(&self) -> Cow<'_, str>;
+
+    /// Return stderr as a string, with bytes that are not valid UTF-8
+    /// replaced by `U+FFFD`.
+    fn stderr_lossy(&self) -> Cow<'_, str>;
+
+    /// Return [`stdout_lossy`](Self::stdout_lossy) without leading and
+    /// trailing whitespace, such as the final newline.
+    fn stdout_trimmed(&self) -> Cow<'_, str>;
+}
+
+impl OutputExt for Output {
+    fn stdout_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    fn stderr_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    fn stdout_trimmed(&self) -> Cow<'_, str> {
+        match self.stdout_lossy() {
+            Cow::Borrowed(stdout) => Cow::Borrowed(stdout.trim()),
+            Cow::Owned(stdout) => Cow::Owned(stdout.trim().to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    #[test]
+    fn accessors() {
+        let output = Output {
+            status: ExitStatus::from_raw(0),
+            stdout: b"  host\xff.example.com\n".to_vec(),
+            stderr: b"warning\n".to_vec(),
+        };
+        assert_eq!(output.stdout_lossy(), "  host\u{fffd}.example.com\n");
+        assert_eq!(output.stderr_lossy(), "warning\n");
+        assert_eq!(output.stdout_trimmed(), "host\u{fffd}.example.com");
+
+        let output = Output {
+            stdout: b"\tdone\r\n".to_vec(),
+            ..output
+        };
+        assert!(matches!(output.stdout_trimmed(), Cow::Borrowed("done")));
+    }
+}
@@ -0,0 +1,128 @@
+use super::{Error, OwningCommand, Session};
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process::Output;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// When a [`PeriodicTask`] runs its command.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    interval: Duration,
+    jitter: Duration,
+    immediately: bool,
+}
+
+impl Schedule {
+    /// Run the command every `interval`.
+    ///
+    /// # Panics
+    ///
+    /// If `interval` is zero.
+    pub fn every(interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "the interval must be non-zero");
+        Self {
+            interval,
+            jitter: Duration::ZERO,
+            immediately: true,
+        }
+    }
+
+    /// Delay each run by a random duration of up to `jitter`, so that tasks
+    /// for many hosts with the same schedule do not all run at once.
+    ///
+    /// Defaults to zero.
+    pub fn jitter(&mut self, jitter: Duration) -> &mut Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Run the command as soon as the task starts, rather than only after
+    /// the first interval.
+    ///
+    /// Defaults to `true`.
+    pub fn immediately(&mut self, immediately: bool) -> &mut Self {
+        self.immediately = immediately;
+        self
+    }
+}
+
+/// Return a random duration below `max`, or zero if `max` is zero.
+fn random_below(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    // Every `RandomState` is seeded differently, which is random enough to
+    // spread runs without a dependency on a random number generator.
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos((u128::from(random) % max.as_nanos()) as u64)
+}
+
+/// A command run on the remote host at a fixed interval, created by
+/// [`Session::run_periodically`](crate::Session::run_periodically).
+///
+/// Dropping the `PeriodicTask` stops running the command and kills the
+/// remote process of a run in progress.
+#[derive(Debug)]
+#[must_use = "the command stops being run when dropped"]
+pub struct PeriodicTask {
+    task: JoinHandle<()>,
+}
+
+impl PeriodicTask {
+    pub(crate) fn new<F, C>(
+        session: Arc<Session>,
+        schedule: Schedule,
+        mut make_command: F,
+        mut on_result: C,
+    ) -> Self
+    where
+        F: FnMut(Arc<Session>) -> OwningCommand<Arc<Session>> + Send + 'static,
+        C: FnMut(Result<Output, Error>) + Send + 'static,
+    {
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(schedule.interval);
+            // Runs never overlap: the ticks missed while a run takes longer
+            // than the interval are skipped.
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            if !schedule.immediately {
+                interval.tick().await;
+            }
+
+            loop {
+                interval.tick().await;
+                tokio::time::sleep(random_below(schedule.jitter)).await;
+
+                let mut command = make_command(Arc::clone(&session));
+                on_result(command.kill_on_drop(true).output().await);
+            }
+        });
+
+        Self { task }
+    }
+}
+
+impl Drop for PeriodicTask {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jitter() {
+        assert_eq!(random_below(Duration::ZERO), Duration::ZERO);
+
+        let max = Duration::from_millis(10);
+        let delays: Vec<_> = (0..100).map(|_| random_below(max)).collect();
+        assert!(delays.iter().all(|&delay| delay < max));
+        assert!(delays.iter().any(|&delay| delay != delays[0]));
+    }
+}
@@ -0,0 +1,607 @@
+use super::{
+    CancellationToken, Error, Facts, FleetEvent, FleetProgress, Host, Session, SessionBuilder,
+};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore};
+
+/// Build a [`SessionPool`].
+#[derive(Debug, Clone)]
+pub struct SessionPoolBuilder {
+    builder: SessionBuilder,
+    max_open: usize,
+    max_connecting: usize,
+    connect_interval: Duration,
+    idle_timeout: Duration,
+    facts_ttl: Duration,
+    #[cfg(feature = "native-mux")]
+    native_mux: bool,
+}
+
+impl SessionPoolBuilder {
+    /// Create a builder for a pool that connects to its destinations with
+    /// `builder`.
+    ///
+    /// Settings of `builder` apply to every session of the pool, unless the
+    /// [`Host`] it connects to overrides them. E.g. to reach all hosts
+    /// through a shared bastion, set [`SessionBuilder::jump_hosts`] once on
+    /// `builder`.
+    pub fn new(builder: SessionBuilder) -> Self {
+        Self {
+            builder,
+            max_open: 16,
+            max_connecting: 10,
+            connect_interval: Duration::ZERO,
+            idle_timeout: Duration::from_secs(300),
+            facts_ttl: Duration::from_secs(600),
+            #[cfg(feature = "native-mux")]
+            native_mux: !cfg!(feature = "process-mux"),
+        }
+    }
+
+    /// Set the maximum number of sessions the pool keeps open, across all
+    /// destinations.
+    ///
+    /// Once the limit is reached, [`SessionPool::get`] closes the session that
+    /// has been idle the longest to make room, or waits until a session is
+    /// returned to the pool.
+    ///
+    /// Defaults to `16`.
+    pub fn max_open(&mut self, max_open: usize) -> &mut Self {
+        self.max_open = max_open;
+        self
+    }
+
+    /// Set the maximum number of sessions the pool connects at the same time.
+    ///
+    /// Connecting to many hosts at once, in particular through a shared jump
+    /// host, may otherwise exceed the `MaxStartups` limit of sshd for
+    /// unauthenticated connections, which drops the excess connections.
+    ///
+    /// Defaults to `10`, the default `MaxStartups` of sshd.
+    pub fn max_connecting(&mut self, max_connecting: usize) -> &mut Self {
+        self.max_connecting = max_connecting;
+        self
+    }
+
+    /// Set the minimum time between the pool starting to connect two
+    /// sessions, to stay below the rate limits of e.g. fail2ban.
+    ///
+    /// Defaults to zero.
+    pub fn connect_interval(&mut self, connect_interval: Duration) -> &mut Self {
+        self.connect_interval = connect_interval;
+        self
+    }
+
+    /// Set how long a session may stay unused in the pool before it is
+    /// closed.
+    ///
+    /// Defaults to 5 minutes.
+    pub fn idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Set how long [`SessionPool::facts`] caches the facts of a host before
+    /// probing it again.
+    ///
+    /// Defaults to 10 minutes.
+    pub fn facts_ttl(&mut self, facts_ttl: Duration) -> &mut Self {
+        self.facts_ttl = facts_ttl;
+        self
+    }
+
+    /// Connect with the native mux implementation rather than the process
+    /// implementation, see the crate-level documentation for the difference.
+    ///
+    /// Defaults to `false`, unless only the `native-mux` feature is enabled.
+    #[cfg(feature = "native-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
+    pub fn native_mux(&mut self, native_mux: bool) -> &mut Self {
+        self.native_mux = native_mux;
+        self
+    }
+
+    /// Create the pool. No session is opened until it is requested.
+    pub fn build(&self) -> SessionPool {
+        SessionPool {
+            cancellation: None,
+            inner: Arc::new(Inner {
+                builder: self.builder.clone(),
+                new_session: self.new_session(),
+                idle_timeout: self.idle_timeout,
+                permits: Arc::new(Semaphore::new(self.max_open)),
+                connecting: Semaphore::new(self.max_connecting),
+                connect_interval: self.connect_interval,
+                next_connect: Mutex::new(Instant::now()),
+                idle: Mutex::new(HashMap::new()),
+                returned: Notify::new(),
+                reaping: AtomicBool::new(false),
+                facts_ttl: self.facts_ttl,
+                facts: Mutex::new(HashMap::new()),
+                progress: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    fn new_session(&self) -> fn(TempDir) -> Session {
+        #[cfg(all(feature = "process-mux", feature = "native-mux"))]
+        {
+            if self.native_mux {
+                Session::new_native_mux
+            } else {
+                Session::new_process_mux
+            }
+        }
+
+        #[cfg(all(feature = "process-mux", not(feature = "native-mux")))]
+        {
+            Session::new_process_mux
+        }
+
+        #[cfg(all(not(feature = "process-mux"), feature = "native-mux"))]
+        {
+            Session::new_native_mux
+        }
+
+        #[cfg(not(any(feature = "process-mux", feature = "native-mux")))]
+        {
+            unreachable!("neither feature process-mux nor native-mux is enabled")
+        }
+    }
+}
+
+/// A session that has been returned to the pool.
+struct Idle {
+    session: Session,
+    permit: OwnedSemaphorePermit,
+    since: Instant,
+}
+
+struct Inner {
+    builder: SessionBuilder,
+    new_session: fn(TempDir) -> Session,
+    idle_timeout: Duration,
+    /// One permit per open session.
+    permits: Arc<Semaphore>,
+    /// One permit per session being connected.
+    connecting: Semaphore,
+    connect_interval: Duration,
+    /// The earliest time the next session may start connecting.
+    next_connect: Mutex<Instant>,
+    /// The idle sessions per destination, the most recently returned last.
+    idle: Mutex<HashMap<String, Vec<Idle>>>,
+    /// Notified when a session is returned to the pool.
+    returned: Notify,
+    /// Whether the task closing idle sessions has been started.
+    reaping: AtomicBool,
+    facts_ttl: Duration,
+    /// The cached facts per host, with when they were gathered.
+    facts: Mutex<HashMap<String, (Facts, Instant)>>,
+    /// The senders of the streams returned by [`SessionPool::progress`].
+    progress: Mutex<Vec<mpsc::UnboundedSender<FleetEvent>>>,
+}
+
+impl Inner {
+    fn pop_idle(&self, destination: &str) -> Option<Idle> {
+        self.idle.lock().unwrap().get_mut(destination)?.pop()
+    }
+
+    /// Take the session that has been idle the longest.
+    fn pop_oldest_idle(&self) -> Option<Idle> {
+        let mut idle = self.idle.lock().unwrap();
+
+        let sessions = idle
+            .values_mut()
+            .filter(|sessions| !sessions.is_empty())
+            .min_by_key(|sessions| sessions[0].since)?;
+        Some(sessions.remove(0))
+    }
+
+    /// Wait until the next session may start connecting, according to the
+    /// connect interval.
+    async fn wait_to_connect(&self) {
+        let at = {
+            let mut next_connect = self.next_connect.lock().unwrap();
+            let at = (*next_connect).max(Instant::now());
+            *next_connect = at + self.connect_interval;
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+    }
+
+    fn take_expired(&self) -> Vec<Idle> {
+        let mut expired = Vec::new();
+
+        self.idle.lock().unwrap().retain(|_, sessions| {
+            let n = sessions
+                .iter()
+                .take_while(|idle| idle.since.elapsed() >= self.idle_timeout)
+                .count();
+            expired.extend(sessions.drain(..n));
+            !sessions.is_empty()
+        });
+
+        expired
+    }
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let idle: HashMap<String, usize> = self
+            .idle
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(destination, sessions)| (destination.clone(), sessions.len()))
+            .collect();
+
+        f.debug_struct("SessionPool")
+            .field("builder", &self.builder)
+            .field("connect_interval", &self.connect_interval)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("idle", &idle)
+            .field("facts_ttl", &self.facts_ttl)
+            .finish()
+    }
+}
+
+/// A pool of sessions, opened on demand and cached per destination, created
+/// by [`SessionPoolBuilder`].
+///
+/// Sessions are checked with [`Session::check`] before they are handed out
+/// again, and closed once they have been idle for the
+/// [idle timeout](SessionPoolBuilder::idle_timeout). Cloning the pool is
+/// cheap and shares its sessions.
+///
+/// ```rust,no_run
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), openssh::Error> {
+/// use openssh::{SessionBuilder, SessionPoolBuilder};
+///
+/// let pool = SessionPoolBuilder::new(SessionBuilder::default())
+///     .max_open(4)
+///     .build();
+///
+/// let session = pool.get("me@ssh.example.com").await?;
+/// let uptime = session.command("uptime").output().await?;
+/// // Dropping the session returns it to the pool.
+/// drop(session);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SessionPool {
+    inner: Arc<Inner>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl SessionPool {
+    /// Get a session to `destination`, reusing an idle one if there is one
+    /// and connecting otherwise.
+    ///
+    /// The format of `destination` is the same as for
+    /// [`SessionBuilder::connect`], and sessions are only reused for the
+    /// exact same `destination`.
+    pub async fn get(&self, destination: &str) -> Result<PooledSession, Error> {
+        self.get_host(&Host::new(destination)).await
+    }
+
+    /// Get a session to `host`, connecting with the connection settings of
+    /// the host applied to the [`SessionBuilder`] of the pool.
+    ///
+    /// Sessions are reused for hosts of the same [name](Host::name).
+    #[allow(clippy::incompatible_msrv)]
+    pub async fn get_host(&self, host: &Host) -> Result<PooledSession, Error> {
+        let destination = host.name();
+        self.start_reaping();
+
+        let permit = loop {
+            // Created before looking for idle sessions, to not miss one
+            // returned in between.
+            let returned = self.inner.returned.notified();
+
+            while let Some(idle) = self.inner.pop_idle(destination) {
+                if idle.since.elapsed() < self.inner.idle_timeout
+                    && idle.session.check().await.is_ok()
+                {
+                    return Ok(self.pooled(destination, idle.session, idle.permit));
+                }
+                let _ = idle.session.close().await;
+            }
+
+            if let Ok(permit) = self.inner.permits.clone().try_acquire_owned() {
+                break permit;
+            }
+            if let Some(idle) = self.inner.pop_oldest_idle() {
+                let _ = idle.session.close().await;
+                continue;
+            }
+
+            tokio::select! {
+                () = returned => (),
+                permit = self.inner.permits.clone().acquire_owned() => {
+                    break permit.expect("the semaphore is never closed");
+                }
+            }
+        };
+
+        let session = {
+            let _connecting = self
+                .inner
+                .connecting
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            self.inner.wait_to_connect().await;
+
+            host.session_builder(&self.inner.builder)
+                .connect_impl(destination, self.inner.new_session)
+                .await?
+        };
+
+        Ok(self.pooled(destination, session, permit))
+    }
+
+    /// Return the [`Facts`] of `host`, probing it if they have not been
+    /// gathered within the [TTL](SessionPoolBuilder::facts_ttl).
+    ///
+    /// Facts are cached per [name](Host::name) of the host, so that e.g.
+    /// deciding which package manager to use does not need a round-trip to
+    /// the host on every operation.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{Host, SessionBuilder, SessionPoolBuilder};
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let host = Host::new("web1.example.com");
+    /// let binary = match pool.facts(&host).await?.arch.as_str() {
+    ///     "aarch64" | "arm64" => "agent-arm64",
+    ///     _ => "agent-amd64",
+    /// };
+    /// # Ok(()) }
+    /// ```
+    pub async fn facts(&self, host: &Host) -> Result<Facts, Error> {
+        if let Some((facts, since)) = self.inner.facts.lock().unwrap().get(host.name()) {
+            if since.elapsed() < self.inner.facts_ttl {
+                return Ok(facts.clone());
+            }
+        }
+
+        let facts = Facts::probe(&*self.get_host(host).await?).await?;
+        self.inner
+            .facts
+            .lock()
+            .unwrap()
+            .insert(host.name().to_owned(), (facts.clone(), Instant::now()));
+
+        Ok(facts)
+    }
+
+    /// Forget the cached facts of the host named `name`, e.g. after
+    /// reinstalling it.
+    pub fn forget_facts(&self, name: &str) {
+        self.inner.facts.lock().unwrap().remove(name);
+    }
+
+    /// Return a handle to the pool, sharing its sessions, whose multi-host
+    /// operations are aborted once `token` is cancelled.
+    ///
+    /// Once cancelled, hosts that have not been started on fail with
+    /// [`Error::Cancelled`], and so do commands that are still running, whose
+    /// remote processes are killed. The operations return the results they
+    /// have so far, e.g. [`SessionPool::run_rolling`] skips the remaining
+    /// batches. [`SessionPool::push_file`] completes the uploads in flight, so
+    /// that no temporary files are left behind.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{CancellationToken, Rollout, SessionBuilder, SessionPoolBuilder};
+    /// use std::num::NonZeroUsize;
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let token = CancellationToken::new();
+    /// tokio::spawn({
+    ///     let token = token.clone();
+    ///     async move {
+    ///         tokio::signal::ctrl_c().await.unwrap();
+    ///         token.cancel();
+    ///     }
+    /// });
+    ///
+    /// let hosts = (1..=20).map(|n| format!("web{}.example.com", n));
+    /// let report = pool
+    ///     .with_cancellation(token)
+    ///     .run_rolling(hosts, "systemctl restart app", &Rollout::new(NonZeroUsize::new(5).unwrap()))
+    ///     .await;
+    /// # Ok(()) }
+    /// ```
+    pub fn with_cancellation(&self, token: CancellationToken) -> SessionPool {
+        SessionPool {
+            inner: self.inner.clone(),
+            cancellation: Some(token),
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .map_or(false, CancellationToken::is_cancelled)
+    }
+
+    /// Wait until the operations of the pool are cancelled, if ever.
+    pub(crate) async fn cancelled(&self) {
+        match &self.cancellation {
+            Some(token) => token.cancelled().await,
+            None => futures_util::future::pending().await,
+        }
+    }
+
+    /// Return a stream of the progress of the multi-host operations of the
+    /// pool, e.g. to render it in a CLI.
+    ///
+    /// Only events of operations started after calling this are reported.
+    ///
+    /// ```rust,no_run
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{FleetEvent, SessionBuilder, SessionPoolBuilder};
+    /// use std::time::Duration;
+    ///
+    /// let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+    ///
+    /// let mut progress = pool.progress();
+    /// tokio::spawn(async move {
+    ///     while let Some(event) = progress.next_event().await {
+    ///         if let FleetEvent::Finished { host, success, .. } = event {
+    ///             eprintln!("{}: {}", host, if success { "done" } else { "failed" });
+    ///         }
+    ///     }
+    /// });
+    ///
+    /// pool.run_on_all(["web1.example.com", "web2.example.com"], "uptime", Duration::from_secs(10))
+    ///     .await;
+    /// # Ok(()) }
+    /// ```
+    pub fn progress(&self) -> FleetProgress {
+        let (sender, progress) = FleetProgress::new();
+        self.inner.progress.lock().unwrap().push(sender);
+        progress
+    }
+
+    /// Send `event` to the streams returned by [`SessionPool::progress`].
+    pub(crate) fn emit(&self, event: FleetEvent) {
+        self.inner
+            .progress
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    fn pooled(
+        &self,
+        destination: &str,
+        session: Session,
+        permit: OwnedSemaphorePermit,
+    ) -> PooledSession {
+        PooledSession {
+            session: Some((session, permit)),
+            destination: destination.to_owned(),
+            pool: self.inner.clone(),
+        }
+    }
+
+    /// Spawn the task closing idle sessions, which exits once the pool is
+    /// dropped.
+    fn start_reaping(&self) {
+        if self.inner.reaping.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let inner = Arc::downgrade(&self.inner);
+        let interval = (self.inner.idle_timeout / 2).max(Duration::from_millis(100));
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let expired = match inner.upgrade() {
+                    Some(inner) => inner.take_expired(),
+                    None => break,
+                };
+                for idle in expired {
+                    let _ = idle.session.close().await;
+                }
+            }
+        });
+    }
+}
+
+/// A session borrowed from a [`SessionPool`], which is returned to the pool
+/// when dropped.
+pub struct PooledSession {
+    session: Option<(Session, OwnedSemaphorePermit)>,
+    destination: String,
+    pool: Arc<Inner>,
+}
+
+impl PooledSession {
+    /// Close the session instead of returning it to the pool, e.g. because
+    /// it is in a bad state.
+    pub async fn close(mut self) -> Result<(), Error> {
+        let (session, _permit) = self.session.take().expect("only taken on close or drop");
+        session.close().await
+    }
+}
+
+impl Deref for PooledSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self
+            .session
+            .as_ref()
+            .expect("only taken on close or drop")
+            .0
+    }
+}
+
+impl fmt::Debug for PooledSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledSession")
+            .field(
+                "session",
+                &self.session.as_ref().map(|(session, _)| session),
+            )
+            .field("destination", &self.destination)
+            .finish()
+    }
+}
+
+impl Drop for PooledSession {
+    fn drop(&mut self) {
+        if let Some((session, permit)) = self.session.take() {
+            self.pool
+                .idle
+                .lock()
+                .unwrap()
+                .entry(std::mem::take(&mut self.destination))
+                .or_default()
+                .push(Idle {
+                    session,
+                    permit,
+                    since: Instant::now(),
+                });
+            self.pool.returned.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_interval() {
+        let pool = SessionPoolBuilder::new(SessionBuilder::default())
+            .connect_interval(Duration::from_millis(50))
+            .build();
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            pool.inner.wait_to_connect().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,13 +1,19 @@
 #[cfg(feature = "native-mux")]
 use super::native_mux_impl;
 
-#[cfg(feature = "process-mux")]
-use std::ffi::OsStr;
+use super::{Error, RemoteStream};
 
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::net::{self, SocketAddr};
+use std::net::{self, Ipv4Addr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
 
 /// Type of forwarding
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -116,7 +122,20 @@ impl Socket<'_> {
         }
     }
 
-    #[cfg(feature = "process-mux")]
+    /// Convert into a `Socket` that owns its host or path.
+    pub fn into_owned(self) -> Socket<'static> {
+        match self {
+            #[cfg(unix)]
+            Socket::UnixSocket { path } => Socket::UnixSocket {
+                path: Cow::Owned(path.into_owned()),
+            },
+            Socket::TcpSocket { host, port } => Socket::TcpSocket {
+                host: Cow::Owned(host.into_owned()),
+                port,
+            },
+        }
+    }
+
     pub(crate) fn as_os_str(&self) -> Cow<'_, OsStr> {
         match self {
             #[cfg(unix)]
@@ -153,3 +172,429 @@ impl<'a> fmt::Display for Socket<'a> {
         }
     }
 }
+
+/// A forwarding established through a [`Session`](crate::Session), see
+/// [`Session::forwards`](crate::Session::forwards).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ActiveForward {
+    /// `listen_socket` on the local machine is forwarded to `connect_socket`
+    /// on the remote machine.
+    Local {
+        /// Listening side of the forwarding.
+        listen_socket: Socket<'static>,
+        /// Connecting side of the forwarding.
+        connect_socket: Socket<'static>,
+    },
+
+    /// `listen_socket` on the remote machine is forwarded to `connect_socket`
+    /// on the local machine.
+    Remote {
+        /// Listening side of the forwarding.
+        listen_socket: Socket<'static>,
+        /// Connecting side of the forwarding.
+        connect_socket: Socket<'static>,
+    },
+
+    /// A SOCKS proxy listening on `listen_socket` on the local machine.
+    Dynamic {
+        /// Listening side of the forwarding.
+        listen_socket: Socket<'static>,
+    },
+}
+
+impl ActiveForward {
+    pub(crate) fn new(
+        forward_type: ForwardType,
+        listen_socket: Socket<'_>,
+        connect_socket: Socket<'_>,
+    ) -> Self {
+        let listen_socket = listen_socket.into_owned();
+        let connect_socket = connect_socket.into_owned();
+
+        match forward_type {
+            ForwardType::Local => ActiveForward::Local {
+                listen_socket,
+                connect_socket,
+            },
+            ForwardType::Remote => ActiveForward::Remote {
+                listen_socket,
+                connect_socket,
+            },
+        }
+    }
+}
+
+/// The forwardings of a session that are still open.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ForwardTable(Arc<Mutex<(u64, BTreeMap<u64, ActiveForward>)>>);
+
+impl ForwardTable {
+    /// Add `forward` to the table, returning the id to remove it with.
+    pub(crate) fn insert(&self, forward: ActiveForward) -> u64 {
+        let mut table = self.0.lock().unwrap();
+        let (next_id, forwards) = &mut *table;
+
+        let id = *next_id;
+        *next_id += 1;
+        forwards.insert(id, forward);
+
+        id
+    }
+
+    fn remove(&self, id: u64) {
+        self.0.lock().unwrap().1.remove(&id);
+    }
+
+    /// Return the forwardings in the order they have been established.
+    pub(crate) fn list(&self) -> Vec<ActiveForward> {
+        self.0.lock().unwrap().1.values().cloned().collect()
+    }
+}
+
+/// Format the arguments to pass to `ssh` to request a forwarding.
+pub(crate) fn forwarding_args(
+    forward_type: ForwardType,
+    listen_socket: &Socket<'_>,
+    connect_socket: &Socket<'_>,
+) -> [OsString; 2] {
+    let flag = match forward_type {
+        ForwardType::Local => "-L",
+        ForwardType::Remote => "-R",
+    };
+
+    let mut forwarding = listen_socket.as_os_str().into_owned();
+    forwarding.push(":");
+    forwarding.push(connect_socket.as_os_str());
+
+    [flag.into(), forwarding]
+}
+
+/// Return true if the remote host will pick the port to listen on.
+pub(crate) fn allocates_port(forward_type: ForwardType, listen_socket: &Socket<'_>) -> bool {
+    forward_type == ForwardType::Remote
+        && matches!(listen_socket, Socket::TcpSocket { port: 0, .. })
+}
+
+/// Parse the port allocated by the remote host from the stdout of `ssh -O forward`.
+pub(crate) fn parse_allocated_port(stdout: &[u8]) -> Option<u16> {
+    std::str::from_utf8(stdout).ok()?.trim().parse().ok()
+}
+
+/// Request a forwarding from the multiplex master by running
+/// `ssh -O forward` with the given `-L`/`-R`/`-D` arguments.
+///
+/// Returns the stdout of ssh, which holds the port allocated by the remote
+/// host if any.
+pub(crate) async fn forward_with_ssh(ctl: &Path, args: &[OsString; 2]) -> Result<Vec<u8>, Error> {
+    let mut cmd: tokio::process::Command = crate::session::new_ctl_cmd(
+        ctl,
+        &[OsStr::new("-O"), OsStr::new("forward"), &args[0], &args[1]],
+    )
+    .into();
+    let output = cmd.output().await.map_err(Error::Ssh)?;
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(interpret_forward_error(&String::from_utf8_lossy(
+            &output.stderr,
+        )))
+    }
+}
+
+/// Interpret the stderr of a failed `ssh -O forward`.
+pub(crate) fn interpret_forward_error(stderr: &str) -> Error {
+    let err = std::io::Error::new(std::io::ErrorKind::Other, stderr.trim());
+
+    // The mux client reports a failure message received from the master as
+    // "forwarding request failed: <reason>".
+    if stderr.contains("forwarding request failed") {
+        Error::PortForwardRefused(err)
+    } else {
+        Error::Ssh(err)
+    }
+}
+
+/// Keeps a forwarding open until it is dropped.
+///
+/// Dropping the guard stops accepting new connections on the listening
+/// side of the forwarding. Connections that have already been accepted
+/// are not affected.
+///
+/// For forwardings requested from the multiplex master, dropping the guard
+/// starts `ssh -O cancel` in the background, without waiting for it, and
+/// ignores any error. Use [`cancel`](ForwardGuard::cancel) to wait for the
+/// forwarding to be closed and find out whether cancelling succeeded.
+#[derive(Debug)]
+#[must_use = "the forwarding is closed when the guard is dropped"]
+pub struct ForwardGuard {
+    imp: Option<ForwardGuardImp>,
+    allocated_port: Option<u16>,
+    /// Entry in the [`ForwardTable`] of the session, removed on drop.
+    entry: Option<(ForwardTable, u64)>,
+}
+
+#[derive(Debug)]
+enum ForwardGuardImp {
+    /// Local listener driven by a task, closed by aborting the task.
+    Listener(JoinHandle<()>),
+
+    /// Forwarding of the multiplex master, closed with `ssh -O cancel`.
+    Mux {
+        ctl: Box<Path>,
+        /// The `-L`/`-R` arguments identifying the forwarding.
+        args: [OsString; 2],
+    },
+}
+
+impl ForwardGuard {
+    /// Bind a tcp listener on an ephemeral port of the loopback interface
+    /// and forward every connection accepted on it to `remote` using
+    /// [`RemoteStream`]s.
+    pub(crate) async fn tunnel_tcp(
+        ctl: Box<Path>,
+        remote: Socket<'static>,
+    ) -> Result<(SocketAddr, Self), Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(Error::Tunnel)?;
+        let addr = listener.local_addr().map_err(Error::Tunnel)?;
+
+        let task = tokio::spawn(async move {
+            while let Ok((mut local, _addr)) = listener.accept().await {
+                // The connection is simply dropped if ssh cannot be spawned.
+                if let Ok(mut remote) = RemoteStream::connect(&ctl, &remote) {
+                    tokio::spawn(async move {
+                        let _ = tokio::io::copy_bidirectional(&mut local, &mut remote).await;
+                    });
+                }
+            }
+        });
+
+        Ok((addr, Self::task(task)))
+    }
+
+    /// Guard a task, which is aborted when the guard is dropped.
+    pub(crate) fn task(task: JoinHandle<()>) -> Self {
+        Self {
+            imp: Some(ForwardGuardImp::Listener(task)),
+            allocated_port: None,
+            entry: None,
+        }
+    }
+
+    /// Guard a forwarding that the multiplex master has set up.
+    ///
+    /// If the remote host allocated the port of `listen_socket`, it must be
+    /// replaced by `allocated_port`, since the forwarding is registered under it.
+    pub(crate) fn mux(
+        ctl: &Path,
+        forward_type: ForwardType,
+        listen_socket: &Socket<'_>,
+        connect_socket: &Socket<'_>,
+        allocated_port: Option<u16>,
+    ) -> Self {
+        let mut guard = Self::mux_args(
+            ctl,
+            forwarding_args(forward_type, listen_socket, connect_socket),
+        );
+        guard.allocated_port = allocated_port;
+        guard
+    }
+
+    /// Guard a forwarding that the multiplex master has set up from the
+    /// `-L`/`-R`/`-D` arguments of `ssh`.
+    pub(crate) fn mux_args(ctl: &Path, args: [OsString; 2]) -> Self {
+        Self {
+            imp: Some(ForwardGuardImp::Mux {
+                ctl: ctl.into(),
+                args,
+            }),
+            allocated_port: None,
+            entry: None,
+        }
+    }
+
+    /// Track the forwarding as `forward` in `table` until the guard is dropped.
+    pub(crate) fn track(mut self, table: &ForwardTable, forward: ActiveForward) -> Self {
+        self.entry = Some((table.clone(), table.insert(forward)));
+        self
+    }
+
+    /// Return the control socket and the `ssh` arguments of a forwarding of
+    /// the multiplex master.
+    pub(crate) fn mux_forwarding(&self) -> Option<(&Path, &[OsString; 2])> {
+        match self.imp.as_ref()? {
+            ForwardGuardImp::Mux { ctl, args } => Some((ctl, args)),
+            ForwardGuardImp::Listener(_) => None,
+        }
+    }
+
+    /// Return the port the remote host allocated for a remote forwarding
+    /// listening on port `0`.
+    ///
+    /// Returns `None` for any other forwarding.
+    pub fn allocated_port(&self) -> Option<u16> {
+        self.allocated_port
+    }
+
+    /// Close the forwarding, reporting any error.
+    pub async fn cancel(mut self) -> Result<(), Error> {
+        match self.imp.take() {
+            Some(ForwardGuardImp::Listener(task)) => {
+                task.abort();
+                Ok(())
+            }
+            Some(ForwardGuardImp::Mux { ctl, args }) => {
+                let mut cmd: tokio::process::Command = cancel_cmd(&ctl, &args).into();
+                let output = cmd
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .output()
+                    .await
+                    .map_err(Error::Ssh)?;
+
+                if output.status.success() {
+                    Ok(())
+                } else {
+                    let exit_err = String::from_utf8_lossy(&output.stderr);
+
+                    Err(Error::Ssh(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        exit_err.trim(),
+                    )))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn cancel_cmd(ctl: &Path, args: &[OsString; 2]) -> std::process::Command {
+    crate::session::new_ctl_cmd(
+        ctl,
+        &[OsStr::new("-O"), OsStr::new("cancel"), &args[0], &args[1]],
+    )
+}
+
+impl Drop for ForwardGuard {
+    fn drop(&mut self) {
+        match self.imp.take() {
+            Some(ForwardGuardImp::Listener(task)) => task.abort(),
+            Some(ForwardGuardImp::Mux { ctl, args }) => {
+                let child = cancel_cmd(&ctl, &args)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn();
+                // Drop cannot wait for ssh, so reap it from a thread instead.
+                if let Ok(mut child) = child {
+                    std::thread::spawn(move || child.wait());
+                }
+            }
+            None => (),
+        }
+
+        if let Some((table, id)) = self.entry.take() {
+            table.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarding_args() {
+        let [flag, forwarding] = forwarding_args(
+            ForwardType::Remote,
+            &Socket::new("*", 8080),
+            &Path::new("/tmp/socket").into(),
+        );
+        assert_eq!(flag, "-R");
+        assert_eq!(forwarding, "*:8080:/tmp/socket");
+
+        let [flag, forwarding] = forwarding_args(
+            ForwardType::Local,
+            &Socket::new("127.0.0.1", 1234),
+            &Socket::new("localhost", 5432),
+        );
+        assert_eq!(flag, "-L");
+        assert_eq!(forwarding, "127.0.0.1:1234:localhost:5432");
+    }
+
+    #[test]
+    fn test_allocates_port() {
+        assert!(allocates_port(ForwardType::Remote, &Socket::new("", 0)));
+        assert!(!allocates_port(ForwardType::Remote, &Socket::new("", 22)));
+        assert!(!allocates_port(ForwardType::Local, &Socket::new("", 0)));
+        assert!(!allocates_port(
+            ForwardType::Remote,
+            &Path::new("/tmp/socket").into()
+        ));
+    }
+
+    #[test]
+    fn test_forward_guard_allocated_port() {
+        let guard = ForwardGuard::mux(
+            Path::new("/nonexistent"),
+            ForwardType::Remote,
+            &Socket::new("localhost", 40123),
+            &Socket::new("localhost", 80),
+            Some(40123),
+        );
+        assert_eq!(guard.allocated_port(), Some(40123));
+
+        match guard.imp.as_ref().unwrap() {
+            ForwardGuardImp::Mux { args, .. } => {
+                assert_eq!(args[1], "localhost:40123:localhost:80")
+            }
+            imp => unreachable!("{:?}", imp),
+        }
+    }
+
+    #[test]
+    fn test_interpret_forward_error() {
+        let err = interpret_forward_error(
+            "mux_client_forward: forwarding request failed: \
+             remote port forwarding failed for listen port 80\n",
+        );
+        match err {
+            Error::PortForwardRefused(err) => assert_eq!(
+                err.to_string(),
+                "mux_client_forward: forwarding request failed: \
+                 remote port forwarding failed for listen port 80"
+            ),
+            err => unreachable!("{:?}", err),
+        }
+
+        let err = interpret_forward_error("Control socket connect(/tmp/ctl): No such file");
+        assert!(matches!(err, Error::Ssh(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_forward_table() {
+        let table = ForwardTable::default();
+        let forward = |port| ActiveForward::Dynamic {
+            listen_socket: Socket::new("127.0.0.1", port),
+        };
+
+        let guard1 = ForwardGuard::task(tokio::spawn(async {})).track(&table, forward(1));
+        let guard2 = ForwardGuard::task(tokio::spawn(async {})).track(&table, forward(2));
+        assert_eq!(table.list(), [forward(1), forward(2)]);
+
+        drop(guard1);
+        assert_eq!(table.list(), [forward(2)]);
+
+        drop(guard2);
+        assert_eq!(table.list(), []);
+    }
+
+    #[test]
+    fn test_parse_allocated_port() {
+        assert_eq!(parse_allocated_port(b"40123\n"), Some(40123));
+        assert_eq!(parse_allocated_port(b""), None);
+        assert_eq!(parse_allocated_port(b"not a port\n"), None);
+    }
+}
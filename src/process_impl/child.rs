@@ -5,14 +5,16 @@ use std::process::ExitStatus;
 
 use tokio::process;
 
-// Disconnects the ssh session at drop, but does not kill the remote process.
+// Disconnects the ssh session at drop unless detached, but does not kill the
+// remote process.
 #[derive(Debug)]
 pub(crate) struct RemoteChild {
     channel: process::Child,
 }
 
 impl RemoteChild {
-    /// * `channel` - Must be created with `process::Command::kill_on_drop(true)`.
+    /// * `channel` - Must be created with `process::Command::kill_on_drop(true)`,
+    ///   unless detached with `DropPolicy::Detach`.
     pub(crate) fn new(channel: process::Child) -> Self {
         Self { channel }
     }
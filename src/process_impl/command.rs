@@ -1,46 +1,125 @@
 use super::Error;
+use super::Prewarm;
 use super::RemoteChild;
 use super::{ChildStderr, ChildStdin, ChildStdout};
 
+use crate::stdio::{Stdio, StdioImpl};
+use crate::wrapper::Wrapper;
+use crate::RequestTty;
+
+use std::borrow::Cow;
 use std::ffi::OsStr;
-use std::process::Stdio;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::OwnedFd;
+use std::path::Path;
+use std::process::Stdio as StdStdio;
+use std::sync::Arc;
 
+use tokio::io::AsyncWriteExt;
 use tokio::process;
 
 #[derive(Debug)]
 pub(crate) struct Command {
-    builder: process::Command,
+    ctl: Box<Path>,
+    /// The remote command line, which ssh joins from its arguments.
+    cmd: Vec<u8>,
+    subsystem: bool,
+
+    /// The pool to take a pre-spawned process from, or `None` if the command
+    /// cannot be run by one.
+    prewarm: Option<Arc<Prewarm>>,
+    /// Whether ssh is left running when the child is dropped.
+    detach: bool,
+    tty: RequestTty,
+    escape_char: Option<char>,
+    /// The `LC_ALL` of the local ssh process.
+    locale: Option<Box<str>>,
+
+    stdin_v: Stdio,
+    stdout_v: Stdio,
+    stderr_v: Stdio,
 }
 
 impl Command {
-    pub(crate) fn new(mut builder: process::Command) -> Self {
-        // Disconnects the ssh session at `RemoteChild::drop`, but does
-        // not kill the remote process.
-        builder.kill_on_drop(true);
+    /// * `prewarm` - The pool of the session, if the command may be run by a
+    ///   pre-spawned process.
+    pub(crate) fn new(
+        ctl: Box<Path>,
+        cmd: Vec<u8>,
+        subsystem: bool,
+        prewarm: Option<Arc<Prewarm>>,
+    ) -> Self {
+        Self {
+            ctl,
+            cmd,
+            subsystem,
+            prewarm,
+            detach: false,
+            tty: RequestTty::default(),
+            escape_char: None,
+            locale: None,
 
-        Self { builder }
+            stdin_v: Stdio::inherit(),
+            stdout_v: Stdio::inherit(),
+            stderr_v: Stdio::inherit(),
+        }
     }
 }
 
 impl Command {
+    pub(crate) fn ctl(&self) -> &Path {
+        &self.ctl
+    }
+
+    pub(crate) fn is_subsystem(&self) -> bool {
+        self.subsystem
+    }
+
+    pub(crate) fn detach(&mut self, detach: bool) {
+        self.detach = detach;
+    }
+
+    pub(crate) fn request_tty(&mut self, tty: RequestTty) {
+        self.tty = tty;
+    }
+
+    /// Return the local terminal that is the stdin of the remote process,
+    /// if it has a pseudo-terminal.
+    pub(crate) fn terminal(&self) -> Option<OwnedFd> {
+        match self.tty {
+            RequestTty::No => None,
+            _ => self.stdin_v.terminal(),
+        }
+    }
+
+    pub(crate) fn escape_char(&mut self, escape_char: Option<char>) {
+        self.escape_char = escape_char;
+    }
+
+    pub(crate) fn locale(&mut self, locale: &str) {
+        self.locale = Some(locale.into());
+    }
+
     pub(crate) fn raw_arg<S: AsRef<OsStr>>(&mut self, arg: S) {
-        self.builder.arg(arg);
+        self.cmd.push(b' ');
+        self.cmd.extend_from_slice(arg.as_ref().as_bytes());
     }
 
-    pub(crate) fn stdin<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stdin(cfg);
+    pub(crate) fn stdin(&mut self, cfg: Stdio) {
+        self.stdin_v = cfg;
     }
 
-    pub(crate) fn stdout<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stdout(cfg);
+    pub(crate) fn stdout(&mut self, cfg: Stdio) {
+        self.stdout_v = cfg;
     }
 
-    pub(crate) fn stderr<T: Into<Stdio>>(&mut self, cfg: T) {
-        self.builder.stderr(cfg);
+    pub(crate) fn stderr(&mut self, cfg: Stdio) {
+        self.stderr_v = cfg;
     }
 
     pub(crate) async fn spawn(
         &mut self,
+        wrapper: &Wrapper,
     ) -> Result<
         (
             RemoteChild,
@@ -50,7 +129,17 @@ impl Command {
         ),
         Error,
     > {
-        let mut channel = self.builder.spawn().map_err(Error::Ssh)?;
+        let line = if self.subsystem {
+            Cow::Borrowed(&*self.cmd)
+        } else {
+            wrapper.wrap(&self.cmd)
+        };
+        let line = OsStr::from_bytes(&line);
+
+        let mut channel = match self.spawn_prewarmed(line).await {
+            Some(channel) => channel,
+            None => self.to_ssh(line)?.spawn().map_err(Error::Ssh)?,
+        };
 
         let child_stdin = channel.stdin.take();
         let child_stdout = channel.stdout.take();
@@ -63,4 +152,117 @@ impl Command {
             child_stderr,
         ))
     }
+
+    /// Build the ssh command running the remote command `line`.
+    fn to_ssh(&self, line: &OsStr) -> Result<process::Command, Error> {
+        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
+        // succeed in establishing a _new_ connection if the master connection has failed.
+        // ssh recognizes `~` by default once a pseudo-terminal is requested.
+        let escape_char = self
+            .escape_char
+            .map_or_else(|| "none".to_owned(), String::from);
+        let mut args = tty_args(self.tty).to_vec();
+        args.extend(["-e", &escape_char, "-p", "9"]);
+        if self.subsystem {
+            args.push("-s");
+        }
+        let mut builder: process::Command = crate::session::new_ctl_cmd(&self.ctl, &args).into();
+        builder
+            .arg("--")
+            .arg(line)
+            .stdin(to_std(&self.stdin_v)?)
+            .stdout(to_std(&self.stdout_v)?)
+            .stderr(to_std(&self.stderr_v)?)
+            // Disconnects the ssh session at `RemoteChild::drop` unless
+            // detached, but does not kill the remote process unless
+            // `OwningCommand::kill_on_drop` is set.
+            //
+            // Unlike `pre_exec`, this does not keep std from spawning ssh with
+            // `posix_spawn`, see `new_ctl_cmd`.
+            .kill_on_drop(!self.detach);
+        if let Some(locale) = &self.locale {
+            builder.env("LC_ALL", &**locale);
+        }
+        Ok(builder)
+    }
+
+    /// Run the command line `line` by a process from the pool of the
+    /// session, if any.
+    async fn spawn_prewarmed(&self, line: &OsStr) -> Option<process::Child> {
+        // Pre-spawned processes are always killed on drop, and have no
+        // pseudo-terminal or locale of their own.
+        if self.detach || self.tty != RequestTty::No || self.locale.is_some() {
+            return None;
+        }
+        let prewarm = self.prewarm.as_ref()?;
+        let stdio = [
+            poolable(&self.stdin_v)?,
+            poolable(&self.stdout_v)?,
+            poolable(&self.stderr_v)?,
+        ];
+        // The command line is read up to the first newline.
+        if line.as_bytes().contains(&b'\n') {
+            return None;
+        }
+
+        let mut channel = prewarm.take()?;
+
+        let mut script = b"eval ".to_vec();
+        script.extend_from_slice(crate::escape::escape(line).as_bytes());
+        for (null, redirect) in stdio
+            .iter()
+            .zip([" </dev/null", " >/dev/null", " 2>/dev/null"])
+        {
+            if *null {
+                script.extend_from_slice(redirect.as_bytes());
+            }
+        }
+        script.push(b'\n');
+
+        let mut channel_stdin = channel.stdin.take()?;
+        channel_stdin.write_all(&script).await.ok()?;
+
+        let [stdin, stdout, stderr] = stdio;
+        if !stdin {
+            channel.stdin = Some(channel_stdin);
+        }
+        if stdout {
+            channel.stdout = None;
+        }
+        if stderr {
+            channel.stderr = None;
+        }
+        Some(channel)
+    }
+}
+
+/// Return the arguments requesting a pseudo-terminal as `tty` of ssh.
+fn tty_args(tty: RequestTty) -> &'static [&'static str] {
+    match tty {
+        RequestTty::No => &["-T"],
+        RequestTty::Auto => &["-o", "RequestTTY=auto"],
+        RequestTty::Yes => &["-t"],
+        RequestTty::Force => &["-tt"],
+    }
+}
+
+/// Convert `cfg` for spawning ssh, leaving it in place so that the command
+/// can be spawned again.
+fn to_std(cfg: &Stdio) -> Result<StdStdio, Error> {
+    Ok(match &cfg.0 {
+        StdioImpl::Null => StdStdio::null(),
+        StdioImpl::Pipe => StdStdio::piped(),
+        StdioImpl::Inherit => StdStdio::inherit(),
+        StdioImpl::Fd(fd, _) => fd.try_clone().map_err(Error::ChildIo)?.into(),
+    })
+}
+
+/// Return whether `cfg` is null or piped, which a pre-spawned process can
+/// provide.
+fn poolable(cfg: &Stdio) -> Option<bool> {
+    match cfg.0 {
+        StdioImpl::Null => Some(true),
+        StdioImpl::Pipe => Some(false),
+        _ => None,
+    }
 }
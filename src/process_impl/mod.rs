@@ -10,3 +10,6 @@ pub(crate) use command::Command;
 
 mod child;
 pub(crate) use child::RemoteChild;
+
+mod prewarm;
+use prewarm::Prewarm;
@@ -0,0 +1,78 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+
+use tokio::process;
+
+/// The remote command run by pre-spawned processes, which runs the command
+/// line it reads from stdin once it is taken from the pool.
+const READER: &str = "exec sh -c 'IFS= read -r c && eval \"$c\"'";
+
+/// A pool of ssh processes that are spawned ahead of time, each with an open
+/// session channel running a remote shell waiting for its command on stdin.
+#[derive(Debug)]
+pub(crate) struct Prewarm {
+    ctl: Box<Path>,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    size: usize,
+    idle: Vec<process::Child>,
+}
+
+impl Prewarm {
+    pub(crate) fn new(ctl: Box<Path>) -> Arc<Self> {
+        Arc::new(Self {
+            ctl,
+            inner: Mutex::default(),
+        })
+    }
+
+    /// Keep `size` processes waiting in the pool, spawning or killing the
+    /// difference.
+    pub(crate) fn set_size(&self, size: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.size = size;
+        inner.idle.truncate(size);
+        self.refill(&mut inner);
+    }
+
+    /// Take a process from the pool, replacing it with a new one.
+    pub(crate) fn take(&self) -> Option<process::Child> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let mut child = None;
+        while let Some(mut idle) = inner.idle.pop() {
+            // Skip processes that exited, e.g. since the master went away.
+            if let Ok(None) = idle.try_wait() {
+                child = Some(idle);
+                break;
+            }
+        }
+
+        self.refill(&mut inner);
+        child
+    }
+
+    fn refill(&self, inner: &mut Inner) {
+        while inner.idle.len() < inner.size {
+            let mut cmd: process::Command =
+                crate::session::new_ctl_cmd(&self.ctl, &["-T", "-p", "9"]).into();
+            // Disconnects the ssh session at drop, like `Command`.
+            cmd.arg("--")
+                .arg(READER)
+                .kill_on_drop(true)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+
+            match cmd.spawn() {
+                Ok(child) => inner.idle.push(child),
+                // Commands fall back to spawning a new process.
+                Err(_) => break,
+            }
+        }
+    }
+}
@@ -1,10 +1,12 @@
-use super::{Command, Error, ForwardType, Socket};
+use super::{Command, Error, ForwardType, Prewarm, Socket};
 
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use std::process::Stdio;
+use std::sync::Arc;
 
 use tokio::process;
 
@@ -15,6 +17,7 @@ pub(crate) struct Session {
     tempdir: Option<TempDir>,
     ctl: Box<Path>,
     master_log: Option<Box<Path>>,
+    prewarm: Arc<Prewarm>,
 }
 
 impl Session {
@@ -24,6 +27,7 @@ impl Session {
 
         Self {
             tempdir: Some(tempdir),
+            prewarm: Prewarm::new(ctl.clone()),
             ctl,
             master_log: Some(log),
         }
@@ -32,28 +36,21 @@ impl Session {
     pub(crate) fn resume(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
         Self {
             tempdir: None,
+            prewarm: Prewarm::new(ctl.clone()),
             ctl,
             master_log,
         }
     }
 
     fn new_std_cmd(&self, args: &[impl AsRef<OsStr>]) -> std::process::Command {
-        let mut cmd = std::process::Command::new("ssh");
-        cmd.stdin(Stdio::null())
-            .arg("-S")
-            .arg(&*self.ctl)
-            .arg("-o")
-            .arg("BatchMode=yes")
-            .args(args)
-            // ssh does not care about the addr as long as we have passed
-            // `-S &*self.ctl`.
-            // It is tested on OpenSSH 8.2p1, 8.9p1, 9.0p1
-            .arg("none");
-        cmd
+        crate::session::new_ctl_cmd(&self.ctl, args)
     }
 
     fn new_cmd(&self, args: &[impl AsRef<OsStr>]) -> process::Command {
-        self.new_std_cmd(args).into()
+        let mut cmd: process::Command = self.new_std_cmd(args).into();
+        // Do not leave ssh behind if the control command times out.
+        cmd.kill_on_drop(true);
+        cmd
     }
 
     pub(crate) async fn check(&self) -> Result<(), Error> {
@@ -78,28 +75,28 @@ impl Session {
         &self.ctl
     }
 
+    pub(crate) fn set_spawn_pool_size(&self, size: usize) {
+        self.prewarm.set_size(size);
+    }
+
     pub(crate) fn raw_command<S: AsRef<OsStr>>(&self, program: S) -> Command {
         // XXX: Should we do a self.check() here first?
-
-        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
-        // succeed in establishing a _new_ connection if the master connection has failed.
-
-        let mut cmd = self.new_cmd(&["-T", "-p", "9"]);
-        cmd.arg("--").arg(program);
-
-        Command::new(cmd)
+        Command::new(
+            self.ctl.clone(),
+            program.as_ref().as_bytes().into(),
+            false,
+            Some(Arc::clone(&self.prewarm)),
+        )
     }
 
     pub(crate) fn subsystem<S: AsRef<OsStr>>(&self, program: S) -> Command {
         // XXX: Should we do a self.check() here first?
-
-        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
-        // succeed in establishing a _new_ connection if the master connection has failed.
-
-        let mut cmd = self.new_cmd(&["-T", "-p", "9", "-s"]);
-        cmd.arg("--").arg(program);
-
-        Command::new(cmd)
+        Command::new(
+            self.ctl.clone(),
+            program.as_ref().as_bytes().into(),
+            true,
+            None,
+        )
     }
 
     pub(crate) async fn request_port_forward(
@@ -107,24 +104,24 @@ impl Session {
         forward_type: ForwardType,
         listen_socket: Socket<'_>,
         connect_socket: Socket<'_>,
-    ) -> Result<(), Error> {
-        let flag = match forward_type {
-            ForwardType::Local => OsStr::new("-L"),
-            ForwardType::Remote => OsStr::new("-R"),
-        };
-
-        let mut forwarding = listen_socket.as_os_str().into_owned();
-        forwarding.push(":");
-        forwarding.push(connect_socket.as_os_str());
+    ) -> Result<Option<u16>, Error> {
+        let [flag, forwarding] =
+            crate::port_forwarding::forwarding_args(forward_type, &listen_socket, &connect_socket);
 
         let port_forwarding = self
-            .new_cmd(&[OsStr::new("-fNT"), flag, &*forwarding])
+            .new_cmd(&[OsStr::new("-O"), OsStr::new("forward"), &flag, &forwarding])
             .output()
             .await
             .map_err(Error::Ssh)?;
 
         if port_forwarding.status.success() {
-            Ok(())
+            if crate::port_forwarding::allocates_port(forward_type, &listen_socket) {
+                Ok(crate::port_forwarding::parse_allocated_port(
+                    &port_forwarding.stdout,
+                ))
+            } else {
+                Ok(None)
+            }
         } else {
             let exit_err = String::from_utf8_lossy(&port_forwarding.stderr);
             let err = exit_err.trim();
@@ -135,7 +132,7 @@ impl Session {
                 }
             }
 
-            Err(Error::Ssh(io::Error::new(io::ErrorKind::Other, err)))
+            Err(crate::port_forwarding::interpret_forward_error(err))
         }
     }
 
@@ -185,6 +182,8 @@ impl Session {
     }
 
     pub(crate) fn detach(mut self) -> (Box<Path>, Option<Box<Path>>) {
+        // `TempDir::keep` is not available in the minimal supported tempfile.
+        #[allow(deprecated)]
         self.tempdir.take().map(TempDir::into_path);
         (self.ctl.clone(), self.master_log.take())
     }
@@ -194,6 +193,8 @@ impl Session {
             Ok(err) => err,
             Err(e) => return Some(Error::Master(e)),
         };
+        // Logged at `LogLevel=DEBUG1` and above, see `SessionBuilder::verbosity`.
+        let err = crate::known_hosts::strip_verbose(&err);
         let mut stderr = err.trim();
 
         stderr = stderr.strip_prefix("ssh: ").unwrap_or(stderr);
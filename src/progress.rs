@@ -0,0 +1,96 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::stream::Stream;
+use tokio::sync::mpsc;
+
+/// Progress of a multi-host operation of a [`SessionPool`](crate::SessionPool),
+/// such as [`run_on_all`](crate::SessionPool::run_on_all) or
+/// [`push_file`](crate::SessionPool::push_file).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(tag = "event", rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum FleetEvent {
+    /// The operation started on `host`.
+    Started {
+        /// The [name](crate::Host::name) of the host.
+        host: String,
+    },
+
+    /// `bytes` more bytes have been transferred to `host`.
+    Transferred {
+        /// The [name](crate::Host::name) of the host.
+        host: String,
+        /// The number of bytes transferred since the last event.
+        bytes: u64,
+    },
+
+    /// The operation finished on `host`.
+    Finished {
+        /// The [name](crate::Host::name) of the host.
+        host: String,
+        /// Whether the operation succeeded on the host.
+        success: bool,
+        /// How long the operation took on the host.
+        duration: Duration,
+    },
+}
+
+/// A stream of the [`FleetEvent`]s of a pool, created by
+/// [`SessionPool::progress`](crate::SessionPool::progress).
+///
+/// Events are buffered until they are read, and the stream ends once the
+/// pool has been dropped.
+#[derive(Debug)]
+pub struct FleetProgress {
+    events: mpsc::UnboundedReceiver<FleetEvent>,
+}
+
+impl FleetProgress {
+    pub(crate) fn new() -> (mpsc::UnboundedSender<FleetEvent>, Self) {
+        let (sender, events) = mpsc::unbounded_channel();
+        (sender, Self { events })
+    }
+
+    /// Wait for the next event, returning `None` once the pool has been
+    /// dropped.
+    pub async fn next_event(&mut self) -> Option<FleetEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for FleetProgress {
+    type Item = FleetEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<FleetEvent>> {
+        self.get_mut().events.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SessionBuilder, SessionPoolBuilder};
+
+    #[tokio::test]
+    async fn emit() {
+        let pool = SessionPoolBuilder::new(SessionBuilder::default()).build();
+
+        let mut progress = pool.progress();
+        drop(pool.progress());
+
+        let event = FleetEvent::Started {
+            host: "web1".into(),
+        };
+        pool.emit(event.clone());
+        assert_eq!(progress.next_event().await, Some(event));
+
+        drop(pool);
+        assert_eq!(progress.next_event().await, None);
+    }
+}
@@ -0,0 +1,99 @@
+use super::{Error, Socket};
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// A stream to a socket on (or reachable from) the remote host, created by
+/// [`Session::connect_remote`](crate::Session::connect_remote).
+///
+/// Behind the scenes, this is the stdin and stdout of a local `ssh -W`
+/// process using the multiplex master of the session.
+///
+/// Dropping the `RemoteStream` kills the local `ssh` process, which
+/// closes the connection.
+#[derive(Debug)]
+pub struct RemoteStream {
+    // Killed on drop.
+    _process: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl RemoteStream {
+    pub(crate) fn connect(ctl: &Path, remote: &Socket<'_>) -> Result<Self, Error> {
+        // NOTE: we pass -p 9 nine here (the "discard" port) to ensure that ssh does not
+        // succeed in establishing a _new_ connection if the master connection has failed.
+        let args = [
+            OsStr::new("-p"),
+            OsStr::new("9"),
+            OsStr::new("-W"),
+            &*remote.as_os_str(),
+        ];
+        let mut cmd: Command = crate::session::new_ctl_cmd(ctl, &args).into();
+
+        let mut process = cmd
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(Error::Ssh)?;
+
+        let stdin = process.stdin.take().expect("stdin is piped");
+        let stdout = process.stdout.take().expect("stdout is piped");
+
+        Ok(Self {
+            _process: process,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+impl AsyncRead for RemoteStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RemoteStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    /// Close the write half of the stream, signalling EOF to the remote socket.
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stdin.is_write_vectored()
+    }
+}
@@ -0,0 +1,161 @@
+use super::Host;
+
+use std::io;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    /// `key=value`
+    Equals(String, String),
+    /// `key!=value`
+    NotEquals(String, String),
+    /// `key`
+    Exists(String),
+    /// `!key`
+    Missing(String),
+}
+
+impl Term {
+    fn parse(term: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid selector term `{}`", term),
+            )
+        };
+        let key = |key: &str| {
+            let key = key.trim();
+            if key.is_empty() || key.contains(|c: char| c.is_whitespace() || "!=&|".contains(c)) {
+                Err(invalid())
+            } else {
+                Ok(key.to_owned())
+            }
+        };
+
+        let term = term.trim();
+        Ok(if let Some((k, v)) = term.split_once("!=") {
+            Term::NotEquals(key(k)?, key(v)?)
+        } else if let Some((k, v)) = term.split_once('=') {
+            Term::Equals(key(k)?, key(v)?)
+        } else if let Some(k) = term.strip_prefix('!') {
+            Term::Missing(key(k)?)
+        } else {
+            Term::Exists(key(term)?)
+        })
+    }
+
+    fn matches(&self, host: &Host) -> bool {
+        match self {
+            Term::Equals(key, value) => host.get_label(key) == Some(value),
+            Term::NotEquals(key, value) => host.get_label(key) != Some(value),
+            Term::Exists(key) => host.get_label(key).is_some(),
+            Term::Missing(key) => host.get_label(key).is_none(),
+        }
+    }
+}
+
+/// A selector of [`Host`]s by their labels, e.g. `role=web && region=eu`.
+///
+/// A selector consists of terms joined by `&&` and `||`, where `&&` binds
+/// tighter than `||`. A term is one of:
+///
+///  - `key=value`: the host has the label `key` with value `value`.
+///  - `key!=value`: the host does not have the label `key` with value
+///    `value`.
+///  - `key`: the host has the label `key`.
+///  - `!key`: the host does not have the label `key`.
+///
+/// ```rust
+/// use openssh::{Inventory, Selector};
+///
+/// let inventory = Inventory::parse(
+///     "web1 role=web region=eu\n\
+///      web2 role=web region=us\n\
+///      db1 role=db region=eu\n",
+/// )?;
+///
+/// let selector: Selector = "role=web && region=eu || role=db".parse()?;
+/// let hosts: Vec<_> = inventory.select(&selector).map(|host| host.name()).collect();
+/// assert_eq!(hosts, ["web1", "db1"]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    /// The alternatives, each matching if all of its terms match.
+    any: Vec<Vec<Term>>,
+}
+
+impl Selector {
+    /// Parse `selector`, failing with [`io::ErrorKind::InvalidInput`] if it
+    /// is malformed.
+    pub fn parse(selector: &str) -> io::Result<Self> {
+        let any = selector
+            .split("||")
+            .map(|all| all.split("&&").map(Term::parse).collect())
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self { any })
+    }
+
+    /// Return whether `host` is selected.
+    pub fn matches(&self, host: &Host) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|term| term.matches(host)))
+    }
+}
+
+impl FromStr for Selector {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        Self::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let mut web = Host::new("web1");
+        web.label("role", "web").label("region", "eu");
+        let mut db = Host::new("db1");
+        db.label("role", "db")
+            .label("region", "us")
+            .label("primary", "yes");
+
+        let selects = |selector: &str| -> Vec<&str> {
+            let selector = Selector::parse(selector).unwrap();
+            [&web, &db]
+                .into_iter()
+                .filter(|host| selector.matches(host))
+                .map(|host| host.name())
+                .collect()
+        };
+
+        assert_eq!(selects("role=web"), ["web1"]);
+        assert_eq!(selects(" role=web&&region=eu "), ["web1"]);
+        assert_eq!(selects("role=web && region=us"), Vec::<&str>::new());
+        assert_eq!(selects("role=web || primary"), ["web1", "db1"]);
+        assert_eq!(selects("region!=eu"), ["db1"]);
+        assert_eq!(selects("!primary"), ["web1"]);
+        assert_eq!(selects("role!=web && !primary || region=eu"), ["web1"]);
+    }
+
+    #[test]
+    fn parse_error() {
+        for selector in [
+            "",
+            "role=web &&",
+            "=web",
+            "role=",
+            "!",
+            "role=web region=eu",
+        ] {
+            let err = Selector::parse(selector).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", selector);
+        }
+    }
+}
@@ -1,4 +1,9 @@
-use super::{Error, ForwardType, KnownHosts, OwningCommand, SessionBuilder, Socket};
+use super::port_forwarding::ForwardTable;
+use super::{
+    ActiveForward, Child, Command, DockerSocket, Error, FileWatch, ForwardGuard, ForwardType,
+    KnownHosts, OwningCommand, PeriodicTask, RemoteStream, RemoteSupervisor, RestartPolicy,
+    Schedule, SessionBuilder, Socket, SocksProxy, SupervisedForward, TailFile,
+};
 
 #[cfg(feature = "process-mux")]
 use super::process_impl;
@@ -7,10 +12,18 @@ use super::process_impl;
 use super::native_mux_impl;
 
 use std::borrow::Cow;
-use std::ffi::OsStr;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
 use std::ops::Deref;
-use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
 
+use futures_util::future::try_join_all;
 use tempfile::TempDir;
 
 #[derive(Debug)]
@@ -42,18 +55,99 @@ macro_rules! delegate {
     }};
 }
 
+/// Create a `ssh` command that talks to the multiplex master listening on `ctl`.
+///
+/// Commands are spawned for every remote command, so keep them cheap to
+/// spawn: std spawns them with `posix_spawn` (which uses `vfork` on Linux)
+/// as long as no `pre_exec` hook, uid, gid or process group is set, and the
+/// file descriptors it opens are close-on-exec, so none leak into ssh. Most
+/// of the remaining time is spent by ssh itself, see
+/// [`Session::set_spawn_pool_size`].
+pub(crate) fn new_ctl_cmd(ctl: &Path, args: &[impl AsRef<OsStr>]) -> std::process::Command {
+    let mut cmd = std::process::Command::new("ssh");
+    cmd.stdin(Stdio::null())
+        .arg("-S")
+        .arg(ctl)
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .args(args)
+        // ssh does not care about the addr as long as we have passed
+        // `-S ctl`.
+        // It is tested on OpenSSH 8.2p1, 8.9p1, 9.0p1
+        .arg("none");
+    cmd
+}
+
+/// Run `fut`, failing with [`Error::Timeout`] if it takes longer than
+/// `timeout`.
+async fn with_deadline<T>(
+    timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .unwrap_or(Err(Error::Timeout)),
+        None => fut.await,
+    }
+}
+
 /// A single SSH session to a remote host.
 ///
 /// You can use [`command`](Session::command) to start a new command on the connected machine.
 ///
 /// When the `Session` is dropped, the connection to the remote host is severed, and any errors
 /// silently ignored. To disconnect and be alerted to errors, use [`close`](Session::close).
+pub struct Session {
+    imp: SessionImp,
+    forwards: ForwardTable,
+    strict_commands: bool,
+    /// Environment variables set for all commands, with valid names.
+    env: Vec<(String, String)>,
+    locale: Option<Box<str>>,
+    control_timeout: Option<Duration>,
+    origin: Option<Origin>,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("destination", &self.destination())
+            .field("user", &self.user())
+            .field("port", &self.port())
+            .field("control_socket", &self.control_socket())
+            .finish_non_exhaustive()
+    }
+}
+
+/// What a session connected by a [`SessionBuilder`] was created with.
 #[derive(Debug)]
-pub struct Session(SessionImp);
+pub(crate) struct Origin {
+    pub(crate) destination: Box<str>,
+    pub(crate) user: Option<Box<str>>,
+    pub(crate) port: Option<u16>,
+    pub(crate) known_hosts: KnownHosts,
+}
 
 // TODO: UserKnownHostsFile for custom known host fingerprint.
 
 impl Session {
+    fn from_imp(imp: SessionImp) -> Self {
+        Self {
+            imp,
+            forwards: ForwardTable::default(),
+            strict_commands: false,
+            env: Vec::new(),
+            locale: None,
+            control_timeout: None,
+            origin: None,
+        }
+    }
+
+    pub(crate) fn set_origin(&mut self, origin: Origin) {
+        self.origin = Some(origin);
+    }
+
     /// The method for creating a [`Session`] and externally control the creation of TempDir.
     ///
     /// By using the built-in [`SessionBuilder`] in openssh, or a custom SessionBuilder,
@@ -96,7 +190,7 @@ impl Session {
     /// ```
     #[cfg(feature = "process-mux")]
     pub fn new_process_mux(tempdir: TempDir) -> Self {
-        Self(SessionImp::ProcessImpl(process_impl::Session::new(tempdir)))
+        Self::from_imp(SessionImp::ProcessImpl(process_impl::Session::new(tempdir)))
     }
 
     /// The method for creating a [`Session`] and externally control the creation of TempDir.
@@ -140,7 +234,7 @@ impl Session {
     /// ```
     #[cfg(feature = "native-mux")]
     pub fn new_native_mux(tempdir: TempDir) -> Self {
-        Self(SessionImp::NativeMuxImpl(native_mux_impl::Session::new(
+        Self::from_imp(SessionImp::NativeMuxImpl(native_mux_impl::Session::new(
             tempdir,
         )))
     }
@@ -159,7 +253,7 @@ impl Session {
     #[cfg(feature = "process-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
     pub fn resume(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
-        Self(SessionImp::ProcessImpl(process_impl::Session::resume(
+        Self::from_imp(SessionImp::ProcessImpl(process_impl::Session::resume(
             ctl, master_log,
         )))
     }
@@ -169,16 +263,48 @@ impl Session {
     #[cfg(feature = "native-mux")]
     #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
     pub fn resume_mux(ctl: Box<Path>, master_log: Option<Box<Path>>) -> Self {
-        Self(SessionImp::NativeMuxImpl(native_mux_impl::Session::resume(
+        Self::from_imp(SessionImp::NativeMuxImpl(native_mux_impl::Session::resume(
             ctl, master_log,
         )))
     }
 
+    /// Same as [`Session::resume`], but verify that the control socket `ctl`
+    /// can be trusted first, failing with [`Error::UntrustedControlSocket`]
+    /// otherwise.
+    ///
+    /// The socket must be owned by and only accessible to the current user,
+    /// its directory must not be writable by other users unless it is
+    /// sticky, and the master listening on it must be run by the current
+    /// user. This prevents attaching to a socket planted by another local
+    /// user at a predictable path.
+    #[cfg(feature = "process-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
+    pub async fn resume_verified(
+        ctl: Box<Path>,
+        master_log: Option<Box<Path>>,
+    ) -> Result<Self, Error> {
+        crate::control_socket::verify(&ctl).await?;
+        Ok(Self::resume(ctl, master_log))
+    }
+
+    /// Same as [`Session::resume_verified`] except that it connects to
+    /// the ssh multiplex master using native mux impl.
+    #[cfg(feature = "native-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "native-mux")))]
+    pub async fn resume_mux_verified(
+        ctl: Box<Path>,
+        master_log: Option<Box<Path>>,
+    ) -> Result<Self, Error> {
+        crate::control_socket::verify(&ctl).await?;
+        Ok(Self::resume_mux(ctl, master_log))
+    }
+
     /// Connect to the host at the given `host` over SSH using process impl, which will
     /// spawn a new ssh process for each `Child` created.
     ///
     /// The format of `destination` is the same as the `destination` argument to `ssh`. It may be
-    /// specified as either `[user@]hostname` or a URI of the form `ssh://[user@]hostname[:port]`.
+    /// specified as either `[user@]hostname` or a URI of the form `ssh://[user@]hostname[:port]`,
+    /// which can carry options as described in [`SessionBuilder::try_resolve`].
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
     /// password), the connection will fail. Consider setting up keypair-based authentication
@@ -200,7 +326,8 @@ impl Session {
     /// See the crate-level documentation for more details on the difference between native and process-based mux.
     ///
     /// The format of `destination` is the same as the `destination` argument to `ssh`. It may be
-    /// specified as either `[user@]hostname` or a URI of the form `ssh://[user@]hostname[:port]`.
+    /// specified as either `[user@]hostname` or a URI of the form `ssh://[user@]hostname[:port]`,
+    /// which can carry options as described in [`SessionBuilder::try_resolve`].
     ///
     /// If connecting requires interactive authentication based on `STDIN` (such as reading a
     /// password), the connection will fail. Consider setting up keypair-based authentication
@@ -219,18 +346,267 @@ impl Session {
             .await
     }
 
+    /// Get the host this session was connected to, without the user and port,
+    /// e.g. `example.com` for `ssh://me@example.com:2222`.
+    ///
+    /// This is the host as passed to `ssh`, which may resolve it to another
+    /// host name with `~/.ssh/config`. Returns `None` if the session was not
+    /// connected with [`SessionBuilder`] or [`Session::connect`], e.g. if it
+    /// was resumed.
+    pub fn destination(&self) -> Option<&str> {
+        self.origin.as_ref().map(|origin| &*origin.destination)
+    }
+
+    /// Get the user this session was connected as, if set in the destination
+    /// or with [`SessionBuilder::user`].
+    ///
+    /// Returns `None` if the user was left to `ssh`, which uses the one set
+    /// in `~/.ssh/config` or the local user, or if the session was not
+    /// connected with [`SessionBuilder`].
+    pub fn user(&self) -> Option<&str> {
+        self.origin.as_ref()?.user.as_deref()
+    }
+
+    /// Get the port this session was connected on, if set in the destination
+    /// or with [`SessionBuilder::port`].
+    ///
+    /// Returns `None` if the port was left to `ssh`, or if the session was
+    /// not connected with [`SessionBuilder`].
+    pub fn port(&self) -> Option<u16> {
+        self.origin.as_ref()?.port
+    }
+
+    /// Get the policy for checking the host key this session was connected
+    /// with, or `None` if it was not connected with [`SessionBuilder`].
+    pub fn known_hosts(&self) -> Option<&KnownHosts> {
+        self.origin.as_ref().map(|origin| &origin.known_hosts)
+    }
+
+    /// Make the commands subsequently created from this session reject
+    /// arguments containing shell metacharacters, including the program
+    /// passed to [`command`](Self::command), see [`OwningCommand::strict`].
+    ///
+    /// The programs passed to [`raw_command`](Self::raw_command) and
+    /// [`subsystem`](Self::subsystem), and the shell command passed to
+    /// [`shell`](Self::shell), are explicitly raw and are not checked.
+    ///
+    /// Defaults to `false`.
+    pub fn set_strict_commands(&mut self, strict: bool) {
+        self.strict_commands = strict;
+    }
+
+    /// Set the environment variables `vars` for the commands subsequently
+    /// created from this session, replacing the values set before for the
+    /// same names.
+    ///
+    /// The variables are set with `env(1)` through the remote shell, so
+    /// unlike `SendEnv` they need not be accepted by the server. Commands
+    /// created with [`subsystem`](Self::subsystem) are not affected.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] without setting any
+    /// variable if a name is not a valid shell variable name.
+    pub fn set_envs<I, K, V>(&mut self, vars: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars: Vec<(String, String)> = vars
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect();
+        if let Some((name, _)) = vars
+            .iter()
+            .find(|(name, _)| !crate::env_file::is_valid_name(name))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid variable name `{}`", name),
+            ));
+        }
+
+        for (name, value) in vars {
+            self.env.retain(|(set, _)| *set != name);
+            self.env.push((name, value));
+        }
+        Ok(())
+    }
+
+    /// Set the environment variables of the local `.env` file at `path` for
+    /// the commands subsequently created from this session, like
+    /// [`set_envs`](Self::set_envs).
+    ///
+    /// The file has one `NAME=value` assignment per line, optionally
+    /// prefixed with `export`, and `#` comments. Values may be single
+    /// quoted, or double quoted with `\"`, `\\`, `\$` and `\n` escapes.
+    /// Nothing is expanded.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] naming the offending line
+    /// if the file cannot be parsed.
+    pub fn source_env(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let vars = crate::env_file::parse(&std::fs::read_to_string(path)?)?;
+        self.set_envs(vars)
+    }
+
+    /// Run the commands subsequently created from this session with the
+    /// locale `locale`, e.g. `C`, or with the locale of the remote user if
+    /// `None`, see [`OwningCommand::locale`].
+    ///
+    /// Defaults to `None`.
+    pub fn set_locale(&mut self, locale: Option<&str>) {
+        self.locale = locale.map(Into::into);
+    }
+
+    /// Remove the environment variables set with
+    /// [`set_envs`](Self::set_envs) or [`source_env`](Self::source_env).
+    pub fn clear_envs(&mut self) {
+        self.env.clear();
+    }
+
+    /// Keep `size` ssh processes spawned ahead of time, each with a session
+    /// channel open and a remote shell waiting for its command, which are
+    /// used to run commands instead of spawning a new ssh process.
+    ///
+    /// This reduces the latency of spawning commands from the time it takes
+    /// to start ssh and open a channel on the master to a single write, which
+    /// helps bursty workloads. A used process is replaced right away.
+    ///
+    /// Only commands created with [`command`](Self::command),
+    /// [`raw_command`](Self::raw_command) or [`shell`](Self::shell) whose
+    /// stdin, stdout and stderr are [`Stdio::piped`](crate::Stdio::piped) or
+    /// [`Stdio::null`](crate::Stdio::null), and whose command line does not
+    /// contain a newline, are run by the pool. They are run through `sh` on
+    /// the remote host.
+    ///
+    /// Every waiting process holds a session of the master, and servers limit
+    /// the sessions of a connection (`MaxSessions`, 10 by default).
+    ///
+    /// Defaults to `0`. Has no effect on sessions using the native mux
+    /// implementation, which spawns commands without new processes anyway.
+    /// Must be called within a tokio runtime.
+    #[cfg(feature = "process-mux")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "process-mux")))]
+    pub fn set_spawn_pool_size(&self, size: usize) {
+        #[allow(irrefutable_let_patterns)]
+        if let SessionImp::ProcessImpl(imp) = &self.imp {
+            imp.set_spawn_pool_size(size);
+        }
+    }
+
+    /// Bound the time [`check`](Self::check), [`close`](Self::close) and
+    /// [`request_port_forward`](Self::request_port_forward) wait for the
+    /// multiplex master to `timeout`, after which they fail with
+    /// [`Error::Timeout`], so that a wedged master cannot hang them.
+    ///
+    /// Defaults to `None`, which waits indefinitely.
+    pub fn set_control_timeout(&mut self, timeout: Option<Duration>) {
+        self.control_timeout = timeout;
+    }
+
     /// Check the status of the underlying SSH connection.
     #[cfg(not(windows))]
     #[cfg_attr(docsrs, doc(cfg(not(windows))))]
     pub async fn check(&self) -> Result<(), Error> {
-        delegate!(&self.0, imp, { imp.check().await })
+        with_deadline(self.control_timeout, async {
+            delegate!(&self.imp, imp, { imp.check().await })
+        })
+        .await
+    }
+
+    /// Measure the latency of the connection to the remote host by running
+    /// an empty remote command, returning the time it took.
+    ///
+    /// This covers opening a channel on the existing connection and the
+    /// command exiting, i.e. several round trips to the remote host, but no
+    /// new connection or authentication. With the process mux
+    /// implementation it also includes spawning a local `ssh` process, so
+    /// only compare latencies measured with the same implementation.
+    ///
+    /// Bounded by the timeout set with
+    /// [`set_control_timeout`](Self::set_control_timeout), if any.
+    pub async fn ping(&self) -> Result<Duration, Error> {
+        with_deadline(self.control_timeout, async {
+            let start = Instant::now();
+            let status = self
+                .raw_command("true")
+                .stdin(crate::Stdio::null())
+                .stdout(crate::Stdio::null())
+                .stderr(crate::Stdio::null())
+                .status()
+                .await?;
+            let elapsed = start.elapsed();
+
+            if !status.success() {
+                return Err(Error::Remote(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("ping failed with {}", status),
+                )));
+            }
+            Ok(elapsed)
+        })
+        .await
+    }
+
+    /// Run the shell command `command` as `user` on the remote host with
+    /// `su - user -c`, for hosts without `sudo`, answering the password
+    /// prompt of `su` with `password`, and collect its output.
+    ///
+    /// The command runs with a pseudo-terminal, which `su` needs to read the
+    /// password, so its stdout and stderr are both collected as stdout, and
+    /// the stderr of the returned [`Output`](std::process::Output) only has
+    /// messages of ssh itself. The password is written to the
+    /// pseudo-terminal rather than passed on a command line, and only if
+    /// `su` prompts for it, which it does not when the remote user is root.
+    ///
+    /// Fails with [`Error::Remote`] of kind
+    /// [`PermissionDenied`](io::ErrorKind::PermissionDenied) if the password
+    /// is wrong. Other failures of `su`, e.g. if `user` does not exist, are
+    /// reported through the exit status and output like failures of
+    /// `command`.
+    pub async fn su_output(
+        &self,
+        user: &str,
+        password: &str,
+        command: &str,
+    ) -> Result<std::process::Output, Error> {
+        crate::su::su_output(self, user, password, command).await
+    }
+
+    /// Run the login shell of the remote user with the local terminal as its
+    /// terminal until it exits, e.g. to let the user of an application drop
+    /// into a shell on the host without authenticating again.
+    ///
+    /// The local terminal is put into raw mode, so that keys such as `^C`
+    /// are sent to the remote shell rather than signalling the local
+    /// process, and restored afterwards. Changes to the size of the local
+    /// terminal are propagated to the remote shell. As in an interactive
+    /// `ssh` session, `~.` at the beginning of a line disconnects.
+    ///
+    /// Fails with [`Error::ChildIo`] if stdin is not a terminal.
+    pub async fn interactive_shell(&self) -> Result<ExitStatus, Error> {
+        crate::terminal::interactive_shell(self).await
+    }
+
+    /// Get the version the server announced when connecting, e.g.
+    /// `OpenSSH_8.9p1 Ubuntu-3ubuntu0.1`, if it was captured with
+    /// [`SessionBuilder::capture_server_version`].
+    ///
+    /// The version is read from the control directory of the session, so
+    /// it is also available for resumed sessions.
+    #[cfg(not(windows))]
+    #[cfg_attr(docsrs, doc(cfg(not(windows))))]
+    pub fn server_version(&self) -> Option<String> {
+        let path = self
+            .control_socket()
+            .with_file_name(crate::builder::SERVER_VERSION_FILE);
+        std::fs::read_to_string(path).ok()
     }
 
     /// Get the SSH connection's control socket path.
     #[cfg(not(windows))]
     #[cfg_attr(docsrs, doc(cfg(not(windows))))]
     pub fn control_socket(&self) -> &Path {
-        delegate!(&self.0, imp, { imp.ctl() })
+        delegate!(&self.imp, imp, { imp.ctl() })
     }
 
     /// Constructs a new [`OwningCommand`] for launching the program at path `program` on the remote
@@ -330,7 +706,11 @@ impl Session {
         P: Into<Cow<'a, str>>,
         S: Deref<Target = Session> + Clone,
     {
-        Self::to_raw_command(session, &*shell_escape::unix::escape(program.into()))
+        let program = program.into();
+        let mut command =
+            Self::to_raw_command(session, &*shell_escape::unix::escape(program.clone()));
+        command.reject_unsafe(&program);
+        command
     }
 
     /// Version of [`raw_command`](Self::raw_command) which stores an
@@ -342,10 +722,18 @@ impl Session {
         P: AsRef<OsStr>,
         S: Deref<Target = Session> + Clone,
     {
-        let session_impl = delegate!(&session.0, imp, {
+        let session_impl = delegate!(&session.imp, imp, {
             imp.raw_command(program.as_ref()).into()
         });
-        OwningCommand::new(session, session_impl)
+        let strict = session.strict_commands;
+        let env = session.env.clone();
+        let locale = session.locale.clone();
+        let mut command = OwningCommand::new(session, session_impl);
+        command.strict(strict).session_env(env);
+        if let Some(locale) = locale {
+            command.locale(&locale);
+        }
+        command
     }
 
     /// Constructs a new [`OwningCommand`] for launching subsystem `program` on the remote
@@ -412,8 +800,17 @@ impl Session {
         P: AsRef<OsStr>,
         S: Deref<Target = Session> + Clone,
     {
-        let session_impl = delegate!(&session.0, imp, { imp.subsystem(program.as_ref()).into() });
-        OwningCommand::new(session, session_impl)
+        let session_impl = delegate!(&session.imp, imp, {
+            imp.subsystem(program.as_ref()).into()
+        });
+        let strict = session.strict_commands;
+        let locale = session.locale.clone();
+        let mut command = OwningCommand::new(session, session_impl);
+        command.strict(strict);
+        if let Some(locale) = locale {
+            command.locale(&locale);
+        }
+        command
     }
 
     /// Constructs a new [`OwningCommand`] that runs the provided shell command on the remote host.
@@ -457,10 +854,188 @@ impl Session {
     ///   [`shell-escape`]: https://crates.io/crates/shell-escape
     pub fn shell<S: AsRef<str>>(&self, command: S) -> OwningCommand<&'_ Self> {
         let mut cmd = self.command("sh");
-        cmd.arg("-c").arg(command.as_ref());
+        cmd.arg("-c")
+            .raw_arg(&*shell_escape::unix::escape(command.as_ref().into()));
         cmd
     }
 
+    /// Spawn all of `commands` at once, returning the children in the same
+    /// order, e.g. to fan out many short commands to this host.
+    ///
+    /// The commands are spawned concurrently, like
+    /// [`OwningCommand::spawn`], so their stdin, stdout and stderr are
+    /// inherited unless configured otherwise. Combine this with
+    /// [`set_spawn_pool_size`](Self::set_spawn_pool_size) to avoid
+    /// starting an ssh process per command with the process mux
+    /// implementation.
+    ///
+    /// If any command fails to spawn, the error is returned and the children
+    /// spawned so far are dropped. Note that servers limit the sessions of a
+    /// connection (`MaxSessions`, 10 by default), so commands beyond the
+    /// limit fail while as many others are running.
+    pub async fn spawn_all<'s>(
+        &'s self,
+        mut commands: Vec<Command<'s>>,
+    ) -> Result<Vec<Child<&'s Self>>, Error> {
+        try_join_all(commands.iter_mut().map(|command| command.spawn())).await
+    }
+
+    /// Expand the glob `pattern` on the remote host, returning the paths of
+    /// the matching files.
+    ///
+    /// Only `*`, `?` and bracket expressions made of alphanumeric characters
+    /// and `-`, `!`, `^`, `_`, `.` (e.g. `[0-9]`) are expanded; everything
+    /// else in `pattern` is passed literally, so it cannot be used to inject
+    /// shell commands. Relative patterns are relative to the home directory of
+    /// the remote user and, like in a shell, wildcards do not match a leading
+    /// `.` of a file name.
+    ///
+    /// Returns an empty `Vec` if nothing matches.
+    pub async fn glob(&self, pattern: impl AsRef<OsStr>) -> Result<Vec<PathBuf>, Error> {
+        let mut cmd: OsString = "for f in ".into();
+        cmd.push(crate::escape::escape_glob(pattern.as_ref()));
+        cmd.push(r#"; do if [ -e "$f" ] || [ -L "$f" ]; then printf '%s\0' "$f"; fi; done"#);
+
+        let output = self
+            .raw_command(cmd)
+            .stdin(crate::Stdio::null())
+            .stderr(crate::Stdio::piped())
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            return Err(Error::Remote(io::Error::new(
+                io::ErrorKind::Other,
+                stderr.trim(),
+            )));
+        }
+
+        Ok(output
+            .stdout
+            .split(|&b| b == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+            .collect())
+    }
+
+    /// Return the environment variables of a command run on the remote
+    /// host, which helps with diagnosing e.g. a `PATH` or locale that
+    /// differs from the one of an interactive login shell.
+    ///
+    /// This is the environment the non-interactive shell of the remote user
+    /// gives commands, including the variables set with
+    /// [`set_envs`](Self::set_envs). It is read with `env -0`, which the
+    /// `env` of GNU coreutils, BusyBox and the BSDs support, but not the
+    /// one of macOS.
+    pub async fn remote_env(&self) -> Result<HashMap<OsString, OsString>, Error> {
+        let output = self
+            .command("env")
+            .arg("-0")
+            .stdin(crate::Stdio::null())
+            .stderr(crate::Stdio::piped())
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            return Err(Error::Remote(io::Error::new(
+                io::ErrorKind::Other,
+                stderr.trim(),
+            )));
+        }
+
+        Ok(parse_env(&output.stdout))
+    }
+
+    /// Stream the lines of the file at `path` on the remote host, starting
+    /// with its last 10 lines.
+    ///
+    /// With `follow`, the file keeps being watched with `tail -F` for lines
+    /// appended to it, and is reopened if it is rotated, i.e. renamed or
+    /// truncated and recreated. Otherwise, the stream ends after the last line.
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{Session, KnownHosts};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let mut log = session.tail_file("/var/log/syslog", true).await?;
+    /// while let Some(line) = log.next_line().await? {
+    ///     println!("{}", line);
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn tail_file(
+        &self,
+        path: impl AsRef<Path>,
+        follow: bool,
+    ) -> Result<TailFile<'_>, Error> {
+        let mut cmd = self.command("tail");
+        if follow {
+            cmd.arg("-F");
+        }
+        cmd.arg("--")
+            .raw_arg(crate::escape::escape(path.as_ref().as_os_str()));
+
+        let child = cmd
+            .stdin(crate::Stdio::null())
+            .stdout(crate::Stdio::piped())
+            .stderr(crate::Stdio::null())
+            .spawn()
+            .await?;
+
+        Ok(TailFile::new(child))
+    }
+
+    /// Watch the file or directory at `path` on the remote host for changes,
+    /// e.g. to sync local files on change.
+    ///
+    /// The changes are watched with `inotifywait` from inotify-tools or, if it
+    /// is not installed, with `fswatch`. If neither is available on the remote
+    /// host, this fails with [`Error::Remote`]. With `recursive`, changes in all
+    /// subdirectories of `path` are watched as well.
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), openssh::Error> {
+    /// use openssh::{Session, KnownHosts, WatchEventKind};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let mut watch = session.watch("/srv/app", true).await?;
+    /// while let Some(event) = watch.next_event().await? {
+    ///     if event.kind == WatchEventKind::Modified {
+    ///         println!("{} changed", event.path.display());
+    ///     }
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub async fn watch(
+        &self,
+        path: impl AsRef<Path>,
+        recursive: bool,
+    ) -> Result<FileWatch<'_>, Error> {
+        let child = self
+            .raw_command(crate::watch::watch_script(
+                path.as_ref().as_os_str(),
+                recursive,
+            ))
+            .stdin(crate::Stdio::null())
+            .stdout(crate::Stdio::piped())
+            .stderr(crate::Stdio::null())
+            .spawn()
+            .await?;
+
+        FileWatch::new(child).await
+    }
+
     /// Request to open a local/remote port forwarding.
     /// The `Socket` can be either a unix socket or a tcp socket.
     ///
@@ -470,22 +1045,335 @@ impl Session {
     /// Otherwise, `listen_socket` on the remote machine will be forwarded to `connect_socket`
     /// on the local machine.
     ///
-    /// Currently, there is no way of stopping a port forwarding due to the fact that
-    /// openssh multiplex server/master does not support this.
+    /// For a remote forwarding, the host of a tcp `listen_socket` is the address
+    /// the remote host binds to. An empty host or `*` binds to all interfaces and
+    /// `localhost` binds to the loopback interface only. Binding to anything but
+    /// the loopback interface requires `GatewayPorts clientspecified` in the sshd
+    /// config of the remote host, otherwise sshd silently binds to the loopback
+    /// interface instead.
+    ///
+    /// If the multiplex master or the remote host refuses the forwarding, e.g.
+    /// because the port is already in use, [`Error::PortForwardRefused`] is
+    /// returned.
+    ///
+    /// The forwarding stays open until the returned [`ForwardGuard`] is
+    /// dropped or [cancelled](ForwardGuard::cancel).
+    ///
+    /// If `forward_type` == Remote and `listen_socket` is a tcp socket with port `0`,
+    /// the remote host picks the port to listen on, which is available through
+    /// [`ForwardGuard::allocated_port`].
+    ///
+    /// Note that the port of a local forwarding cannot be allocated this way, since
+    /// ssh does not report it. Use [`tunnel_tcp`](Session::tunnel_tcp) instead.
     pub async fn request_port_forward(
         &self,
         forward_type: impl Into<ForwardType>,
         listen_socket: impl Into<Socket<'_>>,
         connect_socket: impl Into<Socket<'_>>,
-    ) -> Result<(), Error> {
-        delegate!(&self.0, imp, {
-            imp.request_port_forward(
-                forward_type.into(),
-                listen_socket.into(),
-                connect_socket.into(),
-            )
-            .await
+    ) -> Result<ForwardGuard, Error> {
+        let forward_type = forward_type.into();
+        let listen_socket = listen_socket.into();
+        let connect_socket = connect_socket.into();
+
+        let allocated_port = with_deadline(self.control_timeout, async {
+            delegate!(&self.imp, imp, {
+                imp.request_port_forward(
+                    forward_type,
+                    listen_socket.clone(),
+                    connect_socket.clone(),
+                )
+                .await
+            })
         })
+        .await?;
+
+        let listen_socket = match (listen_socket, allocated_port) {
+            (Socket::TcpSocket { host, .. }, Some(port)) => Socket::TcpSocket { host, port },
+            (listen_socket, _) => listen_socket,
+        };
+
+        Ok(ForwardGuard::mux(
+            self.control_socket(),
+            forward_type,
+            &listen_socket,
+            &connect_socket,
+            allocated_port,
+        )
+        .track(
+            &self.forwards,
+            ActiveForward::new(forward_type, listen_socket, connect_socket),
+        ))
+    }
+
+    /// Return the forwardings established through this session that are
+    /// still open, in the order they have been established.
+    ///
+    /// This includes the forwardings requested with
+    /// [`SessionBuilder::port_forward`] and those whose [`ForwardGuard`],
+    /// [`SocksProxy`] or [`SupervisedForward`] has not been dropped yet.
+    ///
+    /// The ssh multiplex protocol offers no way of listing the forwardings of
+    /// the master, so this is tracked by the session itself. If the master
+    /// has exited, none of them are actually established anymore, which can
+    /// be verified with [`check`](Session::check).
+    pub fn forwards(&self) -> Vec<ActiveForward> {
+        self.forwards.list()
+    }
+
+    /// Track a forwarding requested with [`SessionBuilder::port_forward`],
+    /// which stays open until the session is closed.
+    pub(crate) fn track_forward(&self, forward: ActiveForward) {
+        self.forwards.insert(forward);
+    }
+
+    /// Request a port forwarding like
+    /// [`request_port_forward`](Session::request_port_forward), and keep it up
+    /// by requesting it again every `interval`.
+    ///
+    /// This is meant for forwardings that have to stay up for hours, which
+    /// may be lost e.g. if the multiplex master is restarted. Losing and
+    /// restoring the forwarding is reported by
+    /// [`SupervisedForward::next_event`].
+    ///
+    /// Supervising requires a tokio runtime, since it runs in a task.
+    pub async fn supervise_port_forward(
+        &self,
+        forward_type: impl Into<ForwardType>,
+        listen_socket: impl Into<Socket<'_>>,
+        connect_socket: impl Into<Socket<'_>>,
+        interval: Duration,
+    ) -> Result<SupervisedForward, Error> {
+        let guard = self
+            .request_port_forward(forward_type, listen_socket, connect_socket)
+            .await?;
+
+        Ok(SupervisedForward::new(guard, interval))
+    }
+
+    /// Spawn the command returned by `make_command`, and spawn a new one
+    /// from it whenever the remote process exits, as configured by `policy`,
+    /// e.g. to keep a worker running on the remote host.
+    ///
+    /// The commands are spawned with
+    /// [`kill_on_drop`](OwningCommand::kill_on_drop), so the remote process
+    /// is killed when the returned [`RemoteSupervisor`] is dropped. They
+    /// inherit stdio unless `make_command` sets it. Spawning, exits and
+    /// restarts are reported by [`RemoteSupervisor::next_event`].
+    ///
+    /// ```rust,no_run
+    /// # async fn foo(session: std::sync::Arc<openssh::Session>) {
+    /// use openssh::{RestartPolicy, Session, Stdio};
+    ///
+    /// let mut supervisor = session.supervise_command(RestartPolicy::default(), |session| {
+    ///     let mut command = session.arc_command("/opt/app/worker");
+    ///     command.stdin(Stdio::null()).stdout(Stdio::null());
+    ///     command
+    /// });
+    /// while let Some(event) = supervisor.next_event().await {
+    ///     eprintln!("worker: {:?}", event);
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// Supervising requires a tokio runtime, since it runs in a task.
+    pub fn supervise_command<F>(
+        self: std::sync::Arc<Self>,
+        policy: RestartPolicy,
+        make_command: F,
+    ) -> RemoteSupervisor
+    where
+        F: FnMut(std::sync::Arc<Session>) -> OwningCommand<std::sync::Arc<Session>>
+            + Send
+            + 'static,
+    {
+        RemoteSupervisor::new(self, policy, make_command)
+    }
+
+    /// Run the command returned by `make_command` on the remote host as
+    /// scheduled by `schedule`, passing the result of each run to
+    /// `on_result`, e.g. for health probes without setting up cron on the
+    /// remote host.
+    ///
+    /// Each run is waited for like [`OwningCommand::output`] before the next
+    /// one starts, so runs never overlap; runs due while one is in progress
+    /// are skipped.
+    ///
+    /// ```rust,no_run
+    /// # async fn foo(session: std::sync::Arc<openssh::Session>) {
+    /// use openssh::Schedule;
+    /// use std::time::Duration;
+    ///
+    /// let mut schedule = Schedule::every(Duration::from_secs(30));
+    /// schedule.jitter(Duration::from_secs(5));
+    ///
+    /// let probe = session.run_periodically(
+    ///     schedule,
+    ///     |session| {
+    ///         let mut command = session.arc_command("systemctl");
+    ///         command.args(["is-active", "nginx"]);
+    ///         command
+    ///     },
+    ///     |result| match result {
+    ///         Ok(output) if output.status.success() => (),
+    ///         result => eprintln!("nginx is down: {:?}", result),
+    ///     },
+    /// );
+    /// # }
+    /// ```
+    ///
+    /// Running periodically requires a tokio runtime, since it runs in a
+    /// task. The returned [`PeriodicTask`] stops it when dropped.
+    pub fn run_periodically<F, C>(
+        self: std::sync::Arc<Self>,
+        schedule: Schedule,
+        make_command: F,
+        on_result: C,
+    ) -> PeriodicTask
+    where
+        F: FnMut(std::sync::Arc<Session>) -> OwningCommand<std::sync::Arc<Session>>
+            + Send
+            + 'static,
+        C: FnMut(Result<std::process::Output, Error>) + Send + 'static,
+    {
+        PeriodicTask::new(self, schedule, make_command, on_result)
+    }
+
+    /// Expose the unix socket at `local_path` on the local machine at
+    /// `remote_path` on the remote host, e.g. to publish an ssh-agent socket.
+    ///
+    /// sshd refuses to listen on a path that already exists unless
+    /// `StreamLocalBindUnlink` is enabled in its config, so a socket left
+    /// behind at `remote_path` by a previous connection is removed before
+    /// requesting the forwarding. Anything else at `remote_path`, such as a
+    /// regular file, is left alone and the forwarding is refused. For local
+    /// forwardings, see [`SessionBuilder::stream_local_bind_unlink`].
+    ///
+    /// The forwarding stays open until the returned [`ForwardGuard`] is
+    /// dropped or [cancelled](ForwardGuard::cancel).
+    pub async fn expose_unix_socket(
+        &self,
+        local_path: impl AsRef<Path>,
+        remote_path: impl AsRef<Path>,
+    ) -> Result<ForwardGuard, Error> {
+        let remote_path = remote_path.as_ref();
+
+        // The forwarding request reports it if the path cannot be removed.
+        let path = crate::escape::escape(remote_path.as_os_str());
+        let mut unlink = OsString::from("[ ! -S ");
+        unlink.push(&path);
+        unlink.push(" ] || rm -f -- ");
+        unlink.push(&path);
+        self.raw_command(unlink)
+            .stdin(crate::Stdio::null())
+            .stdout(crate::Stdio::null())
+            .stderr(crate::Stdio::null())
+            .status()
+            .await?;
+
+        self.request_port_forward(ForwardType::Remote, remote_path, local_path.as_ref())
+            .await
+    }
+
+    /// Open a stream to `remote`, as seen from the remote host, by running
+    /// `ssh -W` over the multiplex master.
+    ///
+    /// This lets you speak arbitrary protocols to services that are only
+    /// reachable from the remote host, e.g. a database listening on its
+    /// loopback interface. The `Socket` can be either a unix socket or a
+    /// tcp socket.
+    ///
+    /// If the remote host fails to connect to `remote`, the returned
+    /// [`RemoteStream`] will yield EOF.
+    pub async fn connect_remote(
+        &self,
+        remote: impl Into<Socket<'_>>,
+    ) -> Result<RemoteStream, Error> {
+        RemoteStream::connect(self.control_socket(), &remote.into())
+    }
+
+    /// Bind a tcp listener on an ephemeral port of the local loopback
+    /// interface, and forward every connection made to it to `remote`, as
+    /// seen from the remote host.
+    ///
+    /// Returns the address of the listener and a [`ForwardGuard`] that
+    /// stops the listener when dropped.
+    ///
+    /// Unlike a forwarding requested with
+    /// [`request_port_forward`](Session::request_port_forward), the local
+    /// port is allocated by the OS and the listener is bound before this
+    /// function returns, so there is no race for picking a free port. This is
+    /// the building block for connecting any client library through the ssh
+    /// session:
+    ///
+    /// ```rust,no_run
+    /// # #[cfg(feature = "native-mux")]
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use openssh::{Session, KnownHosts};
+    ///
+    /// let session = Session::connect_mux("me@ssh.example.com", KnownHosts::Strict).await?;
+    ///
+    /// let (addr, _guard) = session.tunnel_tcp(openssh::Socket::new("localhost", 5432)).await?;
+    /// let postgres_url = format!("postgres://user@{}/db", addr);
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// Every connection is carried by its own `ssh -W` process, see
+    /// [`connect_remote`](Session::connect_remote).
+    pub async fn tunnel_tcp(
+        &self,
+        remote: impl Into<Socket<'_>>,
+    ) -> Result<(SocketAddr, ForwardGuard), Error> {
+        let remote = remote.into().into_owned();
+        let (addr, guard) =
+            ForwardGuard::tunnel_tcp(self.control_socket().into(), remote.clone()).await?;
+
+        let forward = ActiveForward::Local {
+            listen_socket: addr.into(),
+            connect_socket: remote,
+        };
+
+        Ok((addr, guard.track(&self.forwards, forward)))
+    }
+
+    /// Forward the docker socket at `remote_path` on the remote host, usually
+    /// `/var/run/docker.sock`, to a unix socket in a temporary directory on
+    /// the local machine.
+    ///
+    /// Docker clients pointed at [`DockerSocket::docker_host`] manage the
+    /// containers of the remote host through this session, without opening
+    /// another ssh connection like `DOCKER_HOST=ssh://` does. The remote
+    /// user needs access to the socket, e.g. by being in the `docker` group.
+    ///
+    /// The forwarding is cancelled when the returned [`DockerSocket`] is
+    /// dropped.
+    pub async fn forward_docker_socket(
+        &self,
+        remote_path: impl AsRef<Path>,
+    ) -> Result<DockerSocket, Error> {
+        let dir = tempfile::Builder::new()
+            .prefix(".docker")
+            .tempdir()
+            .map_err(Error::Tunnel)?;
+        let path = dir.path().join("docker.sock");
+
+        let guard = self
+            .request_port_forward(ForwardType::Local, path.as_path(), remote_path.as_ref())
+            .await?;
+
+        Ok(DockerSocket::new(guard, path, dir))
+    }
+
+    /// Start a SOCKS5 proxy listening on `listen_addr` on the local machine,
+    /// forwarding connections through the remote host.
+    ///
+    /// If the port of `listen_addr` is `0`, a free port is picked, which is
+    /// available through [`SocksProxy::local_addr`]. Since ssh does not report
+    /// the port it binds, another process may grab it in between.
+    ///
+    /// The proxy is closed when the returned [`SocksProxy`] is dropped.
+    pub async fn socks_proxy(&self, listen_addr: SocketAddr) -> Result<SocksProxy, Error> {
+        SocksProxy::new(self.control_socket(), &self.forwards, listen_addr).await
     }
 
     /// Terminate the remote connection.
@@ -493,7 +1381,10 @@ impl Session {
     /// This destructor terminates the ssh multiplex server
     /// regardless of how it was created.
     pub async fn close(self) -> Result<(), Error> {
-        let res: Result<Option<TempDir>, Error> = delegate!(self.0, imp, { imp.close().await });
+        let res: Result<Option<TempDir>, Error> = with_deadline(self.control_timeout, async {
+            delegate!(self.imp, imp, { imp.close().await })
+        })
+        .await;
 
         res?.map(TempDir::close)
             .transpose()
@@ -506,6 +1397,70 @@ impl Session {
     ///
     /// Return (path to control socket, path to ssh multiplex output log)
     pub fn detach(self) -> (Box<Path>, Option<Box<Path>>) {
-        delegate!(self.0, imp, { imp.detach() })
+        delegate!(self.imp, imp, { imp.detach() })
+    }
+}
+
+/// Parse the `NAME=value` entries separated by NUL bytes that `env -0`
+/// outputs, where values may contain `=` and newlines.
+fn parse_env(output: &[u8]) -> HashMap<OsString, OsString> {
+    output
+        .split(|&b| b == 0)
+        .filter_map(|entry| {
+            let eq = entry.iter().position(|&b| b == b'=')?;
+            Some((
+                OsStr::from_bytes(&entry[..eq]).to_owned(),
+                OsStr::from_bytes(&entry[eq + 1..]).to_owned(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    #[tokio::test]
+    async fn control_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctl = dir.path().join("master");
+        // A master that accepts connections, but never answers.
+        let _listener = UnixListener::bind(&ctl).unwrap();
+
+        let mut sessions = Vec::new();
+        #[cfg(feature = "process-mux")]
+        sessions.push(Session::resume(ctl.clone().into(), None));
+        #[cfg(feature = "native-mux")]
+        sessions.push(Session::resume_mux(ctl.clone().into(), None));
+
+        for mut session in sessions {
+            session.set_control_timeout(Some(Duration::from_millis(500)));
+
+            let err = session.check().await.unwrap_err();
+            assert!(matches!(err, Error::Timeout), "{:?}", err);
+
+            let err = session
+                .request_port_forward(
+                    ForwardType::Local,
+                    (std::net::Ipv4Addr::LOCALHOST, 8080),
+                    (std::net::Ipv4Addr::LOCALHOST, 80),
+                )
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::Timeout), "{:?}", err);
+
+            let err = session.close().await.unwrap_err();
+            assert!(matches!(err, Error::Timeout), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn parse_env() {
+        let env = super::parse_env(b"PATH=/usr/bin:/bin\0OPTS=a=b\nc\0EMPTY=\0junk\0");
+        assert_eq!(env.len(), 3);
+        assert_eq!(env[OsStr::new("PATH")], "/usr/bin:/bin");
+        assert_eq!(env[OsStr::new("OPTS")], "a=b\nc");
+        assert_eq!(env[OsStr::new("EMPTY")], "");
     }
 }
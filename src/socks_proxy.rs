@@ -0,0 +1,99 @@
+use super::port_forwarding::ForwardTable;
+use super::{ActiveForward, Error, ForwardGuard};
+
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A SOCKS5 proxy listening on the local machine, created by
+/// [`Session::socks_proxy`](crate::Session::socks_proxy).
+///
+/// Connections made through the proxy originate from the remote host, so
+/// any client that supports SOCKS5 can reach the network of the remote host
+/// through it. Most HTTP clients accept the URL returned by
+/// [`url`](SocksProxy::url), e.g. with `reqwest`:
+///
+/// ```rust,ignore
+/// let proxy = session.socks_proxy(([127, 0, 0, 1], 0).into()).await?;
+/// let client = reqwest::Client::builder()
+///     .proxy(reqwest::Proxy::all(proxy.url())?)
+///     .build()?;
+/// ```
+///
+/// This is a dynamic forwarding (`ssh -D`) of the multiplex master, which is
+/// cancelled when the `SocksProxy` is dropped.
+#[derive(Debug)]
+#[must_use = "the proxy is closed when dropped"]
+pub struct SocksProxy {
+    addr: SocketAddr,
+    guard: ForwardGuard,
+}
+
+impl SocksProxy {
+    pub(crate) async fn new(
+        ctl: &Path,
+        forwards: &ForwardTable,
+        mut addr: SocketAddr,
+    ) -> Result<Self, Error> {
+        if addr.port() == 0 {
+            // ssh does not report the port it binds for a dynamic forwarding,
+            // so pick a free one beforehand.
+            addr = TcpListener::bind(addr)
+                .and_then(|listener| listener.local_addr())
+                .map_err(Error::Tunnel)?;
+        }
+
+        let args = ["-D".into(), addr.to_string().into()];
+        crate::port_forwarding::forward_with_ssh(ctl, &args).await?;
+
+        let forward = ActiveForward::Dynamic {
+            listen_socket: addr.into(),
+        };
+
+        Ok(Self {
+            addr,
+            guard: ForwardGuard::mux_args(ctl, args).track(forwards, forward),
+        })
+    }
+
+    /// Return the address the proxy listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Return the URL of the proxy, e.g. `socks5h://127.0.0.1:1080`.
+    ///
+    /// The `socks5h` scheme tells clients to let the proxy resolve host
+    /// names, so they are resolved by the remote host.
+    pub fn url(&self) -> String {
+        format!("socks5h://{}", self.addr)
+    }
+
+    /// Check that the proxy accepts connections and speaks SOCKS5.
+    ///
+    /// This returns `false` once the proxy is cancelled or the multiplex
+    /// master has exited.
+    pub async fn is_alive(&self) -> bool {
+        async {
+            let mut stream = TcpStream::connect(self.addr).await?;
+
+            // Version 5, offering the "no authentication required" method.
+            stream.write_all(&[5, 1, 0]).await?;
+
+            let mut reply = [0_u8; 2];
+            stream.read_exact(&mut reply).await?;
+
+            Ok::<_, io::Error>(reply == [5, 0])
+        }
+        .await
+        .unwrap_or(false)
+    }
+
+    /// Close the proxy, reporting any error.
+    pub async fn close(self) -> Result<(), Error> {
+        self.guard.cancel().await
+    }
+}
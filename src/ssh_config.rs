@@ -0,0 +1,304 @@
+use super::SessionBuilder;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// An OpenSSH client config file, such as `~/.ssh/config`, to make
+/// [`SessionBuilder`]s from its `Host` entries.
+///
+/// This lets a program honor the settings users already have for their
+/// hosts while still overriding some of them on the returned builder. Only
+/// the settings that have a counterpart on [`SessionBuilder`] are applied:
+/// `HostName`, `User`, `Port`, `IdentityFile` and `ProxyJump`. All other
+/// keywords are ignored, as are `Match` blocks and `Include` directives.
+///
+/// As with ssh, the first value found for a keyword wins, so entries for
+/// specific hosts go before more general ones such as `Host *`.
+///
+/// ```text
+/// Host bastion
+///     HostName bastion.example.com
+///     User jump
+///
+/// Host db? !db9
+///     HostName %h.internal
+///     ProxyJump bastion
+///
+/// Host *
+///     User deploy
+///     IdentityFile ~/.ssh/deploy_ed25519
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+/// The options of a `Host` or `Match` block, or of the lines before the
+/// first block.
+#[derive(Debug, Clone)]
+struct Block {
+    /// The patterns of a `Host` block, or `None` for `Match` blocks, which
+    /// are never applied.
+    patterns: Option<Vec<String>>,
+    /// The keywords in lowercase, with their arguments.
+    options: Vec<(String, Vec<String>)>,
+}
+
+impl Block {
+    fn matches(&self, host: &str) -> bool {
+        let patterns = match &self.patterns {
+            Some(patterns) => patterns,
+            None => return false,
+        };
+
+        let mut matched = false;
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(pattern) if glob(pattern.as_bytes(), host.as_bytes()) => return false,
+                Some(_) => (),
+                None => matched |= glob(pattern.as_bytes(), host.as_bytes()),
+            }
+        }
+        matched
+    }
+}
+
+impl SshConfig {
+    /// Parse the contents of an ssh config file.
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let mut blocks = vec![Block {
+            patterns: Some(vec!["*".into()]),
+            options: Vec::new(),
+        }];
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (keyword, args) = parse_line(line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", n + 1, err),
+                )
+            })?;
+
+            match &*keyword {
+                "host" => blocks.push(Block {
+                    patterns: Some(args),
+                    options: Vec::new(),
+                }),
+                "match" => blocks.push(Block {
+                    patterns: None,
+                    options: Vec::new(),
+                }),
+                _ => blocks
+                    .last_mut()
+                    .expect("there is always a block")
+                    .options
+                    .push((keyword, args)),
+            }
+        }
+
+        Ok(Self { blocks })
+    }
+
+    /// Read and parse the ssh config file at `path`.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Return the first value of `keyword` for `host`.
+    fn get(&self, host: &str, keyword: &str) -> Option<&[String]> {
+        self.blocks
+            .iter()
+            .filter(|block| block.matches(host))
+            .flat_map(|block| &block.options)
+            .find(|(k, _)| k == keyword)
+            .map(|(_, args)| &**args)
+    }
+
+    /// Return a copy of `builder` with the settings of `host` applied, along
+    /// with the destination to pass to [`SessionBuilder::connect`]: the
+    /// `HostName` of `host` if it has one, `host` itself otherwise.
+    ///
+    /// ```rust
+    /// use openssh::{SessionBuilder, SshConfig};
+    ///
+    /// let config = SshConfig::parse("Host web\n  HostName web1.example.com\n  Port 2222\n")?;
+    /// let (mut builder, destination) = config.session_builder("web", &SessionBuilder::default());
+    /// builder.user("admin".to_owned());
+    ///
+    /// assert_eq!(destination, "web1.example.com");
+    /// assert_eq!(builder.get_port(), Some("2222"));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn session_builder(
+        &self,
+        host: &str,
+        builder: &SessionBuilder,
+    ) -> (SessionBuilder, String) {
+        let mut builder = builder.clone();
+        let first = |keyword| {
+            self.get(host, keyword)
+                .and_then(|args| args.first())
+                .map(String::as_str)
+        };
+
+        if let Some(user) = first("user") {
+            builder.user(user.to_owned());
+        }
+        if let Some(port) = first("port").and_then(|port| port.parse().ok()) {
+            builder.port(port);
+        }
+        if let Some(keyfile) = first("identityfile") {
+            builder.keyfile(keyfile);
+        }
+        match first("proxyjump") {
+            Some(jump_hosts) if !jump_hosts.eq_ignore_ascii_case("none") => {
+                builder.jump_hosts(jump_hosts.split(','));
+            }
+            _ => (),
+        }
+
+        let destination = match first("hostname") {
+            Some(hostname) => hostname.replace("%h", host).replace("%%", "%"),
+            None => host.to_owned(),
+        };
+        (builder, destination)
+    }
+}
+
+/// Split `line` into its lowercase keyword and its arguments, which are
+/// separated by whitespace and may be quoted.
+fn parse_line(line: &str) -> Result<(String, Vec<String>), String> {
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args = Vec::new();
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut arg = String::new();
+        match chars.peek() {
+            None => break,
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(format!("unterminated quote in `{}`", keyword)),
+                    }
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        args.push(arg);
+    }
+
+    if args.is_empty() {
+        return Err(format!("missing argument for `{}`", keyword));
+    }
+    Ok((keyword.to_ascii_lowercase(), args))
+}
+
+/// Return whether `s` matches `pattern`, where `*` matches any number of
+/// characters and `?` exactly one, case-insensitively like ssh.
+fn glob(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => (0..=s.len()).any(|i| glob(rest, &s[i..])),
+        Some((&p, rest)) => match s.split_first() {
+            Some((&c, s)) if p == b'?' || p.eq_ignore_ascii_case(&c) => glob(rest, s),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_builder() {
+        let config = SshConfig::parse(
+            "# global\n\
+             Port 22\n\
+             \n\
+             Host bastion\n\
+             \x20   HostName bastion.example.com\n\
+             \n\
+             Host db? !db9\n\
+             \x20   HostName=%h.internal\n\
+             \x20   port 2222\n\
+             \x20   ProxyJump jump@bastion,other\n\
+             \n\
+             Match user root\n\
+             \x20   User root\n\
+             \n\
+             Host *\n\
+             \x20   User deploy\n\
+             \x20   IdentityFile \"~/.ssh/my key\"\n\
+             \x20   ProxyJump none\n",
+        )
+        .unwrap();
+        let base = SessionBuilder::default();
+
+        let (builder, destination) = config.session_builder("db1", &base);
+        assert_eq!(destination, "db1.internal");
+        assert_eq!(builder.get_user(), Some("deploy"));
+        // Options before the first `Host` apply to all hosts, and come first.
+        assert_eq!(builder.get_port(), Some("22"));
+        assert_eq!(
+            config.get("db1", "proxyjump"),
+            Some(&["jump@bastion,other".to_owned()][..])
+        );
+        assert_eq!(
+            config.get("db1", "identityfile"),
+            Some(&["~/.ssh/my key".to_owned()][..])
+        );
+
+        let (_, destination) = config.session_builder("db9", &base);
+        assert_eq!(destination, "db9");
+        assert_eq!(
+            config.get("db9", "proxyjump"),
+            Some(&["none".to_owned()][..])
+        );
+
+        let (_, destination) = config.session_builder("BASTION", &base);
+        assert_eq!(destination, "bastion.example.com");
+    }
+
+    #[test]
+    fn parse_error() {
+        let err = SshConfig::parse("Host web\n  User\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 2: missing argument for `User`");
+
+        let err = SshConfig::parse("IdentityFile \"~/.ssh/id").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: unterminated quote in `IdentityFile`"
+        );
+    }
+
+    #[test]
+    fn glob() {
+        assert!(super::glob(b"*.example.com", b"web.example.com"));
+        assert!(super::glob(b"db?", b"db1"));
+        assert!(!super::glob(b"db?", b"db10"));
+        assert!(super::glob(b"*", b""));
+    }
+}
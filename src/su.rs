@@ -0,0 +1,147 @@
+//! Running commands as another user with `su`, answering its password prompt
+//! through a pseudo-terminal.
+
+use super::{Error, RequestTty, Session, Stdio};
+
+use crate::escape::escape;
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::process::Output;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::try_join;
+
+/// Return the length of the password prompt of `su` that `output` starts
+/// with, if it is complete, e.g. `Password: `.
+///
+/// Only the first line can be the prompt, so that output of the command
+/// itself is never answered with the password.
+fn prompt_len(output: &[u8]) -> Option<usize> {
+    if output.contains(&b'\n') {
+        return None;
+    }
+    let prompt = String::from_utf8_lossy(output);
+    (prompt.trim_end().ends_with(':') && prompt.to_ascii_lowercase().contains("password"))
+        .then_some(output.len())
+}
+
+/// Whether `output` could still become a password prompt.
+fn prompt_pending(output: &[u8]) -> bool {
+    !output.contains(&b'\n')
+}
+
+/// Run `command` as `user` on `session`, see [`Session::su_output`].
+pub(crate) async fn su_output(
+    session: &Session,
+    user: &str,
+    password: &str,
+    command: &str,
+) -> Result<Output, Error> {
+    // The pseudo-terminal would otherwise turn `\n` into `\r\n`, and the
+    // prompt is only recognized in English.
+    let mut line = OsString::from("stty -onlcr 2>/dev/null; exec su - ");
+    line.push(escape(OsStr::new(user)));
+    line.push(" -c ");
+    line.push(escape(OsStr::new(command)));
+
+    let mut child = session
+        .raw_command(line)
+        .request_tty(RequestTty::Force)
+        .locale("C")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+    // Kept open until the command exited, closing it would hang up the
+    // pseudo-terminal.
+    let mut stdin = child.stdin().take().expect("stdin is piped");
+    let mut child_stdout = child.stdout().take().expect("stdout is piped");
+    let mut child_stderr = child.stderr().take().expect("stderr is piped");
+
+    let stdout_read = async {
+        let mut stdout = Vec::new();
+        let mut pending = true;
+        let mut prompted = false;
+        let mut chunk = vec![0; 4096];
+
+        loop {
+            let n = child_stdout
+                .read(&mut chunk)
+                .await
+                .map_err(Error::ChildIo)?;
+            if n == 0 {
+                break;
+            }
+            stdout.extend_from_slice(&chunk[..n]);
+
+            if pending {
+                if let Some(len) = prompt_len(&stdout) {
+                    stdin
+                        .write_all(format!("{}\n", password).as_bytes())
+                        .await
+                        .map_err(Error::ChildIo)?;
+                    stdin.flush().await.map_err(Error::ChildIo)?;
+                    stdout.drain(..len);
+                    prompted = true;
+                }
+                pending = !prompted && prompt_pending(&stdout);
+            }
+        }
+
+        // `su` ends the line of the prompt once it read the password.
+        if prompted && stdout.starts_with(b"\n") {
+            stdout.remove(0);
+        }
+        Ok::<_, Error>(stdout)
+    };
+    let stderr_read = async {
+        let mut stderr = Vec::new();
+        child_stderr
+            .read_to_end(&mut stderr)
+            .await
+            .map_err(Error::ChildIo)?;
+        Ok::<_, Error>(stderr)
+    };
+
+    #[allow(clippy::incompatible_msrv)]
+    let (stdout, stderr) = try_join!(stdout_read, stderr_read)?;
+    let status = child.wait().await?;
+    drop(stdin);
+
+    if !status.success() {
+        let message = String::from_utf8_lossy(&stdout);
+        let message = message.lines().next().unwrap_or("").trim();
+        if message.ends_with("Authentication failure") {
+            return Err(Error::Remote(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                message,
+            )));
+        }
+    }
+
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt() {
+        assert_eq!(prompt_len(b"Password: "), Some(10));
+        assert_eq!(prompt_len(b"Password:"), Some(9));
+        assert_eq!(prompt_len(b"Password"), None);
+        assert_eq!(prompt_len(b"Pass"), None);
+        assert!(prompt_pending(b"Pass"));
+
+        // Output of the command when `su` did not prompt, e.g. run as root.
+        assert_eq!(prompt_len(b"old password:\nnew password: "), None);
+        assert!(!prompt_pending(b"done\n"));
+    }
+}
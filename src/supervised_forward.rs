@@ -0,0 +1,95 @@
+use super::{Error, ForwardGuard};
+
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+/// A change of state of a [`SupervisedForward`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ForwardEvent {
+    /// Re-requesting the forwarding failed, e.g. because the multiplex master
+    /// has exited. It is retried at the next interval.
+    Lost(Error),
+
+    /// The forwarding has been re-established after it was lost.
+    Restored,
+}
+
+/// A forwarding that is re-requested periodically so that it stays up,
+/// created by [`Session::supervise_port_forward`](crate::Session::supervise_port_forward).
+///
+/// Dropping the `SupervisedForward` stops the supervision and cancels the
+/// forwarding.
+#[derive(Debug)]
+#[must_use = "the forwarding is closed when dropped"]
+pub struct SupervisedForward {
+    /// Aborts the supervisor task on drop.
+    task: ForwardGuard,
+    events: mpsc::UnboundedReceiver<ForwardEvent>,
+    guard: ForwardGuard,
+}
+
+impl SupervisedForward {
+    pub(crate) fn new(guard: ForwardGuard, interval: Duration) -> Self {
+        let (ctl, args) = guard
+            .mux_forwarding()
+            .expect("a forwarding of the multiplex master");
+        let (ctl, args) = (Box::<std::path::Path>::from(ctl), args.clone());
+
+        let (sender, events) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(async move {
+            let mut lost = false;
+
+            loop {
+                tokio::time::sleep(interval).await;
+
+                // Requesting an established forwarding again is a no-op for
+                // the multiplex master, so this only restores it if it is gone.
+                match crate::port_forwarding::forward_with_ssh(&ctl, &args).await {
+                    Ok(_) if lost => {
+                        lost = false;
+                        let _ = sender.send(ForwardEvent::Restored);
+                    }
+                    Ok(_) => (),
+                    Err(err) if !lost => {
+                        lost = true;
+                        let _ = sender.send(ForwardEvent::Lost(err));
+                    }
+                    Err(_) => (),
+                }
+            }
+        });
+
+        Self {
+            task: ForwardGuard::task(task),
+            events,
+            guard,
+        }
+    }
+
+    /// Return the port the remote host allocated for a remote forwarding
+    /// listening on port `0`.
+    ///
+    /// The same port is requested again when the forwarding is restored.
+    pub fn allocated_port(&self) -> Option<u16> {
+        self.guard.allocated_port()
+    }
+
+    /// Wait for the next change of state of the forwarding.
+    pub async fn next_event(&mut self) -> ForwardEvent {
+        // The sender is only dropped along with the task, which runs until
+        // `self` is dropped.
+        self.events
+            .recv()
+            .await
+            .expect("supervisor task is running")
+    }
+
+    /// Stop the supervision and close the forwarding, reporting any error.
+    pub async fn cancel(self) -> Result<(), Error> {
+        drop(self.task);
+        self.guard.cancel().await
+    }
+}
@@ -0,0 +1,203 @@
+use super::{Error, OwningCommand, Session};
+
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// When a [`RemoteSupervisor`] restarts its remote process, and how long it
+/// waits before doing so.
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    max_restarts: Option<u32>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    reset_after: Duration,
+    restart_on_success: bool,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: Some(5),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            reset_after: Duration::from_secs(60),
+            restart_on_success: false,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Give up after restarting the process `max_restarts` times in a row,
+    /// or never if `None`.
+    ///
+    /// Defaults to `Some(5)`.
+    pub fn max_restarts(&mut self, max_restarts: Option<u32>) -> &mut Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// Set the backoff before the first restart, which doubles with every
+    /// restart in a row up to `max`.
+    ///
+    /// Defaults to 1s, up to 30s.
+    pub fn backoff(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Consider a process that ran for at least `duration` healthy, so that
+    /// its exit starts over with the first restart and backoff.
+    ///
+    /// Defaults to 60s.
+    pub fn reset_after(&mut self, duration: Duration) -> &mut Self {
+        self.reset_after = duration;
+        self
+    }
+
+    /// Also restart the process when it exits successfully, rather than
+    /// ending the supervision.
+    ///
+    /// Defaults to `false`.
+    pub fn restart_on_success(&mut self, restart: bool) -> &mut Self {
+        self.restart_on_success = restart;
+        self
+    }
+
+    /// Return the backoff before restart `restart` in a row, starting at `1`.
+    fn backoff_for(&self, restart: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(restart - 1))
+            .min(self.max_backoff)
+    }
+}
+
+/// A change of state of the process of a [`RemoteSupervisor`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SupervisorEvent {
+    /// The remote process has been spawned.
+    Started,
+
+    /// The remote process exited with the status.
+    Exited(ExitStatus),
+
+    /// Spawning or waiting for the remote process failed, e.g. because the
+    /// connection was lost.
+    Failed(Error),
+
+    /// The remote process is restarted after `backoff`, for the `restart`th
+    /// time in a row.
+    Restarting {
+        /// The number of restarts in a row, starting at `1`.
+        restart: u32,
+        /// How long the supervisor waits before restarting.
+        backoff: Duration,
+    },
+}
+
+/// A remote process that is restarted when it exits, created by
+/// [`Session::supervise_command`](crate::Session::supervise_command).
+///
+/// Dropping the `RemoteSupervisor` stops the supervision and kills the
+/// remote process.
+#[derive(Debug)]
+#[must_use = "the remote process is killed when dropped"]
+pub struct RemoteSupervisor {
+    task: JoinHandle<()>,
+    events: mpsc::UnboundedReceiver<SupervisorEvent>,
+}
+
+impl RemoteSupervisor {
+    pub(crate) fn new<F>(session: Arc<Session>, policy: RestartPolicy, mut make_command: F) -> Self
+    where
+        F: FnMut(Arc<Session>) -> OwningCommand<Arc<Session>> + Send + 'static,
+    {
+        let (sender, events) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(async move {
+            let mut restarts = 0;
+
+            loop {
+                let started = Instant::now();
+                let mut command = make_command(Arc::clone(&session));
+                let status = match command.kill_on_drop(true).spawn().await {
+                    Ok(child) => {
+                        let _ = sender.send(SupervisorEvent::Started);
+                        child.wait().await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                match status {
+                    Ok(status) => {
+                        let _ = sender.send(SupervisorEvent::Exited(status));
+                        if status.success() && !policy.restart_on_success {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        let _ = sender.send(SupervisorEvent::Failed(err));
+                    }
+                }
+
+                if started.elapsed() >= policy.reset_after {
+                    restarts = 0;
+                }
+                if policy.max_restarts.map_or(false, |max| restarts >= max) {
+                    break;
+                }
+
+                restarts += 1;
+                let backoff = policy.backoff_for(restarts);
+                let _ = sender.send(SupervisorEvent::Restarting {
+                    restart: restarts,
+                    backoff,
+                });
+                tokio::time::sleep(backoff).await;
+            }
+        });
+
+        Self { task, events }
+    }
+
+    /// Wait for the next change of state of the remote process, or return
+    /// `None` once the supervision ended, because the process exited
+    /// successfully or the supervisor gave up restarting it.
+    pub async fn next_event(&mut self) -> Option<SupervisorEvent> {
+        self.events.recv().await
+    }
+
+    /// Return whether the supervision ended, see
+    /// [`next_event`](Self::next_event).
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Drop for RemoteSupervisor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut policy = RestartPolicy::default();
+        policy.backoff(Duration::from_millis(100), Duration::from_millis(500));
+
+        let backoffs: Vec<_> = (1..=5)
+            .map(|restart| policy.backoff_for(restart).as_millis())
+            .collect();
+        assert_eq!(backoffs, [100, 200, 400, 500, 500]);
+        assert_eq!(policy.backoff_for(100), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,118 @@
+use super::{ChildStdout, Error, RemoteChild};
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{ready, stream::Stream};
+use tokio::io::{AsyncBufRead, BufReader};
+
+/// The lines of a remote file, created by [`Session::tail_file`](crate::Session::tail_file).
+///
+/// Lines are yielded without the trailing newline; bytes that are not valid
+/// UTF-8 are replaced with `U+FFFD`.
+///
+/// Dropping the `TailFile` stops the remote `tail`.
+#[derive(Debug)]
+pub struct TailFile<'s> {
+    lines: Lines<'s>,
+}
+
+impl<'s> TailFile<'s> {
+    pub(crate) fn new(child: RemoteChild<'s>) -> Self {
+        Self {
+            lines: Lines::new(child),
+        }
+    }
+
+    /// Wait for the next line of the file.
+    ///
+    /// Returns `None` once the remote `tail` has exited, which only happens
+    /// without `follow` or if the session is closed.
+    pub async fn next_line(&mut self) -> Result<Option<String>, Error> {
+        self.lines.next_line().await
+    }
+
+    /// Stop the remote `tail`.
+    pub async fn close(self) -> Result<(), Error> {
+        self.lines.close().await
+    }
+}
+
+impl Stream for TailFile<'_> {
+    type Item = Result<String, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().lines.poll_next_line(cx)
+    }
+}
+
+/// The lines a remote process writes to its stdout.
+#[derive(Debug)]
+pub(crate) struct Lines<'s> {
+    /// Kept alive so that the remote process keeps running.
+    child: RemoteChild<'s>,
+    stdout: BufReader<ChildStdout>,
+    line: Vec<u8>,
+}
+
+impl<'s> Lines<'s> {
+    /// `child` must have been spawned with a piped stdout.
+    pub(crate) fn new(mut child: RemoteChild<'s>) -> Self {
+        let stdout = child.stdout().take().expect("stdout is piped");
+
+        Self {
+            child,
+            stdout: BufReader::new(stdout),
+            line: Vec::new(),
+        }
+    }
+
+    pub(crate) async fn next_line(&mut self) -> Result<Option<String>, Error> {
+        futures_util::future::poll_fn(|cx| self.poll_next_line(cx))
+            .await
+            .transpose()
+    }
+
+    pub(crate) async fn close(self) -> Result<(), Error> {
+        self.child.disconnect().await.map_err(Error::ChildIo)
+    }
+
+    pub(crate) fn poll_next_line(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<String, Error>>> {
+        loop {
+            let buf = match ready!(Pin::new(&mut self.stdout).poll_fill_buf(cx)) {
+                Ok(buf) => buf,
+                Err(err) => return Poll::Ready(Some(Err(Error::ChildIo(err)))),
+            };
+
+            if buf.is_empty() {
+                // A last line without a trailing newline.
+                if self.line.is_empty() {
+                    return Poll::Ready(None);
+                }
+                return Poll::Ready(Some(Ok(self.take_line())));
+            }
+
+            match buf.iter().position(|&b| b == b'\n') {
+                Some(n) => {
+                    self.line.extend_from_slice(&buf[..n]);
+                    Pin::new(&mut self.stdout).consume(n + 1);
+                    return Poll::Ready(Some(Ok(self.take_line())));
+                }
+                None => {
+                    let n = buf.len();
+                    self.line.extend_from_slice(buf);
+                    Pin::new(&mut self.stdout).consume(n);
+                }
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> String {
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        line
+    }
+}
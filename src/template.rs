@@ -0,0 +1,172 @@
+use super::{Error, Host};
+
+use std::ffi::OsStr;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Host,
+    Label(String),
+}
+
+/// A command that is expanded per host when run across a
+/// [`SessionPool`](crate::SessionPool), e.g. with
+/// [`SessionPool::run_on_all`](crate::SessionPool::run_on_all).
+///
+/// A template is parsed with [`CommandTemplate::parse`] and may contain the
+/// placeholders `{host}`, which expands to the [name](Host::name) of the
+/// host, and `{label:key}`, which expands to the value of the label `key` of
+/// the host. Substituted values are quoted for the remote shell, and `{{` and
+/// `}}` stand for literal braces.
+///
+/// A plain `&str` converts into a template without placeholders, which is
+/// run as-is.
+///
+/// ```rust
+/// use openssh::{CommandTemplate, Host};
+///
+/// let template = CommandTemplate::parse("echo {host} > /etc/region-{label:region}")?;
+///
+/// let mut host = Host::new("web1.example.com");
+/// host.label("region", "eu west");
+/// assert_eq!(
+///     template.render(&host)?,
+///     "echo web1.example.com > /etc/region-'eu west'"
+/// );
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTemplate {
+    parts: Vec<Part>,
+}
+
+impl CommandTemplate {
+    /// Parse `template`, failing with [`io::ErrorKind::InvalidInput`] on an
+    /// unknown placeholder or an unmatched brace.
+    pub fn parse(template: &str) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+
+        let mut rest = template;
+        while let Some(n) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..n]);
+            let brace = &rest[n..];
+
+            if brace.starts_with("{{") || brace.starts_with("}}") {
+                literal.push_str(&brace[..1]);
+                rest = &brace[2..];
+                continue;
+            }
+            if brace.starts_with('}') {
+                return Err(invalid(format!("unmatched `}}` at offset {}", n)));
+            }
+
+            let end = brace
+                .find('}')
+                .ok_or_else(|| invalid(format!("unterminated placeholder `{}`", brace)))?;
+            let part = match &brace[1..end] {
+                "host" => Part::Host,
+                placeholder => match placeholder.strip_prefix("label:") {
+                    Some(key) if !key.is_empty() => Part::Label(key.to_owned()),
+                    _ => return Err(invalid(format!("unknown placeholder `{}`", &brace[..=end]))),
+                },
+            };
+
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+            rest = &brace[end + 1..];
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Expand the template for `host`.
+    ///
+    /// Fails with [`Error::MissingLabel`] if the template refers to a label
+    /// that `host` does not have.
+    pub fn render(&self, host: &Host) -> Result<String, Error> {
+        let mut command = String::new();
+
+        for part in &self.parts {
+            let value = match part {
+                Part::Literal(literal) => {
+                    command.push_str(literal);
+                    continue;
+                }
+                Part::Host => host.name(),
+                Part::Label(key) => host
+                    .get_label(key)
+                    .ok_or_else(|| Error::MissingLabel(key.clone()))?,
+            };
+
+            let escaped = crate::escape::escape(OsStr::new(value));
+            command.push_str(escaped.to_str().expect("escaping keeps valid UTF-8"));
+        }
+
+        Ok(command)
+    }
+}
+
+impl From<&str> for CommandTemplate {
+    fn from(command: &str) -> Self {
+        Self {
+            parts: vec![Part::Literal(command.to_owned())],
+        }
+    }
+}
+
+impl From<&CommandTemplate> for CommandTemplate {
+    fn from(template: &CommandTemplate) -> Self {
+        template.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let mut host = Host::new("web1");
+        host.label("region", "eu").label("path", "/srv/it's here");
+
+        let render = |template| CommandTemplate::parse(template).unwrap().render(&host);
+
+        assert_eq!(render("uptime").unwrap(), "uptime");
+        assert_eq!(render("echo {host}").unwrap(), "echo web1");
+        assert_eq!(
+            render("ls {label:path} # {label:region}").unwrap(),
+            "ls '/srv/it'\\''s here' # eu"
+        );
+        assert_eq!(
+            render("awk '{{print $1}}' {host}").unwrap(),
+            "awk '{print $1}' web1"
+        );
+        assert!(matches!(
+            render("echo {label:role}"),
+            Err(Error::MissingLabel(key)) if key == "role"
+        ));
+
+        // Plain commands are not parsed.
+        let template = CommandTemplate::from("awk '{print $1}'");
+        assert_eq!(template.render(&host).unwrap(), "awk '{print $1}'");
+    }
+
+    #[test]
+    fn parse_error() {
+        for template in ["echo {", "echo }", "echo {name}", "echo {label:}"] {
+            let err = CommandTemplate::parse(template).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", template);
+        }
+    }
+}
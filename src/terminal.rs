@@ -0,0 +1,205 @@
+//! Local terminals connected to the pseudo-terminal of a remote process.
+
+use super::{Error, RequestTty, Session, Stdio};
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::process::ExitStatus;
+
+use tokio::signal::unix::{signal, SignalKind};
+
+/// The remote command run by [`Session::interactive_shell`], which runs the
+/// shell of the remote user as a login shell.
+const LOGIN_SHELL: &str = r#"exec "${SHELL:-/bin/sh}" -l"#;
+
+/// The local terminal that is the stdin of a remote process with a
+/// pseudo-terminal, whose size the master propagates to the remote host.
+#[derive(Debug)]
+pub(crate) struct Terminal {
+    pub(crate) fd: OwnedFd,
+    /// The control socket of the master.
+    pub(crate) ctl: Box<Path>,
+}
+
+impl Terminal {
+    pub(crate) fn resize(&self, cols: u16, rows: u16) -> io::Result<()> {
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: `size` is a valid `winsize`, which is only read.
+        if unsafe { libc::ioctl(self.fd.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn notify_master(&self) -> Result<(), Error> {
+        notify_master(&self.ctl).await
+    }
+}
+
+/// Make the master listening on `ctl` send the size of the terminals of its
+/// sessions to the remote host, which it checks on `SIGWINCH`.
+async fn notify_master(ctl: &Path) -> Result<(), Error> {
+    let output = tokio::process::Command::from(crate::session::new_ctl_cmd(ctl, &["-O", "check"]))
+        .output()
+        .await
+        .map_err(Error::Ssh)?;
+
+    // e.g. "Master running (pid=1234)"
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let pid = stderr
+        .split_once("(pid=")
+        .and_then(|(_, pid)| pid.split_once(')'))
+        .and_then(|(pid, _)| pid.parse().ok())
+        .filter(|_| output.status.success())
+        .ok_or_else(|| Error::Master(io::Error::new(io::ErrorKind::Other, stderr.trim())))?;
+
+    // SAFETY: `kill` has no memory safety requirements.
+    if unsafe { libc::kill(pid, libc::SIGWINCH) } == -1 {
+        return Err(Error::Master(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+/// Puts a local terminal into raw mode, restoring its previous mode on drop.
+struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    /// Fails if `fd` is not a terminal.
+    fn enter(fd: RawFd) -> io::Result<Self> {
+        let mut saved = MaybeUninit::uninit();
+        // SAFETY: `tcgetattr` initializes `saved` if it succeeds.
+        let saved = unsafe {
+            if libc::tcgetattr(fd, saved.as_mut_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            saved.assume_init()
+        };
+
+        let mut raw = saved;
+        // SAFETY: `raw` is a valid `termios`.
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(Self { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: `saved` is the valid `termios` the terminal had before.
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Run the login shell of the remote user on `session` with the local
+/// terminal as its terminal, see [`Session::interactive_shell`].
+#[allow(clippy::incompatible_msrv)]
+pub(crate) async fn interactive_shell(session: &Session) -> Result<ExitStatus, Error> {
+    let _raw_mode = RawMode::enter(libc::STDIN_FILENO).map_err(Error::ChildIo)?;
+    let mut window_change = signal(SignalKind::window_change()).map_err(Error::ChildIo)?;
+
+    let child = session
+        .raw_command(LOGIN_SHELL)
+        .request_tty(RequestTty::Force)
+        // Like an interactive ssh session, `~.` ends a hung connection.
+        .escape_char(Some('~'))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .await?;
+
+    let wait = child.wait();
+    tokio::pin!(wait);
+    loop {
+        tokio::select! {
+            status = &mut wait => return status,
+            // The master reads the new size from the local terminal. Missed
+            // changes are caught up with by the next one.
+            Some(()) = window_change.recv() => {
+                let _ = notify_master(session.control_socket()).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CStr;
+    use std::os::unix::io::FromRawFd;
+
+    /// Return the master and slave of a new pseudo-terminal.
+    fn openpty() -> (OwnedFd, OwnedFd) {
+        // SAFETY: the fds are checked and owned by the `OwnedFd`s.
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let name = CStr::from_ptr(libc::ptsname(master));
+            let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+            assert!(slave >= 0);
+            (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+        }
+    }
+
+    fn lflag(fd: RawFd) -> libc::tcflag_t {
+        let mut termios = MaybeUninit::uninit();
+        // SAFETY: `tcgetattr` initializes `termios` if it succeeds.
+        unsafe {
+            assert_eq!(libc::tcgetattr(fd, termios.as_mut_ptr()), 0);
+            termios.assume_init().c_lflag
+        }
+    }
+
+    #[test]
+    fn raw_mode() {
+        let (_master, slave) = openpty();
+        let fd = slave.as_raw_fd();
+        assert_ne!(lflag(fd) & libc::ICANON, 0);
+
+        let raw_mode = RawMode::enter(fd).unwrap();
+        assert_eq!(lflag(fd) & (libc::ICANON | libc::ECHO | libc::ISIG), 0);
+        drop(raw_mode);
+        assert_ne!(lflag(fd) & libc::ICANON, 0);
+
+        let file = tempfile::tempfile().unwrap();
+        RawMode::enter(file.as_raw_fd()).err().unwrap();
+    }
+
+    #[test]
+    fn resize() {
+        let (master, slave) = openpty();
+        let terminal = Terminal {
+            fd: slave,
+            ctl: Path::new("master").into(),
+        };
+        terminal.resize(100, 40).unwrap();
+
+        let mut size = MaybeUninit::<libc::winsize>::uninit();
+        // SAFETY: `TIOCGWINSZ` initializes `size` if it succeeds.
+        let size = unsafe {
+            assert_eq!(
+                libc::ioctl(master.as_raw_fd(), libc::TIOCGWINSZ, size.as_mut_ptr()),
+                0
+            );
+            size.assume_init()
+        };
+        assert_eq!((size.ws_col, size.ws_row), (100, 40));
+    }
+}
@@ -0,0 +1,979 @@
+use super::filter::Filter;
+use super::{Error, Session, Stdio};
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::num::NonZeroUsize;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::future::try_join_all;
+use tokio::io::AsyncWriteExt;
+
+/// Size of the blocks `dd` reads and writes, which ranges are aligned to.
+const BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Options for [`copy_between_with`].
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    channels: NonZeroUsize,
+    preserve_mode: bool,
+    preserve_mtime: bool,
+    preserve_owner: bool,
+    mode: Option<u32>,
+    mtime: Option<SystemTime>,
+    inplace: bool,
+    partial: bool,
+    delete: bool,
+    sparse: bool,
+    compress: bool,
+    filter: Filter,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            channels: NonZeroUsize::new(1).unwrap(),
+            preserve_mode: false,
+            preserve_mtime: false,
+            preserve_owner: false,
+            mode: None,
+            mtime: None,
+            inplace: false,
+            partial: false,
+            delete: false,
+            sparse: false,
+            compress: false,
+            filter: Filter::default(),
+        }
+    }
+}
+
+impl CopyOptions {
+    /// Create options with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split the file into `channels` ranges that are copied concurrently,
+    /// each over its own ssh channel on both sessions.
+    ///
+    /// A single ssh channel is limited by its flow control window, so
+    /// concurrent channels make better use of links with a high bandwidth
+    /// and a high latency.
+    ///
+    /// The ranges are read and written with `dd`, which must be available
+    /// on both remote hosts.
+    ///
+    /// Defaults to `1`, which streams the file with `cat`.
+    pub fn channels(&mut self, channels: NonZeroUsize) -> &mut Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Give the copy the permission bits of the source, like `scp -p`.
+    ///
+    /// Otherwise, the copy is created according to the umask of the remote
+    /// user, or with [`inplace`](CopyOptions::inplace), keeps the permission
+    /// bits of the file it replaces.
+    ///
+    /// Defaults to `false`.
+    pub fn preserve_mode(&mut self, preserve: bool) -> &mut Self {
+        self.preserve_mode = preserve;
+        self
+    }
+
+    /// Give the copy the modification time of the source, like `scp -p`.
+    ///
+    /// Defaults to `false`.
+    pub fn preserve_mtime(&mut self, preserve: bool) -> &mut Self {
+        self.preserve_mtime = preserve;
+        self
+    }
+
+    /// Give the copy the owner and group ids of the source, like `rsync -a`.
+    ///
+    /// This usually requires the remote user of the destination to be root.
+    ///
+    /// Defaults to `false`.
+    pub fn preserve_owner(&mut self, preserve: bool) -> &mut Self {
+        self.preserve_owner = preserve;
+        self
+    }
+
+    /// Set the permission bits of the copy, e.g. `0o600`, overriding
+    /// [`preserve_mode`](CopyOptions::preserve_mode).
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Set the modification time of the copy, overriding
+    /// [`preserve_mtime`](CopyOptions::preserve_mtime).
+    pub fn mtime(&mut self, mtime: SystemTime) -> &mut Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Write directly into the destination file, like `rsync --inplace`.
+    ///
+    /// Otherwise, the file is written to a temporary file next to the
+    /// destination, which is renamed to the destination once it is complete,
+    /// so that the destination is replaced atomically.
+    ///
+    /// A failed copy leaves the partially written destination behind, since
+    /// the file it replaced is already gone.
+    ///
+    /// Ignored by [`copy_dir_between`].
+    ///
+    /// Defaults to `false`.
+    pub fn inplace(&mut self, inplace: bool) -> &mut Self {
+        self.inplace = inplace;
+        self
+    }
+
+    /// Keep the partially written file if the copy fails, like
+    /// `rsync --partial`.
+    ///
+    /// The partial file then replaces the destination. With
+    /// [`inplace`](CopyOptions::inplace), the destination is written directly
+    /// and always kept.
+    ///
+    /// Ignored by [`copy_dir_between`].
+    ///
+    /// Defaults to `false`, which removes the partially written temporary
+    /// file and leaves the destination untouched.
+    pub fn partial(&mut self, partial: bool) -> &mut Self {
+        self.partial = partial;
+        self
+    }
+
+    /// Remove files from the destination directory that do not exist in the
+    /// source directory, like `rsync --delete`.
+    ///
+    /// Only used by [`copy_dir_between`].
+    ///
+    /// Defaults to `false`.
+    pub fn delete(&mut self, delete: bool) -> &mut Self {
+        self.delete = delete;
+        self
+    }
+
+    /// Turn blocks of zeros into holes in the destination file, like
+    /// `rsync --sparse`, so that copies of sparse files such as VM images
+    /// do not take up their full size on disk.
+    ///
+    /// The zeros still cross the network. The destination is written with
+    /// `dd conv=sparse`, which GNU coreutils and busybox support.
+    ///
+    /// Ignored by [`copy_dir_between`].
+    ///
+    /// Defaults to `false`.
+    pub fn sparse(&mut self, sparse: bool) -> &mut Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Compress the data with `gzip` while it crosses the network.
+    ///
+    /// Unlike [`SessionBuilder::compression`](crate::SessionBuilder::compression),
+    /// this only compresses this copy, so that the latency of other traffic
+    /// on the sessions does not suffer. `gzip` must be available on both
+    /// remote hosts.
+    ///
+    /// Ignored by [`copy_dir_between`].
+    ///
+    /// Defaults to `false`.
+    pub fn compress(&mut self, compress: bool) -> &mut Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Leave out the files matching the gitignore-style `pattern`, e.g.
+    /// `target/` or `*.log`.
+    ///
+    /// A pattern without a `/` other than a trailing one matches the name
+    /// of a file in any directory, otherwise it matches the path relative
+    /// to the copied directory. A trailing `/` only matches directories.
+    /// `*`, `?` and bracket expressions match within a path component and
+    /// `**` matches any number of directories.
+    ///
+    /// The last pattern added with `exclude` or [`include`](CopyOptions::include)
+    /// that matches a file decides whether it is copied, and the content of
+    /// an excluded directory is never copied. With [`delete`](CopyOptions::delete),
+    /// excluded files are not removed from the destination either.
+    ///
+    /// Only used by [`copy_dir_between`], which then needs a `tar` supporting
+    /// `--null`, `-T` and `--no-recursion`, like GNU and BSD tar, on the
+    /// source host.
+    pub fn exclude(&mut self, pattern: &str) -> &mut Self {
+        self.filter.exclude(pattern);
+        self
+    }
+
+    /// Copy the files matching the gitignore-style `pattern` even if an
+    /// earlier [`exclude`](CopyOptions::exclude) pattern matches them, like a
+    /// pattern starting with `!` in a `.gitignore`.
+    ///
+    /// Only used by [`copy_dir_between`].
+    pub fn include(&mut self, pattern: &str) -> &mut Self {
+        self.filter.include(pattern);
+        self
+    }
+}
+
+/// Attributes of a remote file.
+struct Attributes {
+    mode: u32,
+    mtime: SystemTime,
+    uid: u32,
+    gid: u32,
+}
+
+impl Attributes {
+    async fn stat(session: &Session, path: &OsStr) -> Result<Self, Error> {
+        // GNU and busybox stat, falling back to BSD stat.
+        let output = session
+            .raw_command(shell_cmd(&[
+                "stat -c '%a %Y %u %g' -- ".as_ref(),
+                path,
+                " 2>/dev/null || stat -f '%Lp %m %u %g' -- ".as_ref(),
+                path,
+            ]))
+            .stdin(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .await?;
+        let stdout = check_status(output)?;
+
+        Self::parse(&String::from_utf8_lossy(&stdout)).ok_or_else(|| {
+            Error::Remote(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected output of stat",
+            ))
+        })
+    }
+
+    fn parse(stat: &str) -> Option<Self> {
+        let mut fields = stat.split_whitespace();
+
+        let mode = u32::from_str_radix(fields.next()?, 8).ok()?;
+        let mtime = UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?);
+        let uid = fields.next()?.parse().ok()?;
+        let gid = fields.next()?.parse().ok()?;
+
+        Some(Self {
+            mode,
+            mtime,
+            uid,
+            gid,
+        })
+    }
+}
+
+/// Copy the file at `path_a` on the remote host of `session_a` to `path_b`
+/// on the remote host of `session_b`, returning the number of bytes copied.
+///
+/// The data is streamed through the local machine like with `scp -3`, so
+/// the two remote hosts do not need to be able to reach each other. `path_b`
+/// is created or replaced once the copy is complete, see
+/// [`CopyOptions::inplace`].
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "native-mux")]
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use openssh::{copy_between, Session, KnownHosts};
+///
+/// let old = Session::connect_mux("me@old.example.com", KnownHosts::Strict).await?;
+/// let new = Session::connect_mux("me@new.example.com", KnownHosts::Strict).await?;
+///
+/// copy_between(&old, "/srv/backup.tar", &new, "/srv/backup.tar").await?;
+/// # Ok(()) }
+/// ```
+pub async fn copy_between(
+    session_a: &Session,
+    path_a: impl AsRef<Path>,
+    session_b: &Session,
+    path_b: impl AsRef<Path>,
+) -> Result<u64, Error> {
+    copy_between_with(
+        session_a,
+        path_a,
+        session_b,
+        path_b,
+        &CopyOptions::default(),
+    )
+    .await
+}
+
+/// Same as [`copy_between`], with [`CopyOptions`].
+pub async fn copy_between_with(
+    session_a: &Session,
+    path_a: impl AsRef<Path>,
+    session_b: &Session,
+    path_b: impl AsRef<Path>,
+    options: &CopyOptions,
+) -> Result<u64, Error> {
+    let temp_b = if options.inplace {
+        None
+    } else {
+        Some(temp_path(path_b.as_ref()))
+    };
+    let path_a = crate::escape::escape(path_a.as_ref().as_os_str());
+    let path_b = crate::escape::escape(path_b.as_ref().as_os_str());
+    let temp_b = temp_b.map(|temp_b| crate::escape::escape(temp_b.as_os_str()).into_owned());
+    let write_b: &OsStr = temp_b.as_deref().unwrap_or(&path_b);
+
+    let copied = if options.channels.get() == 1 {
+        let read_cmd = if options.compress {
+            shell_cmd(&["gzip -c -1 -- ".as_ref(), &path_a])
+        } else {
+            shell_cmd(&["cat -- ".as_ref(), &path_a])
+        };
+        let write_cmd = if options.sparse {
+            shell_cmd(&[
+                "dd of=".as_ref(),
+                write_b,
+                format!(" bs={} conv=sparse", BLOCK_SIZE).as_ref(),
+            ])
+        } else {
+            shell_cmd(&["cat > ".as_ref(), write_b])
+        };
+
+        match copy(
+            session_a,
+            read_cmd,
+            session_b,
+            decompress(write_cmd, options.compress),
+        )
+        .await
+        {
+            // Only the size of the compressed data is known.
+            Ok(_) if options.compress => size(session_a, &path_a).await,
+            copied => copied,
+        }
+    } else {
+        copy_ranges(session_a, &path_a, session_b, write_b, options).await
+    };
+
+    // dd seeks over trailing zeros instead of writing them, so the file may
+    // need to be extended to its full size.
+    let copied = match copied {
+        Ok(copied) if options.sparse => {
+            let extend = shell_cmd(&[
+                "dd if=/dev/null of=".as_ref(),
+                write_b,
+                format!(" bs=1 seek={}", copied).as_ref(),
+            ]);
+            run(session_b, extend).await.map(|_| copied)
+        }
+        copied => copied,
+    };
+
+    let cleanup = match &temp_b {
+        Some(temp_b) if copied.is_ok() || options.partial => Some(shell_cmd(&[
+            "mv -f -- ".as_ref(),
+            temp_b,
+            " ".as_ref(),
+            &path_b,
+        ])),
+        Some(temp_b) => Some(shell_cmd(&["rm -f -- ".as_ref(), temp_b])),
+        None => None,
+    };
+    let copied = match cleanup {
+        Some(cleanup) => {
+            let cleaned_up = run(session_b, cleanup).await;
+            // Report the error of the copy rather than that of the cleanup.
+            let copied = copied?;
+            cleaned_up?;
+            copied
+        }
+        None => copied?,
+    };
+
+    let preserve = (options.preserve_mode && options.mode.is_none())
+        || (options.preserve_mtime && options.mtime.is_none())
+        || options.preserve_owner;
+    let attributes = if preserve {
+        Some(Attributes::stat(session_a, &path_a).await?)
+    } else {
+        None
+    };
+    let attributes = attributes.as_ref();
+
+    let mut cmds: Vec<OsString> = Vec::new();
+
+    // chown may clear the setuid and setgid bits, so it comes first.
+    if let Some(attributes) = attributes.filter(|_| options.preserve_owner) {
+        let owner = format!("chown {}:{} -- ", attributes.uid, attributes.gid);
+        cmds.push(shell_cmd(&[owner.as_ref(), &path_b]));
+    }
+
+    let mode = options.mode.or_else(|| {
+        attributes
+            .filter(|_| options.preserve_mode)
+            .map(|attributes| attributes.mode)
+    });
+    if let Some(mode) = mode {
+        let chmod = format!("chmod {:o} -- ", mode);
+        cmds.push(shell_cmd(&[chmod.as_ref(), &path_b]));
+    }
+
+    let mtime = options.mtime.or_else(|| {
+        attributes
+            .filter(|_| options.preserve_mtime)
+            .map(|attributes| attributes.mtime)
+    });
+    if let Some(mtime) = mtime {
+        let touch = format!("TZ=UTC0 touch -m -t {} -- ", touch_timestamp(mtime));
+        cmds.push(shell_cmd(&[touch.as_ref(), &path_b]));
+    }
+
+    if !cmds.is_empty() {
+        run(session_b, cmds.join(OsStr::new(" && "))).await?;
+    }
+
+    Ok(copied)
+}
+
+/// Copy the directory at `dir_a` on the remote host of `session_a`
+/// recursively to `dir_b` on the remote host of `session_b`, like
+/// `scp -3 -r`, returning the size of the `tar` archive streamed between
+/// them.
+///
+/// `dir_b` is created if it does not exist. The permissions and modification
+/// times of the files are preserved, and with [`CopyOptions::delete`], files
+/// in `dir_b` that do not exist in `dir_a` are removed.
+///
+/// `tar` and `find` must be available on both remote hosts.
+pub async fn copy_dir_between(
+    session_a: &Session,
+    dir_a: impl AsRef<Path>,
+    session_b: &Session,
+    dir_b: impl AsRef<Path>,
+    options: &CopyOptions,
+) -> Result<u64, Error> {
+    let dir_a = crate::escape::escape(dir_a.as_ref().as_os_str());
+    let dir_b = crate::escape::escape(dir_b.as_ref().as_os_str());
+
+    let extract_cmd = shell_cmd(&[
+        "mkdir -p -- ".as_ref(),
+        &dir_b,
+        " && cd -- ".as_ref(),
+        &dir_b,
+        " && tar -xpf -".as_ref(),
+    ]);
+
+    // The filtered files of dir_a, if there are patterns.
+    let mut files_a = None;
+
+    let copied = if options.filter.is_empty() {
+        copy(
+            session_a,
+            shell_cmd(&["cd -- ".as_ref(), &dir_a, " && tar -cf - .".as_ref()]),
+            session_b,
+            extract_cmd,
+        )
+        .await?
+    } else {
+        let files = list_files(session_a, &dir_a, &options.filter).await?;
+
+        let mut input = Vec::new();
+        for file in files.iter().filter(|file| file.as_slice() != b".") {
+            input.extend_from_slice(file);
+            input.push(0);
+        }
+
+        let copied = copy_with_input(
+            session_a,
+            shell_cmd(&[
+                "cd -- ".as_ref(),
+                &dir_a,
+                " && tar -cf - --null --no-recursion -T -".as_ref(),
+            ]),
+            input,
+            session_b,
+            extract_cmd,
+        )
+        .await?;
+
+        files_a = Some(files);
+        copied
+    };
+
+    if options.delete {
+        let files_a = match files_a {
+            Some(files_a) => files_a,
+            None => list_files(session_a, &dir_a, &options.filter).await?,
+        };
+        let files_b = list_files(session_b, &dir_b, &options.filter).await?;
+
+        let removed = extra_files(&files_a, &files_b);
+
+        for files in removed.chunks(256) {
+            let mut cmd = shell_cmd(&["cd -- ".as_ref(), &dir_b, " && rm -rf --".as_ref()]);
+            for file in files {
+                cmd.push(" ");
+                cmd.push(crate::escape::escape(OsStr::from_bytes(file)));
+            }
+            run(session_b, cmd).await?;
+        }
+    }
+
+    Ok(copied)
+}
+
+/// List the files in `dir` on `session` that `filter` does not exclude, as
+/// paths starting with `./`.
+async fn list_files(
+    session: &Session,
+    dir: &OsStr,
+    filter: &Filter,
+) -> Result<Vec<Vec<u8>>, Error> {
+    // Directories are listed with a trailing `/`.
+    let output = run(
+        session,
+        shell_cmd(&[
+            "cd -- ".as_ref(),
+            dir,
+            r#" && find . -type d -exec printf '%s/\0' {} + -o -print0"#.as_ref(),
+        ]),
+    )
+    .await?;
+
+    Ok(output
+        .split(|&b| b == 0)
+        .filter(|file| !file.is_empty())
+        .filter_map(|file| {
+            let (file, is_dir) = match file.strip_suffix(b"/") {
+                Some(dir) => (dir, true),
+                None => (file, false),
+            };
+            let relative = file.strip_prefix(b"./").unwrap_or(b"");
+
+            if relative.is_empty() || !filter.is_excluded(relative, is_dir) {
+                Some(file.to_vec())
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+/// Return the files of `files_b` that are missing from `files_a`, leaving
+/// out those below a directory that is missing as well.
+fn extra_files<'a>(files_a: &[Vec<u8>], files_b: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+    let files_a: HashSet<&[u8]> = files_a.iter().map(Vec::as_slice).collect();
+    let mut extra: Vec<&[u8]> = files_b
+        .iter()
+        .map(Vec::as_slice)
+        .filter(|file| !files_a.contains(file))
+        .collect();
+    extra.sort_unstable();
+
+    // Removing a directory removes its content as well.
+    let mut removed: Vec<&[u8]> = Vec::new();
+    for file in extra {
+        let in_removed_dir = removed.last().map_or(false, |dir| {
+            file.starts_with(dir) && file.get(dir.len()) == Some(&b'/')
+        });
+        if !in_removed_dir {
+            removed.push(file);
+        }
+    }
+    removed
+}
+
+/// Copy the file concurrently in `channels` ranges.
+async fn copy_ranges(
+    session_a: &Session,
+    path_a: &OsStr,
+    session_b: &Session,
+    path_b: &OsStr,
+    options: &CopyOptions,
+) -> Result<u64, Error> {
+    let size = size(session_a, path_a).await?;
+
+    // Create or truncate the destination before writing the ranges into it.
+    let output = session_b
+        .raw_command(shell_cmd(&[": > ".as_ref(), path_b]))
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+    check_status(output)?;
+
+    let copies = ranges(size, options.channels).map(|(skip, count)| {
+        let mut read_cmd = shell_cmd(&[
+            "dd if=".as_ref(),
+            path_a,
+            format!(" bs={} skip={} count={}", BLOCK_SIZE, skip, count).as_ref(),
+        ]);
+        if options.compress {
+            read_cmd.push(" | gzip -c -1");
+        }
+        let write_cmd = shell_cmd(&[
+            "dd of=".as_ref(),
+            path_b,
+            format!(
+                " bs={} seek={} conv=notrunc{}",
+                BLOCK_SIZE,
+                skip,
+                if options.sparse { ",sparse" } else { "" }
+            )
+            .as_ref(),
+        ]);
+
+        copy(
+            session_a,
+            read_cmd,
+            session_b,
+            decompress(write_cmd, options.compress),
+        )
+    });
+
+    let copied: u64 = try_join_all(copies).await?.into_iter().sum();
+    Ok(if options.compress { size } else { copied })
+}
+
+/// Return the size of the file at `path` on `session`.
+async fn size(session: &Session, path: &OsStr) -> Result<u64, Error> {
+    String::from_utf8_lossy(&run(session, shell_cmd(&["wc -c < ".as_ref(), path])).await?)
+        .trim()
+        .parse()
+        .map_err(|err| Error::Remote(io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+/// Write `data` to `path` on `session`, replacing the file only once the
+/// `cksum` of the written data has been verified on the remote host.
+pub(crate) async fn push(session: &Session, data: &[u8], path: &Path) -> Result<(), Error> {
+    let temp = temp_path(path);
+    let temp = crate::escape::escape(temp.as_os_str());
+    let path = crate::escape::escape(path.as_os_str());
+
+    let mut writer = session
+        .raw_command(shell_cmd(&["cat > ".as_ref(), &temp]))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+
+    let mut stdin = writer.stdin().take().expect("stdin is piped");
+    let written = stdin.write_all(data).await;
+    // Signal EOF to the writer.
+    drop(stdin);
+
+    let output = writer.wait_with_output().await?;
+    let pushed = match check_status(output).and(written.map_err(Error::ChildIo)) {
+        Ok(_) => verify_cksum(session, data, &temp).await,
+        Err(err) => Err(err),
+    };
+
+    let cleanup = if pushed.is_ok() {
+        shell_cmd(&["mv -f -- ".as_ref(), &temp, " ".as_ref(), &path])
+    } else {
+        shell_cmd(&["rm -f -- ".as_ref(), &temp])
+    };
+    let cleaned_up = run(session, cleanup).await;
+    // Report the error of the push rather than that of the cleanup.
+    pushed?;
+    cleaned_up.map(|_| ())
+}
+
+/// Check that the `cksum` of the file at `path` on `session` matches `data`.
+async fn verify_cksum(session: &Session, data: &[u8], path: &OsStr) -> Result<(), Error> {
+    let output = run(session, shell_cmd(&["cksum < ".as_ref(), path])).await?;
+    let output = String::from_utf8_lossy(&output);
+
+    let expected = format!("{} {}", cksum(data), data.len());
+    if output.split_whitespace().take(2).eq(expected.split(' ')) {
+        Ok(())
+    } else {
+        Err(Error::Remote(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "checksum mismatch, expected `{}`, found `{}`",
+                expected,
+                output.trim()
+            ),
+        )))
+    }
+}
+
+/// The checksum that POSIX `cksum` prints for `data`.
+fn cksum(data: &[u8]) -> u32 {
+    fn update(crc: u32, byte: u8) -> u32 {
+        (0..8).fold(crc ^ (u32::from(byte) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            }
+        })
+    }
+
+    let mut crc = data.iter().fold(0, |crc, &byte| update(crc, byte));
+
+    // The length is appended in as few bytes as possible, least significant
+    // first.
+    let mut len = data.len();
+    while len != 0 {
+        crc = update(crc, len as u8);
+        len >>= 8;
+    }
+
+    !crc
+}
+
+/// Make `write_cmd` decompress its input if `compress` is set.
+fn decompress(write_cmd: OsString, compress: bool) -> OsString {
+    if compress {
+        shell_cmd(&["gzip -d -c | ".as_ref(), &write_cmd])
+    } else {
+        write_cmd
+    }
+}
+
+/// Split a file of `size` bytes into at most `channels` ranges of whole
+/// blocks, returned as `(skip, count)` in blocks.
+fn ranges(size: u64, channels: NonZeroUsize) -> impl Iterator<Item = (u64, u64)> {
+    let blocks = (size + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    let channels = channels.get() as u64;
+    let blocks_per_range = ((blocks + channels - 1) / channels).max(1);
+
+    (0..blocks)
+        .step_by(blocks_per_range as usize)
+        .map(move |skip| (skip, blocks_per_range.min(blocks - skip)))
+}
+
+/// Format `time` in UTC for `touch -t`, i.e. as `CCYYMMDDhhmm.SS`.
+fn touch_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Convert days since the epoch to a date in the proleptic Gregorian calendar,
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}.{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// A temporary path in the directory of `path`, like `rsync` uses.
+fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or(0);
+
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_else(|| OsStr::new("copy")));
+    name.push(format!(".{:x}{:x}", std::process::id(), nanos));
+
+    path.with_file_name(name)
+}
+
+fn shell_cmd(parts: &[&OsStr]) -> OsString {
+    parts.iter().copied().collect()
+}
+
+/// Pipe the stdout of `read_cmd` on `session_a` into the stdin of
+/// `write_cmd` on `session_b`.
+async fn copy(
+    session_a: &Session,
+    read_cmd: OsString,
+    session_b: &Session,
+    write_cmd: OsString,
+) -> Result<u64, Error> {
+    copy_with_input(session_a, read_cmd, Vec::new(), session_b, write_cmd).await
+}
+
+/// Same as [`copy`], writing `input` to the stdin of `read_cmd`.
+async fn copy_with_input(
+    session_a: &Session,
+    read_cmd: OsString,
+    input: Vec<u8>,
+    session_b: &Session,
+    write_cmd: OsString,
+) -> Result<u64, Error> {
+    let mut reader = session_a
+        .raw_command(read_cmd)
+        // Killed if the writer fails, since it would otherwise block on
+        // writing to the full channel forever.
+        .kill_on_drop(true)
+        .stdin(if input.is_empty() {
+            Stdio::null()
+        } else {
+            Stdio::piped()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+
+    let reader_stdin = reader.stdin().take();
+
+    let mut writer = session_b
+        .raw_command(write_cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+
+    let mut stdout = reader.stdout().take().expect("stdout is piped");
+    let mut stdin = writer.stdin().take().expect("stdin is piped");
+
+    let write_input = async move {
+        match reader_stdin {
+            // Dropping the stdin afterwards signals EOF to the reader.
+            Some(mut reader_stdin) => reader_stdin.write_all(&input).await,
+            None => Ok(()),
+        }
+    };
+    // The stdout of the reader is dropped with the future, so that it is
+    // not kept open while waiting for the reader.
+    let copy = async move {
+        let copied = stdout.copy_to(&mut stdin).await;
+        // Signal EOF to the writer.
+        drop(stdin);
+        copied
+    };
+    #[allow(clippy::incompatible_msrv)]
+    let (written, copied) = tokio::join!(write_input, copy);
+
+    let copied = match copied {
+        Ok(copied) => copied,
+        Err(err) => {
+            drop(reader);
+            // The writer exiting early is the usual cause, so report its error.
+            check_status(writer.wait_with_output().await?)?;
+            return Err(Error::ChildIo(err));
+        }
+    };
+
+    let read_output = reader.wait_with_output().await?;
+    let write_output = writer.wait_with_output().await?;
+
+    check_status(read_output)?;
+    check_status(write_output)?;
+
+    written.map_err(Error::ChildIo)?;
+    Ok(copied)
+}
+
+/// Run `cmd` on `session`, returning its stdout if it succeeds.
+async fn run(session: &Session, cmd: OsString) -> Result<Vec<u8>, Error> {
+    let output = session
+        .raw_command(cmd)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+    check_status(output)
+}
+
+/// Return the stdout of a successful command.
+fn check_status(output: Output) -> Result<Vec<u8>, Error> {
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        Err(Error::Remote(io::Error::new(
+            io::ErrorKind::Other,
+            stderr.trim(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(size: u64, channels: usize) -> Vec<(u64, u64)> {
+        super::ranges(size, NonZeroUsize::new(channels).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_touch_timestamp() {
+        let timestamp = |secs| touch_timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(timestamp(0), "197001010000.00");
+        assert_eq!(timestamp(951782400), "200002290000.00");
+        assert_eq!(timestamp(1700000000), "202311142213.20");
+        assert_eq!(timestamp(4102444799), "209912312359.59");
+    }
+
+    #[test]
+    fn test_parse_attributes() {
+        let attributes = Attributes::parse("755 1700000000 1000 100\n").unwrap();
+        assert_eq!(attributes.mode, 0o755);
+        assert_eq!(
+            attributes.mtime,
+            UNIX_EPOCH + Duration::from_secs(1700000000)
+        );
+        assert_eq!((attributes.uid, attributes.gid), (1000, 100));
+
+        assert!(Attributes::parse("stat: cannot stat").is_none());
+    }
+
+    #[test]
+    fn test_extra_files() {
+        let files = |files: &[&str]| -> Vec<Vec<u8>> {
+            files.iter().map(|file| file.as_bytes().to_vec()).collect()
+        };
+        let files_a = files(&[".", "./a", "./dir", "./dir/b"]);
+        let files_b = files(&[
+            ".", "./a", "./c", "./dir", "./dir/d", "./old", "./old/e", "./old/f", "./older",
+        ]);
+
+        assert_eq!(
+            extra_files(&files_a, &files_b),
+            [&b"./c"[..], b"./dir/d", b"./old", b"./older"]
+        );
+        assert!(extra_files(&files_a, &files_a).is_empty());
+    }
+
+    #[test]
+    fn test_cksum() {
+        assert_eq!(cksum(b""), 4294967295);
+        assert_eq!(cksum(b"hello\n"), 3015617425);
+
+        let data: Vec<u8> = (0..300u32).map(|n| (n * 7) as u8).collect();
+        assert_eq!(cksum(&data), 1626054974);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(ranges(0, 4), []);
+        assert_eq!(ranges(1, 4), [(0, 1)]);
+        assert_eq!(ranges(BLOCK_SIZE, 4), [(0, 1)]);
+        assert_eq!(ranges(3 * BLOCK_SIZE + 1, 2), [(0, 2), (2, 2)]);
+        assert_eq!(ranges(10 * BLOCK_SIZE, 4), [(0, 3), (3, 3), (6, 3), (9, 1)]);
+        assert_eq!(ranges(10 * BLOCK_SIZE, 1), [(0, 10)]);
+    }
+}
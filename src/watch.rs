@@ -0,0 +1,250 @@
+use super::tail::Lines;
+use super::{Error, RemoteChild};
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{ready, stream::Stream};
+
+/// What happened to a watched file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WatchEventKind {
+    /// The file was created.
+    Created,
+
+    /// The content of the file was written.
+    Modified,
+
+    /// The permissions, ownership or timestamps of the file changed.
+    Metadata,
+
+    /// The file was removed.
+    Removed,
+
+    /// The file was renamed, either from or to the path of the event.
+    Renamed,
+}
+
+/// A change to a file below a path watched with
+/// [`Session::watch`](crate::Session::watch).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WatchEvent {
+    /// What happened to the file.
+    pub kind: WatchEventKind,
+
+    /// The path of the file on the remote host.
+    pub path: PathBuf,
+
+    /// Whether the file is a directory.
+    pub is_dir: bool,
+}
+
+/// The tool watching the remote file system.
+#[derive(Debug, Clone, Copy)]
+enum Watcher {
+    Inotifywait,
+    Fswatch,
+}
+
+impl Watcher {
+    /// Parse a line that `inotifywait --format '%e %w%f'` or
+    /// `fswatch -x --event-flag-separator=,` printed.
+    ///
+    /// Returns `None` for events that are not of interest.
+    fn parse(self, line: &str) -> Option<WatchEvent> {
+        let (flags, path) = match self {
+            Watcher::Inotifywait => line.split_once(' ')?,
+            Watcher::Fswatch => {
+                let (path, flags) = line.rsplit_once(' ')?;
+                (flags, path)
+            }
+        };
+        let has = |flag: &str| flags.split(',').any(|f| f == flag);
+
+        // Ordered by priority, as fswatch may coalesce several events.
+        let kinds: &[(&str, WatchEventKind)] = match self {
+            Watcher::Inotifywait => &[
+                ("DELETE", WatchEventKind::Removed),
+                ("MOVED_FROM", WatchEventKind::Renamed),
+                ("MOVED_TO", WatchEventKind::Renamed),
+                ("CREATE", WatchEventKind::Created),
+                ("CLOSE_WRITE", WatchEventKind::Modified),
+                ("ATTRIB", WatchEventKind::Metadata),
+            ],
+            Watcher::Fswatch => &[
+                ("Removed", WatchEventKind::Removed),
+                ("Renamed", WatchEventKind::Renamed),
+                ("MovedFrom", WatchEventKind::Renamed),
+                ("MovedTo", WatchEventKind::Renamed),
+                ("Created", WatchEventKind::Created),
+                ("Updated", WatchEventKind::Modified),
+                ("AttributeModified", WatchEventKind::Metadata),
+                ("OwnerModified", WatchEventKind::Metadata),
+            ],
+        };
+        let kind = kinds
+            .iter()
+            .find(|(flag, _)| has(flag))
+            .map(|&(_, kind)| kind)?;
+
+        let is_dir = match self {
+            Watcher::Inotifywait => has("ISDIR"),
+            Watcher::Fswatch => has("IsDir"),
+        };
+
+        Some(WatchEvent {
+            kind,
+            path: path.into(),
+            is_dir,
+        })
+    }
+}
+
+/// The shell script that runs `inotifywait`, or `fswatch` if it is not
+/// available, on `path`.
+///
+/// The first line of its output names the tool.
+pub(crate) fn watch_script(path: &OsStr, recursive: bool) -> OsString {
+    let recursive = if recursive { " -r" } else { "" };
+    let path = crate::escape::escape(path);
+
+    let mut cmd = OsString::from("if command -v inotifywait >/dev/null 2>&1; then ");
+    cmd.push("echo inotifywait; exec inotifywait -m -q");
+    cmd.push(recursive);
+    cmd.push(" -e create,close_write,attrib,delete,moved_from,moved_to --format '%e %w%f' -- ");
+    cmd.push(&path);
+    cmd.push("; elif command -v fswatch >/dev/null 2>&1; then ");
+    cmd.push("echo fswatch; exec fswatch -x");
+    cmd.push(recursive);
+    cmd.push(" --event-flag-separator=, -- ");
+    cmd.push(&path);
+    cmd.push("; fi");
+    cmd
+}
+
+/// Changes to the remote file system, created by [`Session::watch`](crate::Session::watch).
+///
+/// Dropping the `FileWatch` stops the remote watcher.
+#[derive(Debug)]
+pub struct FileWatch<'s> {
+    lines: Lines<'s>,
+    watcher: Watcher,
+}
+
+impl<'s> FileWatch<'s> {
+    pub(crate) async fn new(child: RemoteChild<'s>) -> Result<FileWatch<'s>, Error> {
+        let mut lines = Lines::new(child);
+
+        let watcher = match lines.next_line().await?.as_deref() {
+            Some("inotifywait") => Watcher::Inotifywait,
+            Some("fswatch") => Watcher::Fswatch,
+            _ => {
+                return Err(Error::Remote(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "neither inotifywait nor fswatch is available on the remote host",
+                )))
+            }
+        };
+
+        Ok(Self { lines, watcher })
+    }
+
+    /// Wait for the next change.
+    ///
+    /// Returns `None` once the remote watcher has exited, e.g. because the
+    /// watched path does not exist.
+    pub async fn next_event(&mut self) -> Result<Option<WatchEvent>, Error> {
+        futures_util::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+            .await
+            .transpose()
+    }
+
+    /// Stop the remote watcher.
+    pub async fn close(self) -> Result<(), Error> {
+        self.lines.close().await
+    }
+}
+
+impl Stream for FileWatch<'_> {
+    type Item = Result<WatchEvent, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            match ready!(this.lines.poll_next_line(cx)) {
+                Some(Ok(line)) => {
+                    if let Some(event) = this.watcher.parse(&line) {
+                        return Poll::Ready(Some(Ok(event)));
+                    }
+                }
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: WatchEventKind, path: &str, is_dir: bool) -> Option<WatchEvent> {
+        Some(WatchEvent {
+            kind,
+            path: path.into(),
+            is_dir,
+        })
+    }
+
+    #[test]
+    fn parse_inotifywait() {
+        let parse = |line| Watcher::Inotifywait.parse(line);
+
+        assert_eq!(
+            parse("CREATE /srv/a b.txt"),
+            event(WatchEventKind::Created, "/srv/a b.txt", false)
+        );
+        assert_eq!(
+            parse("CREATE,ISDIR /srv/dir"),
+            event(WatchEventKind::Created, "/srv/dir", true)
+        );
+        assert_eq!(
+            parse("CLOSE_WRITE,CLOSE /srv/a"),
+            event(WatchEventKind::Modified, "/srv/a", false)
+        );
+        assert_eq!(
+            parse("MOVED_FROM /srv/a"),
+            event(WatchEventKind::Renamed, "/srv/a", false)
+        );
+        assert_eq!(
+            parse("DELETE /srv/a"),
+            event(WatchEventKind::Removed, "/srv/a", false)
+        );
+        assert_eq!(parse("OPEN /srv/a"), None);
+    }
+
+    #[test]
+    fn parse_fswatch() {
+        let parse = |line| Watcher::Fswatch.parse(line);
+
+        assert_eq!(
+            parse("/srv/a b.txt Created,Updated,IsFile"),
+            event(WatchEventKind::Created, "/srv/a b.txt", false)
+        );
+        assert_eq!(
+            parse("/srv/dir Removed,IsDir"),
+            event(WatchEventKind::Removed, "/srv/dir", true)
+        );
+        assert_eq!(
+            parse("/srv/a AttributeModified,IsFile"),
+            event(WatchEventKind::Metadata, "/srv/a", false)
+        );
+        assert_eq!(parse("/srv/a PlatformSpecific"), None);
+    }
+}
@@ -0,0 +1,530 @@
+//! Running the command line of a remote command through a wrapping shell,
+//! which applies settings to the remote process that ssh has no option for.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::escape::escape;
+
+/// How the remote process records its pid in the file `$TMPDIR/openssh-rs-$ID.pid`
+/// while it runs, started in the background so that the file can be removed
+/// once it exits. Arguments: `ID LINE`.
+const RECORD_PID: &str = concat!(
+    r#"f=${TMPDIR:-/tmp}/openssh-rs-$1.pid; "#,
+    // Background commands get /dev/null as stdin unless redirected.
+    r#"exec 3<&0; sh -c "$2" <&3 3<&- & p=$!; exec 3<&-; "#,
+    r#"echo $p >"$f"; wait $p; s=$?; rm -f "$f"; exit $s"#,
+);
+
+/// How the remote process is killed: wait up to 5s for its pid file to
+/// appear, in case the process is still starting. Arguments: `ID`.
+const KILL: &str = concat!(
+    r#"f=${TMPDIR:-/tmp}/openssh-rs-$1.pid; i=0; "#,
+    r#"while [ ! -s "$f" ] && [ $i -lt 50 ]; do sleep 0.1; i=$((i + 1)); done; "#,
+    r#"[ -s "$f" ] && kill -TERM "$(cat "$f")"; rm -f "$f""#,
+);
+
+/// The I/O scheduling class and priority of a remote process, see
+/// [`OwningCommand::ionice`](crate::OwningCommand::ionice).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IoPriority {
+    /// Only access the disk when no other process does (`ionice -c 3`).
+    Idle,
+
+    /// The default class, with a priority from 0 (highest) to 7 (lowest)
+    /// (`ionice -c 2 -n PRIORITY`).
+    BestEffort(u8),
+
+    /// Access the disk before any other class, with a priority from 0
+    /// (highest) to 7 (lowest), which requires root
+    /// (`ionice -c 1 -n PRIORITY`).
+    Realtime(u8),
+}
+
+impl IoPriority {
+    fn args(self) -> String {
+        match self {
+            Self::Idle => "-c 3".into(),
+            Self::BestEffort(priority) => format!("-c 2 -n {}", priority.min(7)),
+            Self::Realtime(priority) => format!("-c 1 -n {}", priority.min(7)),
+        }
+    }
+}
+
+/// A resource limit of a remote process, see
+/// [`OwningCommand::limit`](crate::OwningCommand::limit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Rlimit {
+    /// The CPU time in seconds, after which the process is killed with
+    /// `SIGXCPU` (`ulimit -t`).
+    CpuSeconds(u64),
+
+    /// The size of the virtual memory in bytes, rounded down to KiB, beyond
+    /// which allocations fail (`ulimit -v`).
+    Memory(u64),
+
+    /// The number of files the process can have open at once (`ulimit -n`).
+    OpenFiles(u64),
+}
+
+impl Rlimit {
+    fn ulimit(self) -> String {
+        match self {
+            Self::CpuSeconds(seconds) => format!("ulimit -t {}", seconds),
+            Self::Memory(bytes) => format!("ulimit -v {}", bytes / 1024),
+            Self::OpenFiles(files) => format!("ulimit -n {}", files),
+        }
+    }
+}
+
+/// A transient systemd unit a remote process is run in with `systemd-run`,
+/// see [`OwningCommand::as_transient_unit`](crate::OwningCommand::as_transient_unit).
+///
+/// By default, the process runs in a service, which is started by the
+/// service manager rather than the ssh session, so it survives a
+/// disconnect, and `systemd-run` returns once it was started. Its output
+/// goes to the journal, e.g. `journalctl -u NAME`.
+///
+/// ```rust,no_run
+/// # async fn foo(session: &openssh::Session) -> Result<(), openssh::Error> {
+/// use openssh::TransientUnit;
+///
+/// let mut unit = TransientUnit::new("nightly-backup");
+/// unit.property("MemoryMax=2G").property("CPUQuota=50%").collect(true);
+///
+/// session
+///     .command("/usr/local/bin/backup")
+///     .as_transient_unit(&unit)
+///     .status()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TransientUnit {
+    name: String,
+    properties: Vec<String>,
+    scope: bool,
+    wait: bool,
+    collect: bool,
+    user: bool,
+}
+
+impl TransientUnit {
+    /// Run the process in a unit named `name`, e.g. `backup` for
+    /// `backup.service`.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            properties: Vec::new(),
+            scope: false,
+            wait: false,
+            collect: false,
+            user: false,
+        }
+    }
+
+    /// Set the property `property` of the unit, e.g. `MemoryMax=1G` or
+    /// `Nice=10`, see `systemd.resource-control(5)` and `systemd.exec(5)`.
+    pub fn property(&mut self, property: impl Into<String>) -> &mut Self {
+        self.properties.push(property.into());
+        self
+    }
+
+    /// Run the process in a scope instead of a service (`--scope`), which
+    /// keeps the process a child of the remote shell with its stdio, so it
+    /// runs in the foreground and is subject to the resource properties,
+    /// but does not survive a disconnect.
+    ///
+    /// Defaults to `false`.
+    pub fn scope(&mut self, scope: bool) -> &mut Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Wait for the service to exit, with its stdio connected to the remote
+    /// process (`--wait --pipe`), so that its output can be read and its
+    /// exit status is the one of the remote process. The service is then
+    /// stopped if the connection is lost.
+    ///
+    /// Has no effect on scopes, which are always waited for. Defaults to
+    /// `false`.
+    pub fn wait(&mut self, wait: bool) -> &mut Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Unload the unit once it exited even if it failed (`--collect`),
+    /// rather than keeping it around for inspection.
+    ///
+    /// Defaults to `false`.
+    pub fn collect(&mut self, collect: bool) -> &mut Self {
+        self.collect = collect;
+        self
+    }
+
+    /// Run the unit with the service manager of the remote user (`--user`)
+    /// rather than the system one, which requires root otherwise.
+    ///
+    /// Defaults to `false`.
+    pub fn user(&mut self, user: bool) -> &mut Self {
+        self.user = user;
+        self
+    }
+
+    /// Return the command line running `line` in the unit.
+    fn wrap(&self, line: &[u8]) -> Vec<u8> {
+        let mut args = vec![format!("--unit={}", self.name)];
+        args.extend(
+            self.properties
+                .iter()
+                .map(|property| format!("--property={}", property)),
+        );
+        let flags = [
+            (self.user, "--user"),
+            (self.scope, "--scope"),
+            (self.wait && !self.scope, "--wait"),
+            (self.wait && !self.scope, "--pipe"),
+            (self.collect, "--collect"),
+        ];
+        args.extend(
+            flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, flag)| flag.to_string()),
+        );
+
+        let mut wrapped = b"systemd-run".to_vec();
+        for arg in &args {
+            wrapped.push(b' ');
+            wrapped.extend_from_slice(escape(OsStr::new(arg)).as_bytes());
+        }
+        wrapped.extend_from_slice(b" --quiet -- ");
+        wrapped.extend_from_slice(&sh(line, &[]));
+        wrapped
+    }
+}
+
+/// The settings applied to a remote command by wrapping its command line.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Wrapper {
+    /// The id of the pid file of the remote process, see [`RemoteKill`].
+    pub(crate) kill_id: Option<String>,
+
+    /// The adjustment of the niceness, run with `nice -n`.
+    pub(crate) nice: Option<i8>,
+    pub(crate) ionice: Option<IoPriority>,
+    /// The CPUs the process may run on, run with `taskset -c`.
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+    /// Applied with `ulimit`, at most one of each kind.
+    pub(crate) limits: Vec<Rlimit>,
+    /// The file mode creation mask, set with `umask`.
+    pub(crate) umask: Option<u32>,
+    /// Environment variables set with `env`, with valid names.
+    pub(crate) env: Vec<(String, String)>,
+    pub(crate) transient_unit: Option<TransientUnit>,
+}
+
+impl Wrapper {
+    /// Return the command line running `line` with the settings applied.
+    pub(crate) fn wrap<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut line = Cow::Borrowed(line);
+
+        if !self.env.is_empty() {
+            let mut wrapped = b"env ".to_vec();
+            for (name, value) in &self.env {
+                wrapped.extend_from_slice(name.as_bytes());
+                wrapped.push(b'=');
+                wrapped.extend_from_slice(escape(OsStr::new(value)).as_bytes());
+                wrapped.push(b' ');
+            }
+            wrapped.extend_from_slice(&sh(&line, &[]));
+            line = Cow::Owned(wrapped);
+        }
+
+        // `ulimit` and `umask` are shell builtins, so they are run by the
+        // shell that runs the command line.
+        if !self.limits.is_empty() || self.umask.is_some() {
+            let mut script: Vec<_> = self.limits.iter().map(|limit| limit.ulimit()).collect();
+            if let Some(umask) = self.umask {
+                script.push(format!("umask {:03o}", umask & 0o777));
+            }
+            script.push(r#"eval "$1""#.into());
+            line = Cow::Owned(sh(script.join(" && ").as_bytes(), &[&line]));
+        }
+
+        // Only numbers are added to the command line, the command line
+        // itself is passed as a single escaped argument.
+        let mut prefix = String::new();
+        if let Some(cpus) = &self.cpu_affinity {
+            let cpus: Vec<_> = cpus.iter().map(usize::to_string).collect();
+            prefix += &format!("taskset -c {} ", cpus.join(","));
+        }
+        if let Some(priority) = self.ionice {
+            prefix += &format!("ionice {} ", priority.args());
+        }
+        if let Some(adjustment) = self.nice {
+            prefix += &format!("nice -n {} ", adjustment);
+        }
+        if !prefix.is_empty() {
+            let mut wrapped = prefix.into_bytes();
+            wrapped.extend_from_slice(&sh(&line, &[]));
+            line = Cow::Owned(wrapped);
+        }
+
+        if let Some(unit) = &self.transient_unit {
+            line = Cow::Owned(unit.wrap(&line));
+        }
+
+        // The pid recorded is the one of the outermost program above, which
+        // execs the command line.
+        if let Some(id) = &self.kill_id {
+            line = Cow::Owned(sh(RECORD_PID.as_bytes(), &[id.as_bytes(), &line]));
+        }
+
+        line
+    }
+}
+
+/// Return the command line running `script` with `sh -c`, passing `args`
+/// as its positional parameters.
+fn sh(script: &[u8], args: &[&[u8]]) -> Vec<u8> {
+    let mut line = b"sh -c ".to_vec();
+    line.extend_from_slice(escape(OsStr::from_bytes(script)).as_bytes());
+    line.extend_from_slice(b" sh");
+    for arg in args {
+        line.push(b' ');
+        line.extend_from_slice(escape(OsStr::from_bytes(arg)).as_bytes());
+    }
+    line
+}
+
+/// Kills a remote process wrapped with a [`Wrapper::kill_id`] on drop,
+/// unless disarmed.
+#[derive(Debug)]
+pub(crate) struct RemoteKill {
+    ctl: Box<Path>,
+    id: Option<String>,
+}
+
+impl RemoteKill {
+    /// Create a guard for a remote process to be spawned through the master
+    /// listening on `ctl`, returning it with the id to set as
+    /// [`Wrapper::kill_id`].
+    pub(crate) fn new(ctl: &Path) -> (Self, String) {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        let id = format!(
+            "{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            nanos
+        );
+
+        let guard = Self {
+            ctl: ctl.into(),
+            id: Some(id.clone()),
+        };
+        (guard, id)
+    }
+
+    /// Keep the remote process from being killed, e.g. once it exited.
+    pub(crate) fn disarm(&mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for RemoteKill {
+    fn drop(&mut self) {
+        let id = match self.id.take() {
+            Some(id) => id,
+            None => return,
+        };
+
+        let line = sh(KILL.as_bytes(), &[id.as_bytes()]);
+        let mut cmd: Command = crate::session::new_ctl_cmd(&self.ctl, &["-T", "-p", "9"]);
+        cmd.arg("--")
+            .arg(OsStr::from_bytes(&line))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        // Drop cannot wait for ssh, so reap it from a thread instead.
+        if let Ok(mut child) = cmd.spawn() {
+            std::thread::spawn(move || child.wait());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    fn sh_in(tmpdir: &Path, line: &[u8]) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(OsStr::from_bytes(line))
+            .env("TMPDIR", tmpdir);
+        cmd
+    }
+
+    #[test]
+    fn record_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            kill_id: Some("test".into()),
+            ..Wrapper::default()
+        };
+
+        let mut child = sh_in(
+            dir.path(),
+            &wrapper.wrap(b"cat; echo \"it's done\"; exit 3"),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+        child.stdin.take().unwrap().write_all(b"foo\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert_eq!(output.stdout, b"foo\nit's done\n");
+        assert_eq!(output.status.code(), Some(3));
+        // The pid file is removed once the process exited.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        assert_eq!(Wrapper::default().wrap(b"true"), &b"true"[..]);
+    }
+
+    #[test]
+    fn kill() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            kill_id: Some("test".into()),
+            ..Wrapper::default()
+        };
+
+        let start = Instant::now();
+        let mut child = sh_in(dir.path(), &wrapper.wrap(b"sleep 10"))
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let status = sh_in(dir.path(), &sh(KILL.as_bytes(), &[b"test"]))
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // `sh -c` exits with 128 + the signal of a killed child.
+        assert_eq!(child.wait().unwrap().code(), Some(128 + libc::SIGTERM));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn scheduling() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            kill_id: Some("test".into()),
+            nice: Some(5),
+            ionice: Some(IoPriority::Idle),
+            cpu_affinity: Some(vec![0]),
+            limits: Vec::new(),
+            umask: None,
+            env: Vec::new(),
+            transient_unit: None,
+        };
+
+        let line = wrapper.wrap(b"nice; ionice; taskset -pc $$ | sed 's/.*: //'");
+        let output = sh_in(dir.path(), &line).output().unwrap();
+        assert_eq!(output.stdout, b"5\nidle\n0\n");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            limits: vec![
+                Rlimit::CpuSeconds(5),
+                Rlimit::Memory(100 * 1024 * 1024),
+                Rlimit::OpenFiles(64),
+            ],
+            ..Wrapper::default()
+        };
+
+        let line = wrapper.wrap(b"ulimit -t; ulimit -v; ulimit -n; exit 3");
+        let output = sh_in(dir.path(), &line).output().unwrap();
+        assert_eq!(output.stdout, b"5\n102400\n64\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn env() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            nice: Some(5),
+            limits: vec![Rlimit::OpenFiles(64)],
+            env: vec![
+                ("GREETING".into(), "it's $HOME".into()),
+                ("EMPTY".into(), String::new()),
+            ],
+            ..Wrapper::default()
+        };
+
+        let line = wrapper.wrap(br#"echo "$GREETING ${EMPTY-unset}"; ulimit -n"#);
+        let output = sh_in(dir.path(), &line).output().unwrap();
+        assert_eq!(output.stdout, b"it's $HOME \n64\n");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn umask() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper = Wrapper {
+            umask: Some(0o027),
+            limits: vec![Rlimit::OpenFiles(64)],
+            ..Wrapper::default()
+        };
+
+        let line = wrapper.wrap(b"umask; ulimit -n");
+        let output = sh_in(dir.path(), &line).output().unwrap();
+        assert_eq!(output.stdout, b"0027\n64\n");
+        assert!(output.status.success());
+    }
+
+    #[test]
+    fn transient_unit() {
+        let mut unit = TransientUnit::new("backup job");
+        unit.property("MemoryMax=1G").collect(true).wait(true);
+        let wrapper = Wrapper {
+            nice: Some(5),
+            transient_unit: Some(unit.clone()),
+            ..Wrapper::default()
+        };
+        assert_eq!(
+            String::from_utf8(wrapper.wrap(b"echo hi").into_owned()).unwrap(),
+            "systemd-run '--unit=backup job' --property=MemoryMax=1G --wait --pipe \
+             --collect --quiet -- sh -c 'nice -n 5 sh -c '\\''echo hi'\\'' sh' sh"
+        );
+
+        unit.scope(true).user(true);
+        assert_eq!(
+            String::from_utf8(unit.wrap(b"true")).unwrap(),
+            "systemd-run '--unit=backup job' --property=MemoryMax=1G --user --scope \
+             --collect --quiet -- sh -c true sh"
+        );
+    }
+}
@@ -1,12 +1,16 @@
+// `ci` is set by `run_ci_tests.sh` to enable the tests requiring a running
+// sshd. `unexpected_cfgs` is unknown to older compilers.
+#![allow(unknown_lints, unexpected_cfgs)]
+
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::{
     env,
     io::{self, Write},
     net::IpAddr,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process,
-    time::Duration,
+    time::{Duration, UNIX_EPOCH},
 };
 use tempfile::tempdir;
 use tokio::{
@@ -124,16 +128,16 @@ async fn control_dir() {
     std::fs::create_dir(dirname).unwrap();
 
     let mut session_builder = SessionBuilder::default();
-    session_builder.control_directory(&dirname);
+    session_builder.control_directory(dirname);
 
     for session in session_builder_connect(session_builder, &addr()).await {
         session.check().await.unwrap();
-        let mut iter = std::fs::read_dir(&dirname).unwrap();
+        let mut iter = std::fs::read_dir(dirname).unwrap();
         assert!(iter.next().is_some());
         session.close().await.unwrap();
     }
 
-    std::fs::remove_dir(&dirname).unwrap();
+    std::fs::remove_dir(dirname).unwrap();
 }
 
 #[derive(Default, Debug, PartialEq, Eq)]
@@ -144,7 +148,7 @@ struct ProtoUserHostPort<'a> {
     port: Option<&'a str>,
 }
 
-fn parse_user_host_port(s: &str) -> Option<ProtoUserHostPort> {
+fn parse_user_host_port(s: &str) -> Option<ProtoUserHostPort<'_>> {
     static SSH_REGEX: Lazy<Regex> = Lazy::new(|| {
         Regex::new(
             r"(?x)^((?P<proto>[[:alpha:]]+)://)?((?P<user>.*?)@)?(?P<host>.*?)(:(?P<port>\d+))?$",
@@ -247,7 +251,7 @@ async fn config_file() {
         session.close().await.unwrap();
     }
 
-    std::fs::remove_dir_all(&dirname).unwrap();
+    std::fs::remove_dir_all(dirname).unwrap();
 }
 
 #[tokio::test]
@@ -559,6 +563,48 @@ async fn connect_timeout() {
     }
 }
 
+#[tokio::test]
+async fn connect_timeout_hung_handshake() {
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+
+    // A tarpit that sends the ssh banner, then never proceeds with key exchange.
+    let listener = TcpListener::bind((loopback(), 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let _ = stream.write_all(b"SSH-2.0-tarpit\r\n").await;
+            streams.push(stream);
+        }
+    });
+
+    let mut sb = SessionBuilder::default();
+    sb.connect_timeout(Duration::from_secs(1))
+        .port(port)
+        .user_known_hosts_file(get_known_hosts_path());
+
+    #[cfg(feature = "process-mux")]
+    {
+        let t = Instant::now();
+        let failed = sb.connect("127.0.0.1").await.unwrap_err();
+        let duration = t.elapsed();
+
+        assert!(duration < Duration::from_secs(3));
+        assert!(matches!(failed, Error::Timeout), "{:?}", failed);
+    }
+
+    #[cfg(feature = "native-mux")]
+    {
+        let t = Instant::now();
+        let failed = sb.connect_mux("127.0.0.1").await.unwrap_err();
+        let duration = t.elapsed();
+
+        assert!(duration < Duration::from_secs(3));
+        assert!(matches!(failed, Error::Timeout), "{:?}", failed);
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 async fn spawn_and_wait() {
@@ -700,7 +746,7 @@ async fn broken_connection() {
         let killed = session
             .command("kill")
             .arg("-9")
-            .arg(&format!("{}", ppid))
+            .arg(format!("{}", ppid))
             .output()
             .await
             .unwrap_err();
@@ -791,6 +837,32 @@ async fn connection_refused() {
     }
 }
 
+#[tokio::test]
+async fn verbose_connection_refused() {
+    let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let lines_ = lines.clone();
+
+    let mut builder = SessionBuilder::default();
+    builder.verbosity(3).on_log(move |line| {
+        lines_.lock().unwrap().push(line.to_owned());
+    });
+
+    for err in session_builder_connects_err("ssh://127.0.0.1:9", builder).await {
+        // The debugging messages are left out of the error.
+        match err {
+            Error::Connect(e) => {
+                eprintln!("{:?}", e);
+                assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+                assert!(!e.to_string().contains("debug"), "{}", e);
+            }
+            e => unreachable!("{:?}", e),
+        }
+    }
+
+    let lines = lines.lock().unwrap();
+    assert!(lines.iter().any(|line| line.starts_with("debug3: ")));
+}
+
 #[tokio::test]
 async fn auth_failed() {
     let addr = if cfg!(ci) {
@@ -822,7 +894,7 @@ async fn remote_socket_forward() {
         let output_listener = UnixListener::bind(&unix_socket).unwrap();
 
         eprintln!("Requesting port forward");
-        session
+        let _guard = session
             .request_port_forward(ForwardType::Remote, (loopback(), *port), &*unix_socket)
             .await
             .unwrap();
@@ -884,7 +956,7 @@ async fn local_socket_forward() {
         let dir = tempdir().unwrap();
         let unix_socket = dir.path().join("unix_socket_forwarded");
 
-        session
+        let _guard = session
             .request_port_forward(ForwardType::Local, &*unix_socket, (loopback(), port))
             .await
             .unwrap();
@@ -909,6 +981,2407 @@ async fn local_socket_forward() {
     }
 }
 
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn connect_with_port_forward() {
+    let mut builder = SessionBuilder::default();
+    builder.known_hosts_check(KnownHosts::Accept).port_forward(
+        ForwardType::Remote,
+        (loopback(), 1235),
+        (loopback(), 2222),
+    );
+
+    for session in session_builder_connect(builder, &addr()).await {
+        assert_eq!(
+            session.forwards(),
+            [ActiveForward::Remote {
+                listen_socket: Socket::new("127.0.0.1", 1235),
+                connect_socket: Socket::new("127.0.0.1", 2222),
+            }]
+        );
+
+        let status = session
+            .raw_command("nc -z localhost 1235")
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        session.close().await.unwrap();
+    }
+
+    // The unprivileged test-user cannot listen on port 80.
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .port_forward(ForwardType::Remote, (loopback(), 1236), (loopback(), 2222))
+        .port_forward(ForwardType::Remote, (loopback(), 80), (loopback(), 2222));
+
+    #[cfg(feature = "process-mux")]
+    let res = builder.connect(&addr()).await;
+    #[cfg(not(feature = "process-mux"))]
+    let res = builder.connect_mux(&addr()).await;
+
+    match res.unwrap_err() {
+        Error::ConnectForwardRefused {
+            forward_type,
+            listen_socket,
+            ..
+        } => {
+            assert_eq!(forward_type, ForwardType::Remote);
+            assert_eq!(listen_socket, Socket::new("127.0.0.1", 80));
+        }
+        e => unreachable!("{:?}", e),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn supervise_port_forward() {
+    for session in connects().await {
+        let forward = session
+            .supervise_port_forward(
+                ForwardType::Remote,
+                (loopback(), 0),
+                (loopback(), 2222),
+                Duration::from_millis(500),
+            )
+            .await
+            .unwrap();
+        let port = forward.allocated_port().unwrap();
+
+        let cmd = format!("nc -z localhost {}", port);
+        assert!(session.raw_command(&cmd).status().await.unwrap().success());
+
+        // Cancel the forwarding behind the supervisor's back.
+        let status = std::process::Command::new("ssh")
+            .arg("-S")
+            .arg(session.control_socket())
+            .args(["-O", "cancel", "-R"])
+            .arg(format!("127.0.0.1:{}:127.0.0.1:2222", port))
+            .arg("none")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        sleep(Duration::from_secs(1)).await;
+        assert!(session.raw_command(&cmd).status().await.unwrap().success());
+
+        forward.cancel().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn expose_unix_socket() {
+    for session in connects().await {
+        let dir = tempdir().unwrap();
+        let unix_socket = dir.path().join("unix_socket_listener");
+        let listener = UnixListener::bind(&unix_socket).unwrap();
+
+        let remote_path = "/tmp/openssh-rs-exposed.sock";
+
+        // Exposing twice must replace the socket left behind by the first time.
+        for _ in 0..2 {
+            let guard = session
+                .expose_unix_socket(&unix_socket, remote_path)
+                .await
+                .unwrap();
+
+            let cmd = format!("echo hello | nc -U {} >/dev/stderr", remote_path);
+            let child = session
+                .raw_command(cmd)
+                .stderr(Stdio::piped())
+                .spawn()
+                .await
+                .unwrap();
+
+            let (mut stream, _addr) = listener.accept().await.unwrap();
+            let mut buffer = [0_u8; 6];
+            stream.read_exact(&mut buffer).await.unwrap();
+            assert_eq!(&buffer, b"hello\n");
+            drop(stream);
+
+            child.wait_with_output().await.unwrap();
+            guard.cancel().await.unwrap();
+        }
+
+        // A regular file at the path is neither removed nor replaced.
+        let remote_file = "/tmp/openssh-rs-exposed.txt";
+        let status = session
+            .raw_command(format!("echo keep > {}", remote_file))
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        session
+            .expose_unix_socket(&unix_socket, remote_file)
+            .await
+            .unwrap_err();
+        let output = session
+            .command("cat")
+            .arg(remote_file)
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"keep\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn cancel_port_forward() {
+    for session in connects().await {
+        let guard = session
+            .request_port_forward(ForwardType::Remote, (loopback(), 0), (loopback(), 2222))
+            .await
+            .unwrap();
+        let port = guard.allocated_port().unwrap();
+        assert_eq!(
+            session.forwards(),
+            [ActiveForward::Remote {
+                listen_socket: Socket::new("127.0.0.1", port),
+                connect_socket: Socket::new("127.0.0.1", 2222),
+            }]
+        );
+
+        let cmd = format!("nc -z localhost {}", port);
+        let status = session.raw_command(&cmd).status().await.unwrap();
+        assert!(status.success());
+
+        guard.cancel().await.unwrap();
+        assert_eq!(session.forwards(), []);
+
+        let status = session.raw_command(&cmd).status().await.unwrap();
+        assert!(!status.success());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn socks_proxy() {
+    for session in connects().await {
+        let proxy = session
+            .socks_proxy(([127, 0, 0, 1], 0).into())
+            .await
+            .unwrap();
+        let addr = proxy.local_addr();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(proxy.url(), format!("socks5h://{}", addr));
+        assert!(proxy.is_alive().await);
+
+        // Connect to sshd through the proxy.
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[5, 1, 0]).await.unwrap();
+        let mut reply = [0_u8; 2];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [5, 0]);
+
+        stream
+            .write_all(&[5, 1, 0, 1, 127, 0, 0, 1, 0x08, 0xae])
+            .await
+            .unwrap();
+        let mut reply = [0_u8; 10];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply[1], 0);
+
+        let mut banner = [0_u8; 8];
+        stream.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"SSH-2.0-");
+        drop(stream);
+
+        proxy.close().await.unwrap();
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn proxy_session() {
+    for proxy in connects().await {
+        let mut builder = SessionBuilder::default();
+        builder
+            .known_hosts_check(KnownHosts::Accept)
+            .proxy_session(&proxy);
+
+        for session in session_builder_connect(builder, &addr()).await {
+            let output = session.command("echo").arg("hello").output().await.unwrap();
+            assert_eq!(output.stdout, b"hello\n");
+
+            session.close().await.unwrap();
+        }
+
+        proxy.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_between_sessions() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    let data = "hello\nworld\n";
+    let status = session_a
+        .command("sh")
+        .arg("-c")
+        .arg(format!("printf '{}' > /tmp/openssh-rs-copy-a", data))
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    let copied = copy_between(
+        session_a,
+        "/tmp/openssh-rs-copy-a",
+        session_b,
+        "/tmp/openssh-rs copy b",
+    )
+    .await
+    .unwrap();
+    assert_eq!(copied, data.len() as u64);
+
+    let output = session_b
+        .command("cat")
+        .arg("/tmp/openssh-rs copy b")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, data.as_bytes());
+
+    let err = copy_between(
+        session_a,
+        "/does/not/exist",
+        session_b,
+        "/tmp/openssh-rs copy b",
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::Remote(_)), "{:?}", err);
+
+    // A failed copy leaves the existing destination untouched.
+    let output = session_b
+        .command("cat")
+        .arg("/tmp/openssh-rs copy b")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, data.as_bytes());
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_between_sessions_write_fails() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    // Larger than the window of the ssh channel, so that the reader blocks
+    // once the writer is gone.
+    let status = session_a
+        .command("sh")
+        .arg("-c")
+        .arg("head -c 16777216 /dev/zero > /tmp/openssh-rs-copy-large")
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    let err = tokio::time::timeout(
+        Duration::from_secs(30),
+        copy_between(
+            session_a,
+            "/tmp/openssh-rs-copy-large",
+            session_b,
+            "/does/not/exist/openssh-rs-copy",
+        ),
+    )
+    .await
+    .expect("the copy hangs")
+    .unwrap_err();
+    assert!(matches!(err, Error::Remote(_)), "{:?}", err);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_between_sessions_parallel() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    let size = 3 * 1024 * 1024 + 42;
+    let status = session_a
+        .raw_command(format!(
+            "head -c {} /dev/urandom > /tmp/openssh-rs-parallel-a",
+            size
+        ))
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    let mut options = CopyOptions::new();
+    options.channels(std::num::NonZeroUsize::new(3).unwrap());
+
+    let copied = copy_between_with(
+        session_a,
+        "/tmp/openssh-rs-parallel-a",
+        session_b,
+        "/tmp/openssh-rs-parallel-b",
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(copied, size);
+
+    let checksum_a = session_a
+        .command("cksum")
+        .arg("/tmp/openssh-rs-parallel-a")
+        .output()
+        .await
+        .unwrap();
+    let checksum_b = session_b
+        .command("cksum")
+        .arg("/tmp/openssh-rs-parallel-b")
+        .output()
+        .await
+        .unwrap();
+    let checksum = |output: &std::process::Output| {
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    assert_eq!(checksum(&checksum_a), checksum(&checksum_b));
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_between_sessions_preserve() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    let status = session_a
+        .raw_command(
+            "echo preserved > /tmp/openssh-rs-preserve-a && \
+             chmod 751 /tmp/openssh-rs-preserve-a && \
+             TZ=UTC0 touch -m -t 202001020304.05 /tmp/openssh-rs-preserve-a && \
+             rm -f /tmp/openssh-rs-preserve-b",
+        )
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    async fn stat(session: &Session, path: &str) -> String {
+        let output = session
+            .command("stat")
+            .args(["-c", "%a %Y", path])
+            .output()
+            .await
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    let mut options = CopyOptions::new();
+    options.preserve_mode(true).preserve_mtime(true);
+    copy_between_with(
+        session_a,
+        "/tmp/openssh-rs-preserve-a",
+        session_b,
+        "/tmp/openssh-rs-preserve-b",
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        stat(session_b, "/tmp/openssh-rs-preserve-b").await,
+        "751 1577934245\n"
+    );
+
+    // explicit values take precedence
+    options.mode(0o600).mtime(UNIX_EPOCH);
+    copy_between_with(
+        session_a,
+        "/tmp/openssh-rs-preserve-a",
+        session_b,
+        "/tmp/openssh-rs-preserve-b",
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        stat(session_b, "/tmp/openssh-rs-preserve-b").await,
+        "600 0\n"
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_between_sessions_atomic() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    let mut options = CopyOptions::new();
+    options.inplace(false);
+
+    let copied = copy_between_with(
+        session_a,
+        "/etc/hostname",
+        session_b,
+        "/tmp/openssh-rs-atomic",
+        &options,
+    )
+    .await
+    .unwrap();
+    let output = session_b
+        .command("cat")
+        .arg("/tmp/openssh-rs-atomic")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout.len() as u64, copied);
+
+    // a failed copy leaves neither the destination nor a temporary file
+    copy_between_with(
+        session_a,
+        "/does-not-exist",
+        session_b,
+        "/tmp/openssh-rs-atomic-failed",
+        &options,
+    )
+    .await
+    .unwrap_err();
+    let output = session_b
+        .raw_command("ls -a /tmp | grep openssh-rs-atomic-failed")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, b"");
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_between_sessions_sparse() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    // 8 MiB of holes with a few bytes of data in the middle
+    let status = session_a
+        .raw_command(
+            "rm -f /tmp/openssh-rs-sparse-a && \
+             dd if=/dev/null of=/tmp/openssh-rs-sparse-a bs=1M seek=8 && \
+             echo data | dd of=/tmp/openssh-rs-sparse-a bs=1M seek=4 conv=notrunc",
+        )
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    for (channels, compress) in [(1, false), (3, false), (1, true), (3, true)] {
+        let mut options = CopyOptions::new();
+        options
+            .sparse(true)
+            .compress(compress)
+            .channels(std::num::NonZeroUsize::new(channels).unwrap());
+
+        let copied = copy_between_with(
+            session_a,
+            "/tmp/openssh-rs-sparse-a",
+            session_b,
+            "/tmp/openssh-rs-sparse-b",
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(copied, 8 * 1024 * 1024);
+
+        let output = session_b
+            .raw_command(
+                "wc -c < /tmp/openssh-rs-sparse-b && du -k /tmp/openssh-rs-sparse-b | cut -f1",
+            )
+            .output()
+            .await
+            .unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        let mut lines = output
+            .lines()
+            .map(|line| line.trim().parse::<u64>().unwrap());
+        assert_eq!(lines.next(), Some(8 * 1024 * 1024));
+        assert!(lines.next().unwrap() < 8 * 1024);
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_dir_between_sessions() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    let status = session_a
+        .raw_command(
+            "rm -rf /tmp/openssh-rs-dir-a && mkdir -p /tmp/openssh-rs-dir-a/sub && \
+             echo a > /tmp/openssh-rs-dir-a/a && echo b > /tmp/openssh-rs-dir-a/sub/b",
+        )
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+    let status = session_b
+        .raw_command(
+            "rm -rf /tmp/openssh-rs-dir-b && mkdir -p /tmp/openssh-rs-dir-b/old && \
+             echo c > /tmp/openssh-rs-dir-b/c",
+        )
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    let mut options = CopyOptions::new();
+    options.delete(true);
+    copy_dir_between(
+        session_a,
+        "/tmp/openssh-rs-dir-a",
+        session_b,
+        "/tmp/openssh-rs-dir-b",
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let output = session_b
+        .raw_command("cd /tmp/openssh-rs-dir-b && find . | sort")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        ".\n./a\n./sub\n./sub/b\n"
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn copy_dir_between_sessions_filtered() {
+    let sessions = connects().await;
+    let (session_a, session_b) = (&sessions[0], &sessions[sessions.len() - 1]);
+
+    let status = session_a
+        .raw_command(
+            "rm -rf /tmp/openssh-rs-filter-a && \
+             mkdir -p /tmp/openssh-rs-filter-a/src /tmp/openssh-rs-filter-a/target/debug && \
+             cd /tmp/openssh-rs-filter-a && \
+             touch src/main.rs target/debug/app app.log keep.log",
+        )
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+    let status = session_b
+        .raw_command(
+            "rm -rf /tmp/openssh-rs-filter-b && mkdir -p /tmp/openssh-rs-filter-b && \
+             touch /tmp/openssh-rs-filter-b/old.log /tmp/openssh-rs-filter-b/old",
+        )
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+
+    let mut options = CopyOptions::new();
+    options
+        .exclude("target/")
+        .exclude("*.log")
+        .include("keep.log")
+        .delete(true);
+    copy_dir_between(
+        session_a,
+        "/tmp/openssh-rs-filter-a",
+        session_b,
+        "/tmp/openssh-rs-filter-b",
+        &options,
+    )
+    .await
+    .unwrap();
+
+    // excluded files are neither copied nor deleted
+    let output = session_b
+        .raw_command("cd /tmp/openssh-rs-filter-b && find . | sort")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        ".\n./keep.log\n./old.log\n./src\n./src/main.rs\n"
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn tail_file() {
+    for session in connects().await {
+        let status = session
+            .raw_command("printf 'a\\nb\\n' > /tmp/openssh-rs-tail")
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let mut tail = session
+            .tail_file("/tmp/openssh-rs-tail", false)
+            .await
+            .unwrap();
+        assert_eq!(tail.next_line().await.unwrap().as_deref(), Some("a"));
+        assert_eq!(tail.next_line().await.unwrap().as_deref(), Some("b"));
+        assert_eq!(tail.next_line().await.unwrap(), None);
+
+        let mut tail = session
+            .tail_file("/tmp/openssh-rs-tail", true)
+            .await
+            .unwrap();
+        assert_eq!(tail.next_line().await.unwrap().as_deref(), Some("a"));
+        assert_eq!(tail.next_line().await.unwrap().as_deref(), Some("b"));
+
+        // rotate the file
+        let status = session
+            .raw_command(
+                "mv /tmp/openssh-rs-tail /tmp/openssh-rs-tail.1 && \
+                 echo c > /tmp/openssh-rs-tail",
+            )
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let line = tokio::time::timeout(Duration::from_secs(10), tail.next_line())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(line.as_deref(), Some("c"));
+
+        tail.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn watch() {
+    for session in connects().await {
+        let status = session
+            .raw_command("rm -rf /tmp/openssh-rs-watch && mkdir /tmp/openssh-rs-watch")
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let mut watch = match session.watch("/tmp/openssh-rs-watch", false).await {
+            Ok(watch) => watch,
+            // neither inotifywait nor fswatch is installed in the container
+            Err(Error::Remote(err)) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => panic!("{:?}", err),
+        };
+
+        // give the watcher time to set up its watches
+        sleep(Duration::from_secs(1)).await;
+        let status = session
+            .raw_command("mkdir /tmp/openssh-rs-watch/dir")
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let event = tokio::time::timeout(Duration::from_secs(10), watch.next_event())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.kind, WatchEventKind::Created);
+        assert_eq!(event.path, Path::new("/tmp/openssh-rs-watch/dir"));
+        assert!(event.is_dir);
+
+        watch.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn glob() {
+    for session in connects().await {
+        let status = session
+            .raw_command(
+                "rm -rf /tmp/openssh-rs-glob && mkdir -p /tmp/openssh-rs-glob && \
+                 cd /tmp/openssh-rs-glob && touch a.gz b.gz 'c d.gz' e.txt",
+            )
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        let paths = session.glob("/tmp/openssh-rs-glob/*.gz").await.unwrap();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/tmp/openssh-rs-glob/a.gz"),
+                Path::new("/tmp/openssh-rs-glob/b.gz"),
+                Path::new("/tmp/openssh-rs-glob/c d.gz"),
+            ]
+        );
+
+        let paths = session.glob("/tmp/openssh-rs-glob/[ab].gz").await.unwrap();
+        assert_eq!(paths.len(), 2);
+
+        let paths = session
+            .glob("/tmp/openssh-rs-glob/*.zip; touch /tmp/openssh-rs-glob/pwned")
+            .await
+            .unwrap();
+        assert!(paths.is_empty());
+        assert_eq!(
+            session
+                .glob("/tmp/openssh-rs-glob/pwned")
+                .await
+                .unwrap()
+                .len(),
+            0
+        );
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+    let pool = SessionPoolBuilder::new(builder).max_open(1).build();
+
+    let session = pool.get(&addr()).await.unwrap();
+    let ctl = session.control_socket().to_owned();
+    drop(session);
+
+    // the idle session is reused
+    let session = pool.get(&addr()).await.unwrap();
+    assert_eq!(session.control_socket(), ctl);
+
+    // no more sessions than max_open are opened
+    let pending = tokio::time::timeout(Duration::from_secs(1), pool.get(&addr())).await;
+    assert!(pending.is_err());
+
+    // a closed session is not reused
+    session.close().await.unwrap();
+    let session = pool.get(&addr()).await.unwrap();
+    assert_ne!(session.control_socket(), ctl);
+    session.check().await.unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_wait_for_returned() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+    let pool = SessionPoolBuilder::new(builder).max_open(1).build();
+
+    let session = pool.get(&addr()).await.unwrap();
+    let ctl = session.control_socket().to_owned();
+
+    let waiting = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.get(&addr()).await }
+    });
+    sleep(Duration::from_millis(500)).await;
+    assert!(!waiting.is_finished());
+
+    // the waiting get is woken up by the returned session
+    drop(session);
+    let session = tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .expect("get is woken up when a session is returned")
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.control_socket(), ctl);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_run_on_all() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .connect_timeout(Duration::from_secs(5));
+    let pool = SessionPoolBuilder::new(builder).build();
+
+    let mut progress = pool.progress();
+
+    let unreachable = "ssh://test-user@127.0.0.1:9";
+    let outputs = pool
+        .run_on_all(
+            [addr().as_str(), unreachable],
+            "echo hello",
+            Duration::from_secs(10),
+        )
+        .await;
+
+    let mut finished = Vec::new();
+    for _ in 0..4 {
+        match progress.next_event().await.unwrap() {
+            FleetEvent::Started { .. } => (),
+            FleetEvent::Finished { host, success, .. } => finished.push((host, success)),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+    finished.sort();
+    assert_eq!(finished, [(addr(), true), (unreachable.to_string(), false)]);
+
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(outputs[&addr()].output.as_ref().unwrap().stdout, b"hello\n");
+    assert!(outputs[unreachable].output.is_err());
+
+    // the timeout covers the command
+    let outputs = pool
+        .run_on_all([addr()], "sleep 10", Duration::from_secs(1))
+        .await;
+    assert!(matches!(outputs[&addr()].output, Err(Error::Timeout)));
+
+    // templates are expanded per host
+    let mut host = Host::new(addr());
+    host.label("greeting", "hello world");
+    let template = CommandTemplate::parse("echo {label:greeting}").unwrap();
+    let outputs = pool
+        .run_on_all([&host], &template, Duration::from_secs(10))
+        .await;
+    assert_eq!(
+        outputs[&addr()].output.as_ref().unwrap().stdout,
+        b"hello world\n"
+    );
+
+    let outputs = pool
+        .run_on_all([addr()], &template, Duration::from_secs(10))
+        .await;
+    assert!(matches!(&outputs[&addr()].output, Err(Error::MissingLabel(key)) if key == "greeting"));
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_run_rolling() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .connect_timeout(Duration::from_secs(5));
+    let pool = SessionPoolBuilder::new(builder).build();
+
+    let unreachable = "ssh://test-user@127.0.0.1:9".to_string();
+    let batch_size = std::num::NonZeroUsize::new(1).unwrap();
+
+    let report = pool
+        .run_rolling(
+            [addr(), unreachable.clone()],
+            "true",
+            &Rollout::new(batch_size),
+        )
+        .await;
+    assert!(!report.aborted());
+    assert_eq!(report.failed().collect::<Vec<_>>(), [unreachable.as_str()]);
+
+    let report = pool
+        .run_rolling(
+            [unreachable.clone(), addr()],
+            "true",
+            &Rollout::new(batch_size),
+        )
+        .await;
+    assert!(report.aborted());
+    assert_eq!(report.skipped, [addr()]);
+
+    let report = pool
+        .run_rolling(
+            [unreachable.clone(), addr()],
+            "true",
+            Rollout::new(batch_size).max_failure_rate(1.0),
+        )
+        .await;
+    assert!(!report.aborted());
+    // without retries, a failed host is exhausted after its only attempt
+    assert_eq!(
+        report.exhausted().collect::<Vec<_>>(),
+        [unreachable.as_str()]
+    );
+
+    // only the failed hosts are retried
+    let report = pool
+        .run_rolling(
+            [addr(), unreachable.clone()],
+            "true",
+            Rollout::new(std::num::NonZeroUsize::new(2).unwrap())
+                .max_failure_rate(1.0)
+                .retries(2)
+                .retry_backoff(Duration::from_millis(10), Duration::from_millis(20)),
+        )
+        .await;
+    assert_eq!(report.outputs[&addr()].attempts, 1);
+    assert_eq!(report.outputs[&unreachable].attempts, 3);
+    assert_eq!(
+        report.exhausted().collect::<Vec<_>>(),
+        [unreachable.as_str()]
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_run_canary() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .connect_timeout(Duration::from_secs(5));
+    let pool = SessionPoolBuilder::new(builder).build();
+
+    let rollout = Rollout::new(std::num::NonZeroUsize::new(1).unwrap());
+    let unreachable = "ssh://test-user@127.0.0.1:9";
+
+    let report = pool
+        .run_canary([addr()], [unreachable], "echo ok", &rollout, |canaries| {
+            canaries[&addr()].output.as_ref().unwrap().stdout == b"ok\n"
+        })
+        .await;
+    assert_eq!(report.outputs.len(), 2);
+    assert!(report.skipped.is_empty());
+
+    let report = pool
+        .run_canary([addr()], [unreachable], "echo ok", &rollout, |_| false)
+        .await;
+    assert!(report.aborted());
+    assert_eq!(report.outputs.len(), 1);
+    assert_eq!(report.skipped, [unreachable]);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_cancellation() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+    let pool = SessionPoolBuilder::new(builder).build();
+
+    let token = CancellationToken::new();
+    tokio::spawn({
+        let token = token.clone();
+        async move {
+            sleep(Duration::from_secs(1)).await;
+            token.cancel();
+        }
+    });
+
+    let pidfile = "/tmp/openssh-rs-pool-cancellation";
+    let hosts = [addr(), "ssh://test-user@127.0.0.1:9".to_string()];
+    let rollout = Rollout::new(std::num::NonZeroUsize::new(1).unwrap());
+    let report = pool
+        .with_cancellation(token)
+        .run_rolling(
+            hosts.clone(),
+            format!("echo $$ > {}; exec sleep 10", pidfile).as_str(),
+            &rollout,
+        )
+        .await;
+
+    assert!(matches!(
+        report.outputs[&addr()].output,
+        Err(Error::Cancelled)
+    ));
+    assert!(report.outputs[&addr()].duration < Duration::from_secs(5));
+    assert_eq!(report.skipped, [hosts[1].clone()]);
+
+    // the remote process is killed
+    let alive = format!("kill -0 \"$(cat {})\"", pidfile);
+    let mut killed = false;
+    for _ in 0..50 {
+        let outputs = pool
+            .run_on_all([addr()], alive.as_str(), Duration::from_secs(10))
+            .await;
+        if !outputs[&addr()].succeeded() {
+            killed = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(killed);
+
+    // the pool itself is not cancelled
+    let outputs = pool
+        .run_on_all([addr()], "true", Duration::from_secs(10))
+        .await;
+    assert!(outputs[&addr()].succeeded());
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_push_file() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .connect_timeout(Duration::from_secs(5));
+    let pool = SessionPoolBuilder::new(builder).build();
+
+    let dir = tempdir().unwrap();
+    let local = dir.path().join("pushed");
+    let data: Vec<u8> = (0..100_000u32).map(|n| n as u8).collect();
+    std::fs::write(&local, &data).unwrap();
+
+    let unreachable = "ssh://test-user@127.0.0.1:9";
+    let remote_path = "/tmp/openssh-rs-pushed";
+    let concurrency = std::num::NonZeroUsize::new(2).unwrap();
+    let results = pool
+        .push_file(
+            [addr().as_str(), unreachable],
+            &local,
+            remote_path,
+            concurrency,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(results.len(), 2);
+    results[&addr()].as_ref().unwrap();
+    assert!(results[unreachable].is_err());
+
+    let session = pool.get(&addr()).await.unwrap();
+    let output = session
+        .command("cat")
+        .arg(remote_path)
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, data);
+
+    // no temporary file is left behind
+    let output = session
+        .shell("ls -a /tmp | grep -c openssh-rs-pushed")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(output.stdout, b"1\n");
+
+    session
+        .command("rm")
+        .arg(remote_path)
+        .status()
+        .await
+        .unwrap();
+
+    // reading the local file fails before connecting
+    let err = pool
+        .push_file(
+            [addr()],
+            dir.path().join("missing"),
+            remote_path,
+            concurrency,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_pool_facts() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept);
+    let pool = SessionPoolBuilder::new(builder).build();
+
+    let host = Host::new(addr());
+    let facts = pool.facts(&host).await.unwrap();
+    assert_eq!(facts.os, "Linux");
+    assert!(!facts.arch.is_empty());
+
+    // cached facts do not need a session
+    let session = pool.get(&addr()).await.unwrap();
+    session.close().await.unwrap();
+    assert_eq!(pool.facts(&host).await.unwrap(), facts);
+
+    pool.forget_facts(host.name());
+    assert_eq!(pool.facts(&host).await.unwrap(), facts);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn agent() {
+    // ssh-add refuses keys that others can read.
+    let dir = tempdir().unwrap();
+    let keyfile = dir.path().join("test-key");
+    std::fs::copy(".test-key", &keyfile).unwrap();
+    std::fs::set_permissions(
+        &keyfile,
+        std::os::unix::fs::PermissionsExt::from_mode(0o600),
+    )
+    .unwrap();
+
+    let agent = agent::Agent::start().await.unwrap();
+    assert_eq!(agent.identities().await.unwrap(), []);
+
+    agent
+        .add_with_lifetime(&keyfile, Duration::from_secs(60))
+        .await
+        .unwrap();
+    let identities = agent.identities().await.unwrap();
+    assert_eq!(identities.len(), 1);
+    assert!(identities[0].fingerprint.starts_with("SHA256:"));
+
+    let mut builder = SessionBuilder::default();
+    builder.ssh_auth_sock(agent.socket());
+    for session in session_builder_connect(builder, &addr()).await {
+        session.close().await.unwrap();
+    }
+
+    agent.remove(&keyfile).await.unwrap();
+    assert_eq!(agent.identities().await.unwrap(), []);
+    agent.add(&keyfile).await.unwrap();
+    agent.remove_all().await.unwrap();
+    assert_eq!(agent.identities().await.unwrap(), []);
+
+    let socket = agent.socket().to_owned();
+    drop(agent);
+    assert!(!socket.exists());
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn host_key_fingerprint() {
+    // sshd inside the container listens on port 2222.
+    let output = process::Command::new("ssh-keyscan")
+        .args(["-t", "ed25519", "-p", "2222", "127.0.0.1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let dir = tempdir().unwrap();
+    let scanned = dir.path().join("scanned");
+    std::fs::write(&scanned, output.stdout).unwrap();
+    let fingerprint = keygen::fingerprint(&scanned).await.unwrap();
+
+    let mut builder = SessionBuilder::default();
+    builder.host_key_fingerprint(&fingerprint);
+    for session in session_builder_connect(builder, &addr()).await {
+        session.close().await.unwrap();
+    }
+
+    let wrong = "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU";
+    let mut builder = SessionBuilder::default();
+    builder.host_key_fingerprint(wrong);
+    for err in session_builder_connects_err(&addr(), builder).await {
+        match err {
+            Error::HostKeyMismatch { expected, .. } => assert_eq!(expected, wrong),
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn ephemeral_known_hosts() {
+    let dir = tempdir().unwrap();
+    let known_hosts = dir.path().join("known_hosts");
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(&known_hosts)
+        .known_hosts_check(KnownHosts::Add)
+        .ephemeral_known_hosts(true);
+
+    #[cfg(feature = "process-mux")]
+    {
+        let session = builder.connect(&addr()).await.unwrap();
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    #[cfg(feature = "native-mux")]
+    {
+        let session = builder.connect_mux(&addr()).await.unwrap();
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    assert!(!known_hosts.exists());
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn revoked_host_keys() {
+    // sshd inside the container listens on port 2222.
+    let output = process::Command::new("ssh-keyscan")
+        .args(["-p", "2222", "127.0.0.1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let dir = tempdir().unwrap();
+    let scanned = dir.path().join("scanned");
+    let krl = dir.path().join("krl");
+
+    // Strip the host names, as ssh-keygen only reads plain public keys.
+    let keys: String = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|line| Some(format!("{}\n", line.split_once(' ')?.1)))
+        .collect();
+    std::fs::write(&scanned, keys).unwrap();
+
+    // An empty KRL revokes nothing.
+    let status = process::Command::new("ssh-keygen")
+        .arg("-k")
+        .arg("-f")
+        .arg(&krl)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut builder = SessionBuilder::default();
+    builder.revoked_host_keys(&krl);
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        session.close().await.unwrap();
+    }
+
+    let status = process::Command::new("ssh-keygen")
+        .arg("-k")
+        .arg("-f")
+        .arg(&krl)
+        .arg(&scanned)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    for err in session_builder_connects_err(&addr(), builder).await {
+        match err {
+            Error::HostKeyRevoked(_) => {}
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn update_host_keys() {
+    let mut builder = SessionBuilder::default();
+    builder
+        .update_host_keys(true)
+        .on_host_key_update(|update| eprintln!("{:?}", update));
+    for session in session_builder_connect(builder.clone(), &addr()).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    // Errors are still interpreted with the verbose master log.
+    let addr = addr().replace("test-user", "bad-user");
+    for err in session_builder_connects_err(&addr, builder).await {
+        match err {
+            Error::Connect(e) => assert_eq!(e.kind(), io::ErrorKind::PermissionDenied),
+            e => unreachable!("{:?}", e),
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn security_profile() {
+    for profile in [
+        SecurityProfile::Modern,
+        SecurityProfile::Fips,
+        SecurityProfile::Compatible,
+    ] {
+        let mut builder = SessionBuilder::default();
+        builder.security_profile(profile);
+        for session in session_builder_connect(builder, &addr()).await {
+            session.check().await.unwrap();
+            session.close().await.unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "process-mux")]
+async fn resume_verified() {
+    for session in connects().await {
+        let (ctl, master_log) = session.detach();
+
+        let session = Session::resume_verified(ctl, master_log).await.unwrap();
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    let dir = tempdir().unwrap();
+    let ctl = dir.path().join("planted");
+    std::fs::write(&ctl, "").unwrap();
+    match Session::resume_verified(ctl.into(), None).await {
+        Err(Error::UntrustedControlSocket(_)) => {}
+        res => unreachable!("{:?}", res.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn strict_commands() {
+    for mut session in connects().await {
+        session.set_strict_commands(true);
+
+        let child = session
+            .command("echo")
+            .arg("foo")
+            .arg("bar-baz.txt")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(child.stdout, b"foo bar-baz.txt\n");
+
+        match session.command("echo").arg("$(id)").output().await {
+            Err(Error::RejectedArgument(arg)) => assert_eq!(arg, "$(id)"),
+            res => unreachable!("{:?}", res),
+        }
+        match session.command("echo;id").output().await {
+            Err(Error::RejectedArgument(arg)) => assert_eq!(arg, "echo;id"),
+            res => unreachable!("{:?}", res),
+        }
+
+        let child = session
+            .command("echo")
+            .raw_arg("'foo bar'")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(child.stdout, b"foo bar\n");
+
+        let child = session
+            .command("echo")
+            .strict(false)
+            .arg("foo bar")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(child.stdout, b"foo bar\n");
+
+        let child = session.shell("echo foo | cat").output().await.unwrap();
+        assert_eq!(child.stdout, b"foo\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "process-mux")]
+async fn spawn_pool() {
+    let session = SessionBuilder::default()
+        .user_known_hosts_file(get_known_hosts_path())
+        .connect(&addr())
+        .await
+        .unwrap();
+    session.set_spawn_pool_size(2);
+
+    for _ in 0..4 {
+        let output = session
+            .command("echo")
+            .arg("foo bar")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"foo bar\n");
+        assert!(output.status.success());
+    }
+
+    let status = session.shell("echo foo; exit 3").status().await.unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    let output = session.shell("echo foo >&2").output().await.unwrap();
+    assert_eq!(output.stdout, b"");
+    assert_eq!(output.stderr, b"foo\n");
+
+    let mut child = session
+        .command("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .await
+        .unwrap();
+    let mut stdin = child.stdin().take().unwrap();
+    stdin.write_all(b"hello world").await.unwrap();
+    drop(stdin);
+    let mut out = String::new();
+    child
+        .stdout()
+        .take()
+        .unwrap()
+        .read_to_string(&mut out)
+        .await
+        .unwrap();
+    assert_eq!(out, "hello world");
+    assert!(child.wait().await.unwrap().success());
+
+    match session.command("no-such-program").output().await {
+        Err(Error::Remote(err)) => assert_eq!(err.kind(), io::ErrorKind::NotFound),
+        res => unreachable!("{:?}", res),
+    }
+
+    session.set_spawn_pool_size(0);
+    session.close().await.unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn spawn_all() {
+    for session in connects().await {
+        let commands = (0..5)
+            .map(|i| {
+                let mut command = session.command("echo");
+                command.arg(i.to_string()).stdout(Stdio::piped());
+                command
+            })
+            .collect();
+        let children = session.spawn_all(commands).await.unwrap();
+        assert_eq!(children.len(), 5);
+
+        for (i, child) in children.into_iter().enumerate() {
+            let output = child.wait_with_output().await.unwrap();
+            assert_eq!(output.stdout, format!("{}\n", i).as_bytes());
+        }
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn pipe_buffer_size() {
+    for session in connects().await {
+        let output = session
+            .command("head")
+            .arg("-c")
+            .arg("4194304")
+            .arg("/dev/zero")
+            .pipe_buffer_size(1024 * 1024)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout.len(), 4 * 1024 * 1024);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn idle_timeout() {
+    let mut builder = SessionBuilder::default();
+    builder.idle_timeout(Duration::from_secs(2));
+
+    for session in session_builder_connect(builder, &addr()).await {
+        // Running commands keeps the session open.
+        let status = session.command("sleep").arg("3").status().await.unwrap();
+        assert!(status.success());
+        session.check().await.unwrap();
+
+        sleep(Duration::from_secs(4)).await;
+        session.check().await.unwrap_err();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn tcp_keepalive() {
+    for enable in [true, false] {
+        let mut builder = SessionBuilder::default();
+        builder.tcp_keepalive(enable);
+        for session in session_builder_connect(builder, &addr()).await {
+            session.check().await.unwrap();
+            session.close().await.unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn output_callbacks() {
+    use std::sync::{Arc, Mutex};
+
+    for session in connects().await {
+        let stdout = Arc::new(Mutex::new(Vec::new()));
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+
+        let status = session
+            .shell("head -c 1048576 /dev/zero; echo foo >&2")
+            .on_stdout({
+                let stdout = Arc::clone(&stdout);
+                move |chunk| {
+                    stdout.lock().unwrap().extend(chunk);
+                    // A slow consumer.
+                    sleep(Duration::from_millis(1))
+                }
+            })
+            .on_stderr({
+                let stderr = Arc::clone(&stderr);
+                move |chunk| {
+                    stderr.lock().unwrap().extend(chunk);
+                    async {}
+                }
+            })
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(*stdout.lock().unwrap(), vec![0; 1024 * 1024]);
+        assert_eq!(*stderr.lock().unwrap(), b"foo\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn kill_on_drop() {
+    for session in connects().await {
+        let pidfile = "/tmp/openssh-rs-kill-on-drop";
+
+        let mut command = session.shell(format!("echo $$ > {}; exec sleep 30", pidfile));
+        command.kill_on_drop(true);
+
+        // Lose a race against a timeout.
+        tokio::select! {
+            _ = command.output() => unreachable!(),
+            _ = sleep(Duration::from_secs(2)) => (),
+        }
+
+        let mut killed = false;
+        for _ in 0..50 {
+            let alive = session
+                .shell(format!("kill -0 \"$(cat {})\"", pidfile))
+                .status()
+                .await
+                .unwrap();
+            if !alive.success() {
+                killed = true;
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert!(killed);
+
+        // The exit status and stdin are passed through.
+        let mut child = session
+            .shell("cat; exit 3")
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+        child
+            .stdin()
+            .take()
+            .unwrap()
+            .write_all(b"foo")
+            .await
+            .unwrap();
+        let output = child.wait_with_output().await.unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"foo");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "process-mux")]
+async fn drop_policy() {
+    let session = SessionBuilder::default()
+        .user_known_hosts_file(get_known_hosts_path())
+        .connect(&addr())
+        .await
+        .unwrap();
+
+    for (policy, survives) in [(DropPolicy::Detach, true), (DropPolicy::KillSsh, false)] {
+        let marker = "/tmp/openssh-rs-drop-policy";
+        session
+            .command("rm")
+            .args(["-f", marker])
+            .status()
+            .await
+            .unwrap();
+
+        // Writing to stdout fails once ssh is gone.
+        let child = session
+            .shell(format!("sleep 1; echo foo; touch {}", marker))
+            .drop_policy(policy)
+            .stdout(Stdio::null())
+            .spawn()
+            .await
+            .unwrap();
+        drop(child);
+        sleep(Duration::from_secs(3)).await;
+
+        let status = session
+            .command("test")
+            .args(["-e", marker])
+            .status()
+            .await
+            .unwrap();
+        assert_eq!(status.success(), survives, "{:?}", policy);
+    }
+
+    session.close().await.unwrap();
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn scheduling() {
+    for session in connects().await {
+        let output = session
+            .shell("nice; ionice; taskset -pc $$ | sed 's/.*: //'")
+            .nice(5)
+            .ionice(IoPriority::BestEffort(7))
+            .cpu_affinity([0])
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout, b"5\nbest-effort: prio 7\n0\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn limits() {
+    for session in connects().await {
+        let output = session
+            .shell("ulimit -t; ulimit -n")
+            .limit(Rlimit::CpuSeconds(10))
+            .limit(Rlimit::CpuSeconds(5))
+            .limit(Rlimit::OpenFiles(64))
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout, b"5\n64\n");
+
+        // A runaway process is stopped, which ssh may report as an error.
+        let status = session
+            .shell("while :; do :; done")
+            .limit(Rlimit::CpuSeconds(1))
+            .status()
+            .await;
+        assert!(!matches!(status, Ok(status) if status.success()));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn ping() {
+    for session in connects().await {
+        let latency = session.ping().await.unwrap();
+        assert!(latency > Duration::ZERO);
+        assert!(latency < Duration::from_secs(10));
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn server_version() {
+    for session in connects().await {
+        assert_eq!(session.server_version(), None);
+        session.close().await.unwrap();
+    }
+
+    let mut builder = SessionBuilder::default();
+    builder.capture_server_version(true);
+
+    for session in session_builder_connect(builder, &addr()).await {
+        let version = session.server_version().unwrap();
+        assert!(version.starts_with("OpenSSH_"), "{}", version);
+
+        // Debug output is not mistaken for errors.
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn origin() {
+    for session in connects().await {
+        if addr() == "ssh://test-user@127.0.0.1:2222" {
+            assert_eq!(session.destination(), Some("127.0.0.1"));
+            assert_eq!(session.user(), Some("test-user"));
+            assert_eq!(session.port(), Some(2222));
+        }
+        assert!(matches!(session.known_hosts(), Some(KnownHosts::Accept)));
+
+        let (ctl, master_log) = session.detach();
+        #[cfg(feature = "process-mux")]
+        let session = Session::resume(ctl, master_log);
+        #[cfg(not(feature = "process-mux"))]
+        let session = Session::resume_mux(ctl, master_log);
+        assert_eq!(session.destination(), None);
+        assert!(session.known_hosts().is_none());
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn uri_options() {
+    let addr = addr();
+    if !addr.starts_with("ssh://") {
+        return;
+    }
+
+    let uri = format!("{}?connect_timeout=10&compression=yes", addr);
+    for session in session_builder_connect(SessionBuilder::default(), &uri).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    let uri = format!("{}?timeout=10", addr);
+    for err in session_builder_connects_err(&uri, SessionBuilder::default()).await {
+        assert!(matches!(err, Error::InvalidDestination(_)), "{:?}", err);
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+#[cfg(feature = "process-mux")]
+async fn control_path() {
+    let dir = tempdir().unwrap();
+    let mut builder = SessionBuilder::default();
+    builder
+        .user_known_hosts_file(get_known_hosts_path())
+        .known_hosts_check(KnownHosts::Accept)
+        .control_path(format!("{}/cm-%C", dir.path().display()));
+
+    let path = builder.control_path_for(&addr()).await.unwrap().unwrap();
+    assert!(path
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("cm-"));
+
+    let session = builder.connect(&addr()).await.unwrap();
+    assert!(path.exists());
+    session.check().await.unwrap();
+
+    // Another process can attach to the master at the same path.
+    let resumed = Session::resume_verified(path.clone().into(), None)
+        .await
+        .unwrap();
+    resumed.check().await.unwrap();
+    drop(resumed);
+
+    let err = builder.connect(&addr()).await.unwrap_err();
+    assert!(matches!(err, Error::Master(_)), "{:?}", err);
+
+    session.close().await.unwrap();
+    assert!(!path.exists());
+
+    builder.control_path(format!("{}/%Z", dir.path().display()));
+    let err = builder.connect(&addr()).await.unwrap_err();
+    assert!(matches!(err, Error::Master(_)), "{:?}", err);
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn verbosity() {
+    let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let lines_ = lines.clone();
+
+    let mut builder = SessionBuilder::default();
+    builder
+        .known_hosts_check(KnownHosts::Accept)
+        .verbosity(1)
+        .on_log(move |line| {
+            lines_.lock().unwrap().push(line.to_owned());
+        });
+
+    for session in session_builder_connect(builder, &addr()).await {
+        session.check().await.unwrap();
+        session.close().await.unwrap();
+    }
+
+    let lines = lines.lock().unwrap();
+    assert!(lines.iter().any(|line| line.starts_with("debug1: ")));
+    assert!(!lines.iter().any(|line| line.starts_with("debug2: ")));
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn request_tty() {
+    for session in connects().await {
+        for (tty, expected) in [
+            (RequestTty::No, "none"),
+            (RequestTty::Auto, "none"),
+            // stdin is not a local terminal.
+            (RequestTty::Yes, "none"),
+            (RequestTty::Force, "tty"),
+        ] {
+            let output = session
+                .raw_command("if [ -t 0 ]; then echo tty; else echo none; fi")
+                .request_tty(tty)
+                .stdin(Stdio::null())
+                .output()
+                .await
+                .unwrap();
+            // A pseudo-terminal translates newlines to CRLF.
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert_eq!(stdout.trim_end(), expected, "{:?}", tty);
+        }
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn escape_char() {
+    for session in connects().await {
+        let mut child = session
+            .command("head")
+            .args(["-n", "3"])
+            .request_tty(RequestTty::Force)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+
+        // `~.` would disconnect if the escape character was enabled.
+        let mut stdin = child.stdin().take().unwrap();
+        stdin.write_all(b"a\n~.\nb\n").await.unwrap();
+        stdin.flush().await.unwrap();
+
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("~."), "{:?}", stdout);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn resize() {
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+
+    for session in connects().await {
+        // SAFETY: the fds are checked and owned by the `OwnedFd`s.
+        let (master, slave) = unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let name = std::ffi::CStr::from_ptr(libc::ptsname(master));
+            let slave = libc::open(name.as_ptr(), libc::O_RDWR | libc::O_NOCTTY);
+            assert!(slave >= 0);
+            (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+        };
+
+        let child = session
+            .raw_command("read line; stty size")
+            .request_tty(RequestTty::Force)
+            // SAFETY: the fd is owned by the `Stdio` alone.
+            .stdin(unsafe { Stdio::from_raw_fd_owned(slave.into_raw_fd()) })
+            .stdout(Stdio::piped())
+            .spawn()
+            .await
+            .unwrap();
+        child.resize(100, 40).await.unwrap();
+
+        // SAFETY: the buffer is valid for its length.
+        let written = unsafe { libc::write(master.as_raw_fd(), b"\n".as_ptr().cast(), 1) };
+        assert_eq!(written, 1);
+
+        let output = child.wait_with_output().await.unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("40 100"), "{:?}", stdout);
+
+        // Without a pseudo-terminal, there is nothing to resize.
+        let child = session
+            .raw_command("true")
+            .stdin(Stdio::null())
+            .spawn()
+            .await
+            .unwrap();
+        let err = child.resize(100, 40).await.unwrap_err();
+        assert!(matches!(err, Error::ChildIo(_)), "{:?}", err);
+        child.wait().await.unwrap();
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn strip_ansi() {
+    use std::sync::{Arc, Mutex};
+
+    for session in connects().await {
+        let output = session
+            .raw_command(r"printf '\033[1;31mred\033[0m\n'; printf '\033]0;title\007err\n' >&2")
+            .strip_ansi(true)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout, b"red\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let streamed = Arc::new(Mutex::new(Vec::new()));
+        let chunks = Arc::clone(&streamed);
+        let status = session
+            .raw_command(r"printf '\033[32mok\033[0m\n'")
+            .strip_ansi(true)
+            .on_stdout(move |chunk| {
+                chunks.lock().unwrap().extend(chunk);
+                async {}
+            })
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(*streamed.lock().unwrap(), b"ok\n");
+
+        // Without the option, the output is left as is.
+        let output = session
+            .raw_command(r"printf '\033[1mbold\033[0m'")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"\x1b[1mbold\x1b[0m");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn output_json() {
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Entry {
+        name: String,
+        size: u64,
+    }
+
+    for session in connects().await {
+        let entry: Entry = session
+            .raw_command(r#"echo '{"name": "a", "size": 1}'"#)
+            .output_json()
+            .await
+            .unwrap();
+        assert_eq!(
+            entry,
+            Entry {
+                name: "a".into(),
+                size: 1
+            }
+        );
+
+        let entries: Vec<Entry> = session
+            .raw_command(r#"printf '{"name":"a","size":1}\n{"name":"b","size":2}\n'"#)
+            .output_json_lines()
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name, "b");
+
+        let err = session
+            .raw_command("echo not json")
+            .output_json::<Entry>()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Remote(e) if e.kind() == io::ErrorKind::InvalidData),
+            "{:?}",
+            err
+        );
+
+        let err = session
+            .raw_command("echo nope >&2; exit 3")
+            .output_json::<Entry>()
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Remote(e) if e.to_string() == "nope"),
+            "{:?}",
+            err
+        );
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn output_streamed() {
+    for session in connects().await {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let output = session
+            .raw_command("echo out; echo err >&2; sleep 0.1; echo more")
+            .output_streamed(
+                |chunk| stdout.extend_from_slice(chunk),
+                |chunk| stderr.extend_from_slice(chunk),
+            )
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout, b"out\nmore\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(stdout, output.stdout);
+        assert_eq!(stderr, output.stderr);
+
+        // Streams that are not piped are neither collected nor streamed.
+        let mut called = false;
+        let output = session
+            .raw_command("echo out")
+            .stdout(Stdio::null())
+            .output_streamed(|_| called = true, |_| ())
+            .await
+            .unwrap();
+        assert!(output.stdout.is_empty());
+        assert!(!called);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn session_env() {
+    let dir = tempdir().unwrap();
+    let env_file = dir.path().join(".env");
+    std::fs::write(&env_file, "export APP_MODE=test\nGREETING='$HOME stays'\n").unwrap();
+
+    for mut session in connects().await {
+        session
+            .set_envs([("APP_MODE", "dev"), ("APP_NAME", "demo")])
+            .unwrap();
+        session.source_env(&env_file).unwrap();
+
+        let output = session
+            .command("printenv")
+            .args(["APP_MODE", "APP_NAME", "GREETING"])
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout, b"test\ndemo\n$HOME stays\n");
+
+        let err = session.set_envs([("NOT-VALID", "x")]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        session.clear_envs();
+        let output = session
+            .command("printenv")
+            .arg("APP_MODE")
+            .output()
+            .await
+            .unwrap();
+        assert!(output.stdout.is_empty());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn remote_env() {
+    for mut session in connects().await {
+        session.set_envs([("APP_MODE", "a=b\nc")]).unwrap();
+
+        let env = session.remote_env().await.unwrap();
+        assert!(env.contains_key(std::ffi::OsStr::new("PATH")), "{:?}", env);
+        assert_eq!(env[std::ffi::OsStr::new("APP_MODE")], "a=b\nc");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn locale() {
+    for mut session in connects().await {
+        let output = session
+            .command("printenv")
+            .arg("LC_ALL")
+            .locale("C")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"C\n");
+
+        session.set_locale(Some("POSIX"));
+        let output = session
+            .command("printenv")
+            .arg("LC_ALL")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"POSIX\n");
+
+        // The locale of a command overrides the one of the session.
+        let output = session
+            .command("printenv")
+            .arg("LC_ALL")
+            .locale("C")
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, b"C\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn umask() {
+    for session in connects().await {
+        let output = session
+            .raw_command("f=$(mktemp -u) && touch $f && stat -c %a $f && rm $f")
+            .umask(0o077)
+            .output()
+            .await
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(output.stdout, b"600\n");
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn su_output() {
+    for session in connects().await {
+        let err = session
+            .su_output("root", "not the password", "id -u")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Remote(e) if e.kind() == io::ErrorKind::PermissionDenied),
+            "{:?}",
+            err
+        );
+
+        let output = session
+            .su_output("no-such-user", "password", "id -u")
+            .await
+            .unwrap();
+        assert!(!output.status.success(), "{:?}", output);
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn supervise_command() {
+    for session in connects().await {
+        let session = std::sync::Arc::new(session);
+
+        let mut policy = RestartPolicy::default();
+        policy
+            .max_restarts(Some(2))
+            .backoff(Duration::from_millis(10), Duration::from_millis(20));
+        let mut supervisor = std::sync::Arc::clone(&session).supervise_command(policy, |session| {
+            let mut command = session.arc_raw_command("exit 3");
+            command.stdin(Stdio::null());
+            command
+        });
+
+        let mut events = Vec::new();
+        while let Some(event) = supervisor.next_event().await {
+            events.push(match event {
+                SupervisorEvent::Started => "started".to_owned(),
+                SupervisorEvent::Exited(status) => format!("exited {:?}", status.code()),
+                SupervisorEvent::Restarting { restart, backoff } => {
+                    format!("restarting {} {:?}", restart, backoff)
+                }
+                event => panic!("unexpected event {:?}", event),
+            });
+        }
+        assert_eq!(
+            events,
+            [
+                "started",
+                "exited Some(3)",
+                "restarting 1 10ms",
+                "started",
+                "exited Some(3)",
+                "restarting 2 20ms",
+                "started",
+                "exited Some(3)",
+            ]
+        );
+        assert!(supervisor.is_finished());
+
+        // A successful exit ends the supervision.
+        let mut supervisor = std::sync::Arc::clone(&session).supervise_command(
+            RestartPolicy::default(),
+            |session| {
+                let mut command = session.arc_raw_command("true");
+                command.stdin(Stdio::null());
+                command
+            },
+        );
+        assert!(matches!(
+            supervisor.next_event().await,
+            Some(SupervisorEvent::Started)
+        ));
+        assert!(
+            matches!(supervisor.next_event().await, Some(SupervisorEvent::Exited(status)) if status.success())
+        );
+        assert!(supervisor.next_event().await.is_none());
+
+        drop(supervisor);
+        std::sync::Arc::try_unwrap(session)
+            .unwrap()
+            .close()
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn run_periodically() {
+    for session in connects().await {
+        let session = std::sync::Arc::new(session);
+
+        let (sender, mut results) = tokio::sync::mpsc::unbounded_channel();
+        let mut schedule = Schedule::every(Duration::from_millis(100));
+        schedule.jitter(Duration::from_millis(10));
+        let task = std::sync::Arc::clone(&session).run_periodically(
+            schedule,
+            |session| session.arc_raw_command("echo ok"),
+            move |result| {
+                let _ = sender.send(result);
+            },
+        );
+
+        for _ in 0..3 {
+            let output = results.recv().await.unwrap().unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            assert_eq!(output.stdout, b"ok\n");
+        }
+
+        // Runs that take longer than the interval do not overlap.
+        drop(task);
+        let (sender, mut results) = tokio::sync::mpsc::unbounded_channel();
+        let task = std::sync::Arc::clone(&session).run_periodically(
+            Schedule::every(Duration::from_millis(10)),
+            |session| session.arc_raw_command("sleep 0.2"),
+            move |result| {
+                let _ = sender.send((std::time::Instant::now(), result));
+            },
+        );
+        let (first, _) = results.recv().await.unwrap();
+        let (second, _) = results.recv().await.unwrap();
+        assert!(second - first >= Duration::from_millis(200));
+
+        drop(task);
+        sleep(Duration::from_millis(50)).await;
+        std::sync::Arc::try_unwrap(session)
+            .unwrap()
+            .close()
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn forward_docker_socket() {
+    for session in connects().await {
+        let docker = session
+            .forward_docker_socket("/var/run/docker.sock")
+            .await
+            .unwrap();
+        let path = docker.path().to_owned();
+        assert!(path.exists());
+        assert_eq!(docker.docker_host(), format!("unix://{}", path.display()));
+        assert!(session
+            .forwards()
+            .iter()
+            .any(|forward| matches!(forward, ActiveForward::Local { .. })));
+
+        docker.close().await.unwrap();
+        assert!(!path.exists());
+
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn connect_remote() {
+    for session in connects().await {
+        // sshd inside the container listens on port 2222.
+        let mut stream = session.connect_remote((loopback(), 2222)).await.unwrap();
+
+        let mut banner = [0_u8; 8];
+        stream.read_exact(&mut banner).await.unwrap();
+        assert_eq!(&banner, b"SSH-2.0-");
+
+        drop(stream);
+        session.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(ci), ignore)]
+async fn tunnel_tcp() {
+    for session in connects().await {
+        let (addr, guard) = session.tunnel_tcp((loopback(), 2222)).await.unwrap();
+
+        for _ in 0..2 {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+            let mut banner = [0_u8; 8];
+            stream.read_exact(&mut banner).await.unwrap();
+            assert_eq!(&banner, b"SSH-2.0-");
+        }
+
+        drop(guard);
+        sleep(Duration::from_millis(100)).await;
+        tokio::net::TcpStream::connect(addr).await.unwrap_err();
+
+        session.close().await.unwrap();
+    }
+}
+
 #[tokio::test]
 #[cfg_attr(not(ci), ignore)]
 #[cfg(feature = "process-mux")]